@@ -0,0 +1,53 @@
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+
+use ical_merge::bench::{sample_calendar, sample_steps};
+use ical_merge::config::{DefaultAction, OutputConfig};
+use ical_merge::filter::process_tagged_events_with_stats;
+use ical_merge::ical::{Event, parse_calendar, serialize_events_with};
+use ical_merge::merge::deduplicate_events;
+
+/// Events in the calendar each stage is timed with
+const EVENTS: usize = 1_000;
+
+fn tagged(events: &[Event]) -> Vec<(String, Event)> {
+    events
+        .iter()
+        .map(|event| ("source:0".to_string(), event.clone()))
+        .collect()
+}
+
+fn stages(c: &mut Criterion) {
+    let ical_text = sample_calendar(EVENTS);
+    let events = parse_calendar(&ical_text).unwrap().into_events();
+    let steps = sample_steps().unwrap();
+    let output = OutputConfig::default();
+
+    let mut group = c.benchmark_group("merge");
+    group.sample_size(20);
+    group.bench_function("parse", |b| b.iter(|| parse_calendar(&ical_text).unwrap()));
+    group.bench_function("steps", |b| {
+        b.iter_batched(
+            || tagged(&events),
+            |events| process_tagged_events_with_stats(events, &steps, &DefaultAction::Allow),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("dedupe", |b| {
+        b.iter_batched(
+            || tagged(&events),
+            deduplicate_events,
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("serialize", |b| {
+        b.iter_batched(
+            || events.clone(),
+            |events| serialize_events_with(events, &output, None),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, stages);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+#![no_main]
+
+use ical_merge::ical::{parse_calendar_bytes, serialize_events};
+use libfuzzer_sys::fuzz_target;
+
+// Whatever an upstream sends, reading it and writing its events back out must not panic
+fuzz_target!(|data: &[u8]| {
+    if let Ok(calendar) = parse_calendar_bytes(data) {
+        serialize_events(calendar.into_events());
+    }
+});
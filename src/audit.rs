@@ -0,0 +1,127 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::config::AuditLogConfig;
+use crate::error::Result;
+
+/// One access to a calendar, written as a JSON line
+#[derive(Debug, Serialize)]
+pub struct AuditEntry<'a> {
+    /// RFC 3339 timestamp of the access
+    pub time: String,
+    /// Calendar that was served
+    pub calendar: &'a str,
+    /// Id used in the URL, which differs from `calendar` when an alias was used
+    pub requested: &'a str,
+    pub ip: Option<&'a str>,
+    pub forwarded_for: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+/// Appends entries to the configured audit log, rotating it when it gets too large
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    /// Serializes writes so concurrent entries and rotation don't interleave
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append an entry to the log described by `config`
+    pub fn record(&self, config: &AuditLogConfig, entry: &AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry).expect("audit entries always serialize");
+        line.push('\n');
+
+        let _guard = self.lock.lock().unwrap();
+
+        let size = fs::metadata(&config.path).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > config.max_size() {
+            rotate(&config.path, config.max_files())?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Path of the `n`th rotated log, e.g. `access.log.1`
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Shift `path` to `path.1`, `path.1` to `path.2` and so on, dropping the oldest
+fn rotate(path: &Path, max_files: usize) -> Result<()> {
+    if max_files == 0 {
+        fs::remove_file(path)?;
+        return Ok(());
+    }
+
+    let _ = fs::remove_file(rotated_path(path, max_files));
+    for n in (1..max_files).rev() {
+        let from = rotated_path(path, n);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, n + 1))?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(calendar: &str) -> AuditEntry<'_> {
+        AuditEntry {
+            time: "2025-01-01T00:00:00Z".to_string(),
+            calendar,
+            requested: calendar,
+            ip: Some("192.0.2.1"),
+            forwarded_for: None,
+            user_agent: None,
+        }
+    }
+
+    #[test]
+    fn test_record_and_rotate() {
+        let dir = std::env::temp_dir().join(format!("audit-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let line_len = serde_json::to_string(&entry("work")).unwrap().len() as u64 + 1;
+        let config = AuditLogConfig {
+            path: dir.join("access.log"),
+            max_size: Some(line_len * 2),
+            max_files: Some(2),
+        };
+
+        let log = AuditLog::new();
+        for _ in 0..7 {
+            log.record(&config, &entry("work")).unwrap();
+        }
+
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        let current = read(config.path.clone());
+        assert_eq!(current.lines().count(), 1);
+        assert!(current.contains("\"calendar\":\"work\""));
+        assert!(current.contains("\"ip\":\"192.0.2.1\""));
+        assert_eq!(read(rotated_path(&config.path, 1)).lines().count(), 2);
+        assert_eq!(read(rotated_path(&config.path, 2)).lines().count(), 2);
+        assert!(!rotated_path(&config.path, 3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
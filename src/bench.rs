@@ -0,0 +1,155 @@
+use std::time::{Duration, Instant};
+
+use crate::config::{DefaultAction, OutputConfig, Step};
+use crate::error::Result;
+use crate::filter::{CompiledStep, process_tagged_events_with_stats};
+use crate::ical::{Event, parse_calendar, serialize_events_with};
+use crate::merge::deduplicate_events;
+
+/// Events in the sample calendar when no size is given. Parsing takes more than
+/// twice as long when the events double, so much larger feeds are slow to time
+pub const DEFAULT_EVENTS: usize = 2_000;
+
+const ROUTES: [&str; 6] = [
+    "Red Line",
+    "Blue Line",
+    "Route 42",
+    "Harbour Ferry",
+    "Night Bus N7",
+    "Airport Express",
+];
+
+/// A large calendar to benchmark with, shaped like a transit feed. It's the same
+/// every time, and one event in ten repeats the times of another for deduplication
+pub fn sample_calendar(events: usize) -> String {
+    let mut ical =
+        String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//ical-merge//bench//EN\r\n");
+    for idx in 0..events {
+        // Every tenth event shares its slot with the one before it
+        let slot = if idx % 10 == 9 { idx - 1 } else { idx };
+        let day = slot / 96;
+        let minutes = (slot % 96) * 15;
+        let route = ROUTES[idx % ROUTES.len()];
+        let status = if idx % 7 == 0 { "CANCELLED: " } else { "" };
+        ical.push_str(&format!(
+            "BEGIN:VEVENT\r\n\
+             UID:trip-{idx}@bench.ical-merge\r\n\
+             DTSTAMP:20250101T000000Z\r\n\
+             DTSTART:2025{month:02}{date:02}T{hour:02}{minute:02}00Z\r\n\
+             DTEND:2025{month:02}{date:02}T{hour:02}{end:02}00Z\r\n\
+             SUMMARY:{status}{route} trip {idx}\r\n\
+             DESCRIPTION:Calls at stops 1 to {stops}\\, platform {platform}\r\n\
+             LOCATION:Stop {platform}\r\n\
+             END:VEVENT\r\n",
+            month = day / 28 % 12 + 1,
+            date = day % 28 + 1,
+            hour = minutes / 60,
+            minute = minutes % 60,
+            end = minutes % 60 + 10,
+            stops = idx % 40 + 2,
+            platform = idx % 12 + 1,
+        ));
+    }
+    ical.push_str("END:VCALENDAR\r\n");
+    ical
+}
+
+/// A pipeline like one used to tidy up a transit feed, using the common kinds of step
+pub fn sample_steps() -> Result<Vec<CompiledStep>> {
+    let steps: Vec<Step> = serde_json::from_value(serde_json::json!([
+        { "type": "deny", "patterns": ["^CANCELLED"] },
+        { "type": "allow", "patterns": ["Line", "Route", "Ferry", "Express"], "action": "annotate" },
+        { "type": "replace", "pattern": "(?i)\\btrip (\\d+)", "replacement": "#$1" },
+        { "type": "case", "transform": "title" },
+        { "type": "strip", "field": "location" },
+    ]))
+    .map_err(|e| crate::error::Error::Config(format!("Invalid sample steps: {}", e)))?;
+    CompiledStep::compile_many(&steps)
+}
+
+/// The stages of a merge that are timed
+pub const STAGES: [&str; 4] = ["parse", "steps", "dedupe", "serialize"];
+
+/// How long a stage of a merge took
+#[derive(Debug, Clone)]
+pub struct StageTiming {
+    pub stage: &'static str,
+    /// Middle of the runs' times, which a slow first run doesn't skew
+    pub median: Duration,
+    pub fastest: Duration,
+}
+
+/// Time parsing `ical_text` and putting its events through the sample steps,
+/// deduplication and serialization, `iterations` times each
+pub fn run(ical_text: &str, iterations: usize) -> Result<Vec<StageTiming>> {
+    let steps = sample_steps()?;
+    let output = OutputConfig::default();
+    let iterations = iterations.max(1);
+    let mut times: Vec<Vec<Duration>> = vec![Vec::with_capacity(iterations); STAGES.len()];
+
+    for _ in 0..iterations {
+        let started = Instant::now();
+        let events = parse_calendar(ical_text)?.into_events();
+        times[0].push(started.elapsed());
+
+        let tagged: Vec<(String, Event)> = events
+            .into_iter()
+            .map(|event| ("source:0".to_string(), event))
+            .collect();
+        let started = Instant::now();
+        let (processed, _) =
+            process_tagged_events_with_stats(tagged, &steps, &DefaultAction::Allow);
+        times[1].push(started.elapsed());
+
+        let started = Instant::now();
+        let (deduplicated, _) = deduplicate_events(processed);
+        times[2].push(started.elapsed());
+
+        let started = Instant::now();
+        let serialized = serialize_events_with(deduplicated, &output, None);
+        times[3].push(started.elapsed());
+        std::hint::black_box(serialized);
+    }
+
+    Ok(STAGES
+        .iter()
+        .zip(times)
+        .map(|(stage, mut times)| {
+            times.sort();
+            StageTiming {
+                stage,
+                median: times[times.len() / 2],
+                fastest: times[0],
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_calendar() {
+        let ical = sample_calendar(100);
+        assert_eq!(ical, sample_calendar(100));
+
+        let events = parse_calendar(&ical).unwrap().into_events();
+        assert_eq!(events.len(), 100);
+        let tagged = events
+            .into_iter()
+            .map(|event| ("source:0".to_string(), event))
+            .collect();
+        let (deduplicated, stats) = deduplicate_events(tagged);
+        assert_eq!(deduplicated.len(), 90);
+        assert_eq!(stats.removed, 10);
+    }
+
+    #[test]
+    fn test_run() {
+        let timings = run(&sample_calendar(50), 3).unwrap();
+        let stages: Vec<_> = timings.iter().map(|timing| timing.stage).collect();
+        assert_eq!(stages, STAGES);
+        assert!(timings.iter().all(|timing| timing.fastest <= timing.median));
+    }
+}
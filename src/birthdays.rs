@@ -0,0 +1,420 @@
+use chrono::{Datelike, NaiveDate};
+use icalendar::{Component, EventLike};
+
+use crate::ical::Event;
+
+/// Body of the CardDAV REPORT asking an address book for every contact's vCard
+pub const ADDRESSBOOK_QUERY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<C:addressbook-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:carddav">
+  <D:prop>
+    <C:address-data/>
+  </D:prop>
+</C:addressbook-query>
+"#;
+
+/// Year given to birthdays without one, a leap year so 29 February is a real date
+const UNKNOWN_YEAR: i32 = 2000;
+
+/// A contact from an address book that has a birthday
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contact {
+    pub uid: Option<String>,
+    pub name: String,
+    /// Year of birth, when the address book knows it
+    pub year: Option<i32>,
+    pub month: u32,
+    pub day: u32,
+}
+
+/// Undo vCard text escaping of commas, semicolons, backslashes and newlines
+fn unescape(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => text.push('\n'),
+            Some(escaped) => text.push(escaped),
+            None => text.push('\\'),
+        }
+    }
+    text
+}
+
+/// Parse a BDAY value such as `1985-04-12`, `19850412` or `--04-12` for a birthday
+/// without a year. A time after the date is ignored
+fn parse_birthday(value: &str) -> Option<(Option<i32>, u32, u32)> {
+    let date = value.trim().split('T').next()?;
+    let (year, month_day): (Option<i32>, String) = match date.strip_prefix("--") {
+        Some(month_day) => (None, month_day.replace('-', "")),
+        None => {
+            let digits = date.replace('-', "");
+            if digits.len() != 8 {
+                return None;
+            }
+            (Some(digits[..4].parse().ok()?), digits[4..].to_string())
+        }
+    };
+    if month_day.len() != 4 || !month_day.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let month = month_day[..2].parse().ok()?;
+    let day = month_day[2..].parse().ok()?;
+    NaiveDate::from_ymd_opt(year.unwrap_or(UNKNOWN_YEAR), month, day)?;
+    Some((year, month, day))
+}
+
+/// Read the contacts with a name and a birthday from vCard text, which may hold
+/// any number of cards
+pub fn parse_contacts(text: &str) -> Vec<Contact> {
+    // Long lines are folded onto following lines that start with whitespace
+    let unfolded = text
+        .replace("\r\n", "\n")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+
+    let mut contacts = Vec::new();
+    let mut card: Option<Vec<(String, String, String)>> = None;
+    for line in unfolded.lines() {
+        let Some((name_params, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, params) = name_params.split_once(';').unwrap_or((name_params, ""));
+        // Apple groups related properties as `item1.BDAY`
+        let name = name.rsplit('.').next().unwrap_or(name).to_ascii_uppercase();
+
+        match (name.as_str(), value.trim().to_ascii_uppercase().as_str()) {
+            ("BEGIN", "VCARD") => card = Some(Vec::new()),
+            ("END", "VCARD") => {
+                if let Some(contact) = card.take().and_then(|card| contact(&card)) {
+                    contacts.push(contact);
+                }
+            }
+            _ => {
+                if let Some(card) = &mut card {
+                    card.push((name, params.to_ascii_uppercase(), value.to_string()));
+                }
+            }
+        }
+    }
+    contacts
+}
+
+/// Build a contact from a card's properties, if it has a name and a birthday
+fn contact(card: &[(String, String, String)]) -> Option<Contact> {
+    let property = |wanted: &str| {
+        card.iter()
+            .find(|(name, _, value)| name == wanted && !value.trim().is_empty())
+    };
+
+    let (_, params, bday) = property("BDAY")?;
+    let (mut year, month, day) = parse_birthday(bday)?;
+    // Apple stores birthdays without a year with a placeholder year it names
+    if let Some(omitted) = params
+        .split(';')
+        .find_map(|param| param.strip_prefix("X-APPLE-OMIT-YEAR="))
+        && omitted.parse().ok() == year
+    {
+        year = None;
+    }
+
+    let name = match property("FN") {
+        Some((_, _, name)) => unescape(name.trim()),
+        None => {
+            // N is family;given;additional;prefixes;suffixes
+            let (_, _, n) = property("N")?;
+            let parts: Vec<String> = n.split(';').map(unescape).collect();
+            let given = parts.get(1).map(String::as_str).unwrap_or_default();
+            format!("{} {}", given, parts[0]).trim().to_string()
+        }
+    };
+    if name.is_empty() {
+        return None;
+    }
+
+    Some(Contact {
+        uid: property("UID").map(|(_, _, uid)| unescape(uid.trim())),
+        name,
+        year,
+        month,
+        day,
+    })
+}
+
+/// Build a yearly all-day event for each contact's birthday
+pub fn birthday_events(contacts: &[Contact]) -> Vec<Event> {
+    contacts
+        .iter()
+        .filter_map(|contact| {
+            let start = NaiveDate::from_ymd_opt(
+                contact.year.unwrap_or(UNKNOWN_YEAR),
+                contact.month,
+                contact.day,
+            )?;
+            let key = match &contact.uid {
+                Some(uid) => uid.clone(),
+                None => contact
+                    .name
+                    .to_lowercase()
+                    .chars()
+                    .map(|c| if c.is_alphanumeric() { c } else { '-' })
+                    .collect(),
+            };
+            // Outside leap years a 29 February birthday is marked on the last day of February
+            let rrule = if start.month() == 2 && start.day() == 29 {
+                "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=-1"
+            } else {
+                "FREQ=YEARLY"
+            };
+
+            let mut event = icalendar::Event::new();
+            event
+                .uid(&format!("birthday-{}@birthdays.ical-merge", key))
+                .summary(&format!("{}'s birthday", contact.name))
+                .starts(start)
+                .add_property("DURATION", "P1D")
+                .add_property("RRULE", rrule)
+                .add_property("TRANSP", "TRANSPARENT");
+            if let Some(year) = contact.year {
+                event.description(&format!("Born in {}", year));
+            }
+            Some(Event::new(event.done()))
+        })
+        .collect()
+}
+
+/// Pull the vCards out of a CardDAV multistatus response, whatever namespace
+/// prefix the server gives its `address-data` elements
+pub fn address_data(xml: &str) -> String {
+    let mut vcards = String::new();
+    let mut rest = xml;
+    while let Some(open) = rest.find('<') {
+        rest = &rest[open + 1..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        if name.rsplit(':').next() != Some("address-data") || tag.ends_with('/') {
+            continue;
+        }
+        let close = format!("</{}>", name);
+        let Some(length) = rest.find(&close) else {
+            break;
+        };
+        let data = rest[..length].trim();
+        match data
+            .strip_prefix("<![CDATA[")
+            .and_then(|data| data.strip_suffix("]]>"))
+        {
+            Some(raw) => vcards.push_str(raw),
+            None => vcards.push_str(&unescape_xml(data)),
+        }
+        vcards.push_str("\r\n");
+        rest = &rest[length + close.len()..];
+    }
+    vcards
+}
+
+/// Replace XML's named and numeric character references
+fn unescape_xml(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        unescaped.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity = rest.find(';').map(|end| &rest[1..end]);
+        let replacement = entity.and_then(|entity| match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                let code = match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#')?.parse().ok(),
+                };
+                code.and_then(char::from_u32)
+            }
+        });
+        match (entity, replacement) {
+            (Some(entity), Some(c)) => {
+                unescaped.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Whether an address book URL names a collection to ask with a CardDAV REPORT,
+/// rather than a vCard file to download
+pub fn is_collection(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    ["http://", "https://"]
+        .iter()
+        .any(|scheme| url.to_ascii_lowercase().starts_with(scheme))
+        && !path.to_ascii_lowercase().ends_with(".vcf")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ical::serialize_events;
+
+    const VCARDS: &str = "BEGIN:VCARD\r\n\
+        VERSION:3.0\r\n\
+        UID:ada\r\n\
+        FN:Ada Lovelace\r\n\
+        BDAY:1815-12-10\r\n\
+        END:VCARD\r\n\
+        BEGIN:VCARD\r\n\
+        VERSION:4.0\r\n\
+        N:Hopper;Grace;;;\r\n\
+        BDAY:--1209\r\n\
+        END:VCARD\r\n\
+        BEGIN:VCARD\r\n\
+        VERSION:3.0\r\n\
+        FN:Leap\\, Day\r\n\
+        item1.BDAY;X-APPLE-OMIT-YEAR=1604:1604-02-29\r\n\
+        END:VCARD\r\n\
+        BEGIN:VCARD\r\n\
+        VERSION:3.0\r\n\
+        FN:No Birthday\r\n\
+        END:VCARD\r\n";
+
+    #[test]
+    fn test_parse_birthday() {
+        assert_eq!(parse_birthday("19850412"), Some((Some(1985), 4, 12)));
+        assert_eq!(parse_birthday("1985-04-12"), Some((Some(1985), 4, 12)));
+        assert_eq!(
+            parse_birthday("1985-04-12T00:00:00Z"),
+            Some((Some(1985), 4, 12))
+        );
+        assert_eq!(parse_birthday("--0412"), Some((None, 4, 12)));
+        assert_eq!(parse_birthday("--04-12"), Some((None, 4, 12)));
+        assert_eq!(parse_birthday("--0229"), Some((None, 2, 29)));
+        assert_eq!(parse_birthday("1985-02-30"), None);
+        assert_eq!(parse_birthday("circa 1800"), None);
+    }
+
+    #[test]
+    fn test_parse_contacts() {
+        let contacts = parse_contacts(VCARDS);
+        assert_eq!(
+            contacts,
+            vec![
+                Contact {
+                    uid: Some("ada".to_string()),
+                    name: "Ada Lovelace".to_string(),
+                    year: Some(1815),
+                    month: 12,
+                    day: 10,
+                },
+                Contact {
+                    uid: None,
+                    name: "Grace Hopper".to_string(),
+                    year: None,
+                    month: 12,
+                    day: 9,
+                },
+                Contact {
+                    uid: None,
+                    name: "Leap, Day".to_string(),
+                    year: None,
+                    month: 2,
+                    day: 29,
+                },
+            ]
+        );
+
+        // Folded lines are joined back up
+        let folded = "BEGIN:VCARD\r\nFN:Ada Love\r\n lace\r\nBDAY:18151210\r\nEND:VCARD\r\n";
+        assert_eq!(parse_contacts(folded)[0].name, "Ada Lovelace");
+    }
+
+    #[test]
+    fn test_birthday_events() {
+        let events = birthday_events(&parse_contacts(VCARDS));
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].summary(), Some("Ada Lovelace's birthday"));
+        assert_eq!(events[0].uid(), Some("birthday-ada@birthdays.ical-merge"));
+        assert_eq!(events[0].property("RRULE"), Some("FREQ=YEARLY"));
+        assert_eq!(events[0].property("DESCRIPTION"), Some("Born in 1815"));
+        assert_eq!(
+            events[1].uid(),
+            Some("birthday-grace-hopper@birthdays.ical-merge")
+        );
+        assert_eq!(events[1].property("DESCRIPTION"), None);
+        assert_eq!(
+            events[2].property("RRULE"),
+            Some("FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=-1")
+        );
+
+        let ical = serialize_events(events);
+        assert!(ical.contains("DTSTART;VALUE=DATE:18151210"));
+        assert!(ical.contains("DTSTART;VALUE=DATE:20001209"));
+        assert!(ical.contains("DTSTART;VALUE=DATE:20000229"));
+    }
+
+    #[test]
+    fn test_address_data() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:card="urn:ietf:params:xml:ns:carddav">
+  <d:response>
+    <d:href>/contacts/ada.vcf</d:href>
+    <d:propstat><d:prop>
+      <card:address-data>BEGIN:VCARD&#13;
+FN:Ada &amp; Co&#13;
+BDAY:18151210&#13;
+END:VCARD&#13;
+</card:address-data>
+    </d:prop></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/contacts/grace.vcf</d:href>
+    <d:propstat><d:prop>
+      <address-data xmlns="urn:ietf:params:xml:ns:carddav"><![CDATA[BEGIN:VCARD
+FN:Grace <Hopper>
+BDAY:--1209
+END:VCARD]]></address-data>
+    </d:prop></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/contacts/empty.vcf</d:href>
+    <d:propstat><d:prop><card:address-data/></d:prop></d:propstat>
+  </d:response>
+</d:multistatus>"#;
+
+        let names: Vec<String> = parse_contacts(&address_data(xml))
+            .into_iter()
+            .map(|contact| contact.name)
+            .collect();
+        assert_eq!(names, vec!["Ada & Co", "Grace <Hopper>"]);
+        assert_eq!(unescape_xml("&#x41;&bogus; &"), "A&bogus; &");
+    }
+
+    #[test]
+    fn test_is_collection() {
+        assert!(is_collection(
+            "https://dav.example.com/addressbooks/me/contacts/"
+        ));
+        assert!(!is_collection("https://example.com/contacts.VCF?token=1"));
+        assert!(!is_collection("file:///home/me/contacts.vcf"));
+        assert!(!is_collection("s3://team/contacts"));
+    }
+}
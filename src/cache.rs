@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::merge::MergeResult;
+
+/// A merged calendar kept for reuse
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub result: Arc<MergeResult>,
+    pub merged_at: Instant,
+}
+
+/// Caps on what the cache holds across every calendar, unlimited when unset
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheLimits {
+    /// Approximate bytes of events, going by the size of their iCal text
+    pub max_bytes: Option<u64>,
+    pub max_events: Option<usize>,
+}
+
+/// What the cache is holding
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CacheStats {
+    /// Calendars with a cached merge
+    pub entries: usize,
+    /// Approximate bytes of the cached events
+    pub bytes: u64,
+    pub events: usize,
+    /// Merges dropped to stay within the limits since the server started
+    pub evictions: u64,
+}
+
+#[derive(Debug)]
+struct Stored {
+    entry: CacheEntry,
+    bytes: u64,
+    events: usize,
+    /// When the merge was last inserted or read, on the cache's own clock
+    used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    calendars: HashMap<String, Stored>,
+    limits: CacheLimits,
+    stats: CacheStats,
+    clock: u64,
+}
+
+impl Entries {
+    /// Mark a calendar as just used, so it's the last to be evicted
+    fn touch(&mut self, calendar_id: &str) -> Option<&CacheEntry> {
+        self.clock += 1;
+        let stored = self.calendars.get_mut(calendar_id)?;
+        stored.used = self.clock;
+        Some(&stored.entry)
+    }
+
+    fn remove(&mut self, calendar_id: &str) -> Option<Stored> {
+        let stored = self.calendars.remove(calendar_id)?;
+        self.stats.entries -= 1;
+        self.stats.bytes -= stored.bytes;
+        self.stats.events -= stored.events;
+        Some(stored)
+    }
+
+    fn over_limits(&self) -> bool {
+        self.limits
+            .max_bytes
+            .is_some_and(|max| self.stats.bytes > max)
+            || self
+                .limits
+                .max_events
+                .is_some_and(|max| self.stats.events > max)
+    }
+
+    /// Whether a merge is too large for the cache even on its own
+    fn too_large(&self, bytes: u64, events: usize) -> bool {
+        self.limits.max_bytes.is_some_and(|max| bytes > max)
+            || self.limits.max_events.is_some_and(|max| events > max)
+    }
+
+    /// Drop the least recently used merges until the cache is within its limits
+    fn evict(&mut self) {
+        while self.over_limits() {
+            let Some(oldest) = self
+                .calendars
+                .iter()
+                .min_by_key(|(_, stored)| stored.used)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            self.remove(&oldest);
+            self.stats.evictions += 1;
+            tracing::debug!(
+                "Evicted the cached merge of '{}' to stay within the cache limits",
+                oldest
+            );
+        }
+    }
+}
+
+/// In-memory cache of merged calendars keyed by calendar id, evicting the least
+/// recently used when it grows past its limits
+#[derive(Debug, Default)]
+pub struct MergeCache {
+    entries: Mutex<Entries>,
+}
+
+impl MergeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cache holding no more than `limits` across every calendar
+    pub fn with_limits(limits: CacheLimits) -> Self {
+        let cache = Self::new();
+        cache.set_limits(limits);
+        cache
+    }
+
+    /// Change the limits, evicting straight away if the cache is now over them
+    pub fn set_limits(&self, limits: CacheLimits) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.limits = limits;
+        entries.evict();
+    }
+
+    /// Get a merged calendar if it was merged less than `ttl` ago
+    pub fn get_fresh(&self, calendar_id: &str, ttl: Duration) -> Option<Arc<MergeResult>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.calendars.get(calendar_id)?;
+        if entry.entry.merged_at.elapsed() >= ttl {
+            return None;
+        }
+        entries.touch(calendar_id).map(|entry| entry.result.clone())
+    }
+
+    /// Get a merged calendar regardless of its age
+    pub fn get(&self, calendar_id: &str) -> Option<CacheEntry> {
+        self.entries.lock().unwrap().touch(calendar_id).cloned()
+    }
+
+    pub fn insert(&self, calendar_id: &str, result: Arc<MergeResult>) {
+        let bytes = result
+            .events
+            .iter()
+            .map(|event| event.approximate_size() as u64)
+            .sum();
+        let events = result.events.len();
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(calendar_id);
+        // Rather than emptying the cache to make room for a merge that won't fit anyway
+        if entries.too_large(bytes, events) {
+            tracing::debug!(
+                "Not caching the merge of '{}', it's larger than the cache limits",
+                calendar_id
+            );
+            return;
+        }
+        entries.clock += 1;
+        let stored = Stored {
+            entry: CacheEntry {
+                result,
+                merged_at: Instant::now(),
+            },
+            bytes,
+            events,
+            used: entries.clock,
+        };
+        entries.calendars.insert(calendar_id.to_string(), stored);
+        entries.stats.entries += 1;
+        entries.stats.bytes += bytes;
+        entries.stats.events += events;
+        entries.evict();
+    }
+
+    /// Drop a cached calendar, e.g. because its upstream says it changed
+    pub fn remove(&self, calendar_id: &str) {
+        self.entries.lock().unwrap().remove(calendar_id);
+    }
+
+    /// Drop every cached calendar, e.g. because the config changed
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.calendars.clear();
+        entries.stats = CacheStats {
+            evictions: entries.stats.evictions,
+            ..CacheStats::default()
+        };
+    }
+
+    /// Snapshot of what the cache is holding
+    pub fn stats(&self) -> CacheStats {
+        self.entries.lock().unwrap().stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ical::parse_calendar;
+
+    fn merge(events: usize) -> Arc<MergeResult> {
+        let mut ical = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n");
+        for idx in 0..events {
+            ical.push_str(&format!(
+                "BEGIN:VEVENT\r\nUID:{}@example.com\r\nDTSTART:20250101T090000Z\r\nSUMMARY:Event {}\r\nEND:VEVENT\r\n",
+                idx, idx
+            ));
+        }
+        ical.push_str("END:VCALENDAR\r\n");
+        let events = parse_calendar(&ical).unwrap().into_events();
+        Arc::new(MergeResult::new(events, vec![]))
+    }
+
+    #[test]
+    fn test_get_fresh() {
+        let cache = MergeCache::new();
+        assert!(cache.get_fresh("work", Duration::from_secs(60)).is_none());
+
+        cache.insert("work", Arc::new(MergeResult::new(vec![], vec![])));
+        assert!(cache.get_fresh("work", Duration::from_secs(60)).is_some());
+        assert!(cache.get_fresh("work", Duration::ZERO).is_none());
+        assert!(cache.get("work").is_some());
+        assert!(cache.get_fresh("home", Duration::from_secs(60)).is_none());
+
+        cache.insert("home", Arc::new(MergeResult::new(vec![], vec![])));
+        cache.remove("home");
+        assert!(cache.get("home").is_none());
+        assert!(cache.get("work").is_some());
+
+        cache.clear();
+        assert!(cache.get_fresh("work", Duration::from_secs(60)).is_none());
+    }
+
+    #[test]
+    fn test_stats() {
+        let cache = MergeCache::new();
+        cache.insert("work", merge(3));
+        cache.insert("home", merge(2));
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.events, 5);
+        assert!(stats.bytes > 0);
+
+        // Replacing a merge doesn't count it twice
+        cache.insert("work", merge(1));
+        assert_eq!(cache.stats().events, 3);
+
+        cache.remove("home");
+        assert_eq!(cache.stats().events, 1);
+        cache.clear();
+        assert_eq!(cache.stats(), CacheStats::default());
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = MergeCache::with_limits(CacheLimits {
+            max_bytes: None,
+            max_events: Some(5),
+        });
+        cache.insert("work", merge(2));
+        cache.insert("home", merge(2));
+        // Reading work makes home the least recently used
+        assert!(cache.get("work").is_some());
+        cache.insert("school", merge(2));
+
+        assert!(cache.get("home").is_none());
+        assert!(cache.get("work").is_some());
+        assert!(cache.get("school").is_some());
+        let stats = cache.stats();
+        assert_eq!(stats.events, 4);
+        assert_eq!(stats.evictions, 1);
+
+        // A merge bigger than the whole cache isn't kept, and doesn't push others out
+        cache.insert("everything", merge(6));
+        assert!(cache.get("everything").is_none());
+        assert_eq!(cache.stats().entries, 2);
+
+        // Tighter limits apply straight away
+        cache.insert("home", merge(1));
+        assert!(cache.get("work").is_some());
+        let size = cache.stats().bytes;
+        cache.set_limits(CacheLimits {
+            max_bytes: Some(size - 1),
+            max_events: None,
+        });
+        assert!(cache.get("school").is_none());
+        assert!(cache.get("work").is_some());
+        assert!(cache.get("home").is_some());
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use futures::FutureExt;
+use futures::future::{BoxFuture, Shared};
+
+/// Coalesces concurrent work for the same key onto a single in-flight future
+/// (the singleflight pattern)
+pub struct Coalescer<T: Clone> {
+    inflight: Arc<Mutex<HashMap<String, Shared<BoxFuture<'static, T>>>>>,
+}
+
+impl<T: Clone + Send + Sync + 'static> Coalescer<T> {
+    pub fn new() -> Self {
+        Self {
+            inflight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Run the future made by `make` unless one is already running for `key`, in
+    /// which case wait for that one's result instead
+    pub async fn run<F, Fut>(&self, key: &str, make: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T> + Send + 'static,
+    {
+        let shared = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get(key) {
+                Some(shared) => shared.clone(),
+                None => {
+                    let work = make();
+                    let map = self.inflight.clone();
+                    let owned_key = key.to_string();
+                    let shared = async move {
+                        let output = work.await;
+                        // Later callers start fresh work rather than reusing this result
+                        map.lock().unwrap().remove(&owned_key);
+                        output
+                    }
+                    .boxed()
+                    .shared();
+                    inflight.insert(key.to_string(), shared.clone());
+                    shared
+                }
+            }
+        };
+
+        shared.await
+    }
+
+    /// Number of keys with work in flight
+    pub fn in_flight(&self) -> usize {
+        self.inflight.lock().unwrap().len()
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Default for Coalescer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrent_calls_share_work() {
+        let coalescer = Coalescer::<usize>::new();
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let calls = (0..10).map(|_| {
+            let runs = runs.clone();
+            coalescer.run("calendar", move || async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                runs.fetch_add(1, Ordering::SeqCst) + 1
+            })
+        });
+        let results = futures::future::join_all(calls).await;
+
+        assert_eq!(results, vec![1; 10]);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(coalescer.in_flight(), 0);
+
+        // Once finished the next call does the work again
+        let runs_again = runs.clone();
+        let result = coalescer
+            .run("calendar", move || async move {
+                runs_again.fetch_add(1, Ordering::SeqCst) + 1
+            })
+            .await;
+        assert_eq!(result, 2);
+    }
+
+    #[tokio::test]
+    async fn test_different_keys_run_separately() {
+        let coalescer = Coalescer::<&'static str>::new();
+
+        let (a, b) = tokio::join!(
+            coalescer.run("a", || async { "a" }),
+            coalescer.run("b", || async { "b" })
+        );
+        assert_eq!((a, b), ("a", "b"));
+    }
+}
@@ -1,304 +1,4155 @@
+use chrono::{NaiveDateTime, NaiveTime, Weekday};
 use figment::{
     Figment,
-    providers::{Format, Json, Toml},
+    providers::{Format, Json, Serialized, Toml, Yaml},
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use crate::cache::CacheLimits;
 use crate::error::{Error, Result};
+use crate::fetcher::{Credentials, local_path};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
+    /// Schema version the config was written for, older ones are upgraded as they're loaded
+    #[serde(default)]
+    pub version: Option<u32>,
+    /// Upstream sources shared between calendars, keyed by id
+    #[serde(default)]
+    pub sources: HashMap<String, SharedSourceConfig>,
+    /// Calendars by id, which a config serving only tenants can leave out
+    #[serde(default)]
     pub calendars: HashMap<String, CalendarConfig>,
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub output: OutputConfig,
+    #[serde(default)]
+    pub fetcher: FetcherConfig,
+    /// Example events checked against calendars' steps whenever the config is loaded
+    #[serde(default)]
+    pub selftest: Vec<SelfTest>,
 }
 
+/// An example event and what a calendar's steps should do with it
 #[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct CalendarConfig {
-    pub sources: Vec<SourceConfig>,
+pub struct SelfTest {
+    pub calendar: String,
+    /// Index of a source in the calendar whose steps run first, as they would for its events
     #[serde(default)]
-    pub steps: Vec<Step>,
+    pub source: Option<usize>,
+    pub summary: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub expect: SelfTestExpect,
+    /// Summary the event should end up with when it is kept
+    #[serde(default)]
+    pub result: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(untagged)]
-pub enum SourceConfig {
-    Url {
-        url: String,
-        #[serde(default)]
-        steps: Vec<Step>,
-    },
-    Calendar {
-        calendar: String,
-        #[serde(default)]
-        steps: Vec<Step>,
-    },
+/// Whether a self-test's event should make it through the steps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SelfTestExpect {
+    #[default]
+    Keep,
+    Reject,
 }
 
-impl SourceConfig {
-    /// Get the steps for this source
-    pub fn steps(&self) -> &[Step] {
-        match self {
-            SourceConfig::Url { steps, .. } => steps,
-            SourceConfig::Calendar { steps, .. } => steps,
-        }
+/// How upstream calendars are fetched, changes take effect on reload
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct FetcherConfig {
+    /// Seconds to wait for an upstream calendar, defaults to 30
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// User-Agent sent to upstreams instead of ical-merge and its version
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Proxy to fetch through, e.g. `http://proxy.internal:3128`
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Headers sent with every fetch, e.g. an `Authorization` header
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Oldest TLS version to accept from upstreams, defaults to 1.2
+    #[serde(default)]
+    pub min_tls_version: Option<TlsVersion>,
+    /// Addresses to connect to for hosts instead of looking them up in DNS, like curl's `--resolve`
+    #[serde(default)]
+    pub resolve: BTreeMap<String, IpAddr>,
+    /// Which IP versions to connect to upstreams over
+    #[serde(default)]
+    pub ip_family: IpFamily,
+    /// Most fetches to have in flight at once, others wait for one to finish
+    #[serde(default)]
+    pub max_concurrent_fetches: Option<usize>,
+    /// Largest calendar in bytes to download from an upstream, defaults to 50MiB
+    #[serde(default)]
+    pub max_response_size: Option<u64>,
+    /// Redirects to follow before giving up, defaults to 10, 0 to never follow any
+    #[serde(default)]
+    pub max_redirects: Option<usize>,
+    /// Directory git sources are cloned into, defaults to `ical-merge-git` in the
+    /// system's temporary directory
+    #[serde(default)]
+    pub git_dir: Option<PathBuf>,
+    /// Requests a minute to send to any one host, unlimited when unset
+    #[serde(default)]
+    pub host_rate_limit: Option<u32>,
+    /// Requests a minute for particular hosts, instead of `host_rate_limit`
+    #[serde(default)]
+    pub host_rate_limits: BTreeMap<String, u32>,
+    /// Directory fetched calendars are kept in between fetches, off when unset
+    #[serde(default)]
+    pub cache_dir: Option<PathBuf>,
+    /// Seconds a kept calendar is used for before fetching it again, defaults to 300
+    #[serde(default)]
+    pub cache_ttl: Option<u64>,
+    /// Seconds past `cache_ttl` a kept calendar is still served while it's fetched
+    /// again in the background, defaults to an hour
+    #[serde(default)]
+    pub cache_max_stale: Option<u64>,
+}
+
+/// Which IP versions to connect over
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpFamily {
+    /// Whatever DNS returns, trying both when a host has both
+    #[default]
+    Any,
+    /// Only connect over IPv4
+    Ipv4,
+    /// Only connect over IPv6
+    Ipv6,
+    /// Try IPv4 first, falling back to IPv6
+    PreferIpv4,
+    /// Try IPv6 first, falling back to IPv4
+    PreferIpv6,
+}
+
+/// A TLS protocol version
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl FetcherConfig {
+    /// Get how long to wait for an upstream calendar
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(30))
     }
 
-    /// Get an identifier for this source (URL or calendar reference)
-    pub fn identifier(&self) -> String {
-        match self {
-            SourceConfig::Url { url, .. } => url.clone(),
-            SourceConfig::Calendar { calendar, .. } => format!("calendar:{}", calendar),
+    /// Get how many redirects to follow
+    pub fn max_redirects(&self) -> usize {
+        self.max_redirects.unwrap_or(10)
+    }
+
+    /// Get the largest calendar to download
+    pub fn max_response_size(&self) -> u64 {
+        self.max_response_size.unwrap_or(50 * 1024 * 1024)
+    }
+
+    /// Get how many requests a minute may be sent to `host`, `None` when unlimited
+    pub fn host_rate_limit(&self, host: &str) -> Option<u32> {
+        self.host_rate_limits
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(host))
+            .map(|(_, limit)| *limit)
+            .or(self.host_rate_limit)
+    }
+
+    /// Get how long a kept calendar is used for before fetching it again
+    pub fn cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.cache_ttl.unwrap_or(300))
+    }
+
+    /// Get how long past its `cache_ttl` a kept calendar may be served while it's
+    /// fetched again
+    pub fn cache_max_stale(&self) -> Duration {
+        Duration::from_secs(self.cache_max_stale.unwrap_or(3600))
+    }
+
+    /// Get the directory git sources are cloned into
+    pub fn git_dir(&self) -> PathBuf {
+        self.git_dir
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("ical-merge-git"))
+    }
+}
+
+/// How merged calendars are written out
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OutputConfig {
+    /// Fold lines longer than 75 octets as RFC 5545 requires, turn off to make output easier to read
+    #[serde(default = "default_true")]
+    pub fold_lines: bool,
+    /// PRODID to identify calendars with instead of ical-merge and its version,
+    /// an empty string leaves it out
+    #[serde(default)]
+    pub prodid: Option<String>,
+    /// Add an `X-ICAL-MERGE-GENERATED` property with the time the calendar was written
+    #[serde(default)]
+    pub generated_timestamp: bool,
+    /// What to write as events' DTSTAMP
+    #[serde(default)]
+    pub dtstamp: DtstampMode,
+    /// What to write as events' SEQUENCE
+    #[serde(default)]
+    pub sequence: SequenceMode,
+    /// Locale for dates in agendas when the request's `Accept-Language` doesn't
+    /// name one, e.g. `fr_FR`
+    #[serde(default)]
+    pub locale: Option<String>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            fold_lines: true,
+            prodid: None,
+            generated_timestamp: false,
+            dtstamp: DtstampMode::default(),
+            sequence: SequenceMode::default(),
+            locale: None,
         }
     }
 }
 
-/// Match mode for allow/deny steps
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+/// How events' DTSTAMP is written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
-pub enum MatchMode {
+pub enum DtstampMode {
+    /// Keep the upstream value
     #[default]
-    Any,
-    All,
+    Keep,
+    /// Set it to when the calendar was last merged
+    Refresh,
 }
 
-/// Case transformation mode
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// How events' SEQUENCE is written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
-pub enum CaseTransform {
-    Lower,
-    Upper,
-    Sentence,
-    Title,
+pub enum SequenceMode {
+    /// Keep the upstream value
+    #[default]
+    Keep,
+    /// Leave SEQUENCE out
+    Drop,
+    /// Write every event as revision 0
+    Zero,
 }
 
-fn default_step_fields() -> Vec<String> {
-    vec!["summary".to_string(), "description".to_string()]
+fn default_true() -> bool {
+    true
 }
 
-fn default_step_field() -> String {
-    "summary".to_string()
+/// Settings for the HTTP server
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ServerConfig {
+    /// Scheme and host the server is reachable at, used to build subscription links
+    #[serde(default, alias = "public_url")]
+    pub base_url: Option<String>,
+    /// Sub-path the server is mounted at behind a reverse proxy, e.g. `/calendars`
+    #[serde(default)]
+    pub path_prefix: Option<String>,
+    /// Seconds a request may take before failing with 504, defaults to 60
+    #[serde(default)]
+    pub request_timeout: Option<u64>,
+    /// Seconds a merged calendar is reused for, caching is disabled when unset
+    #[serde(default)]
+    pub cache_ttl: Option<u64>,
+    /// Approximate bytes of events cached across every calendar before the least
+    /// recently used merges are dropped, unlimited when unset
+    #[serde(default)]
+    pub cache_max_bytes: Option<u64>,
+    /// Events cached across every calendar before the least recently used merges
+    /// are dropped, unlimited when unset
+    #[serde(default)]
+    pub cache_max_events: Option<usize>,
+    /// Calendars to merge at startup before the server reports itself ready
+    #[serde(default)]
+    pub warmup: WarmupConfig,
+    /// Tracing filter directives, e.g. `ical_merge=info,ical_merge::fetcher=debug`
+    #[serde(default)]
+    pub log_level: Option<String>,
+    /// Bearer token for the `/admin` endpoints, which are disabled when unset
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Where to record who fetched which calendar, nothing is recorded when unset
+    #[serde(default)]
+    pub audit_log: Option<AuditLogConfig>,
+    /// Tenants served under `/t/{id}` with calendars from configs of their own
+    #[serde(default)]
+    pub tenants: BTreeMap<String, TenantConfig>,
 }
 
-fn default_replacement() -> String {
-    String::new()
+/// A tenant, such as a household or team, whose calendars are kept apart from
+/// every other tenant's
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TenantConfig {
+    /// Config file holding the tenant's calendars, relative to this config
+    pub config: PathBuf,
+    /// Token needed for any of the tenant's routes, as a bearer token or a
+    /// `token` query parameter. Open to anyone when unset
+    #[serde(default)]
+    pub token: Option<String>,
 }
 
-/// Processing step configuration
+impl TenantConfig {
+    /// Get where the tenant's config is, given the path of the config naming it
+    pub fn path(&self, config_path: &Path) -> PathBuf {
+        config_path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(&self.config)
+    }
+}
+
+/// Persistent log of calendar accesses
 #[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(tag = "type", rename_all = "lowercase")]
-pub enum Step {
-    Allow {
-        patterns: Vec<String>,
-        #[serde(default)]
-        mode: MatchMode,
-        #[serde(default = "default_step_fields")]
-        fields: Vec<String>,
-    },
-    Deny {
-        patterns: Vec<String>,
-        #[serde(default)]
-        mode: MatchMode,
-        #[serde(default = "default_step_fields")]
-        fields: Vec<String>,
-    },
-    Replace {
-        pattern: String,
-        #[serde(default = "default_replacement")]
-        replacement: String,
-        #[serde(default = "default_step_field")]
-        field: String,
-    },
-    Strip {
-        field: String,
-    },
-    Case {
-        transform: CaseTransform,
-        #[serde(default = "default_step_field")]
-        field: String,
-    },
+pub struct AuditLogConfig {
+    pub path: PathBuf,
+    /// Bytes the log may grow to before it's rotated, defaults to 10 MiB
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// Rotated logs to keep, defaults to 5
+    #[serde(default)]
+    pub max_files: Option<usize>,
 }
 
-impl Config {
-    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
-        let path = path.as_ref();
-        let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("json");
+impl AuditLogConfig {
+    pub fn max_size(&self) -> u64 {
+        self.max_size.unwrap_or(10 * 1024 * 1024)
+    }
 
-        let figment = Figment::new();
-        let figment = match extension {
-            "toml" => figment.merge(Toml::file(path)),
-            _ => figment.merge(Json::file(path)),
-        };
+    pub fn max_files(&self) -> usize {
+        self.max_files.unwrap_or(5)
+    }
+}
 
-        figment.extract().map_err(|e| Error::Config(e.to_string()))
+/// Which calendars are merged at startup, `true` for all of them or a list of ids
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum WarmupConfig {
+    All(bool),
+    Calendars(Vec<String>),
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        WarmupConfig::All(false)
     }
+}
 
-    pub fn validate(&self) -> Result<()> {
-        if self.calendars.is_empty() {
-            return Err(Error::Config("No calendars configured".to_string()));
+impl ServerConfig {
+    /// Get how long merged calendars are cached for, `None` when caching is disabled
+    pub fn cache_ttl(&self) -> Option<Duration> {
+        self.cache_ttl
+            .filter(|ttl| *ttl > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// Get the caps on what the merge cache holds
+    pub fn cache_limits(&self) -> CacheLimits {
+        CacheLimits {
+            max_bytes: self.cache_max_bytes,
+            max_events: self.cache_max_events,
         }
+    }
 
-        for (id, calendar) in &self.calendars {
-            if calendar.sources.is_empty() {
-                return Err(Error::Config(format!("Calendar '{}' has no sources", id)));
-            }
+    /// Get the maximum time to spend handling a request
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout.unwrap_or(60))
+    }
 
-            for (idx, source) in calendar.sources.iter().enumerate() {
-                match source {
-                    SourceConfig::Url { url, steps } => {
-                        if url.is_empty() {
-                            return Err(Error::Config(format!(
-                                "Calendar '{}' source {} has empty URL",
-                                id, idx
-                            )));
-                        }
-                        // Validate source steps
-                        Self::validate_steps(steps, &format!("Calendar '{}' source {}", id, idx))?;
-                    }
-                    SourceConfig::Calendar {
-                        calendar: ref_id,
-                        steps,
-                    } => {
-                        if ref_id.is_empty() {
-                            return Err(Error::Config(format!(
-                                "Calendar '{}' source {} has empty calendar reference",
-                                id, idx
-                            )));
-                        }
-                        // Check that referenced calendar exists
-                        if !self.calendars.contains_key(ref_id) {
-                            return Err(Error::Config(format!(
-                                "Calendar '{}' source {} references unknown calendar '{}'",
-                                id, idx, ref_id
-                            )));
-                        }
-                        // Validate source steps
-                        Self::validate_steps(steps, &format!("Calendar '{}' source {}", id, idx))?;
-                    }
-                }
-            }
+    /// Get the path prefix with a leading slash and no trailing slash, empty when unset
+    pub fn path_prefix(&self) -> String {
+        let prefix = self.path_prefix.as_deref().unwrap_or("").trim_matches('/');
+        if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", prefix)
+        }
+    }
+}
 
-            // Validate calendar-level steps
-            Self::validate_steps(&calendar.steps, &format!("Calendar '{}'", id))?;
+/// An upstream source declared once and referenced by id from calendars
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SharedSourceConfig {
+    pub url: String,
+    /// HTTP Basic credentials for the upstream
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Token sent to upstreams behind API gateways
+    #[serde(default)]
+    pub auth: Option<SourceAuth>,
+    /// Seconds to wait for this source instead of the fetcher's timeout
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// PEM client certificate for upstreams that want mutual TLS, along with
+    /// its key unless that is in the same file
+    #[serde(default)]
+    pub client_cert: Option<PathBuf>,
+    #[serde(default)]
+    pub client_key: Option<PathBuf>,
+    /// Accept any certificate from the upstream, for intranet servers with self-signed ones
+    #[serde(default)]
+    pub insecure_tls: bool,
+}
+
+impl SharedSourceConfig {
+    /// The credentials to fetch the source with
+    pub fn credentials(&self) -> Credentials<'_> {
+        Credentials {
+            basic: self
+                .username
+                .as_deref()
+                .map(|username| (username, self.password.as_deref())),
+            auth: self.auth.as_ref(),
+            client_cert: self
+                .client_cert
+                .as_deref()
+                .map(|cert| (cert, self.client_key.as_deref())),
+            insecure_tls: self.insecure_tls,
         }
+    }
 
-        // Detect cycles in calendar references
-        for id in self.calendars.keys() {
-            self.detect_cycle(
-                id,
-                &mut std::collections::HashSet::new(),
-                &mut std::collections::HashSet::new(),
-            )?;
+    /// Get how long to wait for the source, when it overrides the fetcher's timeout
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+}
+
+/// Token based credentials for a source
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceAuth {
+    /// Sent as `Authorization: Bearer <token>`
+    Bearer(String),
+    /// Sent as a header of its own, such as `X-Api-Key`
+    Header { name: String, value: String },
+    /// Bearer token fetched with the OAuth2 client credentials grant, and
+    /// fetched again when it expires
+    Oauth2 {
+        token_url: String,
+        client_id: String,
+        client_secret: String,
+        #[serde(default)]
+        scopes: Vec<String>,
+    },
+    /// Requests signed for S3 compatible object storage, such as AWS S3, Google
+    /// Cloud Storage with HMAC keys or MinIO
+    S3 {
+        access_key_id: String,
+        secret_access_key: String,
+        /// Session token that comes with temporary credentials
+        #[serde(default)]
+        session_token: Option<String>,
+        /// Region the bucket is in, defaults to `us-east-1`
+        #[serde(default)]
+        region: Option<String>,
+        /// Base URL of the store when it isn't AWS, e.g. `https://storage.googleapis.com`
+        #[serde(default)]
+        endpoint: Option<String>,
+    },
+}
+
+/// Written out by hand so tokens and secrets never end up in logs
+impl std::fmt::Debug for SourceAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const REDACTED: &str = "<redacted>";
+        match self {
+            Self::Bearer(_) => f.debug_tuple("Bearer").field(&REDACTED).finish(),
+            Self::Header { name, .. } => f
+                .debug_struct("Header")
+                .field("name", name)
+                .field("value", &REDACTED)
+                .finish(),
+            Self::Oauth2 {
+                token_url,
+                client_id,
+                scopes,
+                ..
+            } => f
+                .debug_struct("Oauth2")
+                .field("token_url", token_url)
+                .field("client_id", client_id)
+                .field("client_secret", &REDACTED)
+                .field("scopes", scopes)
+                .finish(),
+            Self::S3 {
+                access_key_id,
+                session_token,
+                region,
+                endpoint,
+                ..
+            } => f
+                .debug_struct("S3")
+                .field("access_key_id", access_key_id)
+                .field("secret_access_key", &REDACTED)
+                .field("session_token", &session_token.as_ref().map(|_| REDACTED))
+                .field("region", region)
+                .field("endpoint", endpoint)
+                .finish(),
         }
+    }
+}
 
-        Ok(())
+/// The setting of a source's URLs or credentials that a tenant can't use, since
+/// it would read the host's files or accept any certificate
+fn escaping_setting(urls: &[String], credentials: Credentials<'_>) -> Option<&'static str> {
+    if urls.iter().any(|url| local_path(url).is_some()) {
+        Some("a file URL")
+    } else if credentials.client_cert.is_some() {
+        Some("client_cert")
+    } else if credentials.insecure_tls {
+        Some("insecure_tls")
+    } else {
+        None
     }
+}
 
-    /// Detect cycles in calendar references using DFS
-    fn detect_cycle(
-        &self,
-        calendar_id: &str,
-        visited: &mut std::collections::HashSet<String>,
-        stack: &mut std::collections::HashSet<String>,
-    ) -> Result<()> {
-        if stack.contains(calendar_id) {
+/// Check a source's `s3://` URLs name an object and come with S3 credentials,
+/// `context` naming the source in errors
+fn validate_s3_urls(urls: &[String], credentials: Credentials<'_>, context: &str) -> Result<()> {
+    for url in urls.iter().filter(|url| url.starts_with("s3:")) {
+        if crate::s3::parse_url(url).is_none() {
             return Err(Error::Config(format!(
-                "Circular calendar reference detected involving '{}'",
-                calendar_id
+                "{} has invalid S3 URL '{}' (expected s3://bucket/key)",
+                context, url
             )));
         }
-
-        if visited.contains(calendar_id) {
-            return Ok(());
+        if !matches!(credentials.auth, Some(SourceAuth::S3 { .. })) {
+            return Err(Error::Config(format!(
+                "{} needs s3 auth to fetch '{}'",
+                context, url
+            )));
         }
+    }
+    Ok(())
+}
 
-        visited.insert(calendar_id.to_string());
-        stack.insert(calendar_id.to_string());
+/// Check a source's credentials, `context` naming the source in errors
+fn validate_credentials(credentials: Credentials<'_>, context: &str) -> Result<()> {
+    if let Some((cert, key)) = credentials.client_cert {
+        crate::fetcher::load_identity(cert, key).map_err(|e| {
+            Error::Config(format!(
+                "{} has an unusable client certificate: {}",
+                context, e
+            ))
+        })?;
+    }
 
-        if let Some(calendar) = self.calendars.get(calendar_id) {
-            for source in &calendar.sources {
-                if let SourceConfig::Calendar {
-                    calendar: ref_id, ..
-                } = source
-                {
-                    self.detect_cycle(ref_id, visited, stack)?;
-                }
+    let Some(auth) = credentials.auth else {
+        return Ok(());
+    };
+    let sets_authorization = match auth {
+        SourceAuth::Bearer(token) => {
+            if token.is_empty() {
+                return Err(Error::Config(format!(
+                    "{} has an empty bearer token",
+                    context
+                )));
+            }
+            true
+        }
+        SourceAuth::Header { name, value } => {
+            reqwest::header::HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+                Error::Config(format!(
+                    "{} has invalid auth header name '{}'",
+                    context, name
+                ))
+            })?;
+            reqwest::header::HeaderValue::from_str(value).map_err(|_| {
+                Error::Config(format!(
+                    "{} has an invalid value for header '{}'",
+                    context, name
+                ))
+            })?;
+            name.eq_ignore_ascii_case("authorization")
+        }
+        SourceAuth::Oauth2 {
+            token_url,
+            client_id,
+            ..
+        } => {
+            if !token_url.starts_with("http://") && !token_url.starts_with("https://") {
+                return Err(Error::Config(format!(
+                    "{} has OAuth2 token_url '{}' that isn't an http:// or https:// URL",
+                    context, token_url
+                )));
+            }
+            if client_id.is_empty() {
+                return Err(Error::Config(format!(
+                    "{} has an empty OAuth2 client_id",
+                    context
+                )));
+            }
+            true
+        }
+        SourceAuth::S3 {
+            access_key_id,
+            secret_access_key,
+            endpoint,
+            ..
+        } => {
+            if access_key_id.is_empty() || secret_access_key.is_empty() {
+                return Err(Error::Config(format!(
+                    "{} needs an S3 access_key_id and secret_access_key",
+                    context
+                )));
             }
+            if let Some(endpoint) = endpoint
+                && !endpoint.starts_with("http://")
+                && !endpoint.starts_with("https://")
+            {
+                return Err(Error::Config(format!(
+                    "{} has S3 endpoint '{}' that isn't an http:// or https:// URL",
+                    context, endpoint
+                )));
+            }
+            true
         }
+    };
+    if sets_authorization && credentials.basic.is_some() {
+        return Err(Error::Config(format!(
+            "{} can't use both a username and an Authorization token",
+            context
+        )));
+    }
+    Ok(())
+}
 
-        stack.remove(calendar_id);
-        Ok(())
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CalendarConfig {
+    pub sources: Vec<SourceConfig>,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    #[serde(default)]
+    pub default: DefaultAction,
+    /// Add an event warning subscribers when sources failed or stale data is served
+    #[serde(default)]
+    pub warning_event: bool,
+    /// Extra ids the calendar is also served under, e.g. old names or secret slugs
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Rewrite event times into this zone (e.g. `Europe/London`) when serving
+    #[serde(default)]
+    pub output_timezone: Option<String>,
+    /// Whether recurring events are served as rules, occurrences or both
+    #[serde(default)]
+    pub recurrence: RecurrenceConfig,
+    /// Prefix the summary of events overlapping another with "⚠ "
+    #[serde(default)]
+    pub flag_conflicts: bool,
+    /// Named sets of extra steps a request can pick with `?variant=`, such as a
+    /// redacted view of the calendar
+    #[serde(default)]
+    pub variants: BTreeMap<String, Vec<Step>>,
+    /// Seconds an older merge may be served for while merging keeps failing, after
+    /// which requests fail with a 503 instead
+    #[serde(default)]
+    pub max_stale: Option<u64>,
+    /// Days after they end to keep serving events, so small clients get a compact feed
+    #[serde(default)]
+    pub hide_ended_after: Option<u32>,
+    /// Bearer token upstream systems can POST to `/hooks/refresh/{id}` with to have
+    /// the calendar merged again straight away
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
+impl CalendarConfig {
+    /// Get how old a merge may get before it's no longer served
+    pub fn max_stale(&self) -> Option<Duration> {
+        self.max_stale.map(Duration::from_secs)
     }
 
-    fn validate_steps(steps: &[Step], context: &str) -> Result<()> {
-        use regex::Regex;
+    /// Get the time before which events that have ended are left out when serving
+    pub fn serve_cutoff(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        self.hide_ended_after
+            .map(|days| now - chrono::Duration::days(days.into()))
+    }
+}
 
-        for (idx, step) in steps.iter().enumerate() {
-            match step {
-                Step::Allow { patterns, .. } | Step::Deny { patterns, .. } => {
-                    if patterns.is_empty() {
-                        return Err(Error::Config(format!(
-                            "{} step {} has no patterns",
-                            context, idx
-                        )));
-                    }
-                    for pattern in patterns {
-                        Regex::new(pattern).map_err(|e| {
-                            Error::Config(format!(
-                                "{} step {} has invalid pattern '{}': {}",
-                                context, idx, pattern, e
-                            ))
-                        })?;
-                    }
-                }
-                Step::Replace { pattern, .. } => {
-                    Regex::new(pattern).map_err(|e| {
-                        Error::Config(format!(
-                            "{} step {} has invalid pattern '{}': {}",
-                            context, idx, pattern, e
-                        ))
-                    })?;
-                }
-                Step::Strip { field } => {
-                    if field != "reminder" {
-                        return Err(Error::Config(format!(
-                            "{} step {} has unsupported strip field '{}' (only 'reminder' is supported)",
-                            context, idx, field
-                        )));
-                    }
-                }
-                Step::Case { .. } => {
-                    // No validation needed for case transformation
-                }
+/// How recurring events are served
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecurrenceMode {
+    /// Pass recurring events through with their rules untouched
+    #[default]
+    Master,
+    /// Replace recurring events with their occurrences in the window
+    Expand,
+    /// Keep recurring events and add their occurrences in the window as overrides
+    Both,
+}
+
+/// Recurrence handling and the window occurrences are expanded over
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RecurrenceConfig {
+    #[serde(default)]
+    pub mode: RecurrenceMode,
+    /// Days before today to expand occurrences from
+    #[serde(default = "default_past_days")]
+    pub past_days: u32,
+    /// Days after today to expand occurrences until
+    #[serde(default = "default_future_days")]
+    pub future_days: u32,
+}
+
+impl Default for RecurrenceConfig {
+    fn default() -> Self {
+        Self {
+            mode: RecurrenceMode::default(),
+            past_days: default_past_days(),
+            future_days: default_future_days(),
+        }
+    }
+}
+
+fn default_past_days() -> u32 {
+    30
+}
+
+fn default_future_days() -> u32 {
+    365
+}
+
+/// Longest expansion window either side of today, about ten years
+const MAX_EXPAND_DAYS: u32 = 3660;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum SourceConfig {
+    Url {
+        url: String,
+        /// HTTP Basic credentials for the upstream
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        #[serde(default)]
+        auth: Option<SourceAuth>,
+        /// Seconds to wait for this source instead of the fetcher's timeout
+        #[serde(default)]
+        timeout: Option<u64>,
+        /// PEM client certificate for upstreams that want mutual TLS, along with
+        /// its key unless that is in the same file
+        #[serde(default)]
+        client_cert: Option<PathBuf>,
+        #[serde(default)]
+        client_key: Option<PathBuf>,
+        /// Accept any certificate from the upstream, for intranet servers with self-signed ones
+        #[serde(default)]
+        insecure_tls: bool,
+        #[serde(default)]
+        steps: Vec<Step>,
+        #[serde(default)]
+        default: DefaultAction,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        only_future: bool,
+        #[serde(default)]
+        skip_older_than: Option<String>,
+    },
+    /// The same calendar published in several places, fetched from the first
+    /// that works
+    Urls {
+        urls: Vec<String>,
+        /// HTTP Basic credentials for the upstream
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        #[serde(default)]
+        auth: Option<SourceAuth>,
+        /// Seconds to wait for this source instead of the fetcher's timeout
+        #[serde(default)]
+        timeout: Option<u64>,
+        /// PEM client certificate for upstreams that want mutual TLS, along with
+        /// its key unless that is in the same file
+        #[serde(default)]
+        client_cert: Option<PathBuf>,
+        #[serde(default)]
+        client_key: Option<PathBuf>,
+        /// Accept any certificate from the upstream, for intranet servers with self-signed ones
+        #[serde(default)]
+        insecure_tls: bool,
+        #[serde(default)]
+        steps: Vec<Step>,
+        #[serde(default)]
+        default: DefaultAction,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        only_future: bool,
+        #[serde(default)]
+        skip_older_than: Option<String>,
+    },
+    Calendar {
+        calendar: String,
+        #[serde(default)]
+        steps: Vec<Step>,
+        #[serde(default)]
+        default: DefaultAction,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        only_future: bool,
+        #[serde(default)]
+        skip_older_than: Option<String>,
+    },
+    Holidays {
+        /// Country code such as `US` or `GB`
+        holidays: String,
+        /// Region within the country such as `SCT`
+        #[serde(default)]
+        region: Option<String>,
+        #[serde(default)]
+        steps: Vec<Step>,
+        #[serde(default)]
+        default: DefaultAction,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        only_future: bool,
+        #[serde(default)]
+        skip_older_than: Option<String>,
+    },
+    Generate {
+        #[serde(alias = "events")]
+        generate: Vec<GeneratedEventConfig>,
+        #[serde(default)]
+        steps: Vec<Step>,
+        #[serde(default)]
+        default: DefaultAction,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        only_future: bool,
+        #[serde(default)]
+        skip_older_than: Option<String>,
+    },
+    /// Calendar printed by a program, such as a script scraping a school portal
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        /// Seconds to wait for the program instead of the fetcher's timeout
+        #[serde(default)]
+        timeout: Option<u64>,
+        #[serde(default)]
+        steps: Vec<Step>,
+        #[serde(default)]
+        default: DefaultAction,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        only_future: bool,
+        #[serde(default)]
+        skip_older_than: Option<String>,
+    },
+    /// Yearly birthdays of the contacts in a CardDAV address book or vCard file
+    Birthdays {
+        birthdays: String,
+        /// HTTP Basic credentials for the address book
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        #[serde(default)]
+        auth: Option<SourceAuth>,
+        /// Seconds to wait for the address book instead of the fetcher's timeout
+        #[serde(default)]
+        timeout: Option<u64>,
+        #[serde(default)]
+        steps: Vec<Step>,
+        #[serde(default)]
+        default: DefaultAction,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        only_future: bool,
+        #[serde(default)]
+        skip_older_than: Option<String>,
+    },
+    /// Events mapped out of the items a JSON API returns
+    Json {
+        json: String,
+        /// JSON pointer to the array of items, the whole response when absent
+        #[serde(default)]
+        items: Option<String>,
+        fields: JsonFieldsConfig,
+        /// IANA timezone for times without an offset, floating local time when absent
+        #[serde(default)]
+        timezone: Option<String>,
+        /// HTTP Basic credentials for the API
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        #[serde(default)]
+        auth: Option<SourceAuth>,
+        /// Seconds to wait for the API instead of the fetcher's timeout
+        #[serde(default)]
+        timeout: Option<u64>,
+        #[serde(default)]
+        steps: Vec<Step>,
+        #[serde(default)]
+        default: DefaultAction,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        only_future: bool,
+        #[serde(default)]
+        skip_older_than: Option<String>,
+    },
+    /// Calendars kept in a git repository, cloned and then pulled now and then
+    Git {
+        git: String,
+        /// Paths of the calendars in the repository, a directory meaning every
+        /// `.ics` file in it
+        files: Vec<String>,
+        /// Branch or tag to check out instead of the repository's default branch
+        #[serde(default)]
+        branch: Option<String>,
+        /// Seconds to keep using the checkout before pulling again, defaults to 300
+        #[serde(default)]
+        interval: Option<u64>,
+        /// Seconds to wait for each git command instead of the fetcher's timeout
+        #[serde(default)]
+        timeout: Option<u64>,
+        #[serde(default)]
+        steps: Vec<Step>,
+        #[serde(default)]
+        default: DefaultAction,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        only_future: bool,
+        #[serde(default)]
+        skip_older_than: Option<String>,
+    },
+    /// A small fixed calendar written out in the config
+    Inline {
+        ics: String,
+        #[serde(default)]
+        steps: Vec<Step>,
+        #[serde(default)]
+        default: DefaultAction,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        only_future: bool,
+        #[serde(default)]
+        skip_older_than: Option<String>,
+    },
+    Source {
+        source: String,
+        #[serde(default)]
+        steps: Vec<Step>,
+        #[serde(default)]
+        default: DefaultAction,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        only_future: bool,
+        #[serde(default)]
+        skip_older_than: Option<String>,
+    },
+}
+
+impl SourceConfig {
+    /// The credentials to fetch a URL source with
+    pub fn credentials(&self) -> Credentials<'_> {
+        match self {
+            SourceConfig::Url {
+                username,
+                password,
+                auth,
+                client_cert,
+                client_key,
+                insecure_tls,
+                ..
+            }
+            | SourceConfig::Urls {
+                username,
+                password,
+                auth,
+                client_cert,
+                client_key,
+                insecure_tls,
+                ..
+            } => Credentials {
+                basic: username
+                    .as_deref()
+                    .map(|username| (username, password.as_deref())),
+                auth: auth.as_ref(),
+                client_cert: client_cert
+                    .as_deref()
+                    .map(|cert| (cert, client_key.as_deref())),
+                insecure_tls: *insecure_tls,
+            },
+            SourceConfig::Birthdays {
+                username,
+                password,
+                auth,
+                ..
             }
+            | SourceConfig::Json {
+                username,
+                password,
+                auth,
+                ..
+            } => Credentials {
+                basic: username
+                    .as_deref()
+                    .map(|username| (username, password.as_deref())),
+                auth: auth.as_ref(),
+                ..Credentials::default()
+            },
+            _ => Credentials::default(),
         }
+    }
 
-        Ok(())
+    /// Get how long to wait for a URL, address book, JSON, git or command source,
+    /// when it overrides the fetcher's timeout
+    pub fn timeout(&self) -> Option<Duration> {
+        match self {
+            SourceConfig::Url { timeout, .. }
+            | SourceConfig::Urls { timeout, .. }
+            | SourceConfig::Birthdays { timeout, .. }
+            | SourceConfig::Json { timeout, .. }
+            | SourceConfig::Git { timeout, .. }
+            | SourceConfig::Command { timeout, .. } => timeout.map(Duration::from_secs),
+            _ => None,
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
+    /// Get the URLs a source is fetched from, in the order they're tried
+    pub fn urls(&self) -> &[String] {
+        match self {
+            SourceConfig::Url { url, .. } => std::slice::from_ref(url),
+            SourceConfig::Urls { urls, .. } => urls,
+            SourceConfig::Birthdays { birthdays, .. } => std::slice::from_ref(birthdays),
+            SourceConfig::Json { json, .. } => std::slice::from_ref(json),
+            _ => &[],
+        }
+    }
+
+    /// Get the steps for this source
+    pub fn steps(&self) -> &[Step] {
+        match self {
+            SourceConfig::Url { steps, .. } => steps,
+            SourceConfig::Urls { steps, .. } => steps,
+            SourceConfig::Calendar { steps, .. } => steps,
+            SourceConfig::Holidays { steps, .. } => steps,
+            SourceConfig::Generate { steps, .. } => steps,
+            SourceConfig::Command { steps, .. } => steps,
+            SourceConfig::Birthdays { steps, .. } => steps,
+            SourceConfig::Json { steps, .. } => steps,
+            SourceConfig::Git { steps, .. } => steps,
+            SourceConfig::Inline { steps, .. } => steps,
+            SourceConfig::Source { steps, .. } => steps,
+        }
+    }
+
+    /// Get the default action for this source's step pipeline
+    pub fn default_action(&self) -> &DefaultAction {
+        match self {
+            SourceConfig::Url { default, .. } => default,
+            SourceConfig::Urls { default, .. } => default,
+            SourceConfig::Calendar { default, .. } => default,
+            SourceConfig::Holidays { default, .. } => default,
+            SourceConfig::Generate { default, .. } => default,
+            SourceConfig::Command { default, .. } => default,
+            SourceConfig::Birthdays { default, .. } => default,
+            SourceConfig::Json { default, .. } => default,
+            SourceConfig::Git { default, .. } => default,
+            SourceConfig::Inline { default, .. } => default,
+            SourceConfig::Source { default, .. } => default,
+        }
+    }
+
+    /// Get the human readable label for this source
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            SourceConfig::Url { label, .. } => label.as_deref(),
+            SourceConfig::Urls { label, .. } => label.as_deref(),
+            SourceConfig::Calendar { label, .. } => label.as_deref(),
+            SourceConfig::Holidays { label, .. } => label.as_deref(),
+            SourceConfig::Generate { label, .. } => label.as_deref(),
+            SourceConfig::Command { label, .. } => label.as_deref(),
+            SourceConfig::Birthdays { label, .. } => label.as_deref(),
+            SourceConfig::Json { label, .. } => label.as_deref(),
+            SourceConfig::Git { label, .. } => label.as_deref(),
+            SourceConfig::Inline { label, .. } => label.as_deref(),
+            SourceConfig::Source { label, .. } => label.as_deref(),
+        }
+    }
+
+    /// Get the tags for this source
+    pub fn tags(&self) -> &[String] {
+        match self {
+            SourceConfig::Url { tags, .. } => tags,
+            SourceConfig::Urls { tags, .. } => tags,
+            SourceConfig::Calendar { tags, .. } => tags,
+            SourceConfig::Holidays { tags, .. } => tags,
+            SourceConfig::Generate { tags, .. } => tags,
+            SourceConfig::Command { tags, .. } => tags,
+            SourceConfig::Birthdays { tags, .. } => tags,
+            SourceConfig::Json { tags, .. } => tags,
+            SourceConfig::Git { tags, .. } => tags,
+            SourceConfig::Inline { tags, .. } => tags,
+            SourceConfig::Source { tags, .. } => tags,
+        }
+    }
+
+    /// Whether to drop events that have already ended before running any steps
+    pub fn only_future(&self) -> bool {
+        match self {
+            SourceConfig::Url { only_future, .. } => *only_future,
+            SourceConfig::Urls { only_future, .. } => *only_future,
+            SourceConfig::Calendar { only_future, .. } => *only_future,
+            SourceConfig::Holidays { only_future, .. } => *only_future,
+            SourceConfig::Generate { only_future, .. } => *only_future,
+            SourceConfig::Command { only_future, .. } => *only_future,
+            SourceConfig::Birthdays { only_future, .. } => *only_future,
+            SourceConfig::Json { only_future, .. } => *only_future,
+            SourceConfig::Git { only_future, .. } => *only_future,
+            SourceConfig::Inline { only_future, .. } => *only_future,
+            SourceConfig::Source { only_future, .. } => *only_future,
+        }
+    }
+
+    /// Get how long ago events may have ended and still be kept, such as `90d`
+    pub fn skip_older_than(&self) -> Option<&str> {
+        match self {
+            SourceConfig::Url {
+                skip_older_than, ..
+            } => skip_older_than.as_deref(),
+            SourceConfig::Urls {
+                skip_older_than, ..
+            } => skip_older_than.as_deref(),
+            SourceConfig::Calendar {
+                skip_older_than, ..
+            } => skip_older_than.as_deref(),
+            SourceConfig::Holidays {
+                skip_older_than, ..
+            } => skip_older_than.as_deref(),
+            SourceConfig::Generate {
+                skip_older_than, ..
+            } => skip_older_than.as_deref(),
+            SourceConfig::Command {
+                skip_older_than, ..
+            } => skip_older_than.as_deref(),
+            SourceConfig::Birthdays {
+                skip_older_than, ..
+            } => skip_older_than.as_deref(),
+            SourceConfig::Json {
+                skip_older_than, ..
+            } => skip_older_than.as_deref(),
+            SourceConfig::Git {
+                skip_older_than, ..
+            } => skip_older_than.as_deref(),
+            SourceConfig::Inline {
+                skip_older_than, ..
+            } => skip_older_than.as_deref(),
+            SourceConfig::Source {
+                skip_older_than, ..
+            } => skip_older_than.as_deref(),
+        }
+    }
+
+    /// Get the time before which events that have ended are dropped as they are
+    /// read, before any steps run
+    pub fn include_cutoff(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let future = self.only_future().then_some(now);
+        let older = self
+            .skip_older_than()
+            .and_then(parse_age)
+            .map(|age| now - age);
+        future.max(older)
+    }
+
+    /// Get an identifier for this source (URL, calendar reference or shared source),
+    /// prefixed with its label when it has an explicit one
+    pub fn identifier(&self) -> String {
+        let identifier = self.origin();
+        match self.label() {
+            Some(label) => format!("{} ({})", label, identifier),
+            None => identifier,
+        }
+    }
+
+    /// Get where the source's events come from, ignoring its label
+    pub fn origin(&self) -> String {
+        match self {
+            SourceConfig::Url { url, .. } => url.clone(),
+            SourceConfig::Urls { urls, .. } => urls.first().cloned().unwrap_or_default(),
+            SourceConfig::Calendar { calendar, .. } => format!("calendar:{}", calendar),
+            SourceConfig::Holidays {
+                holidays, region, ..
+            } => match region {
+                Some(region) => format!("holidays:{}-{}", holidays, region),
+                None => format!("holidays:{}", holidays),
+            },
+            SourceConfig::Generate { .. } => "generated".to_string(),
+            SourceConfig::Command { command, args, .. } => {
+                let mut line = format!("command:{}", command);
+                for arg in args {
+                    line.push(' ');
+                    line.push_str(arg);
+                }
+                line
+            }
+            SourceConfig::Birthdays { birthdays, .. } => format!("birthdays:{}", birthdays),
+            SourceConfig::Json { json, .. } => format!("json:{}", json),
+            SourceConfig::Git { git, .. } => format!("git:{}", git),
+            SourceConfig::Inline { .. } => "inline".to_string(),
+            SourceConfig::Source { source, .. } => format!("source:{}", source),
+        }
+    }
+}
+
+/// Parse an age such as `90d` or `12w` into a duration
+pub fn parse_age(value: &str) -> Option<chrono::Duration> {
+    let value = value.trim();
+    let (count, unit) = value.split_at(value.len().checked_sub(1)?);
+    let count: i64 = count.parse().ok()?;
+    match unit {
+        "d" => chrono::Duration::try_days(count),
+        "w" => chrono::Duration::try_weeks(count),
+        _ => None,
+    }
+    .filter(|age| *age >= chrono::Duration::zero())
+}
+
+/// An event produced locally by a `generate` source
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GeneratedEventConfig {
+    pub summary: String,
+    /// Local start as `YYYY-MM-DD` for all-day events or `YYYY-MM-DDTHH:MM[:SS]`
+    pub start: String,
+    /// RFC 5545 duration such as `PT1H`
+    #[serde(default)]
+    pub duration: Option<String>,
+    /// Local end in the same form as `start`, the last day for all-day events
+    #[serde(default)]
+    pub end: Option<String>,
+    /// RFC 5545 recurrence rule such as `FREQ=WEEKLY;BYDAY=MO,WE`
+    #[serde(default)]
+    pub rrule: Option<String>,
+    /// IANA timezone for the start time, floating local time when absent
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub location: Option<String>,
+}
+
+/// Where each item of a `json` source keeps an event's fields, as JSON pointers
+/// such as `/venue/name`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JsonFieldsConfig {
+    pub summary: String,
+    /// An RFC 3339 date-time, a `YYYY-MM-DD` date for all-day events, a local
+    /// date-time or a Unix timestamp in seconds or milliseconds
+    pub start: String,
+    /// In the same forms as `start`
+    #[serde(default)]
+    pub end: Option<String>,
+    /// Identifier kept by the event across fetches, made from the start and
+    /// summary when absent
+    #[serde(default)]
+    pub uid: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+/// Match mode for allow/deny steps
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    #[default]
+    Any,
+    All,
+}
+
+/// What an allow/deny step does with events it would filter out
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    /// Reject the event
+    #[default]
+    Enforce,
+    /// Keep the event but mark it as one that would have been rejected
+    Annotate,
+}
+
+/// What happens to events that reach the end of a step pipeline
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultAction {
+    /// Keep events unless a step rejects them
+    #[default]
+    Allow,
+    /// Reject events unless an allow step matched them
+    Deny,
+}
+
+/// Case transformation mode
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaseTransform {
+    Lower,
+    Upper,
+    Sentence,
+    Title,
+}
+
+/// Pattern to category mapping for classify steps
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClassifyRule {
+    pub pattern: String,
+    pub category: String,
+}
+
+fn default_step_fields() -> Vec<String> {
+    vec!["summary".to_string(), "description".to_string()]
+}
+
+fn default_step_field() -> String {
+    "summary".to_string()
+}
+
+fn default_replacement() -> String {
+    String::new()
+}
+
+fn default_attendee_format() -> String {
+    " ({count} ppl)".to_string()
+}
+
+fn default_attendee_min() -> u32 {
+    1
+}
+
+fn default_soon_prefix() -> String {
+    "⏰ ".to_string()
+}
+
+fn default_buffer_summary() -> String {
+    "Travel".to_string()
+}
+
+fn default_working_days() -> Vec<String> {
+    ["mon", "tue", "wed", "thu", "fri"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/// What an hours step does with events that run outside working hours
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HoursMode {
+    /// Trim events to the working hours they overlap
+    #[default]
+    Clip,
+    /// Drop events that aren't entirely within working hours
+    Drop,
+}
+
+/// Where a notice step puts its text in the description
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoticePosition {
+    /// Before the description, as a header
+    Start,
+    /// After the description, as a footer
+    #[default]
+    End,
+}
+
+/// Parse an hours step's window and days
+pub(crate) fn working_hours(
+    start: &str,
+    end: &str,
+    days: &[String],
+) -> std::result::Result<(NaiveTime, NaiveTime, Vec<Weekday>), String> {
+    let time = |value: &str| {
+        NaiveTime::parse_from_str(value, "%H:%M")
+            .map_err(|_| format!("has invalid time '{}' (expected HH:MM)", value))
+    };
+    let (start, end) = (time(start)?, time(end)?);
+    if end <= start {
+        return Err("has an end time that isn't after its start".to_string());
+    }
+    if days.is_empty() {
+        return Err("has no days".to_string());
+    }
+    let days = days
+        .iter()
+        .map(|day| {
+            day.parse::<Weekday>()
+                .map_err(|_| format!("has invalid day '{}'", day))
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok((start, end, days))
+}
+
+/// Processing step configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Step {
+    Allow {
+        patterns: Vec<String>,
+        #[serde(default)]
+        mode: MatchMode,
+        #[serde(default = "default_step_fields")]
+        fields: Vec<String>,
+        #[serde(default)]
+        action: FilterAction,
+        #[serde(default)]
+        unless: Vec<String>,
+        #[serde(default)]
+        literal: bool,
+    },
+    Deny {
+        patterns: Vec<String>,
+        #[serde(default)]
+        mode: MatchMode,
+        #[serde(default = "default_step_fields")]
+        fields: Vec<String>,
+        #[serde(default)]
+        action: FilterAction,
+        #[serde(default)]
+        unless: Vec<String>,
+        #[serde(default)]
+        literal: bool,
+    },
+    Replace {
+        pattern: String,
+        #[serde(default = "default_replacement")]
+        replacement: String,
+        #[serde(default = "default_step_field")]
+        field: String,
+        #[serde(default)]
+        literal: bool,
+    },
+    Strip {
+        field: String,
+    },
+    /// Keep only the reminders matching every criterion given, dropping the rest
+    Alarms {
+        #[serde(default)]
+        actions: Vec<String>,
+        #[serde(default)]
+        min_before: Option<i64>,
+        #[serde(default)]
+        max_before: Option<i64>,
+    },
+    Case {
+        transform: CaseTransform,
+        #[serde(default = "default_step_field")]
+        field: String,
+        #[serde(default)]
+        locale: Option<String>,
+        #[serde(default)]
+        exceptions: Vec<String>,
+    },
+    Extract {
+        pattern: String,
+        #[serde(default = "default_step_field")]
+        field: String,
+    },
+    Classify {
+        rules: Vec<ClassifyRule>,
+        #[serde(default = "default_step_fields")]
+        fields: Vec<String>,
+        #[serde(default)]
+        property: Option<String>,
+        #[serde(default)]
+        literal: bool,
+    },
+    Hours {
+        start: String,
+        end: String,
+        #[serde(default = "default_working_days")]
+        days: Vec<String>,
+        #[serde(default)]
+        mode: HoursMode,
+    },
+    Buffer {
+        #[serde(default)]
+        patterns: Vec<String>,
+        #[serde(default = "default_step_fields")]
+        fields: Vec<String>,
+        #[serde(default)]
+        literal: bool,
+        #[serde(default)]
+        location: bool,
+        #[serde(default)]
+        before: u32,
+        #[serde(default)]
+        after: u32,
+        #[serde(default = "default_buffer_summary")]
+        summary: String,
+    },
+    Attendees {
+        #[serde(default = "default_step_field")]
+        field: String,
+        #[serde(default = "default_attendee_format")]
+        format: String,
+        #[serde(default = "default_attendee_min")]
+        min: u32,
+    },
+    Organizer {
+        #[serde(default)]
+        email: Option<String>,
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        remove: bool,
+    },
+    /// Prefix events starting soon, applied when the calendar is served
+    Soon {
+        #[serde(default)]
+        within: Option<u32>,
+        #[serde(default = "default_soon_prefix")]
+        prefix: String,
+    },
+    /// Add a block of text to the start or end of the description, such as where
+    /// the event was imported from
+    Notice {
+        text: String,
+        #[serde(default)]
+        position: NoticePosition,
+    },
+    /// Rewrite the URL property and links in the description
+    Links {
+        pattern: String,
+        #[serde(default = "default_replacement")]
+        replacement: String,
+        #[serde(default)]
+        literal: bool,
+    },
+    /// Regex replacement over each unfolded line of the event's iCal text, for
+    /// properties and parameters the other steps can't reach
+    #[serde(rename = "raw_replace")]
+    RawReplace {
+        pattern: String,
+        #[serde(default = "default_replacement")]
+        replacement: String,
+        #[serde(default)]
+        literal: bool,
+    },
+}
+
+/// Newest config schema, bumped whenever old configs need converting to be read
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Conversions from each schema version to the next, starting from version 1
+const UPGRADES: &[fn(&mut serde_json::Value) -> Vec<String>] = &[migrate_legacy];
+
+/// Convert a config written for an older schema to the current one, returning
+/// warnings for anything that was converted. Configs without a version are taken
+/// to be version 1, and ones for a newer version than this build knows are refused
+/// A config file, read as TOML, YAML or JSON depending on its extension. Unless
+/// `exact`, a relative path is also looked for in parent directories
+fn config_file(path: &Path, exact: bool) -> Figment {
+    match (path.extension().and_then(|s| s.to_str()), exact) {
+        (Some("toml"), false) => Figment::new().merge(Toml::file(path)),
+        (Some("toml"), true) => Figment::new().merge(Toml::file_exact(path)),
+        (Some("yaml" | "yml"), false) => Figment::new().merge(Yaml::file(path)),
+        (Some("yaml" | "yml"), true) => Figment::new().merge(Yaml::file_exact(path)),
+        (_, false) => Figment::new().merge(Json::file(path)),
+        (_, true) => Figment::new().merge(Json::file_exact(path)),
+    }
+}
+
+/// Take the list of files a config includes out of it, relative to the config's
+/// own directory
+fn include_files(config: &mut serde_json::Value, path: &Path) -> Result<Vec<PathBuf>> {
+    let include = match config.as_object_mut().and_then(|map| map.remove("include")) {
+        None | Some(serde_json::Value::Null) => return Ok(Vec::new()),
+        Some(include) => include,
+    };
+    let files: Vec<String> = serde_json::from_value(include)
+        .map_err(|_| Error::Config("Config include must be a list of file paths".to_string()))?;
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut included = Vec::with_capacity(files.len());
+    for file in files {
+        if file.is_empty() {
+            return Err(Error::Config(
+                "Config include has an empty path".to_string(),
+            ));
+        }
+        let file = dir.join(file);
+        if file == path || included.contains(&file) {
+            return Err(Error::Config(format!(
+                "Config includes {} more than once",
+                file.display()
+            )));
+        }
+        included.push(file);
+    }
+    Ok(included)
+}
+
+/// Add the calendars and shared sources of an included file to the config at
+/// `path`, refusing ids that are already taken unless the new entry sets
+/// `override`. `origins` records which included file each id came from
+fn merge_include(
+    config: &mut serde_json::Value,
+    path: &Path,
+    file: &Path,
+    origins: &mut HashMap<(&'static str, String), PathBuf>,
+) -> Result<()> {
+    // Included files are only ever where the config says they are
+    let mut include: serde_json::Value = config_file(file, true)
+        .extract()
+        .map_err(|e| Error::Config(format!("Failed to read included config: {}", e)))?;
+    let include = include.as_object_mut().ok_or_else(|| {
+        Error::Config(format!("Included config {} isn't a table", file.display()))
+    })?;
+    if let Some(key) = include
+        .keys()
+        .find(|key| !matches!(key.as_str(), "calendars" | "sources"))
+    {
+        return Err(Error::Config(format!(
+            "Included config {} can only have calendars and sources, not '{}'",
+            file.display(),
+            key
+        )));
+    }
+
+    let config = config
+        .as_object_mut()
+        .ok_or_else(|| Error::Config(format!("Config {} isn't a table", path.display())))?;
+    for (section, kind) in [("calendars", "Calendar"), ("sources", "Source")] {
+        let entries = match include.remove(section) {
+            None | Some(serde_json::Value::Null) => continue,
+            Some(serde_json::Value::Object(entries)) => entries,
+            Some(_) => {
+                return Err(Error::Config(format!(
+                    "Included config {} {} must be a table",
+                    file.display(),
+                    section
+                )));
+            }
+        };
+        let existing = config
+            .entry(section)
+            .or_insert_with(|| serde_json::Value::Object(Default::default()))
+            .as_object_mut()
+            .ok_or_else(|| {
+                Error::Config(format!(
+                    "Config {} {} must be a table",
+                    path.display(),
+                    section
+                ))
+            })?;
+
+        for (id, mut entry) in entries {
+            let replace = entry
+                .as_object_mut()
+                .and_then(|entry| entry.remove("override"))
+                .is_some_and(|value| value == serde_json::Value::Bool(true));
+            if existing.contains_key(&id) {
+                let previous = origins
+                    .get(&(section, id.clone()))
+                    .map_or(path, PathBuf::as_path);
+                if !replace {
+                    return Err(Error::Config(format!(
+                        "{} '{}' in {} is already defined in {}, set \"override\": true to replace it",
+                        kind,
+                        id,
+                        file.display(),
+                        previous.display()
+                    )));
+                }
+                tracing::info!(
+                    "{} '{}' in {} overrides the one in {}",
+                    kind,
+                    id,
+                    file.display(),
+                    previous.display()
+                );
+            }
+            origins.insert((section, id.clone()), file.to_path_buf());
+            existing.insert(id, entry);
+        }
+    }
+    Ok(())
+}
+
+fn upgrade(config: &mut serde_json::Value) -> Result<Vec<String>> {
+    let version = match config.get("version") {
+        None | Some(serde_json::Value::Null) => 1,
+        Some(version) => version
+            .as_u64()
+            .filter(|version| *version >= 1)
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| {
+                Error::Config(format!(
+                    "Config version must be a whole number from 1 to {}",
+                    CONFIG_VERSION
+                ))
+            })?,
+    };
+    if version > CONFIG_VERSION {
+        return Err(Error::Config(format!(
+            "Config is for version {} but this ical-merge only understands up to version {}, upgrade ical-merge to use it",
+            version, CONFIG_VERSION
+        )));
+    }
+
+    let mut warnings = Vec::new();
+    for upgrade in &UPGRADES[version as usize - 1..] {
+        warnings.extend(upgrade(config));
+    }
+    if !warnings.is_empty() {
+        if let Some(config) = config.as_object_mut() {
+            config.insert("version".to_string(), CONFIG_VERSION.into());
+        }
+        warnings.push(format!(
+            "Config was upgraded from version {} to {} as it was loaded, `config convert` prints the upgraded config",
+            version, CONFIG_VERSION
+        ));
+    }
+    Ok(warnings)
+}
+
+/// Turn the `filters` and `modifiers` that calendars and sources had before steps
+/// into the equivalent steps, returning a warning for each one converted
+fn migrate_legacy(config: &mut serde_json::Value) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let Some(calendars) = config
+        .get_mut("calendars")
+        .and_then(|calendars| calendars.as_object_mut())
+    else {
+        return warnings;
+    };
+
+    for (id, calendar) in calendars {
+        migrate_steps(calendar, &format!("Calendar '{}'", id), &mut warnings);
+        let Some(sources) = calendar
+            .get_mut("sources")
+            .and_then(|sources| sources.as_array_mut())
+        else {
+            continue;
+        };
+        for (idx, source) in sources.iter_mut().enumerate() {
+            migrate_steps(
+                source,
+                &format!("Calendar '{}' source {}", id, idx),
+                &mut warnings,
+            );
+        }
+    }
+    warnings
+}
+
+/// Replace an object's legacy `filters` and `modifiers` with steps run before any it
+/// already has. Filters become an allow then a deny step and modifiers are steps already
+fn migrate_steps(item: &mut serde_json::Value, context: &str, warnings: &mut Vec<String>) {
+    use serde_json::{Value, json};
+
+    let Some(item) = item.as_object_mut() else {
+        return;
+    };
+    let filters = item.remove("filters");
+    let modifiers = item.remove("modifiers");
+    if filters.is_none() && modifiers.is_none() {
+        return;
+    }
+
+    let mut steps = Vec::new();
+    if let Some(filters) = &filters {
+        for kind in ["allow", "deny"] {
+            match filters.get(kind) {
+                Some(Value::Array(patterns)) if !patterns.is_empty() => {
+                    steps.push(json!({ "type": kind, "patterns": patterns }));
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Some(Value::Array(modifiers)) = modifiers {
+        steps.extend(modifiers);
+    }
+
+    let replacement = serde_json::to_string(&steps).unwrap_or_default();
+    if let Some(Value::Array(existing)) = item.remove("steps") {
+        steps.extend(existing);
+    }
+    item.insert("steps".to_string(), Value::Array(steps));
+
+    warnings.push(format!(
+        "{} uses the deprecated filters and modifiers, replace them with \"steps\": {}",
+        context, replacement
+    ));
+}
+
+impl Config {
+    /// Get the ids of the calendars to merge at startup
+    pub fn warmup_calendars(&self) -> Vec<String> {
+        let mut ids: Vec<String> = match &self.server.warmup {
+            WarmupConfig::All(true) => self.calendars.keys().cloned().collect(),
+            WarmupConfig::All(false) => Vec::new(),
+            WarmupConfig::Calendars(ids) => ids.clone(),
+        };
+        ids.sort();
+        ids
+    }
+
+    /// Get the files read by `file://` sources, shared or not
+    pub fn local_files(&self) -> Vec<PathBuf> {
+        let shared = self.sources.values().map(|source| &source.url);
+        let sources = self
+            .calendars
+            .values()
+            .flat_map(|calendar| &calendar.sources)
+            .flat_map(|source| source.urls());
+        let mut files: Vec<PathBuf> = shared
+            .chain(sources)
+            .filter_map(|url| local_path(url))
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// Get the ids of `id` and every calendar that includes it, directly or through
+    /// other calendars
+    pub fn dependent_calendars(&self, id: &str) -> Vec<String> {
+        let mut found = vec![id.to_string()];
+        let mut next = 0;
+        while let Some(current) = found.get(next).cloned() {
+            next += 1;
+            for (calendar_id, calendar) in &self.calendars {
+                let includes = calendar.sources.iter().any(|source| {
+                    matches!(source, SourceConfig::Calendar { calendar, .. } if *calendar == current)
+                });
+                if includes && !found.contains(calendar_id) {
+                    found.push(calendar_id.clone());
+                }
+            }
+        }
+        found.sort();
+        found
+    }
+
+    /// Get the id of the calendar served under `id`, which may be an alias
+    pub fn resolve_calendar<'a>(&'a self, id: &'a str) -> Option<&'a str> {
+        if self.calendars.contains_key(id) {
+            return Some(id);
+        }
+
+        self.calendars
+            .iter()
+            .find(|(_, calendar)| calendar.aliases.iter().any(|alias| alias == id))
+            .map(|(calendar_id, _)| calendar_id.as_str())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let figment = config_file(path, false);
+
+        let mut value: serde_json::Value = figment
+            .extract()
+            .map_err(|e| Error::Config(e.to_string()))?;
+        let included = include_files(&mut value, path)?;
+        let mut origins = HashMap::new();
+        for file in &included {
+            merge_include(&mut value, path, file, &mut origins)?;
+        }
+
+        // Configs written for older versions are converted before being read
+        let migrated = upgrade(&mut value)?;
+        if migrated.is_empty() && included.is_empty() {
+            return figment.extract().map_err(|e| Error::Config(e.to_string()));
+        }
+        for warning in &migrated {
+            tracing::warn!("{}", warning);
+        }
+        Figment::from(Serialized::defaults(value))
+            .extract()
+            .map_err(|e| Error::Config(e.to_string()))
+    }
+
+    /// Load the config of the tenant `id` that this config, loaded from
+    /// `config_path`, lists. Links the tenant's server builds point under
+    /// `/t/{id}`, and it uses this server's `base_url` unless it sets its own
+    pub fn load_tenant(&self, config_path: &Path, id: &str) -> Result<Config> {
+        let tenant = self
+            .server
+            .tenants
+            .get(id)
+            .ok_or_else(|| Error::Config(format!("No tenant '{}'", id)))?;
+        let path = tenant.path(config_path);
+        let failed =
+            |e: Error| Error::Config(format!("Failed to load tenant '{}' config: {}", id, e));
+        // Checked before loading, so the files are never read
+        if !Config::included_files(&path).map_err(failed)?.is_empty() {
+            return Err(Error::Config(format!(
+                "Tenant '{}' config can't set include, only the main config can",
+                id
+            )));
+        }
+        let mut config = Config::load(&path).map_err(failed)?;
+
+        // Only the main config decides how the server itself is run, and where on
+        // the host it reads and writes
+        let server = &config.server;
+        for (setting, set) in [
+            ("server tenants", !server.tenants.is_empty()),
+            ("server path_prefix", server.path_prefix.is_some()),
+            ("server log_level", server.log_level.is_some()),
+            ("server admin_token", server.admin_token.is_some()),
+            ("server audit_log", server.audit_log.is_some()),
+            ("fetcher cache_dir", config.fetcher.cache_dir.is_some()),
+            ("fetcher git_dir", config.fetcher.git_dir.is_some()),
+        ] {
+            if set {
+                return Err(Error::Config(format!(
+                    "Tenant '{}' config can't set {}, only the main config can",
+                    id, setting
+                )));
+            }
+        }
+        if let Some(source) = config.tenant_escape() {
+            return Err(Error::Config(format!(
+                "Tenant '{}' config can't use {}, as it reaches outside the tenant",
+                id, source
+            )));
+        }
+        config.server.path_prefix = Some(format!("{}/t/{}", self.server.path_prefix(), id));
+        if config.server.base_url.is_none() {
+            config.server.base_url = self.server.base_url.clone();
+        }
+
+        config
+            .validate()
+            .map_err(|e| Error::Config(format!("Tenant '{}' config is invalid: {}", id, e)))?;
+        Ok(config)
+    }
+
+    /// The first source in a tenant's config that could reach the host's programs
+    /// and files, or skip checking who it's talking to, if there is one
+    fn tenant_escape(&self) -> Option<String> {
+        for (id, source) in &self.sources {
+            let context = format!("source '{}'", id);
+            if let Some(setting) =
+                escaping_setting(std::slice::from_ref(&source.url), source.credentials())
+            {
+                return Some(format!("{} on {}", setting, context));
+            }
+        }
+        for (id, calendar) in &self.calendars {
+            for (idx, source) in calendar.sources.iter().enumerate() {
+                let context = format!("calendar '{}' source {}", id, idx);
+                match source {
+                    SourceConfig::Command { .. } => return Some(format!("command {}", context)),
+                    SourceConfig::Git { .. } => return Some(format!("git {}", context)),
+                    _ => {}
+                }
+                if let Some(setting) = escaping_setting(source.urls(), source.credentials()) {
+                    return Some(format!("{} on {}", setting, context));
+                }
+            }
+        }
+        None
+    }
+
+    /// Get the files the config at `path` includes, so changes to them can be
+    /// watched along with it
+    pub fn included_files(path: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+        let path = path.as_ref();
+        let mut value: serde_json::Value = config_file(path, false)
+            .extract()
+            .map_err(|e| Error::Config(e.to_string()))?;
+        include_files(&mut value, path)
+    }
+
+    /// Write the config as pretty-printed JSON, spelling out every default
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(&self.to_value()?)
+            .map_err(|e| Error::Config(format!("Failed to write config as JSON: {}", e)))
+    }
+
+    /// Write the config as TOML, spelling out every default
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string_pretty(&self.to_value()?)
+            .map_err(|e| Error::Config(format!("Failed to write config as TOML: {}", e)))
+    }
+
+    /// Write the config as YAML, spelling out every default
+    pub fn to_yaml(&self) -> Result<String> {
+        serde_yaml::to_string(&self.to_value()?)
+            .map_err(|e| Error::Config(format!("Failed to write config as YAML: {}", e)))
+    }
+
+    /// The config as a tree with keys in order and unset options left out, since
+    /// TOML has no null
+    fn to_value(&self) -> Result<serde_json::Value> {
+        fn strip_nulls(value: &mut serde_json::Value) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    map.retain(|_, value| !value.is_null());
+                    map.values_mut().for_each(strip_nulls);
+                }
+                serde_json::Value::Array(values) => values.iter_mut().for_each(strip_nulls),
+                _ => {}
+            }
+        }
+
+        let mut value = serde_json::to_value(self)
+            .map_err(|e| Error::Config(format!("Failed to convert config: {}", e)))?;
+        strip_nulls(&mut value);
+        // Whatever was loaded has been upgraded, so it's written as the current version
+        if let serde_json::Value::Object(map) = &mut value {
+            map.insert("version".to_string(), CONFIG_VERSION.into());
+        }
+        Ok(value)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.calendars.is_empty() && self.server.tenants.is_empty() {
+            return Err(Error::Config("No calendars configured".to_string()));
+        }
+
+        if let Some(base_url) = &self.server.base_url
+            && !base_url.starts_with("http://")
+            && !base_url.starts_with("https://")
+        {
+            return Err(Error::Config(format!(
+                "Server base_url '{}' must be an http:// or https:// URL",
+                base_url
+            )));
+        }
+
+        if self.fetcher.timeout == Some(0) {
+            return Err(Error::Config(
+                "Fetcher timeout must be at least 1 second".to_string(),
+            ));
+        }
+        if self.fetcher.max_response_size == Some(0) {
+            return Err(Error::Config(
+                "Fetcher max_response_size must be at least 1 byte".to_string(),
+            ));
+        }
+        if self.fetcher.max_concurrent_fetches == Some(0) {
+            return Err(Error::Config(
+                "Fetcher max_concurrent_fetches must be at least 1".to_string(),
+            ));
+        }
+        if self.fetcher.host_rate_limit == Some(0) {
+            return Err(Error::Config(
+                "Fetcher host_rate_limit must be at least 1 request a minute".to_string(),
+            ));
+        }
+        if let Some(host) = self
+            .fetcher
+            .host_rate_limits
+            .iter()
+            .find(|(_, limit)| **limit == 0)
+            .map(|(host, _)| host)
+        {
+            return Err(Error::Config(format!(
+                "Fetcher host_rate_limits for '{}' must be at least 1 request a minute",
+                host
+            )));
+        }
+        if self.fetcher.cache_dir.is_none()
+            && (self.fetcher.cache_ttl.is_some() || self.fetcher.cache_max_stale.is_some())
+        {
+            return Err(Error::Config(
+                "Fetcher cache_ttl and cache_max_stale need cache_dir to be set so there is somewhere to keep calendars".to_string(),
+            ));
+        }
+        if self
+            .fetcher
+            .cache_dir
+            .as_ref()
+            .is_some_and(|dir| dir.as_os_str().is_empty())
+        {
+            return Err(Error::Config(
+                "Fetcher cache_dir is an empty path".to_string(),
+            ));
+        }
+        crate::fetcher::Fetcher::from_config(&self.fetcher)
+            .map_err(|e| Error::Config(format!("Invalid fetcher settings: {}", e)))?;
+
+        if let Some(locale) = &self.output.locale
+            && crate::query::parse_locale(locale).is_none()
+        {
+            return Err(Error::Config(format!(
+                "Output locale '{}' isn't a known locale such as 'en_GB' or 'fr_FR'",
+                locale
+            )));
+        }
+
+        if self.server.request_timeout == Some(0) {
+            return Err(Error::Config(
+                "Server request_timeout must be at least 1 second".to_string(),
+            ));
+        }
+
+        for (id, tenant) in &self.server.tenants {
+            // Tenant ids appear in URLs as they are
+            if id.is_empty()
+                || !id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+            {
+                return Err(Error::Config(format!(
+                    "Tenant id '{}' can only use letters, digits, '-' and '_'",
+                    id
+                )));
+            }
+            if tenant.config.as_os_str().is_empty() {
+                return Err(Error::Config(format!("Tenant '{}' has no config", id)));
+            }
+            if tenant.token.as_deref() == Some("") {
+                return Err(Error::Config(format!(
+                    "Tenant '{}' token can't be empty",
+                    id
+                )));
+            }
+        }
+
+        if self.server.cache_max_bytes == Some(0) || self.server.cache_max_events == Some(0) {
+            return Err(Error::Config(
+                "Server cache_max_bytes and cache_max_events must be at least 1".to_string(),
+            ));
+        }
+        if self.server.cache_limits() != CacheLimits::default() && self.server.cache_ttl().is_none()
+        {
+            return Err(Error::Config(
+                "Server cache_max_bytes and cache_max_events need cache_ttl to be set so there is a cache to limit".to_string(),
+            ));
+        }
+
+        let warmup = self.warmup_calendars();
+        if !warmup.is_empty() && self.server.cache_ttl().is_none() {
+            return Err(Error::Config(
+                "Server warmup needs cache_ttl to be set so warmed calendars are kept".to_string(),
+            ));
+        }
+        for id in &warmup {
+            if !self.calendars.contains_key(id) {
+                return Err(Error::Config(format!(
+                    "Server warmup references unknown calendar '{}'",
+                    id
+                )));
+            }
+        }
+
+        if let Some(log_level) = &self.server.log_level {
+            crate::logging::parse_filter(log_level)?;
+        }
+
+        if let Some(audit_log) = &self.server.audit_log
+            && audit_log.max_size() == 0
+        {
+            return Err(Error::Config(
+                "Server audit_log max_size must be more than 0".to_string(),
+            ));
+        }
+
+        if self.server.admin_token.as_deref() == Some("") {
+            return Err(Error::Config(
+                "Server admin_token must not be empty".to_string(),
+            ));
+        }
+
+        if self.server.path_prefix().contains(['?', '#', '{', '}']) {
+            return Err(Error::Config(format!(
+                "Server path_prefix '{}' must be a plain path",
+                self.server.path_prefix()
+            )));
+        }
+
+        for (id, source) in &self.sources {
+            if source.url.is_empty() {
+                return Err(Error::Config(format!("Source '{}' has empty URL", id)));
+            }
+            if source.url.starts_with("file:") && local_path(&source.url).is_none() {
+                return Err(Error::Config(format!(
+                    "Source '{}' has invalid file URL '{}'",
+                    id, source.url
+                )));
+            }
+            if source.client_key.is_some() && source.client_cert.is_none() {
+                return Err(Error::Config(format!(
+                    "Source '{}' has a client_key but no client_cert",
+                    id
+                )));
+            }
+            if source.password.is_some() && source.username.is_none() {
+                return Err(Error::Config(format!(
+                    "Source '{}' has a password but no username",
+                    id
+                )));
+            }
+            if source.timeout == Some(0) {
+                return Err(Error::Config(format!(
+                    "Source '{}' timeout must be at least 1 second",
+                    id
+                )));
+            }
+            validate_credentials(source.credentials(), &format!("Source '{}'", id))?;
+            validate_s3_urls(
+                std::slice::from_ref(&source.url),
+                source.credentials(),
+                &format!("Source '{}'", id),
+            )?;
+        }
+
+        let mut aliases = std::collections::HashMap::new();
+        for (id, calendar) in &self.calendars {
+            for alias in &calendar.aliases {
+                if alias.is_empty() || alias.contains('/') {
+                    return Err(Error::Config(format!(
+                        "Calendar '{}' has invalid alias '{}'",
+                        id, alias
+                    )));
+                }
+                if self.calendars.contains_key(alias) {
+                    return Err(Error::Config(format!(
+                        "Calendar '{}' alias '{}' is already a calendar id",
+                        id, alias
+                    )));
+                }
+                if let Some(other) = aliases.insert(alias.as_str(), id.as_str()) {
+                    return Err(Error::Config(format!(
+                        "Alias '{}' is used by both calendar '{}' and calendar '{}'",
+                        alias, other, id
+                    )));
+                }
+            }
+        }
+
+        for (id, calendar) in &self.calendars {
+            if calendar.sources.is_empty() {
+                return Err(Error::Config(format!("Calendar '{}' has no sources", id)));
+            }
+
+            let recurrence = &calendar.recurrence;
+            if recurrence.past_days > MAX_EXPAND_DAYS || recurrence.future_days > MAX_EXPAND_DAYS {
+                return Err(Error::Config(format!(
+                    "Calendar '{}' recurrence window can't extend more than {} days either side of today",
+                    id, MAX_EXPAND_DAYS
+                )));
+            }
+
+            if calendar.refresh_token.as_deref() == Some("") {
+                return Err(Error::Config(format!(
+                    "Calendar '{}' refresh_token must not be empty",
+                    id
+                )));
+            }
+
+            if calendar.max_stale.is_some() && self.server.cache_ttl().is_none() {
+                return Err(Error::Config(format!(
+                    "Calendar '{}' max_stale needs server cache_ttl to be set so there are merges to fall back to",
+                    id
+                )));
+            }
+
+            if let Some(timezone) = &calendar.output_timezone {
+                crate::timezone::Zone::load(timezone).map_err(|e| {
+                    Error::Config(format!("Calendar '{}' output_timezone: {}", id, e))
+                })?;
+            }
+
+            let mut labels = std::collections::HashSet::new();
+            for (idx, source) in calendar.sources.iter().enumerate() {
+                // Labels name the source's pipeline in metrics so must be distinct
+                if let Some(label) = source.label() {
+                    if label.is_empty() {
+                        return Err(Error::Config(format!(
+                            "Calendar '{}' source {} has empty label",
+                            id, idx
+                        )));
+                    }
+                    if !labels.insert(label) {
+                        return Err(Error::Config(format!(
+                            "Calendar '{}' has more than one source labelled '{}'",
+                            id, label
+                        )));
+                    }
+                }
+
+                if let Some(age) = source.skip_older_than()
+                    && parse_age(age).is_none()
+                {
+                    return Err(Error::Config(format!(
+                        "Calendar '{}' source {} has invalid skip_older_than '{}' (expected a number of days or weeks such as '90d' or '12w')",
+                        id, idx, age
+                    )));
+                }
+
+                match source {
+                    SourceConfig::Url {
+                        username,
+                        password,
+                        client_cert,
+                        client_key,
+                        steps,
+                        ..
+                    }
+                    | SourceConfig::Urls {
+                        username,
+                        password,
+                        client_cert,
+                        client_key,
+                        steps,
+                        ..
+                    } => {
+                        if source.urls().is_empty() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has no URLs",
+                                id, idx
+                            )));
+                        }
+                        if source.urls().iter().any(|url| url.is_empty()) {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has empty URL",
+                                id, idx
+                            )));
+                        }
+                        if let Some(url) = source
+                            .urls()
+                            .iter()
+                            .find(|url| url.starts_with("file:") && local_path(url).is_none())
+                        {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has invalid file URL '{}'",
+                                id, idx, url
+                            )));
+                        }
+                        if client_key.is_some() && client_cert.is_none() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has a client_key but no client_cert",
+                                id, idx
+                            )));
+                        }
+                        if password.is_some() && username.is_none() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has a password but no username",
+                                id, idx
+                            )));
+                        }
+                        if source.timeout() == Some(Duration::ZERO) {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} timeout must be at least 1 second",
+                                id, idx
+                            )));
+                        }
+                        validate_credentials(
+                            source.credentials(),
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                        validate_s3_urls(
+                            source.urls(),
+                            source.credentials(),
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                        // Validate source steps
+                        Self::validate_source_steps(
+                            steps,
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                    }
+                    SourceConfig::Calendar {
+                        calendar: ref_id,
+                        steps,
+                        ..
+                    } => {
+                        if ref_id.is_empty() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has empty calendar reference",
+                                id, idx
+                            )));
+                        }
+                        // Check that referenced calendar exists
+                        if !self.calendars.contains_key(ref_id) {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} references unknown calendar '{}'",
+                                id, idx, ref_id
+                            )));
+                        }
+                        // Validate source steps
+                        Self::validate_source_steps(
+                            steps,
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                    }
+                    SourceConfig::Holidays {
+                        holidays,
+                        region,
+                        steps,
+                        ..
+                    } => {
+                        crate::holidays::validate(holidays, region.as_deref()).map_err(|e| {
+                            Error::Config(format!("Calendar '{}' source {}: {}", id, idx, e))
+                        })?;
+                        // Validate source steps
+                        Self::validate_source_steps(
+                            steps,
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                    }
+                    SourceConfig::Generate {
+                        generate, steps, ..
+                    } => {
+                        if generate.is_empty() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has no events to generate",
+                                id, idx
+                            )));
+                        }
+                        for event in generate {
+                            crate::generate::validate(event).map_err(|e| {
+                                Error::Config(format!("Calendar '{}' source {}: {}", id, idx, e))
+                            })?;
+                        }
+                        // Validate source steps
+                        Self::validate_source_steps(
+                            steps,
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                    }
+                    SourceConfig::Command { command, steps, .. } => {
+                        if command.is_empty() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has empty command",
+                                id, idx
+                            )));
+                        }
+                        if source.timeout() == Some(Duration::ZERO) {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} timeout must be at least 1 second",
+                                id, idx
+                            )));
+                        }
+                        // Validate source steps
+                        Self::validate_source_steps(
+                            steps,
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                    }
+                    SourceConfig::Birthdays {
+                        birthdays,
+                        username,
+                        password,
+                        auth,
+                        steps,
+                        ..
+                    } => {
+                        if birthdays.is_empty() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has empty address book URL",
+                                id, idx
+                            )));
+                        }
+                        if birthdays.starts_with("file:") && local_path(birthdays).is_none() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has invalid file URL '{}'",
+                                id, idx, birthdays
+                            )));
+                        }
+                        if password.is_some() && username.is_none() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has a password but no username",
+                                id, idx
+                            )));
+                        }
+                        // S3 requests are signed as downloads, which a CardDAV REPORT isn't
+                        if matches!(auth, Some(SourceAuth::S3 { .. }))
+                            && crate::birthdays::is_collection(birthdays)
+                        {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} can only use s3 auth to fetch a .vcf file",
+                                id, idx
+                            )));
+                        }
+                        if source.timeout() == Some(Duration::ZERO) {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} timeout must be at least 1 second",
+                                id, idx
+                            )));
+                        }
+                        validate_credentials(
+                            source.credentials(),
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                        validate_s3_urls(
+                            source.urls(),
+                            source.credentials(),
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                        // Validate source steps
+                        Self::validate_source_steps(
+                            steps,
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                    }
+                    SourceConfig::Json {
+                        json,
+                        items,
+                        fields,
+                        timezone,
+                        username,
+                        password,
+                        steps,
+                        ..
+                    } => {
+                        if json.is_empty() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has empty JSON URL",
+                                id, idx
+                            )));
+                        }
+                        if json.starts_with("file:") && local_path(json).is_none() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has invalid file URL '{}'",
+                                id, idx, json
+                            )));
+                        }
+                        crate::json::validate(items.as_deref(), fields).map_err(|e| {
+                            Error::Config(format!("Calendar '{}' source {}: {}", id, idx, e))
+                        })?;
+                        if let Some(timezone) = timezone {
+                            crate::timezone::Zone::load(timezone).map_err(|e| {
+                                Error::Config(format!(
+                                    "Calendar '{}' source {} timezone: {}",
+                                    id, idx, e
+                                ))
+                            })?;
+                        }
+                        if password.is_some() && username.is_none() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has a password but no username",
+                                id, idx
+                            )));
+                        }
+                        if source.timeout() == Some(Duration::ZERO) {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} timeout must be at least 1 second",
+                                id, idx
+                            )));
+                        }
+                        validate_credentials(
+                            source.credentials(),
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                        validate_s3_urls(
+                            source.urls(),
+                            source.credentials(),
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                        // Validate source steps
+                        Self::validate_source_steps(
+                            steps,
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                    }
+                    SourceConfig::Git {
+                        git,
+                        files,
+                        branch,
+                        steps,
+                        ..
+                    } => {
+                        // Nothing given to git may be read as an option
+                        if git.is_empty() || git.starts_with('-') {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has invalid git repository '{}'",
+                                id, idx, git
+                            )));
+                        }
+                        if let Some(branch) = branch
+                            && (branch.is_empty() || branch.starts_with('-'))
+                        {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has invalid branch '{}'",
+                                id, idx, branch
+                            )));
+                        }
+                        if files.is_empty() {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} has no files to read",
+                                id, idx
+                            )));
+                        }
+                        if let Some(file) = files.iter().find(|file| !crate::git::is_inside(file)) {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} file '{}' isn't a path inside the repository",
+                                id, idx, file
+                            )));
+                        }
+                        if source.timeout() == Some(Duration::ZERO) {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} timeout must be at least 1 second",
+                                id, idx
+                            )));
+                        }
+                        // Validate source steps
+                        Self::validate_source_steps(
+                            steps,
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                    }
+                    SourceConfig::Inline { ics, steps, .. } => {
+                        // Mistakes in the config should be found when it's loaded
+                        crate::ical::parse_calendar(ics).map_err(|e| {
+                            Error::Config(format!(
+                                "Calendar '{}' source {} has invalid inline iCal: {}",
+                                id, idx, e
+                            ))
+                        })?;
+                        // Validate source steps
+                        Self::validate_source_steps(
+                            steps,
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                    }
+                    SourceConfig::Source {
+                        source: source_id,
+                        steps,
+                        ..
+                    } => {
+                        if !self.sources.contains_key(source_id) {
+                            return Err(Error::Config(format!(
+                                "Calendar '{}' source {} references unknown source '{}'",
+                                id, idx, source_id
+                            )));
+                        }
+                        // Validate source steps
+                        Self::validate_source_steps(
+                            steps,
+                            &format!("Calendar '{}' source {}", id, idx),
+                        )?;
+                    }
+                }
+            }
+
+            // Validate calendar-level steps
+            Self::validate_steps(&calendar.steps, &format!("Calendar '{}'", id))?;
+            for (name, steps) in &calendar.variants {
+                if name.is_empty()
+                    || !name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                {
+                    return Err(Error::Config(format!(
+                        "Calendar '{}' variant '{}' must be letters, digits, '-' or '_'",
+                        id, name
+                    )));
+                }
+                Self::validate_steps(steps, &format!("Calendar '{}' variant '{}'", id, name))?;
+            }
+        }
+
+        // Detect cycles in calendar references
+        for id in self.calendars.keys() {
+            self.detect_cycle(
+                id,
+                &mut std::collections::HashSet::new(),
+                &mut std::collections::HashSet::new(),
+            )?;
+        }
+
+        crate::selftest::run(self)?;
+
+        Ok(())
+    }
+
+    /// Detect cycles in calendar references using DFS
+    fn detect_cycle(
+        &self,
+        calendar_id: &str,
+        visited: &mut std::collections::HashSet<String>,
+        stack: &mut std::collections::HashSet<String>,
+    ) -> Result<()> {
+        if stack.contains(calendar_id) {
+            return Err(Error::Config(format!(
+                "Circular calendar reference detected involving '{}'",
+                calendar_id
+            )));
+        }
+
+        if visited.contains(calendar_id) {
+            return Ok(());
+        }
+
+        visited.insert(calendar_id.to_string());
+        stack.insert(calendar_id.to_string());
+
+        if let Some(calendar) = self.calendars.get(calendar_id) {
+            for source in &calendar.sources {
+                if let SourceConfig::Calendar {
+                    calendar: ref_id, ..
+                } = source
+                {
+                    self.detect_cycle(ref_id, visited, stack)?;
+                }
+            }
+        }
+
+        stack.remove(calendar_id);
+        Ok(())
+    }
+
+    /// Validate the steps of a source, which can't include steps applied when serving
+    fn validate_source_steps(steps: &[Step], context: &str) -> Result<()> {
+        Self::validate_steps(steps, context)?;
+        if let Some(idx) = steps
+            .iter()
+            .position(|step| matches!(step, Step::Soon { .. }))
+        {
+            return Err(Error::Config(format!(
+                "{} step {} is a soon step, which can only be used in calendar steps",
+                context, idx
+            )));
+        }
+        Ok(())
+    }
+
+    fn validate_steps(steps: &[Step], context: &str) -> Result<()> {
+        use regex::Regex;
+
+        for (idx, step) in steps.iter().enumerate() {
+            match step {
+                Step::Allow {
+                    patterns,
+                    unless,
+                    literal,
+                    ..
+                }
+                | Step::Deny {
+                    patterns,
+                    unless,
+                    literal,
+                    ..
+                } => {
+                    if patterns.is_empty() {
+                        return Err(Error::Config(format!(
+                            "{} step {} has no patterns",
+                            context, idx
+                        )));
+                    }
+                    if *literal {
+                        continue;
+                    }
+                    for pattern in patterns.iter().chain(unless) {
+                        Regex::new(pattern).map_err(|e| {
+                            Error::Config(format!(
+                                "{} step {} has invalid pattern '{}': {}",
+                                context, idx, pattern, e
+                            ))
+                        })?;
+                    }
+                }
+                Step::Replace {
+                    pattern, literal, ..
+                }
+                | Step::Links {
+                    pattern, literal, ..
+                }
+                | Step::RawReplace {
+                    pattern, literal, ..
+                } => {
+                    if *literal {
+                        continue;
+                    }
+                    Regex::new(pattern).map_err(|e| {
+                        Error::Config(format!(
+                            "{} step {} has invalid pattern '{}': {}",
+                            context, idx, pattern, e
+                        ))
+                    })?;
+                }
+                Step::Strip { field } => {
+                    if field != "reminder" {
+                        return Err(Error::Config(format!(
+                            "{} step {} has unsupported strip field '{}' (only 'reminder' is supported)",
+                            context, idx, field
+                        )));
+                    }
+                }
+                Step::Alarms {
+                    actions,
+                    min_before,
+                    max_before,
+                } => {
+                    if actions.is_empty() && min_before.is_none() && max_before.is_none() {
+                        return Err(Error::Config(format!(
+                            "{} step {} needs actions, min_before or max_before to choose reminders",
+                            context, idx
+                        )));
+                    }
+                    if let Some(action) = actions.iter().find(|action| {
+                        !["AUDIO", "DISPLAY", "EMAIL"]
+                            .iter()
+                            .any(|known| known.eq_ignore_ascii_case(action))
+                    }) {
+                        return Err(Error::Config(format!(
+                            "{} step {} has unknown alarm action '{}' (must be 'audio', 'display' or 'email')",
+                            context, idx, action
+                        )));
+                    }
+                    if let Some(minutes) = min_before
+                        .iter()
+                        .chain(max_before)
+                        .find(|minutes| chrono::Duration::try_minutes(**minutes).is_none())
+                    {
+                        return Err(Error::Config(format!(
+                            "{} step {} has {} minutes before the start, too long to hold",
+                            context, idx, minutes
+                        )));
+                    }
+                    if let (Some(min), Some(max)) = (min_before, max_before)
+                        && min > max
+                    {
+                        return Err(Error::Config(format!(
+                            "{} step {} has a min_before of {} minutes, more than its max_before of {}",
+                            context, idx, min, max
+                        )));
+                    }
+                }
+                Step::Case { .. } => {
+                    // No validation needed for case transformation
+                }
+                Step::Notice { text, .. } => {
+                    if text.trim().is_empty() {
+                        return Err(Error::Config(format!(
+                            "{} step {} has no notice text",
+                            context, idx
+                        )));
+                    }
+                }
+                Step::Extract { pattern, .. } => {
+                    let regex = Regex::new(pattern).map_err(|e| {
+                        Error::Config(format!(
+                            "{} step {} has invalid pattern '{}': {}",
+                            context, idx, pattern, e
+                        ))
+                    })?;
+                    if regex.capture_names().flatten().next().is_none() {
+                        return Err(Error::Config(format!(
+                            "{} step {} pattern '{}' has no named capture groups",
+                            context, idx, pattern
+                        )));
+                    }
+                }
+                Step::Classify {
+                    rules,
+                    property,
+                    literal,
+                    ..
+                } => {
+                    if rules.is_empty() {
+                        return Err(Error::Config(format!(
+                            "{} step {} has no rules",
+                            context, idx
+                        )));
+                    }
+                    for rule in rules.iter().filter(|_| !*literal) {
+                        Regex::new(&rule.pattern).map_err(|e| {
+                            Error::Config(format!(
+                                "{} step {} has invalid pattern '{}': {}",
+                                context, idx, rule.pattern, e
+                            ))
+                        })?;
+                    }
+                    if let Some(property) = property
+                        && !property.to_uppercase().starts_with("X-")
+                        && !property.eq_ignore_ascii_case("categories")
+                    {
+                        return Err(Error::Config(format!(
+                            "{} step {} has unsupported classify property '{}' (must be 'CATEGORIES' or start with 'X-')",
+                            context, idx, property
+                        )));
+                    }
+                }
+                Step::Hours {
+                    start, end, days, ..
+                } => {
+                    working_hours(start, end, days)
+                        .map_err(|e| Error::Config(format!("{} step {} {}", context, idx, e)))?;
+                }
+                Step::Attendees { field, format, .. } => {
+                    if !format.contains("{count}") {
+                        return Err(Error::Config(format!(
+                            "{} step {} format '{}' doesn't include {{count}}",
+                            context, idx, format
+                        )));
+                    }
+                    if !matches!(field.as_str(), "summary" | "description" | "location")
+                        && !field.to_uppercase().starts_with("X-")
+                    {
+                        return Err(Error::Config(format!(
+                            "{} step {} can't annotate field '{}'",
+                            context, idx, field
+                        )));
+                    }
+                }
+                Step::Soon { within, prefix } => {
+                    if *within == Some(0) {
+                        return Err(Error::Config(format!(
+                            "{} step {} needs a within of at least 1 hour",
+                            context, idx
+                        )));
+                    }
+                    if prefix.is_empty() {
+                        return Err(Error::Config(format!(
+                            "{} step {} has an empty prefix",
+                            context, idx
+                        )));
+                    }
+                }
+                Step::Organizer {
+                    email,
+                    name,
+                    remove,
+                } => {
+                    if *remove && (email.is_some() || name.is_some()) {
+                        return Err(Error::Config(format!(
+                            "{} step {} can't both remove and set the organizer",
+                            context, idx
+                        )));
+                    }
+                    if !*remove && email.is_none() && name.is_none() {
+                        return Err(Error::Config(format!(
+                            "{} step {} needs an email, a name or remove",
+                            context, idx
+                        )));
+                    }
+                    if let Some(email) = email
+                        && !email.contains('@')
+                    {
+                        return Err(Error::Config(format!(
+                            "{} step {} has invalid organizer email '{}'",
+                            context, idx, email
+                        )));
+                    }
+                }
+                Step::Buffer {
+                    patterns,
+                    literal,
+                    location,
+                    before,
+                    after,
+                    ..
+                } => {
+                    if patterns.is_empty() && !*location {
+                        return Err(Error::Config(format!(
+                            "{} step {} needs patterns or location to choose events",
+                            context, idx
+                        )));
+                    }
+                    if *before == 0 && *after == 0 {
+                        return Err(Error::Config(format!(
+                            "{} step {} needs a before or after duration",
+                            context, idx
+                        )));
+                    }
+                    for pattern in patterns.iter().filter(|_| !*literal) {
+                        Regex::new(pattern).map_err(|e| {
+                            Error::Config(format!(
+                                "{} step {} has invalid pattern '{}': {}",
+                                context, idx, pattern, e
+                            ))
+                        })?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_config_parsing() {
+        let config_json = r#"{
+            "calendars": {
+                "test": {
+                    "sources": [
+                        {
+                            "url": "https://example.com/test.ics"
+                        }
+                    ]
+                }
+            }
+        }"#;
+
+        let temp_dir = std::env::temp_dir();
+        let config_path = temp_dir.join("test_config.json");
+        fs::write(&config_path, config_json).unwrap();
+
+        let config = Config::load(&config_path).unwrap();
+        assert_eq!(config.calendars.len(), 1);
+        assert!(config.calendars.contains_key("test"));
+
+        fs::remove_file(config_path).unwrap();
+    }
+
+    #[test]
+    fn test_config_minimal() {
+        let config_json = r#"{
+            "calendars": {
+                "test": {
+                    "sources": [
+                        {
+                            "url": "https://example.com/test.ics"
+                        }
+                    ]
+                }
+            }
+        }"#;
+
+        let temp_dir = std::env::temp_dir();
+        let config_path = temp_dir.join("test_config_minimal.json");
+        fs::write(&config_path, config_json).unwrap();
+
+        let config = Config::load(&config_path).unwrap();
+        assert_eq!(config.calendars.len(), 1);
+        assert!(config.calendars.contains_key("test"));
+
+        fs::remove_file(config_path).unwrap();
+    }
+
+    #[test]
+    fn test_step_fields_default() {
+        let config_json = r#"{
+            "calendars": {
+                "test": {
+                    "sources": [
+                        {
+                            "url": "https://example.com/test.ics",
+                            "steps": [
+                                {
+                                    "type": "allow",
+                                    "patterns": ["meeting"]
+                                }
+                            ]
+                        }
+                    ]
+                }
+            }
+        }"#;
+
+        let temp_dir = std::env::temp_dir();
+        let config_path = temp_dir.join("test_step_defaults.json");
+        fs::write(&config_path, config_json).unwrap();
+
+        let config = Config::load(&config_path).unwrap();
+        let source = &config.calendars["test"].sources[0];
+        let steps = source.steps();
+        if let Step::Allow { fields, mode, .. } = &steps[0] {
+            assert_eq!(
+                fields,
+                &vec!["summary".to_string(), "description".to_string()]
+            );
+            assert!(matches!(mode, MatchMode::Any));
+        } else {
+            panic!("Expected Allow step");
+        }
+
+        fs::remove_file(config_path).unwrap();
+    }
+
+    #[test]
+    fn test_default_action_parsing() {
+        let config_json = r#"{
+            "calendars": {
+                "test": {
+                    "default": "deny",
+                    "sources": [
+                        {
+                            "url": "https://example.com/test.ics",
+                            "default": "deny"
+                        },
+                        {
+                            "url": "https://example.com/other.ics"
+                        }
+                    ]
+                }
+            }
+        }"#;
+
+        let temp_dir = std::env::temp_dir();
+        let config_path = temp_dir.join("test_default_action.json");
+        fs::write(&config_path, config_json).unwrap();
+
+        let config = Config::load(&config_path).unwrap();
+        let calendar = &config.calendars["test"];
+        assert_eq!(calendar.default, DefaultAction::Deny);
+        assert_eq!(calendar.sources[0].default_action(), &DefaultAction::Deny);
+        assert_eq!(calendar.sources[1].default_action(), &DefaultAction::Allow);
+
+        fs::remove_file(config_path).unwrap();
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let config = Config {
+            calendars: HashMap::new(),
+            sources: HashMap::new(),
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_err());
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test".to_string(),
+            CalendarConfig {
+                sources: vec![],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_err());
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: "https://example.com/test.ics".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_replace_step_default_replacement() {
+        let config_json = r#"{
+            "calendars": {
+                "test": {
+                    "sources": [
+                        {
+                            "url": "https://example.com/test.ics",
+                            "steps": [
+                                {
+                                    "type": "replace",
+                                    "pattern": "🔔"
+                                }
+                            ]
+                        }
+                    ]
+                }
+            }
+        }"#;
+
+        let temp_dir = std::env::temp_dir();
+        let config_path = temp_dir.join("test_replace_default.json");
+        fs::write(&config_path, config_json).unwrap();
+
+        let config = Config::load(&config_path).unwrap();
+        let source = &config.calendars["test"].sources[0];
+        let steps = source.steps();
+        if let Step::Replace {
+            pattern,
+            replacement,
+            field,
+            ..
+        } = &steps[0]
+        {
+            assert_eq!(pattern, "🔔");
+            assert_eq!(replacement, ""); // Default empty string
+            assert_eq!(field, "summary"); // Default field
+        } else {
+            panic!("Expected Replace step");
+        }
+
+        fs::remove_file(config_path).unwrap();
+    }
+
+    #[test]
+    fn test_step_validation() {
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: "https://example.com/test.ics".to_string(),
+                    steps: vec![Step::Allow {
+                        patterns: vec!["(?i)meeting".to_string()],
+                        mode: MatchMode::Any,
+                        fields: vec!["summary".to_string()],
+                        action: FilterAction::Enforce,
+                        unless: vec![],
+                        literal: false,
+                    }],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_ok());
+
+        // Test invalid regex
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: "https://example.com/test.ics".to_string(),
+                    steps: vec![Step::Allow {
+                        patterns: vec!["[invalid".to_string()],
+                        mode: MatchMode::Any,
+                        fields: vec!["summary".to_string()],
+                        action: FilterAction::Enforce,
+                        unless: vec![],
+                        literal: false,
+                    }],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_err());
+
+        // Test empty patterns
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: "https://example.com/test.ics".to_string(),
+                    steps: vec![Step::Allow {
+                        patterns: vec![],
+                        mode: MatchMode::Any,
+                        fields: vec!["summary".to_string()],
+                        action: FilterAction::Enforce,
+                        unless: vec![],
+                        literal: false,
+                    }],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_err());
+
+        // Test extract without named captures
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: "https://example.com/test.ics".to_string(),
+                    steps: vec![Step::Extract {
+                        pattern: "[A-Z]+-[0-9]+".to_string(),
+                        field: "summary".to_string(),
+                    }],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_err());
+
+        // Test invalid strip field
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: "https://example.com/test.ics".to_string(),
+                    steps: vec![Step::Strip {
+                        field: "invalid".to_string(),
+                    }],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_hours_step_validation() {
+        let hours = |start: &str, end: &str, days: &[&str]| {
+            let step = Step::Hours {
+                start: start.to_string(),
+                end: end.to_string(),
+                days: days.iter().map(|day| day.to_string()).collect(),
+                mode: HoursMode::Clip,
+            };
+            Config::validate_steps(&[step], "Calendar 'test'")
+        };
+
+        assert!(hours("09:00", "17:30", &["mon", "Tuesday"]).is_ok());
+        assert!(hours("9am", "17:00", &["mon"]).is_err());
+        assert!(hours("17:00", "09:00", &["mon"]).is_err());
+        assert!(hours("09:00", "17:00", &[]).is_err());
+        assert!(hours("09:00", "17:00", &["someday"]).is_err());
+
+        let step: Step =
+            serde_json::from_str(r#"{"type": "hours", "start": "09:00", "end": "17:00"}"#).unwrap();
+        let Step::Hours { days, mode, .. } = step else {
+            panic!("expected an hours step");
+        };
+        assert_eq!(days, vec!["mon", "tue", "wed", "thu", "fri"]);
+        assert_eq!(mode, HoursMode::Clip);
+    }
+
+    #[test]
+    fn test_buffer_step_validation() {
+        let buffer = |patterns: &[&str], location: bool, before: u32, after: u32| {
+            let step = Step::Buffer {
+                patterns: patterns.iter().map(|p| p.to_string()).collect(),
+                fields: default_step_fields(),
+                literal: false,
+                location,
+                before,
+                after,
+                summary: default_buffer_summary(),
+            };
+            Config::validate_steps(&[step], "Calendar 'test'")
+        };
+
+        assert!(buffer(&["(?i)onsite"], false, 30, 0).is_ok());
+        assert!(buffer(&[], true, 0, 15).is_ok());
+        assert!(buffer(&[], false, 30, 30).is_err());
+        assert!(buffer(&["(?i)onsite"], false, 0, 0).is_err());
+        assert!(buffer(&["[invalid"], false, 30, 0).is_err());
+    }
+
+    #[test]
+    fn test_attendees_step_validation() {
+        let attendees = |field: &str, format: &str| {
+            let step = Step::Attendees {
+                field: field.to_string(),
+                format: format.to_string(),
+                min: default_attendee_min(),
+            };
+            Config::validate_steps(&[step], "Calendar 'test'")
+        };
+
+        assert!(attendees("summary", &default_attendee_format()).is_ok());
+        assert!(attendees("X-ATTENDEES", "{count}").is_ok());
+        assert!(attendees("summary", " (lots of people)").is_err());
+        assert!(attendees("categories", "{count}").is_err());
+    }
+
+    #[test]
+    fn test_soon_step_validation() {
+        let soon = |within: Option<u32>, prefix: &str| Step::Soon {
+            within,
+            prefix: prefix.to_string(),
+        };
+
+        assert!(Config::validate_steps(&[soon(None, "TODAY: ")], "Calendar 'test'").is_ok());
+        assert!(Config::validate_steps(&[soon(Some(2), "⏰ ")], "Calendar 'test'").is_ok());
+        assert!(Config::validate_steps(&[soon(Some(0), "⏰ ")], "Calendar 'test'").is_err());
+        assert!(Config::validate_steps(&[soon(None, "")], "Calendar 'test'").is_err());
+
+        // Sources are merged ahead of time so can't decorate
+        let err =
+            Config::validate_source_steps(&[soon(None, "TODAY: ")], "Calendar 'test' source 0")
+                .unwrap_err();
+        assert!(err.to_string().contains("only be used in calendar steps"));
+    }
+
+    #[test]
+    fn test_alarms_step_validation() {
+        let alarms = |actions: &[&str], min_before: Option<i64>, max_before: Option<i64>| {
+            let step = Step::Alarms {
+                actions: actions.iter().map(|action| action.to_string()).collect(),
+                min_before,
+                max_before,
+            };
+            Config::validate_steps(&[step], "Calendar 'test'")
+        };
+
+        assert!(alarms(&["display"], None, None).is_ok());
+        assert!(alarms(&[], Some(5), Some(60)).is_ok());
+        assert!(alarms(&[], None, Some(60)).is_ok());
+        assert!(alarms(&[], None, None).is_err());
+        assert!(alarms(&["popup"], None, None).is_err());
+        assert!(alarms(&[], Some(60), Some(5)).is_err());
+        assert!(alarms(&[], None, Some(i64::MAX)).is_err());
+    }
+
+    #[test]
+    fn test_notice_step_validation() {
+        let notice = |text: &str| Step::Notice {
+            text: text.to_string(),
+            position: NoticePosition::End,
+        };
+
+        assert!(Config::validate_steps(&[notice("From {source}")], "Calendar 'test'").is_ok());
+        assert!(Config::validate_steps(&[notice(" ")], "Calendar 'test'").is_err());
+
+        let step: Step =
+            serde_json::from_str(r#"{ "type": "notice", "text": "Copy", "position": "start" }"#)
+                .unwrap();
+        assert!(matches!(
+            step,
+            Step::Notice {
+                position: NoticePosition::Start,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_organizer_step_validation() {
+        let organizer = |email: Option<&str>, name: Option<&str>, remove: bool| {
+            let step = Step::Organizer {
+                email: email.map(str::to_string),
+                name: name.map(str::to_string),
+                remove,
+            };
+            Config::validate_steps(&[step], "Calendar 'test'")
+        };
+
+        assert!(organizer(Some("calendar@example.com"), None, false).is_ok());
+        assert!(organizer(None, Some("Family"), false).is_ok());
+        assert!(organizer(None, None, true).is_ok());
+        assert!(organizer(None, None, false).is_err());
+        assert!(organizer(Some("calendar@example.com"), None, true).is_err());
+        assert!(organizer(Some("calendar"), None, false).is_err());
+    }
+
+    #[test]
+    fn test_source_include_cutoff() {
+        assert_eq!(parse_age("90d"), Some(chrono::Duration::days(90)));
+        assert_eq!(parse_age("2w"), Some(chrono::Duration::days(14)));
+        assert_eq!(parse_age("90"), None);
+        assert_eq!(parse_age("-1d"), None);
+        assert_eq!(parse_age("d"), None);
+        assert_eq!(parse_age(""), None);
+
+        let source = |only_future: bool, skip_older_than: Option<&str>| SourceConfig::Url {
+            url: "https://example.com/test.ics".to_string(),
+            steps: vec![],
+            default: DefaultAction::Allow,
+            label: None,
+            tags: vec![],
+            only_future,
+            skip_older_than: skip_older_than.map(str::to_string),
+            username: None,
+            password: None,
+            auth: None,
+            timeout: None,
+            client_cert: None,
+            client_key: None,
+            insecure_tls: false,
+        };
+        let now = chrono::NaiveDate::from_ymd_opt(2025, 3, 31)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+
+        assert_eq!(source(false, None).include_cutoff(now), None);
+        assert_eq!(source(true, None).include_cutoff(now), Some(now));
+        assert_eq!(
+            source(false, Some("30d")).include_cutoff(now),
+            Some(now - chrono::Duration::days(30))
+        );
+        assert_eq!(source(true, Some("30d")).include_cutoff(now), Some(now));
+
+        let config: Config = serde_json::from_str(
+            r#"{"calendars": {"test": {"sources": [
+                {"url": "https://example.com/test.ics", "skip_older_than": "three months"}
+            ]}}}"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_source_label_validation() {
+        let source = |label: &str| SourceConfig::Url {
+            url: "https://example.com/test.ics".to_string(),
+            steps: vec![],
+            default: DefaultAction::Allow,
+            label: Some(label.to_string()),
+            tags: vec![],
+            only_future: false,
+            skip_older_than: None,
+            username: None,
+            password: None,
+            auth: None,
+            timeout: None,
+            client_cert: None,
+            client_key: None,
+            insecure_tls: false,
+        };
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test".to_string(),
+            CalendarConfig {
+                sources: vec![source("Work"), source("Home")],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let mut config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.calendars["test"].sources[0].identifier(),
+            "Work (https://example.com/test.ics)"
+        );
+
+        // Labels must be unique within a calendar
+        config.calendars.get_mut("test").unwrap().sources[1] = source("Work");
+        assert!(config.validate().is_err());
+
+        config.calendars.get_mut("test").unwrap().sources[1] = source("");
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_calendar_reference_validation() {
+        // Valid calendar reference
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "base".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: "https://example.com/base.ics".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        calendars.insert(
+            "derived".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Calendar {
+                    calendar: "base".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_ok());
+
+        // Unknown calendar reference
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "derived".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Calendar {
+                    calendar: "nonexistent".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_cycle_detection_direct() {
+        // Direct self-reference A→A
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "a".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Calendar {
+                    calendar: "a".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_cycle_detection_indirect() {
+        // Indirect cycle A→B→A
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "a".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Calendar {
+                    calendar: "b".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        calendars.insert(
+            "b".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Calendar {
+                    calendar: "a".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_diamond_dependency() {
+        // Diamond dependency A→B, A→C, B→D, C→D (valid)
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "d".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: "https://example.com/d.ics".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        calendars.insert(
+            "b".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Calendar {
+                    calendar: "d".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        calendars.insert(
+            "c".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Calendar {
+                    calendar: "d".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        calendars.insert(
+            "a".to_string(),
+            CalendarConfig {
+                sources: vec![
+                    SourceConfig::Calendar {
+                        calendar: "b".to_string(),
+                        steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                    },
+                    SourceConfig::Calendar {
+                        calendar: "c".to_string(),
+                        steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                    },
+                ],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        assert!(config.validate().is_ok());
+    }
 
     #[test]
-    fn test_config_parsing() {
-        let config_json = r#"{
-            "calendars": {
-                "test": {
-                    "sources": [
-                        {
-                            "url": "https://example.com/test.ics"
-                        }
-                    ]
-                }
-            }
-        }"#;
+    fn test_config_parsing_toml() {
+        let config_toml = r#"
+[calendars.test]
+
+[[calendars.test.sources]]
+url = "https://example.com/test.ics"
+steps = []
+
+calendars.test.steps = []
+"#;
 
         let temp_dir = std::env::temp_dir();
-        let config_path = temp_dir.join("test_config.json");
-        fs::write(&config_path, config_json).unwrap();
+        let config_path = temp_dir.join("test_config.toml");
+        fs::write(&config_path, config_toml).unwrap();
 
         let config = Config::load(&config_path).unwrap();
         assert_eq!(config.calendars.len(), 1);
@@ -308,518 +4159,1164 @@ mod tests {
     }
 
     #[test]
-    fn test_config_minimal() {
-        let config_json = r#"{
-            "calendars": {
-                "test": {
-                    "sources": [
-                        {
-                            "url": "https://example.com/test.ics"
-                        }
-                    ]
-                }
-            }
-        }"#;
+    fn test_config_toml_with_steps() {
+        let config_toml = r#"
+[calendars.test]
+
+[[calendars.test.sources]]
+url = "https://example.com/test.ics"
+
+[[calendars.test.sources.steps]]
+type = "allow"
+patterns = ["(?i)meeting"]
+mode = "any"
+fields = ["summary"]
+
+[[calendars.test.sources.steps]]
+type = "replace"
+pattern = "^"
+replacement = "[TEST] "
+field = "summary"
+
+[[calendars.test.sources.steps]]
+type = "case"
+transform = "title"
+
+calendars.test.steps = []
+"#;
 
         let temp_dir = std::env::temp_dir();
-        let config_path = temp_dir.join("test_config_minimal.json");
-        fs::write(&config_path, config_json).unwrap();
+        let config_path = temp_dir.join("test_config_steps.toml");
+        fs::write(&config_path, config_toml).unwrap();
 
         let config = Config::load(&config_path).unwrap();
         assert_eq!(config.calendars.len(), 1);
-        assert!(config.calendars.contains_key("test"));
+
+        let calendar = config.calendars.get("test").unwrap();
+        assert_eq!(calendar.sources.len(), 1);
+
+        let steps = calendar.sources[0].steps();
+        assert_eq!(steps.len(), 3);
+
+        // Verify allow step
+        match &steps[0] {
+            Step::Allow {
+                patterns,
+                mode,
+                fields,
+                ..
+            } => {
+                assert_eq!(patterns.len(), 1);
+                assert_eq!(patterns[0], "(?i)meeting");
+                assert!(matches!(mode, MatchMode::Any));
+                assert_eq!(fields.len(), 1);
+                assert_eq!(fields[0], "summary");
+            }
+            _ => panic!("Expected Allow step"),
+        }
+
+        // Verify replace step
+        match &steps[1] {
+            Step::Replace {
+                pattern,
+                replacement,
+                field,
+                ..
+            } => {
+                assert_eq!(pattern, "^");
+                assert_eq!(replacement, "[TEST] ");
+                assert_eq!(field, "summary");
+            }
+            _ => panic!("Expected Replace step"),
+        }
+
+        // Verify case step
+        match &steps[2] {
+            Step::Case {
+                transform, field, ..
+            } => {
+                assert!(matches!(transform, CaseTransform::Title));
+                assert_eq!(field, "summary");
+            }
+            _ => panic!("Expected Case step"),
+        }
 
         fs::remove_file(config_path).unwrap();
     }
 
     #[test]
-    fn test_step_fields_default() {
-        let config_json = r#"{
-            "calendars": {
-                "test": {
-                    "sources": [
-                        {
-                            "url": "https://example.com/test.ics",
-                            "steps": [
-                                {
-                                    "type": "allow",
-                                    "patterns": ["meeting"]
-                                }
-                            ]
-                        }
-                    ]
-                }
-            }
-        }"#;
+    fn test_config_toml_calendar_reference() {
+        let config_toml = r#"
+[calendars.base]
+
+[[calendars.base.sources]]
+url = "https://example.com/base.ics"
+steps = []
+
+calendars.base.steps = []
+
+[calendars.derived]
+
+[[calendars.derived.sources]]
+calendar = "base"
+
+[[calendars.derived.sources.steps]]
+type = "replace"
+pattern = "^"
+replacement = "[DERIVED] "
+field = "summary"
+
+calendars.derived.steps = []
+"#;
 
         let temp_dir = std::env::temp_dir();
-        let config_path = temp_dir.join("test_step_defaults.json");
-        fs::write(&config_path, config_json).unwrap();
+        let config_path = temp_dir.join("test_config_ref.toml");
+        fs::write(&config_path, config_toml).unwrap();
 
         let config = Config::load(&config_path).unwrap();
-        let source = &config.calendars["test"].sources[0];
-        let steps = source.steps();
-        if let Step::Allow { fields, mode, .. } = &steps[0] {
-            assert_eq!(
-                fields,
-                &vec!["summary".to_string(), "description".to_string()]
-            );
-            assert!(matches!(mode, MatchMode::Any));
-        } else {
-            panic!("Expected Allow step");
+        config.validate().unwrap();
+
+        assert_eq!(config.calendars.len(), 2);
+        assert!(config.calendars.contains_key("base"));
+        assert!(config.calendars.contains_key("derived"));
+
+        let derived = config.calendars.get("derived").unwrap();
+        assert_eq!(derived.sources.len(), 1);
+
+        match &derived.sources[0] {
+            SourceConfig::Calendar {
+                calendar, steps, ..
+            } => {
+                assert_eq!(calendar, "base");
+                assert_eq!(steps.len(), 1);
+            }
+            _ => panic!("Expected Calendar source"),
         }
 
         fs::remove_file(config_path).unwrap();
     }
 
     #[test]
-    fn test_config_validation() {
-        let config = Config {
-            calendars: HashMap::new(),
-        };
-        assert!(config.validate().is_err());
+    fn test_config_toml_shared_sources() {
+        let config_toml = r#"
+[sources.team]
+url = "https://example.com/team.ics"
 
-        let mut calendars = HashMap::new();
-        calendars.insert(
-            "test".to_string(),
-            CalendarConfig {
-                sources: vec![],
-                steps: vec![],
-            },
-        );
-        let config = Config { calendars };
-        assert!(config.validate().is_err());
+[[calendars.all.sources]]
+source = "team"
 
-        let mut calendars = HashMap::new();
-        calendars.insert(
-            "test".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Url {
-                    url: "https://example.com/test.ics".to_string(),
-                    steps: vec![],
-                }],
-                steps: vec![],
-            },
-        );
-        let config = Config { calendars };
-        assert!(config.validate().is_ok());
-    }
+[[calendars.required.sources]]
+source = "team"
 
-    #[test]
-    fn test_replace_step_default_replacement() {
-        let config_json = r#"{
-            "calendars": {
-                "test": {
-                    "sources": [
-                        {
-                            "url": "https://example.com/test.ics",
-                            "steps": [
-                                {
-                                    "type": "replace",
-                                    "pattern": "🔔"
-                                }
-                            ]
-                        }
-                    ]
-                }
-            }
-        }"#;
+[[calendars.required.sources.steps]]
+type = "deny"
+patterns = ["(?i)optional"]
+"#;
 
         let temp_dir = std::env::temp_dir();
-        let config_path = temp_dir.join("test_replace_default.json");
-        fs::write(&config_path, config_json).unwrap();
+        let config_path = temp_dir.join("test_config_shared.toml");
+        fs::write(&config_path, config_toml).unwrap();
 
-        let config = Config::load(&config_path).unwrap();
-        let source = &config.calendars["test"].sources[0];
-        let steps = source.steps();
-        if let Step::Replace {
-            pattern,
-            replacement,
-            field,
-        } = &steps[0]
-        {
-            assert_eq!(pattern, "🔔");
-            assert_eq!(replacement, ""); // Default empty string
-            assert_eq!(field, "summary"); // Default field
-        } else {
-            panic!("Expected Replace step");
+        let mut config = Config::load(&config_path).unwrap();
+        config.validate().unwrap();
+        fs::remove_file(config_path).unwrap();
+
+        assert_eq!(config.sources["team"].url, "https://example.com/team.ics");
+        match &config.calendars["required"].sources[0] {
+            SourceConfig::Source { source, steps, .. } => {
+                assert_eq!(source, "team");
+                assert_eq!(steps.len(), 1);
+            }
+            _ => panic!("Expected shared source"),
         }
 
-        fs::remove_file(config_path).unwrap();
+        // References to undeclared sources are rejected
+        config.sources.clear();
+        assert!(config.validate().is_err());
     }
 
     #[test]
-    fn test_step_validation() {
-        let mut calendars = HashMap::new();
-        calendars.insert(
-            "test".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Url {
-                    url: "https://example.com/test.ics".to_string(),
-                    steps: vec![Step::Allow {
-                        patterns: vec!["(?i)meeting".to_string()],
-                        mode: MatchMode::Any,
-                        fields: vec!["summary".to_string()],
-                    }],
-                }],
-                steps: vec![],
-            },
-        );
-        let config = Config { calendars };
-        assert!(config.validate().is_ok());
+    fn test_source_basic_auth() {
+        let config_toml = r#"
+[sources.team]
+url = "https://example.com/team.ics"
+username = "calendar"
+password = "secret"
 
-        // Test invalid regex
-        let mut calendars = HashMap::new();
-        calendars.insert(
-            "test".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Url {
-                    url: "https://example.com/test.ics".to_string(),
-                    steps: vec![Step::Allow {
-                        patterns: vec!["[invalid".to_string()],
-                        mode: MatchMode::Any,
-                        fields: vec!["summary".to_string()],
-                    }],
-                }],
-                steps: vec![],
-            },
+[[calendars.all.sources]]
+source = "team"
+
+[[calendars.all.sources]]
+url = "https://example.com/private.ics"
+username = "alice"
+
+[[calendars.all.sources]]
+url = "https://api.example.com/calendar.ics"
+auth = { header = { name = "X-Api-Key", value = "k3y" } }
+"#;
+
+        let temp_dir = std::env::temp_dir();
+        let config_path = temp_dir.join("test_config_basic_auth.toml");
+        fs::write(&config_path, config_toml).unwrap();
+
+        let mut config = Config::load(&config_path).unwrap();
+        config.validate().unwrap();
+        fs::remove_file(config_path).unwrap();
+
+        assert_eq!(config.sources["team"].username.as_deref(), Some("calendar"));
+        assert_eq!(config.sources["team"].password.as_deref(), Some("secret"));
+
+        assert_eq!(
+            config.calendars["all"].sources[2].credentials().auth,
+            Some(&SourceAuth::Header {
+                name: "X-Api-Key".to_string(),
+                value: "k3y".to_string()
+            })
         );
-        let config = Config { calendars };
+
+        // Two ways of setting Authorization
+        let team = config.sources.get_mut("team").unwrap();
+        team.auth = Some(SourceAuth::Bearer("t0ken".to_string()));
         assert!(config.validate().is_err());
 
-        // Test empty patterns
-        let mut calendars = HashMap::new();
-        calendars.insert(
-            "test".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Url {
-                    url: "https://example.com/test.ics".to_string(),
-                    steps: vec![Step::Allow {
-                        patterns: vec![],
-                        mode: MatchMode::Any,
-                        fields: vec!["summary".to_string()],
-                    }],
-                }],
-                steps: vec![],
-            },
-        );
-        let config = Config { calendars };
+        let team = config.sources.get_mut("team").unwrap();
+        team.auth = Some(SourceAuth::Header {
+            name: "Bad Header".to_string(),
+            value: "k3y".to_string(),
+        });
         assert!(config.validate().is_err());
 
-        // Test invalid strip field
-        let mut calendars = HashMap::new();
-        calendars.insert(
-            "test".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Url {
-                    url: "https://example.com/test.ics".to_string(),
-                    steps: vec![Step::Strip {
-                        field: "invalid".to_string(),
-                    }],
-                }],
-                steps: vec![],
-            },
+        let oauth2 = |token_url: &str| SourceAuth::Oauth2 {
+            token_url: token_url.to_string(),
+            client_id: "ical-merge".to_string(),
+            client_secret: "s3cret".to_string(),
+            scopes: vec![],
+        };
+        let team = config.sources.get_mut("team").unwrap();
+        team.auth = Some(oauth2("https://login.example.com/token"));
+        assert!(config.validate().is_err());
+        let team = config.sources.get_mut("team").unwrap();
+        team.username = None;
+        team.password = None;
+        config.validate().unwrap();
+        let team = config.sources.get_mut("team").unwrap();
+        team.auth = Some(oauth2("login.example.com/token"));
+        assert!(config.validate().is_err());
+
+        // Sources can't be given no time at all
+        let team = config.sources.get_mut("team").unwrap();
+        team.auth = None;
+        team.timeout = Some(0);
+        assert!(config.validate().is_err());
+        let team = config.sources.get_mut("team").unwrap();
+        team.timeout = Some(120);
+        config.validate().unwrap();
+        assert_eq!(
+            config.sources["team"].timeout(),
+            Some(Duration::from_secs(120))
         );
-        let config = Config { calendars };
+
+        let team = config.sources.get_mut("team").unwrap();
+        team.client_cert = Some(PathBuf::from("tests/fixtures/tls/client.crt"));
+        team.client_key = Some(PathBuf::from("tests/fixtures/tls/client.key"));
+        config.validate().unwrap();
+        let team = config.sources.get_mut("team").unwrap();
+        team.client_key = Some(PathBuf::from("tests/fixtures/tls/missing.key"));
+        assert!(config.validate().is_err());
+        let team = config.sources.get_mut("team").unwrap();
+        team.client_cert = None;
+        assert!(config.validate().is_err());
+        let team = config.sources.get_mut("team").unwrap();
+        team.client_key = None;
+
+        // A password is no use without a username
+        let team = config.sources.get_mut("team").unwrap();
+        team.password = Some("secret".to_string());
         assert!(config.validate().is_err());
     }
 
     #[test]
-    fn test_calendar_reference_validation() {
-        // Valid calendar reference
-        let mut calendars = HashMap::new();
-        calendars.insert(
-            "base".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Url {
-                    url: "https://example.com/base.ics".to_string(),
-                    steps: vec![],
-                }],
-                steps: vec![],
-            },
-        );
-        calendars.insert(
-            "derived".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Calendar {
-                    calendar: "base".to_string(),
-                    steps: vec![],
-                }],
-                steps: vec![],
-            },
+    fn test_calendar_source_timeout() {
+        let config_toml = r#"
+[[calendars.all.sources]]
+url = "https://example.com/team.ics"
+timeout = 0
+"#;
+        let temp_dir = std::env::temp_dir();
+        let config_path = temp_dir.join("test_config_source_timeout.toml");
+        fs::write(&config_path, config_toml).unwrap();
+        let mut config = Config::load(&config_path).unwrap();
+        fs::remove_file(config_path).unwrap();
+
+        // Sources listed by a calendar can't be given no time at all either
+        let err = config.validate().unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("timeout must be at least 1 second")
         );
-        let config = Config { calendars };
-        assert!(config.validate().is_ok());
 
-        // Unknown calendar reference
-        let mut calendars = HashMap::new();
-        calendars.insert(
-            "derived".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Calendar {
-                    calendar: "nonexistent".to_string(),
-                    steps: vec![],
-                }],
-                steps: vec![],
-            },
+        if let SourceConfig::Url { timeout, .. } =
+            &mut config.calendars.get_mut("all").unwrap().sources[0]
+        {
+            *timeout = Some(90);
+        }
+        config.validate().unwrap();
+        assert_eq!(
+            config.calendars["all"].sources[0].timeout(),
+            Some(Duration::from_secs(90))
         );
-        let config = Config { calendars };
-        assert!(config.validate().is_err());
     }
 
     #[test]
-    fn test_cycle_detection_direct() {
-        // Direct self-reference A→A
-        let mut calendars = HashMap::new();
-        calendars.insert(
-            "a".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Calendar {
-                    calendar: "a".to_string(),
-                    steps: vec![],
-                }],
-                steps: vec![],
+    fn test_source_auth_debug_redacted() {
+        let auths = [
+            SourceAuth::Bearer("t0ken".to_string()),
+            SourceAuth::Header {
+                name: "X-Api-Key".to_string(),
+                value: "k3y".to_string(),
             },
-        );
-        let config = Config { calendars };
-        assert!(config.validate().is_err());
+            SourceAuth::Oauth2 {
+                token_url: "https://login.example.com/token".to_string(),
+                client_id: "ical-merge".to_string(),
+                client_secret: "s3cret".to_string(),
+                scopes: vec![],
+            },
+            SourceAuth::S3 {
+                access_key_id: "AKIDEXAMPLE".to_string(),
+                secret_access_key: "wJalrXUtnFEMI".to_string(),
+                session_token: Some("FwoGZX".to_string()),
+                region: None,
+                endpoint: None,
+            },
+        ];
+        for auth in auths {
+            let debug = format!("{:?}", auth);
+            for secret in ["t0ken", "k3y", "s3cret", "wJalrXUtnFEMI", "FwoGZX"] {
+                assert!(!debug.contains(secret), "{} leaks {}", debug, secret);
+            }
+            assert!(debug.contains("<redacted>"));
+        }
     }
 
     #[test]
-    fn test_cycle_detection_indirect() {
-        // Indirect cycle A→B→A
-        let mut calendars = HashMap::new();
-        calendars.insert(
-            "a".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Calendar {
-                    calendar: "b".to_string(),
-                    steps: vec![],
-                }],
-                steps: vec![],
-            },
-        );
-        calendars.insert(
-            "b".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Calendar {
-                    calendar: "a".to_string(),
-                    steps: vec![],
-                }],
-                steps: vec![],
-            },
-        );
-        let config = Config { calendars };
+    fn test_s3_source() {
+        let config_toml = r#"
+[[calendars.team.sources]]
+url = "s3://exports/team/rota.ics"
+auth = { s3 = { access_key_id = "AKIA", secret_access_key = "s3cret", region = "eu-west-2" } }
+"#;
+        let mut config: Config = toml::from_str(config_toml).unwrap();
+        config.validate().unwrap();
+
+        let set_url = |config: &mut Config, url: &str| {
+            if let SourceConfig::Url { url: source, .. } =
+                &mut config.calendars.get_mut("team").unwrap().sources[0]
+            {
+                *source = url.to_string();
+            }
+        };
+        let set_auth = |config: &mut Config, auth: Option<SourceAuth>| {
+            if let SourceConfig::Url { auth: source, .. } =
+                &mut config.calendars.get_mut("team").unwrap().sources[0]
+            {
+                *source = auth;
+            }
+        };
+
+        // Every S3 URL names a bucket and a key
+        set_url(&mut config, "s3://exports");
+        assert!(config.validate().is_err());
+        set_url(&mut config, "s3://exports/team/rota.ics");
+
+        let s3 = |endpoint: &str| SourceAuth::S3 {
+            access_key_id: "GOOG".to_string(),
+            secret_access_key: "s3cret".to_string(),
+            session_token: None,
+            region: Some("auto".to_string()),
+            endpoint: Some(endpoint.to_string()),
+        };
+        set_auth(&mut config, Some(s3("https://storage.googleapis.com")));
+        config.validate().unwrap();
+        set_auth(&mut config, Some(s3("storage.googleapis.com")));
+        assert!(config.validate().is_err());
+
+        // Without S3 credentials there's no telling where the bucket is
+        set_auth(&mut config, Some(SourceAuth::Bearer("t0ken".to_string())));
+        assert!(config.validate().is_err());
+        set_auth(&mut config, None);
         assert!(config.validate().is_err());
     }
 
     #[test]
-    fn test_diamond_dependency() {
-        // Diamond dependency A→B, A→C, B→D, C→D (valid)
-        let mut calendars = HashMap::new();
-        calendars.insert(
-            "d".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Url {
-                    url: "https://example.com/d.ics".to_string(),
-                    steps: vec![],
-                }],
-                steps: vec![],
-            },
-        );
-        calendars.insert(
-            "b".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Calendar {
-                    calendar: "d".to_string(),
-                    steps: vec![],
-                }],
-                steps: vec![],
-            },
-        );
-        calendars.insert(
-            "c".to_string(),
-            CalendarConfig {
-                sources: vec![SourceConfig::Calendar {
-                    calendar: "d".to_string(),
-                    steps: vec![],
-                }],
-                steps: vec![],
-            },
+    fn test_convert_config() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        for example in ["config.example.toml", "config.example.json"] {
+            let config = Config::load(example).unwrap();
+
+            for (name, text) in [
+                ("converted.toml", config.to_toml().unwrap()),
+                ("converted.json", config.to_json().unwrap()),
+                ("converted.yaml", config.to_yaml().unwrap()),
+            ] {
+                let path = temp_dir.path().join(name);
+                fs::write(&path, text).unwrap();
+                let converted = Config::load(&path).unwrap();
+                converted.validate().unwrap();
+                assert_eq!(converted.to_value().unwrap(), config.to_value().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_legacy_filters_and_modifiers() {
+        let legacy = r#"{
+            "calendars": {
+                "work": {
+                    "sources": [
+                        {
+                            "url": "https://example.com/work.ics",
+                            "filters": { "allow": ["(?i)meeting"], "deny": ["Optional"] },
+                            "modifiers": [{ "type": "replace", "pattern": "^Meeting: ", "replacement": "" }],
+                            "steps": [{ "type": "strip", "field": "reminder" }]
+                        },
+                        { "url": "https://example.com/home.ics" }
+                    ],
+                    "filters": { "deny": ["Lunch"] }
+                }
+            }
+        }"#;
+
+        let mut value: serde_json::Value = serde_json::from_str(legacy).unwrap();
+        let warnings = migrate_legacy(&mut value);
+        assert_eq!(warnings.len(), 2);
+        assert!(
+            warnings.contains(
+                &"Calendar 'work' uses the deprecated filters and modifiers, replace them with \
+              \"steps\": [{\"patterns\":[\"Lunch\"],\"type\":\"deny\"}]"
+                    .to_string()
+            )
         );
-        calendars.insert(
-            "a".to_string(),
-            CalendarConfig {
-                sources: vec![
-                    SourceConfig::Calendar {
-                        calendar: "b".to_string(),
-                        steps: vec![],
-                    },
-                    SourceConfig::Calendar {
-                        calendar: "c".to_string(),
-                        steps: vec![],
-                    },
-                ],
-                steps: vec![],
-            },
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("legacy.json");
+        fs::write(&path, legacy).unwrap();
+        let config = Config::load(&path).unwrap();
+        config.validate().unwrap();
+
+        let work = &config.calendars["work"];
+        assert!(matches!(work.steps[..], [Step::Deny { .. }]));
+        assert!(matches!(
+            work.sources[0].steps(),
+            [
+                Step::Allow { .. },
+                Step::Deny { .. },
+                Step::Replace { .. },
+                Step::Strip { .. }
+            ]
+        ));
+        assert!(work.sources[1].steps().is_empty());
+    }
+
+    #[test]
+    fn test_config_version() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let load = |config: &str| {
+            let path = temp_dir.path().join("config.toml");
+            fs::write(&path, config).unwrap();
+            Config::load(&path)
+        };
+        let calendars = r#"
+[[calendars.work.sources]]
+url = "https://example.com/work.ics"
+filters = { deny = ["Lunch"] }
+"#;
+
+        // Unversioned configs are version 1 and get upgraded
+        let config = load(calendars).unwrap();
+        assert_eq!(config.version, Some(CONFIG_VERSION));
+        assert_eq!(config.calendars["work"].sources[0].steps().len(), 1);
+
+        let config = load(&format!("version = 1\n{}", calendars)).unwrap();
+        assert_eq!(config.version, Some(CONFIG_VERSION));
+
+        let current =
+            "version = 2\n[[calendars.work.sources]]\nurl = \"https://example.com/work.ics\"\n";
+        assert_eq!(load(current).unwrap().version, Some(2));
+
+        let err = load(&format!("version = {}\n{}", CONFIG_VERSION + 1, calendars)).unwrap_err();
+        assert!(err.to_string().contains("upgrade ical-merge"), "{}", err);
+        assert!(load(&format!("version = 0\n{}", calendars)).is_err());
+    }
+
+    #[test]
+    fn test_server_path_prefix() {
+        let server = |prefix: Option<&str>| ServerConfig {
+            path_prefix: prefix.map(str::to_string),
+            ..Default::default()
+        };
+        assert_eq!(server(None).path_prefix(), "");
+        assert_eq!(server(Some("/")).path_prefix(), "");
+        assert_eq!(server(Some("calendars/")).path_prefix(), "/calendars");
+        assert_eq!(server(Some("/a/b")).path_prefix(), "/a/b");
+
+        // The old name for base_url is still accepted
+        let server: ServerConfig =
+            serde_json::from_str(r#"{ "public_url": "https://example.com" }"#).unwrap();
+        assert_eq!(server.base_url.as_deref(), Some("https://example.com"));
+    }
+
+    #[test]
+    fn test_warmup_config() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "server": { "cache_ttl": 300, "warmup": ["work"] },
+                "calendars": {
+                    "work": { "sources": [{ "url": "https://example.com/work.ics" }] },
+                    "home": { "sources": [{ "url": "https://example.com/home.ics" }] }
+                }
+            }"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.warmup_calendars(), vec!["work"]);
+
+        let mut all = config.clone();
+        all.server.warmup = WarmupConfig::All(true);
+        assert_eq!(all.warmup_calendars(), vec!["home", "work"]);
+
+        // Warming up is pointless without a cache to keep the result
+        let mut uncached = config.clone();
+        uncached.server.cache_ttl = None;
+        assert!(uncached.validate().is_err());
+
+        let mut unknown = config;
+        unknown.server.warmup = WarmupConfig::Calendars(vec!["missing".to_string()]);
+        assert!(unknown.validate().is_err());
+    }
+
+    #[test]
+    fn test_cache_limits_validation() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "server": { "cache_ttl": 300, "cache_max_bytes": 67108864, "cache_max_events": 50000 },
+                "calendars": {
+                    "work": { "sources": [{ "url": "https://example.com/work.ics" }] }
+                }
+            }"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(
+            config.server.cache_limits(),
+            CacheLimits {
+                max_bytes: Some(64 * 1024 * 1024),
+                max_events: Some(50000),
+            }
         );
-        let config = Config { calendars };
-        assert!(config.validate().is_ok());
+
+        config.server.cache_max_events = Some(0);
+        assert!(config.validate().is_err());
+
+        // There's nothing to limit without a cache
+        config.server.cache_max_events = None;
+        config.server.cache_ttl = None;
+        assert!(config.validate().is_err());
     }
 
     #[test]
-    fn test_config_parsing_toml() {
-        let config_toml = r#"
-[calendars.test]
+    fn test_log_level_validation() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "server": { "log_level": "ical_merge=info,ical_merge::fetcher=debug" },
+                "calendars": {
+                    "work": { "sources": [{ "url": "https://example.com/work.ics" }] }
+                }
+            }"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
 
-[[calendars.test.sources]]
-url = "https://example.com/test.ics"
-steps = []
+        config.server.log_level = Some("ical_merge=nonsense".to_string());
+        assert!(config.validate().is_err());
 
-calendars.test.steps = []
-"#;
+        config.server.log_level = None;
+        config.server.admin_token = Some(String::new());
+        assert!(config.validate().is_err());
+    }
 
-        let temp_dir = std::env::temp_dir();
-        let config_path = temp_dir.join("test_config.toml");
-        fs::write(&config_path, config_toml).unwrap();
+    #[test]
+    fn test_calendar_aliases() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "calendars": {
+                    "family": {
+                        "sources": [{ "url": "https://example.com/family.ics" }],
+                        "aliases": ["famille", "x7kq2p"]
+                    },
+                    "work": { "sources": [{ "url": "https://example.com/work.ics" }] }
+                }
+            }"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.resolve_calendar("family"), Some("family"));
+        assert_eq!(config.resolve_calendar("famille"), Some("family"));
+        assert_eq!(config.resolve_calendar("x7kq2p"), Some("family"));
+        assert_eq!(config.resolve_calendar("missing"), None);
 
-        let config = Config::load(&config_path).unwrap();
-        assert_eq!(config.calendars.len(), 1);
-        assert!(config.calendars.contains_key("test"));
+        // Aliases must not shadow calendars or each other
+        let mut shadowing = config.clone();
+        shadowing
+            .calendars
+            .get_mut("family")
+            .unwrap()
+            .aliases
+            .push("work".to_string());
+        assert!(shadowing.validate().is_err());
 
-        fs::remove_file(config_path).unwrap();
+        let mut duplicate = config.clone();
+        duplicate
+            .calendars
+            .get_mut("work")
+            .unwrap()
+            .aliases
+            .push("famille".to_string());
+        assert!(duplicate.validate().is_err());
+
+        let mut invalid = config;
+        invalid
+            .calendars
+            .get_mut("work")
+            .unwrap()
+            .aliases
+            .push("a/b".to_string());
+        assert!(invalid.validate().is_err());
     }
 
     #[test]
-    fn test_config_toml_with_steps() {
-        let config_toml = r#"
-[calendars.test]
+    fn test_output_timezone_validation() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "calendars": {
+                    "family": {
+                        "sources": [{ "url": "https://example.com/family.ics" }],
+                        "output_timezone": "Not/A_Zone"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
 
-[[calendars.test.sources]]
-url = "https://example.com/test.ics"
+        let calendar = config.calendars.get_mut("family").unwrap();
+        calendar.output_timezone = Some("../../etc/passwd".to_string());
+        assert!(config.validate().is_err());
+    }
 
-[[calendars.test.sources.steps]]
-type = "allow"
-patterns = ["(?i)meeting"]
-mode = "any"
-fields = ["summary"]
+    #[test]
+    fn test_command_source() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "calendars": {
+                    "school": {
+                        "sources": [{
+                            "command": "/usr/local/bin/school-portal",
+                            "args": ["--term", "autumn"],
+                            "timeout": 60,
+                            "label": "School"
+                        }]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
 
-[[calendars.test.sources.steps]]
-type = "replace"
-pattern = "^"
-replacement = "[TEST] "
-field = "summary"
+        let source = &config.calendars["school"].sources[0];
+        assert!(matches!(source, SourceConfig::Command { .. }));
+        assert_eq!(source.timeout(), Some(Duration::from_secs(60)));
+        assert_eq!(
+            source.origin(),
+            "command:/usr/local/bin/school-portal --term autumn"
+        );
 
-[[calendars.test.sources.steps]]
-type = "case"
-transform = "title"
+        let calendar = config.calendars.get_mut("school").unwrap();
+        if let SourceConfig::Command { timeout, .. } = &mut calendar.sources[0] {
+            *timeout = Some(0);
+        }
+        assert!(config.validate().is_err());
 
-calendars.test.steps = []
-"#;
+        let calendar = config.calendars.get_mut("school").unwrap();
+        if let SourceConfig::Command {
+            command, timeout, ..
+        } = &mut calendar.sources[0]
+        {
+            *timeout = None;
+            command.clear();
+        }
+        assert!(config.validate().is_err());
+    }
 
-        let temp_dir = std::env::temp_dir();
-        let config_path = temp_dir.join("test_config_steps.toml");
-        fs::write(&config_path, config_toml).unwrap();
+    #[test]
+    fn test_generate_source() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "calendars": {
+                    "reminders": {
+                        "sources": [
+                            { "generate": [{ "summary": "Gym", "start": "2025-01-06T07:00", "duration": "PT1H" }] },
+                            { "events": [{ "summary": "Standup", "start": "2025-01-06T09:30", "end": "2025-01-06T09:45", "rrule": "FREQ=DAILY" }] }
+                        ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
 
-        let config = Config::load(&config_path).unwrap();
-        assert_eq!(config.calendars.len(), 1);
+        let sources = &config.calendars["reminders"].sources;
+        let SourceConfig::Generate { generate, .. } = &sources[1] else {
+            panic!("events should be read as a generate source");
+        };
+        assert_eq!(generate[0].end.as_deref(), Some("2025-01-06T09:45"));
+    }
 
-        let calendar = config.calendars.get("test").unwrap();
-        assert_eq!(calendar.sources.len(), 1);
+    #[test]
+    fn test_inline_source() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "calendars": {
+                    "work": {
+                        "sources": [{
+                            "ics": "BEGIN:VCALENDAR\nVERSION:2.0\nBEGIN:VEVENT\nUID:closed@example.com\nDTSTART;VALUE=DATE:20251225\nSUMMARY:Office closed\nEND:VEVENT\nEND:VCALENDAR\n",
+                            "label": "Closures"
+                        }]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
 
-        let steps = calendar.sources[0].steps();
-        assert_eq!(steps.len(), 3);
+        let source = &config.calendars["work"].sources[0];
+        assert!(matches!(source, SourceConfig::Inline { .. }));
+        assert_eq!(source.identifier(), "Closures (inline)");
 
-        // Verify allow step
-        match &steps[0] {
-            Step::Allow {
-                patterns,
-                mode,
-                fields,
-            } => {
-                assert_eq!(patterns.len(), 1);
-                assert_eq!(patterns[0], "(?i)meeting");
-                assert!(matches!(mode, MatchMode::Any));
-                assert_eq!(fields.len(), 1);
-                assert_eq!(fields[0], "summary");
-            }
-            _ => panic!("Expected Allow step"),
+        let calendar = config.calendars.get_mut("work").unwrap();
+        if let SourceConfig::Inline { ics, .. } = &mut calendar.sources[0] {
+            *ics = "BEGIN:VCALENDAR\nBEGIN:VEVENT".to_string();
         }
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("invalid inline iCal"));
+    }
 
-        // Verify replace step
-        match &steps[1] {
-            Step::Replace {
-                pattern,
-                replacement,
-                field,
-            } => {
-                assert_eq!(pattern, "^");
-                assert_eq!(replacement, "[TEST] ");
-                assert_eq!(field, "summary");
-            }
-            _ => panic!("Expected Replace step"),
+    #[test]
+    fn test_json_source() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "calendars": {
+                    "gigs": {
+                        "sources": [{
+                            "json": "https://api.example.com/v1/events?city=leeds",
+                            "items": "/data",
+                            "fields": {
+                                "summary": "/title",
+                                "start": "/dates/start",
+                                "end": "/dates/end",
+                                "location": "/venue/name"
+                            },
+                            "timezone": "Europe/London",
+                            "auth": { "header": { "name": "X-Api-Key", "value": "key" } }
+                        }]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        let source = &config.calendars["gigs"].sources[0];
+        assert!(matches!(source, SourceConfig::Json { .. }));
+        assert_eq!(
+            source.origin(),
+            "json:https://api.example.com/v1/events?city=leeds"
+        );
+        assert!(source.credentials().auth.is_some());
+
+        let calendar = config.calendars.get_mut("gigs").unwrap();
+        if let SourceConfig::Json { fields, .. } = &mut calendar.sources[0] {
+            fields.location = Some("venue.name".to_string());
         }
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("Invalid location pointer"));
 
-        // Verify case step
-        match &steps[2] {
-            Step::Case { transform, field } => {
-                assert!(matches!(transform, CaseTransform::Title));
-                assert_eq!(field, "summary");
-            }
-            _ => panic!("Expected Case step"),
+        let calendar = config.calendars.get_mut("gigs").unwrap();
+        if let SourceConfig::Json {
+            fields, timezone, ..
+        } = &mut calendar.sources[0]
+        {
+            fields.location = None;
+            *timezone = Some("Mars/Olympus_Mons".to_string());
         }
+        assert!(config.validate().is_err());
+    }
 
-        fs::remove_file(config_path).unwrap();
+    #[test]
+    fn test_config_include() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        fs::write(
+            &config_path,
+            r#"{
+                "include": ["teams/platform.toml", "teams/web.json"],
+                "sources": { "feed": { "url": "https://example.com/feed.ics" } },
+                "calendars": {
+                    "all": { "sources": [{ "url": "https://example.com/all.ics" }] }
+                }
+            }"#,
+        )
+        .unwrap();
+        fs::create_dir(dir.path().join("teams")).unwrap();
+        fs::write(
+            dir.path().join("teams/platform.toml"),
+            r#"
+[[calendars.platform.sources]]
+url = "https://example.com/platform.ics"
+"#,
+        )
+        .unwrap();
+        let web = dir.path().join("teams/web.json");
+        fs::write(
+            &web,
+            r#"{ "calendars": { "web": { "sources": [{ "url": "https://example.com/web.ics" }] } } }"#,
+        )
+        .unwrap();
+
+        let config = Config::load(&config_path).unwrap();
+        config.validate().unwrap();
+        let mut ids: Vec<_> = config.calendars.keys().cloned().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["all", "platform", "web"]);
+        assert!(config.sources.contains_key("feed"));
+        assert_eq!(
+            Config::included_files(&config_path).unwrap(),
+            vec![
+                dir.path().join("teams/platform.toml"),
+                dir.path().join("teams/web.json")
+            ]
+        );
+        // Writing the config out flattens the includes into it
+        assert!(!config.to_json().unwrap().contains("include"));
+
+        // Ids already taken are reported with both files
+        fs::write(
+            &web,
+            r#"{ "calendars": { "platform": { "sources": [{ "url": "https://example.com/web.ics" }] } } }"#,
+        )
+        .unwrap();
+        let err = Config::load(&config_path).unwrap_err().to_string();
+        assert!(err.contains("Calendar 'platform'"));
+        assert!(err.contains("web.json is already defined in"));
+        assert!(err.contains("platform.toml"));
+
+        fs::write(
+            &web,
+            r#"{ "sources": { "feed": { "url": "https://example.com/other.ics" } } }"#,
+        )
+        .unwrap();
+        let err = Config::load(&config_path).unwrap_err().to_string();
+        assert!(err.contains("Source 'feed'"));
+        assert!(err.contains("config.json"));
+
+        // Unless the later one says it replaces it
+        fs::write(
+            &web,
+            r#"{ "calendars": { "all": { "override": true, "sources": [{ "url": "https://example.com/web.ics" }] } } }"#,
+        )
+        .unwrap();
+        let config = Config::load(&config_path).unwrap();
+        assert_eq!(
+            config.calendars["all"].sources[0].urls(),
+            ["https://example.com/web.ics"]
+        );
+
+        // Included files only add calendars and sources
+        fs::write(&web, r#"{ "server": { "port": 9000 } }"#).unwrap();
+        let err = Config::load(&config_path).unwrap_err().to_string();
+        assert!(err.contains("can only have calendars and sources"));
+
+        fs::remove_file(&web).unwrap();
+        assert!(Config::load(&config_path).is_err());
     }
 
     #[test]
-    fn test_config_toml_calendar_reference() {
-        let config_toml = r#"
-[calendars.base]
+    fn test_fetcher_cache_validation() {
+        let fetcher = |fetcher: serde_json::Value| {
+            let config: Config = serde_json::from_value(serde_json::json!({
+                "fetcher": fetcher,
+                "calendars": {
+                    "team": { "sources": [{ "url": "https://example.com/team.ics" }] }
+                }
+            }))
+            .unwrap();
+            config.validate()
+        };
 
-[[calendars.base.sources]]
-url = "https://example.com/base.ics"
-steps = []
+        assert!(fetcher(serde_json::json!({ "cache_dir": "/var/cache/ical-merge" })).is_ok());
+        assert!(
+            fetcher(serde_json::json!({
+                "cache_dir": "/var/cache/ical-merge",
+                "cache_ttl": 600,
+                "cache_max_stale": 0
+            }))
+            .is_ok()
+        );
+        assert!(fetcher(serde_json::json!({ "cache_ttl": 600 })).is_err());
+        assert!(fetcher(serde_json::json!({ "cache_dir": "" })).is_err());
+    }
 
-calendars.base.steps = []
+    #[test]
+    fn test_max_stale_validation() {
+        let config = |server: serde_json::Value| {
+            let config: Config = serde_json::from_value(serde_json::json!({
+                "server": server,
+                "calendars": {
+                    "team": {
+                        "sources": [{ "url": "https://example.com/team.ics" }],
+                        "max_stale": 86400
+                    }
+                }
+            }))
+            .unwrap();
+            config.validate()
+        };
 
-[calendars.derived]
+        assert!(config(serde_json::json!({ "cache_ttl": 300 })).is_ok());
+        // Without cached merges there's nothing old to refuse to serve
+        let err = config(serde_json::json!({})).unwrap_err().to_string();
+        assert!(err.contains("max_stale needs server cache_ttl"));
+    }
 
-[[calendars.derived.sources]]
-calendar = "base"
+    #[test]
+    fn test_tenants() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "server": {
+                    "path_prefix": "/calendars",
+                    "tenants": { "smiths": { "config": "tenants/smiths.toml", "token": "s3cret" } }
+                }
+            }"#,
+        )
+        .unwrap();
+        // Serving only tenants needs no calendars of its own
+        config.validate().unwrap();
 
-[[calendars.derived.sources.steps]]
-type = "replace"
-pattern = "^"
-replacement = "[DERIVED] "
-field = "summary"
+        fs::create_dir(dir.path().join("tenants")).unwrap();
+        let tenant_path = dir.path().join("tenants/smiths.toml");
+        assert_eq!(
+            config.server.tenants["smiths"].path(&config_path),
+            tenant_path
+        );
+        fs::write(
+            &tenant_path,
+            "[[calendars.family.sources]]\nurl = \"https://example.com/family.ics\"\n",
+        )
+        .unwrap();
+        let tenant = config.load_tenant(&config_path, "smiths").unwrap();
+        assert!(tenant.calendars.contains_key("family"));
+        assert_eq!(tenant.server.path_prefix(), "/calendars/t/smiths");
+        assert!(config.load_tenant(&config_path, "jones").is_err());
 
-calendars.derived.steps = []
-"#;
+        // Settings for the whole server stay in the main config
+        fs::write(
+            &tenant_path,
+            "server.log_level = \"debug\"\n[[calendars.family.sources]]\nurl = \"https://example.com/family.ics\"\n",
+        )
+        .unwrap();
+        let err = config.load_tenant(&config_path, "smiths").unwrap_err();
+        assert!(err.to_string().contains("can't set server log_level"));
 
-        let temp_dir = std::env::temp_dir();
-        let config_path = temp_dir.join("test_config_ref.toml");
-        fs::write(&config_path, config_toml).unwrap();
+        // As do settings reaching the host's programs and files
+        fs::write(dir.path().join("tenants/more.toml"), "").unwrap();
+        for (tenant_toml, refused) in [
+            (
+                "server.audit_log.path = \"/tmp/audit.log\"\n",
+                "can't set server audit_log",
+            ),
+            (
+                "fetcher.cache_dir = \"/var/cache\"\n",
+                "can't set fetcher cache_dir",
+            ),
+            (
+                "fetcher.git_dir = \"/srv/git\"\n",
+                "can't set fetcher git_dir",
+            ),
+            ("include = [\"more.toml\"]\n", "can't set include"),
+            (
+                "[[calendars.family.sources]]\ncommand = \"cat\"\n",
+                "can't use command calendar 'family' source 0",
+            ),
+            (
+                "[[calendars.family.sources]]\ngit = \"https://example.com/rotas.git\"\nfiles = [\"rota.ics\"]\n",
+                "can't use git calendar 'family' source 0",
+            ),
+            (
+                "[[calendars.family.sources]]\nurl = \"file:///etc/ical-merge/config.json\"\n",
+                "can't use a file URL on calendar 'family' source 0",
+            ),
+            (
+                "[[calendars.family.sources]]\nurls = [\"https://example.com/a.ics\", \"file:///etc/passwd\"]\n",
+                "can't use a file URL on calendar 'family' source 0",
+            ),
+            (
+                "[[calendars.family.sources]]\nurl = \"https://example.com/family.ics\"\ninsecure_tls = true\n",
+                "can't use insecure_tls on calendar 'family' source 0",
+            ),
+            (
+                "[[calendars.family.sources]]\nurl = \"https://example.com/family.ics\"\nclient_cert = \"tests/fixtures/tls/client.crt\"\nclient_key = \"tests/fixtures/tls/client.key\"\n",
+                "can't use client_cert on calendar 'family' source 0",
+            ),
+            (
+                "[sources.shared]\nurl = \"file:///etc/passwd\"\n[[calendars.family.sources]]\nsource = \"shared\"\n",
+                "can't use a file URL on source 'shared'",
+            ),
+            (
+                "[sources.shared]\nurl = \"https://example.com/family.ics\"\ninsecure_tls = true\n[[calendars.family.sources]]\nsource = \"shared\"\n",
+                "can't use insecure_tls on source 'shared'",
+            ),
+        ] {
+            let tenant_toml = if tenant_toml.contains("calendars.family") {
+                tenant_toml.to_string()
+            } else {
+                format!(
+                    "{}[[calendars.family.sources]]\nurl = \"https://example.com/family.ics\"\n",
+                    tenant_toml
+                )
+            };
+            fs::write(&tenant_path, tenant_toml).unwrap();
+            let err = config.load_tenant(&config_path, "smiths").unwrap_err();
+            assert!(err.to_string().contains(refused), "{}", err);
+        }
 
-        let config = Config::load(&config_path).unwrap();
+        let tenant = config.server.tenants.remove("smiths").unwrap();
+        config
+            .server
+            .tenants
+            .insert("the smiths".to_string(), tenant.clone());
+        assert!(config.validate().is_err());
+        config.server.tenants.clear();
+        config.server.tenants.insert(
+            "smiths".to_string(),
+            TenantConfig {
+                token: Some(String::new()),
+                ..tenant
+            },
+        );
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_git_source() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "calendars": {
+                    "rotas": {
+                        "sources": [{
+                            "git": "https://github.com/example/rotas.git",
+                            "files": ["platform.ics", "holidays/"],
+                            "branch": "main",
+                            "interval": 600
+                        }]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
         config.validate().unwrap();
 
-        assert_eq!(config.calendars.len(), 2);
-        assert!(config.calendars.contains_key("base"));
-        assert!(config.calendars.contains_key("derived"));
+        let source = &config.calendars["rotas"].sources[0];
+        assert!(matches!(source, SourceConfig::Git { .. }));
+        assert_eq!(source.origin(), "git:https://github.com/example/rotas.git");
+        assert!(source.urls().is_empty());
+        assert_eq!(
+            config.fetcher.git_dir(),
+            std::env::temp_dir().join("ical-merge-git")
+        );
 
-        let derived = config.calendars.get("derived").unwrap();
-        assert_eq!(derived.sources.len(), 1);
+        let calendar = config.calendars.get_mut("rotas").unwrap();
+        if let SourceConfig::Git { files, .. } = &mut calendar.sources[0] {
+            *files = vec!["../secrets.ics".to_string()];
+        }
+        let err = config.validate().unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("isn't a path inside the repository")
+        );
 
-        match &derived.sources[0] {
-            SourceConfig::Calendar { calendar, steps } => {
-                assert_eq!(calendar, "base");
-                assert_eq!(steps.len(), 1);
-            }
-            _ => panic!("Expected Calendar source"),
+        let calendar = config.calendars.get_mut("rotas").unwrap();
+        if let SourceConfig::Git { files, branch, .. } = &mut calendar.sources[0] {
+            *files = vec!["platform.ics".to_string()];
+            *branch = Some("--upload-pack=touch".to_string());
         }
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("invalid branch"));
 
-        fs::remove_file(config_path).unwrap();
+        let calendar = config.calendars.get_mut("rotas").unwrap();
+        if let SourceConfig::Git { git, branch, .. } = &mut calendar.sources[0] {
+            *branch = None;
+            *git = "--upload-pack=touch".to_string();
+        }
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_birthdays_source() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "calendars": {
+                    "family": {
+                        "sources": [{
+                            "birthdays": "https://dav.example.com/addressbooks/me/contacts/",
+                            "username": "me",
+                            "password": "secret",
+                            "timeout": 20
+                        }]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        let source = &config.calendars["family"].sources[0];
+        assert!(matches!(source, SourceConfig::Birthdays { .. }));
+        assert_eq!(
+            source.origin(),
+            "birthdays:https://dav.example.com/addressbooks/me/contacts/"
+        );
+        assert_eq!(source.credentials().basic, Some(("me", Some("secret"))));
+        assert_eq!(source.timeout(), Some(Duration::from_secs(20)));
+
+        let calendar = config.calendars.get_mut("family").unwrap();
+        if let SourceConfig::Birthdays { username, .. } = &mut calendar.sources[0] {
+            *username = None;
+        }
+        assert!(config.validate().is_err());
+
+        // Signed S3 requests can download a vCard file but not query an address book
+        let calendar = config.calendars.get_mut("family").unwrap();
+        calendar.sources[0] = serde_json::from_str(
+            r#"{ "birthdays": "s3://family/contacts.vcf", "auth": { "s3": { "access_key_id": "key", "secret_access_key": "secret" } } }"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        let calendar = config.calendars.get_mut("family").unwrap();
+        if let SourceConfig::Birthdays { birthdays, .. } = &mut calendar.sources[0] {
+            *birthdays = "https://dav.example.com/contacts/".to_string();
+        }
+        assert!(config.validate().is_err());
     }
 
     #[test]
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use icalendar::{CalendarDateTime, DatePerhapsTime};
+use serde::Serialize;
+
+use crate::ical::Event;
+use crate::merge::SOURCE_LABEL_PROPERTY;
+use crate::query::EventView;
+use crate::timezone::Zone;
+
+/// Prefix added to the summary of events that overlap another
+pub const CONFLICT_PREFIX: &str = "⚠ ";
+
+/// Two events taking place at the same time
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    pub events: [EventView; 2],
+    /// Labels of the sources the events came from, where known
+    pub sources: [Option<String>; 2],
+    /// When the overlap starts and ends, in UTC for zoned times
+    pub start: String,
+    pub end: String,
+}
+
+/// A timed event's span, compared in UTC where the event says what zone it is in
+struct Span {
+    index: usize,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+}
+
+/// Convert to UTC when the zone is known, otherwise take the time as it is
+fn instant(value: &DatePerhapsTime, zones: &mut HashMap<String, Option<Zone>>) -> NaiveDateTime {
+    match value {
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(utc)) => utc.naive_utc(),
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, tzid }) => {
+            match zones
+                .entry(tzid.clone())
+                .or_insert_with(|| Zone::load(tzid).ok())
+            {
+                Some(zone) => zone.to_utc(*date_time),
+                None => *date_time,
+            }
+        }
+        DatePerhapsTime::DateTime(CalendarDateTime::Floating(date_time)) => *date_time,
+        DatePerhapsTime::Date(date) => date.and_hms_opt(0, 0, 0).unwrap(),
+    }
+}
+
+/// Whether an event blocks out its time, so all-day, free and cancelled events don't
+fn is_busy(event: &Event) -> bool {
+    !event.is_all_day()
+        && !event
+            .property("TRANSP")
+            .is_some_and(|transp| transp.eq_ignore_ascii_case("TRANSPARENT"))
+        && !event
+            .property("STATUS")
+            .is_some_and(|status| status.eq_ignore_ascii_case("CANCELLED"))
+}
+
+/// Find pairs of busy events that overlap, as indices into `events` ordered by
+/// when the overlap starts
+fn overlapping(events: &[Event]) -> Vec<(usize, usize, NaiveDateTime, NaiveDateTime)> {
+    let mut zones = HashMap::new();
+    let mut spans: Vec<Span> = events
+        .iter()
+        .enumerate()
+        .filter(|(_, event)| is_busy(event))
+        .filter_map(|(index, event)| {
+            let start = instant(&event.start()?, &mut zones);
+            let end = match event.end() {
+                Some(end) => instant(&end, &mut zones),
+                None => start,
+            };
+            Some(Span { index, start, end })
+        })
+        .filter(|span| span.end > span.start)
+        .collect();
+    spans.sort_by_key(|span| (span.start, span.end));
+
+    let mut pairs = Vec::new();
+    for (i, first) in spans.iter().enumerate() {
+        for second in spans[i + 1..]
+            .iter()
+            .take_while(|second| second.start < first.end)
+        {
+            pairs.push((
+                first.index,
+                second.index,
+                second.start,
+                first.end.min(second.end),
+            ));
+        }
+    }
+    pairs.sort_by_key(|(_, _, start, _)| *start);
+    pairs
+}
+
+/// List every pair of overlapping events
+pub fn find_conflicts(events: &[Event]) -> Vec<Conflict> {
+    overlapping(events)
+        .into_iter()
+        .map(|(first, second, start, end)| {
+            let (first, second) = (&events[first], &events[second]);
+            let source = |event: &Event| event.property(SOURCE_LABEL_PROPERTY).map(str::to_string);
+            Conflict {
+                events: [EventView::from(first), EventView::from(second)],
+                sources: [source(first), source(second)],
+                start: start.format("%Y-%m-%dT%H:%M:%S").to_string(),
+                end: end.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Prefix the summary of every event overlapping another with a warning sign
+pub fn flag_conflicts(mut events: Vec<Event>) -> Vec<Event> {
+    let mut conflicting = vec![false; events.len()];
+    for (first, second, _, _) in overlapping(&events) {
+        conflicting[first] = true;
+        conflicting[second] = true;
+    }
+
+    for (event, conflicting) in events.iter_mut().zip(conflicting) {
+        let summary = event.summary().unwrap_or_default();
+        if conflicting && !summary.starts_with(CONFLICT_PREFIX) {
+            let summary = format!("{}{}", CONFLICT_PREFIX, summary);
+            event.set_summary(&summary);
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events() -> Vec<Event> {
+        let ical = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nUID:standup\r\nSUMMARY:Standup\r\n\
+            DTSTART:20250303T090000Z\r\nDTEND:20250303T093000Z\r\n\
+            X-ICAL-MERGE-SOURCE:Work\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:dentist\r\nSUMMARY:Dentist\r\n\
+            DTSTART:20250303T091500Z\r\nDTEND:20250303T100000Z\r\n\
+            X-ICAL-MERGE-SOURCE:Family\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:after\r\nSUMMARY:After\r\n\
+            DTSTART:20250303T100000Z\r\nDTEND:20250303T110000Z\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:free\r\nSUMMARY:Focus time\r\nTRANSP:TRANSPARENT\r\n\
+            DTSTART:20250303T090000Z\r\nDTEND:20250303T120000Z\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:holiday\r\nSUMMARY:Holiday\r\n\
+            DTSTART;VALUE=DATE:20250303\r\nEND:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        crate::ical::parse_calendar(ical).unwrap().into_events()
+    }
+
+    #[test]
+    fn test_find_conflicts() {
+        let conflicts = find_conflicts(&events());
+        assert_eq!(conflicts.len(), 1);
+
+        let conflict = &conflicts[0];
+        assert_eq!(conflict.events[0].summary.as_deref(), Some("Standup"));
+        assert_eq!(conflict.events[1].summary.as_deref(), Some("Dentist"));
+        assert_eq!(
+            conflict.sources,
+            [Some("Work".to_string()), Some("Family".to_string())]
+        );
+        assert_eq!(conflict.start, "2025-03-03T09:15:00");
+        assert_eq!(conflict.end, "2025-03-03T09:30:00");
+    }
+
+    #[test]
+    fn test_zoned_conflicts() {
+        if Zone::load("Europe/Paris").is_err() {
+            return;
+        }
+
+        // 10:00 in Paris is 09:00 UTC
+        let ical = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nUID:paris\r\nSUMMARY:Paris\r\n\
+            DTSTART;TZID=Europe/Paris:20250303T100000\r\n\
+            DTEND;TZID=Europe/Paris:20250303T110000\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:utc\r\nSUMMARY:UTC\r\n\
+            DTSTART:20250303T093000Z\r\nDTEND:20250303T100000Z\r\nEND:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        let events = crate::ical::parse_calendar(ical).unwrap().into_events();
+        assert_eq!(find_conflicts(&events).len(), 1);
+    }
+
+    #[test]
+    fn test_flag_conflicts() {
+        let flagged = flag_conflicts(events());
+        let summaries: Vec<_> = flagged.iter().map(|e| e.summary().unwrap()).collect();
+        assert_eq!(
+            summaries,
+            vec!["⚠ Standup", "⚠ Dentist", "After", "Focus time", "Holiday"]
+        );
+
+        // Flagging again doesn't stack prefixes
+        let flagged = flag_conflicts(flagged);
+        assert_eq!(flagged[0].summary(), Some("⚠ Standup"));
+    }
+}
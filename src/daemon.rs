@@ -0,0 +1,232 @@
+#[cfg(unix)]
+use std::path::Path;
+
+#[cfg(unix)]
+use crate::error::Result;
+
+/// Detach from the terminal and carry on in the background. This forks, so it must
+/// be called before any threads are started, which includes the async runtime.
+/// The daemon stays in the current directory so relative paths in the config still
+/// resolve, and its output goes to `log_file`, or nowhere
+#[cfg(unix)]
+pub fn daemonize(pid_file: Option<&Path>, log_file: Option<&Path>) -> Result<()> {
+    use std::fs::OpenOptions;
+    use std::os::fd::AsRawFd;
+
+    // Open everything up front so problems are still reported to the terminal
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    let output = match log_file {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+        None => null.try_clone()?,
+    };
+    let pid = match pid_file {
+        Some(path) => Some(std::fs::File::create(path)?),
+        None => None,
+    };
+
+    // Fork twice with a new session between so the daemon can never regain a terminal
+    fork()?;
+    // SAFETY: setsid only changes the session of this process
+    if unsafe { libc::setsid() } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    fork()?;
+
+    for (from, to) in [(&null, 0), (&output, 1), (&output, 2)] {
+        // SAFETY: both descriptors are open and the standard ones are ours to replace
+        if unsafe { libc::dup2(from.as_raw_fd(), to) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+
+    if let Some(file) = pid {
+        write_pid(file, std::process::id())?;
+    }
+    Ok(())
+}
+
+/// Fork, leaving only the child running
+#[cfg(unix)]
+fn fork() -> Result<()> {
+    // SAFETY: the caller makes sure there's only one thread
+    match unsafe { libc::fork() } {
+        pid if pid < 0 => Err(std::io::Error::last_os_error().into()),
+        0 => Ok(()),
+        // SAFETY: the parent leaves straight away without running any cleanup
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+/// Write the daemon's process ID to the emptied pid file
+#[cfg(unix)]
+fn write_pid(mut file: std::fs::File, pid: u32) -> Result<()> {
+    use std::io::Write;
+
+    writeln!(file, "{}", pid)?;
+    Ok(())
+}
+
+/// Registering with and running under the Windows service manager
+#[cfg(windows)]
+pub mod service {
+    use std::ffi::OsString;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use tokio::sync::oneshot;
+    use windows_service::service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    use crate::error::{Error, Result};
+
+    pub const SERVICE_NAME: &str = "ical-merge";
+
+    type Serve = Box<dyn FnOnce(oneshot::Receiver<()>) -> Result<()> + Send>;
+
+    /// What to run once the service manager starts the service
+    static SERVE: Mutex<Option<Serve>> = Mutex::new(None);
+
+    fn service_error(err: windows_service::Error) -> Error {
+        Error::Io(std::io::Error::other(err))
+    }
+
+    /// Register the service to start with Windows, running this program with `arguments`
+    pub fn install(arguments: Vec<OsString>) -> Result<()> {
+        let manager = ServiceManager::local_computer(
+            None::<&str>,
+            ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+        )
+        .map_err(service_error)?;
+        let info = ServiceInfo {
+            name: SERVICE_NAME.into(),
+            display_name: "iCal Merge".into(),
+            service_type: ServiceType::OWN_PROCESS,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: std::env::current_exe()?,
+            launch_arguments: arguments,
+            dependencies: vec![],
+            account_name: None,
+            account_password: None,
+        };
+        let service = manager
+            .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+            .map_err(service_error)?;
+        service
+            .set_description("Merges and filters iCal calendars")
+            .map_err(service_error)?;
+        Ok(())
+    }
+
+    /// Stop the service if it's running and remove it
+    pub fn uninstall() -> Result<()> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+            .map_err(service_error)?;
+        let service = manager
+            .open_service(
+                SERVICE_NAME,
+                ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+            )
+            .map_err(service_error)?;
+        if service.query_status().map_err(service_error)?.current_state != ServiceState::Stopped {
+            service.stop().map_err(service_error)?;
+        }
+        service.delete().map_err(service_error)?;
+        Ok(())
+    }
+
+    /// Hand over to the service manager, which calls `serve` on its own thread. The
+    /// receiver fires when Windows asks the service to stop. Only works when the
+    /// service manager started the process
+    pub fn run(
+        serve: impl FnOnce(oneshot::Receiver<()>) -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        *SERVE.lock().unwrap() = Some(Box::new(serve));
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main).map_err(service_error)
+    }
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(err) = run_service() {
+            tracing::error!("Service failed: {}", err);
+        }
+    }
+
+    fn run_service() -> Result<()> {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let shutdown_tx = Mutex::new(Some(shutdown_tx));
+        let status =
+            service_control_handler::register(SERVICE_NAME, move |control| match control {
+                ServiceControl::Stop | ServiceControl::Shutdown => {
+                    if let Some(shutdown) = shutdown_tx.lock().unwrap().take() {
+                        let _ = shutdown.send(());
+                    }
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _ => ServiceControlHandlerResult::NotImplemented,
+            })
+            .map_err(service_error)?;
+
+        let set_state = |state, accept, exit_code| {
+            status
+                .set_service_status(ServiceStatus {
+                    service_type: ServiceType::OWN_PROCESS,
+                    current_state: state,
+                    controls_accepted: accept,
+                    exit_code,
+                    checkpoint: 0,
+                    wait_hint: Duration::default(),
+                    process_id: None,
+                })
+                .map_err(service_error)
+        };
+        set_state(
+            ServiceState::Running,
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            ServiceExitCode::Win32(0),
+        )?;
+
+        let result = match SERVE.lock().unwrap().take() {
+            Some(serve) => serve(shutdown_rx),
+            None => Err(Error::Config(
+                "Service started without a server".to_string(),
+            )),
+        };
+
+        let exit_code = match result {
+            Ok(()) => ServiceExitCode::Win32(0),
+            Err(_) => ServiceExitCode::ServiceSpecific(1),
+        };
+        set_state(
+            ServiceState::Stopped,
+            ServiceControlAccept::empty(),
+            exit_code,
+        )?;
+        result
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_pid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ical-merge.pid");
+        std::fs::write(&path, "123456789\n").unwrap();
+
+        write_pid(std::fs::File::create(&path).unwrap(), 42).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "42\n");
+    }
+}
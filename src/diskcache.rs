@@ -0,0 +1,149 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Numbers the files copies are written to before they're moved into place
+static WRITES: AtomicU64 = AtomicU64::new(0);
+
+/// A fetched calendar as it's kept on disk
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    /// Where it was fetched from, so the files can be told apart by hand
+    url: String,
+    /// Seconds since the Unix epoch when it was fetched
+    fetched_at: u64,
+    body: String,
+}
+
+/// Fetched calendars kept in a directory, one file for each URL and set of
+/// credentials, so they can be served without fetching them again, even after a
+/// restart
+#[derive(Debug)]
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// File holding the copy for `key`, named by its hash so credentials in the key
+    /// never appear on disk
+    fn path(&self, key: &str) -> PathBuf {
+        let hash: String = Sha256::digest(key.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        self.dir.join(format!("{}.json", hash))
+    }
+
+    /// The copy kept for `key` and how long ago it was fetched
+    pub async fn get(&self, key: &str) -> Option<(String, Duration)> {
+        let path = self.path(key);
+        let text = tokio::fs::read_to_string(&path).await.ok()?;
+        let stored: Stored = match serde_json::from_str(&text) {
+            Ok(stored) => stored,
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable cached copy {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(stored.fetched_at);
+        let age = SystemTime::now()
+            .duration_since(fetched_at)
+            .unwrap_or_default();
+        Some((stored.body, age))
+    }
+
+    /// Keep `body`, fetched from `url`, as the copy for `key`. A copy that can't be
+    /// written is only logged, the fetch itself still worked
+    pub async fn put(&self, key: &str, url: &str, body: &str) {
+        if let Err(e) = self.write(key, url, body).await {
+            tracing::warn!(
+                "Failed to keep a copy of {} in {}: {}",
+                url,
+                self.dir.display(),
+                e
+            );
+        }
+    }
+
+    async fn write(&self, key: &str, url: &str, body: &str) -> std::io::Result<()> {
+        let stored = Stored {
+            url: url.to_string(),
+            fetched_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            body: body.to_string(),
+        };
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        // Written to a file of its own and moved into place, so a copy being read
+        // is never only partly written
+        let path = self.path(key);
+        let partial = path.with_extension(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::write(&partial, serde_json::to_vec(&stored)?).await?;
+        tokio::fs::rename(&partial, &path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_get_and_put() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path().join("fetched"));
+        assert!(
+            cache
+                .get("https://example.com/team.ics None")
+                .await
+                .is_none()
+        );
+
+        cache
+            .put(
+                "https://example.com/team.ics None",
+                "https://example.com/team.ics",
+                "BEGIN:VCALENDAR",
+            )
+            .await;
+        let (body, age) = cache
+            .get("https://example.com/team.ics None")
+            .await
+            .unwrap();
+        assert_eq!(body, "BEGIN:VCALENDAR");
+        assert!(age < Duration::from_secs(5));
+
+        // Other credentials have copies of their own
+        assert!(
+            cache
+                .get("https://example.com/team.ics Some(\"alice\")")
+                .await
+                .is_none()
+        );
+
+        // Copies survive being read by a new cache, and nothing is left half written
+        let cache = DiskCache::new(dir.path().join("fetched"));
+        assert!(
+            cache
+                .get("https://example.com/team.ics None")
+                .await
+                .is_some()
+        );
+        let files: Vec<_> = std::fs::read_dir(dir.path().join("fetched"))
+            .unwrap()
+            .collect();
+        assert_eq!(files.len(), 1);
+    }
+}
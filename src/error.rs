@@ -6,6 +6,18 @@ pub enum Error {
     #[error("HTTP fetch error: {0}")]
     Fetch(#[from] reqwest::Error),
 
+    #[error("HTTP fetch error: {0}")]
+    Redirect(String),
+
+    #[error("HTTP fetch error: response from {0} is larger than {1} bytes")]
+    ResponseTooLarge(String, u64),
+
+    #[error("HTTP fetch error: {0}")]
+    RateLimited(String),
+
+    #[error("Command error: {0}")]
+    Command(String),
+
     #[error("iCal parse error: {0}")]
     Parse(String),
 
@@ -20,6 +32,9 @@ pub enum Error {
 
     #[error("Calendar not found: {0}")]
     CalendarNotFound(String),
+
+    #[error(transparent)]
+    Shared(std::sync::Arc<Error>),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,177 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::ical::Event;
+use crate::merge::SOURCE_LABEL_PROPERTY;
+
+/// A column of the CSV export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvColumn {
+    Start,
+    End,
+    Summary,
+    Location,
+    Description,
+    /// Label of the source the event came from
+    Source,
+    Uid,
+}
+
+/// Columns exported when none are asked for
+pub const DEFAULT_CSV_COLUMNS: &[CsvColumn] = &[
+    CsvColumn::Start,
+    CsvColumn::End,
+    CsvColumn::Summary,
+    CsvColumn::Location,
+    CsvColumn::Source,
+];
+
+impl CsvColumn {
+    fn name(&self) -> &'static str {
+        match self {
+            CsvColumn::Start => "start",
+            CsvColumn::End => "end",
+            CsvColumn::Summary => "summary",
+            CsvColumn::Location => "location",
+            CsvColumn::Description => "description",
+            CsvColumn::Source => "source",
+            CsvColumn::Uid => "uid",
+        }
+    }
+
+    fn value(&self, event: &Event) -> String {
+        let format_time = |time: chrono::NaiveDateTime| {
+            if event.is_all_day() {
+                time.format("%Y-%m-%d").to_string()
+            } else {
+                time.format("%Y-%m-%d %H:%M:%S").to_string()
+            }
+        };
+
+        match self {
+            CsvColumn::Start => event.start_time().map(format_time).unwrap_or_default(),
+            CsvColumn::End => event.end_time().map(format_time).unwrap_or_default(),
+            CsvColumn::Summary => event.summary().unwrap_or_default().to_string(),
+            CsvColumn::Location => event.location().unwrap_or_default().to_string(),
+            CsvColumn::Description => event.description().unwrap_or_default().to_string(),
+            CsvColumn::Source => event
+                .property(SOURCE_LABEL_PROPERTY)
+                .unwrap_or_default()
+                .to_string(),
+            CsvColumn::Uid => event.uid().unwrap_or_default().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CsvColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for CsvColumn {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "start" => Ok(CsvColumn::Start),
+            "end" => Ok(CsvColumn::End),
+            "summary" => Ok(CsvColumn::Summary),
+            "location" => Ok(CsvColumn::Location),
+            "description" => Ok(CsvColumn::Description),
+            "source" => Ok(CsvColumn::Source),
+            "uid" => Ok(CsvColumn::Uid),
+            other => Err(Error::Config(format!(
+                "Unknown CSV column '{}', expected start, end, summary, location, description, source or uid",
+                other
+            ))),
+        }
+    }
+}
+
+/// Parse a comma-separated list of columns, e.g. `start,summary`
+pub fn parse_columns(list: &str) -> Result<Vec<CsvColumn>> {
+    list.split(',')
+        .filter(|name| !name.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Render events as CSV with a header row, ordered by start
+pub fn to_csv(events: &[Event], columns: &[CsvColumn]) -> String {
+    let mut events: Vec<&Event> = events.iter().collect();
+    events.sort_by_key(|event| event.start_time());
+
+    let mut out = String::new();
+    let header: Vec<String> = columns.iter().map(|column| column.to_string()).collect();
+    push_row(&mut out, &header);
+    for event in events {
+        let row: Vec<String> = columns.iter().map(|column| column.value(event)).collect();
+        push_row(&mut out, &row);
+    }
+    out
+}
+
+fn push_row(out: &mut String, fields: &[String]) {
+    let fields: Vec<String> = fields.iter().map(|field| escape_field(field)).collect();
+    out.push_str(&fields.join(","));
+    out.push_str("\r\n");
+}
+
+/// Quote a field when needed (RFC 4180) and defuse values a spreadsheet would
+/// run as a formula, since event text comes from upstream calendars
+fn escape_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", field)
+    } else {
+        field.to_string()
+    };
+
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use icalendar::{Component, EventLike};
+
+    #[test]
+    fn test_parse_columns() {
+        assert_eq!(
+            parse_columns("start, Summary,source").unwrap(),
+            vec![CsvColumn::Start, CsvColumn::Summary, CsvColumn::Source]
+        );
+        assert!(parse_columns("start,organizer").is_err());
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let date = chrono::NaiveDate::from_ymd_opt(2025, 3, 3).unwrap();
+
+        let mut meeting = icalendar::Event::new();
+        meeting
+            .summary("Planning, \"Q2\"")
+            .location("Room 1")
+            .starts(date.and_hms_opt(10, 0, 0).unwrap())
+            .ends(date.and_hms_opt(11, 0, 0).unwrap());
+        let mut meeting = Event::new(meeting.done());
+        meeting.set_property(SOURCE_LABEL_PROPERTY, "Work");
+
+        let mut holiday = icalendar::Event::new();
+        holiday.summary("=HYPERLINK(\"x\")").starts(date);
+        let holiday = Event::new(holiday.done());
+
+        let csv = to_csv(&[meeting, holiday], DEFAULT_CSV_COLUMNS);
+        assert_eq!(
+            csv,
+            "start,end,summary,location,source\r\n\
+             2025-03-03,2025-03-04,\"'=HYPERLINK(\"\"x\"\")\",,\r\n\
+             2025-03-03 10:00:00,2025-03-03 11:00:00,\"Planning, \"\"Q2\"\"\",Room 1,Work\r\n"
+        );
+    }
+}
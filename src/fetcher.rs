@@ -1,8 +1,32 @@
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
-use reqwest::Client;
+use futures::future::BoxFuture;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::{
+    CONTENT_TYPE, ETAG, HeaderMap, HeaderName, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED, LOCATION,
+};
+use reqwest::redirect::Policy;
+use reqwest::{
+    Client, ClientBuilder, Identity, Method, Proxy, RequestBuilder, Response, StatusCode,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::Semaphore;
 
-use crate::error::Result;
+use crate::birthdays;
+use crate::config::{FetcherConfig, IpFamily, SourceAuth, TlsVersion};
+use crate::diskcache::DiskCache;
+use crate::error::{Error, Result};
+use crate::git;
+use crate::provider::{self, Providers, SourceProvider};
+use crate::ratelimit::HostRateLimiter;
+use crate::s3::{self, S3Credentials};
 
 /// Normalize webcal:// and webcals:// URLs to http:// and https://
 fn normalize_calendar_url(url: &str) -> String {
@@ -15,42 +39,992 @@ fn normalize_calendar_url(url: &str) -> String {
     }
 }
 
+/// Get the file a `file://` URL points at
+pub fn local_path(url: &str) -> Option<PathBuf> {
+    if !url.starts_with("file:") {
+        return None;
+    }
+    reqwest::Url::parse(url).ok()?.to_file_path().ok()
+}
+
+/// Keep only the addresses of the allowed IP versions, with the preferred version first
+fn order_addresses(family: IpFamily, addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let mut addresses: Vec<SocketAddr> = match family {
+        IpFamily::Ipv4 => addresses.into_iter().filter(|a| a.is_ipv4()).collect(),
+        IpFamily::Ipv6 => addresses.into_iter().filter(|a| a.is_ipv6()).collect(),
+        _ => addresses,
+    };
+    match family {
+        IpFamily::PreferIpv4 => addresses.sort_by_key(|a| !a.is_ipv4()),
+        IpFamily::PreferIpv6 => addresses.sort_by_key(|a| !a.is_ipv6()),
+        _ => {}
+    }
+    addresses
+}
+
+/// Looks hosts up with the system resolver, then applies the configured IP versions.
+/// The first address's version is tried first, falling back to the other after a
+/// short delay when both are allowed
+struct FamilyResolver(IpFamily);
+
+impl Resolve for FamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let family = self.0;
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addresses = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            let addresses = order_addresses(family, addresses);
+            if addresses.is_empty() {
+                return Err(format!("{} has no {:?} addresses", host, family).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Credentials a source sends with its requests
+#[derive(Default, Clone, Copy)]
+pub struct Credentials<'a> {
+    /// HTTP Basic user name and password
+    pub basic: Option<(&'a str, Option<&'a str>)>,
+    /// Bearer token, API key header or OAuth2 client
+    pub auth: Option<&'a SourceAuth>,
+    /// PEM client certificate for mutual TLS, and its key if it's in a file of its own
+    pub client_cert: Option<(&'a Path, Option<&'a Path>)>,
+    /// Accept any certificate from the upstream, such as a self-signed one
+    pub insecure_tls: bool,
+}
+
+impl Credentials<'_> {
+    /// Who the request is made as, since different users may be shown different
+    /// calendars at the same URL. Whether the certificate was checked is part of it,
+    /// so an unverified response is never served to a source that verifies. It's a
+    /// hash of the credentials so the secrets in them never end up in cache keys
+    pub(crate) fn identity(&self) -> Option<String> {
+        if matches!(
+            (self.basic, self.auth, self.client_cert, self.insecure_tls),
+            (None, None, None, false)
+        ) {
+            return None;
+        }
+        let material = serde_json::json!({
+            "basic": self.basic,
+            "auth": self.auth,
+            "client_cert": self.client_cert,
+            "insecure_tls": self.insecure_tls,
+        });
+        Some(
+            Sha256::digest(material.to_string().as_bytes())
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        )
+    }
+}
+
+/// Written out by hand so passwords never end up in logs
+impl std::fmt::Debug for Credentials<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("basic", &self.basic.map(|(username, _)| username))
+            .field("auth", &self.auth)
+            .field("client_cert", &self.client_cert)
+            .field("insecure_tls", &self.insecure_tls)
+            .finish()
+    }
+}
+
+/// Credentials copied out of the config, for fetches that outlive the request
+/// asking for them
+struct OwnedCredentials {
+    basic: Option<(String, Option<String>)>,
+    auth: Option<SourceAuth>,
+    client_cert: Option<(PathBuf, Option<PathBuf>)>,
+    insecure_tls: bool,
+}
+
+impl OwnedCredentials {
+    fn new(credentials: Credentials<'_>) -> Self {
+        Self {
+            basic: credentials
+                .basic
+                .map(|(username, password)| (username.to_string(), password.map(str::to_string))),
+            auth: credentials.auth.cloned(),
+            client_cert: credentials
+                .client_cert
+                .map(|(cert, key)| (cert.to_path_buf(), key.map(Path::to_path_buf))),
+            insecure_tls: credentials.insecure_tls,
+        }
+    }
+
+    fn borrow(&self) -> Credentials<'_> {
+        Credentials {
+            basic: self
+                .basic
+                .as_ref()
+                .map(|(username, password)| (username.as_str(), password.as_deref())),
+            auth: self.auth.as_ref(),
+            client_cert: self
+                .client_cert
+                .as_ref()
+                .map(|(cert, key)| (cert.as_path(), key.as_deref())),
+            insecure_tls: self.insecure_tls,
+        }
+    }
+}
+
+/// Read a PEM client certificate and its key, which may be in the same file
+pub fn load_identity(cert: &Path, key: Option<&Path>) -> Result<(Vec<u8>, Identity)> {
+    let read = |path: &Path| {
+        std::fs::read(path).map_err(|e| {
+            Error::Io(std::io::Error::new(
+                e.kind(),
+                format!("Failed to read {}: {}", path.display(), e),
+            ))
+        })
+    };
+
+    let mut pem = read(cert)?;
+    if let Some(key) = key {
+        pem.push(b'\n');
+        pem.extend(read(key)?);
+    }
+    let identity = Identity::from_pem(&pem)?;
+    Ok((pem, identity))
+}
+
+/// A fetched calendar along with the validators to check it is still current
+struct Validated {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    body: String,
+}
+
+/// TLS settings that need a client of their own
+#[derive(PartialEq, Eq, Hash)]
+struct TlsSettings {
+    client_cert: Option<(PathBuf, Option<PathBuf>)>,
+    insecure_tls: bool,
+}
+
+/// A client built for a source's TLS settings, along with the PEM of its client
+/// certificate so a renewed certificate gets a new client
+struct SourceClient {
+    pem: Vec<u8>,
+    client: Client,
+}
+
+/// An OAuth2 access token and when to stop using it
+struct AccessToken {
+    token: String,
+    expires: Instant,
+}
+
+/// Reply from an OAuth2 token endpoint
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+/// How long before it expires to fetch a new token, so a token doesn't run out mid-request
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// How much of a failed command's error output is kept, only the last line is reported
+const MAX_COMMAND_ERRORS: u64 = 64 * 1024;
+
 /// HTTP fetcher for iCal calendars
 pub struct Fetcher {
     client: Client,
+    /// Headers from the config, sent along with each source's credentials
+    headers: HeaderMap,
+    /// Directory calendars are read from instead of the network, in offline mode
+    fixtures: Option<PathBuf>,
+    /// Last response for each URL and set of credentials that sent `ETag` or `Last-Modified`,
+    /// reused on 304
+    validated: Mutex<HashMap<(String, Option<String>), Validated>>,
+    /// OAuth2 access tokens by token URL, client ID and scopes, kept until they expire
+    tokens: Mutex<HashMap<(String, String, String), AccessToken>>,
+    /// Settings clients are built with, for building ones with other TLS settings
+    config: FetcherConfig,
+    timeout: Duration,
+    /// Clients for sources with a client certificate or that accept any certificate
+    clients: Mutex<HashMap<TlsSettings, SourceClient>>,
+    /// Limits how many fetches are in flight at once, if configured
+    fetches: Option<Semaphore>,
+    /// Providers registered for other URL schemes, by scheme
+    providers: Providers,
+    /// Checkouts of git sources' repositories
+    checkouts: git::Checkouts,
+    /// Requests made recently to hosts with a rate limit
+    rate_limiter: HostRateLimiter,
+    /// Calendars kept on disk between fetches, when `cache_dir` is set
+    disk_cache: Option<DiskCache>,
+    /// Kept calendars being fetched again in the background
+    refreshing: Mutex<HashSet<String>>,
+    /// The fetcher itself once it's shared with [`Fetcher::into_shared`], for
+    /// refreshing kept calendars in the background
+    shared: Weak<Fetcher>,
+}
+
+/// Files that could hold the fixture for a URL: `<host>/<path>` under the
+/// fixtures directory, then just the file name
+fn fixture_candidates(fixtures: &Path, url: &str) -> Result<Vec<PathBuf>> {
+    let parsed = reqwest::Url::parse(&normalize_calendar_url(url))
+        .map_err(|e| Error::Config(format!("Invalid URL '{}': {}", url, e)))?;
+
+    let mut candidates = Vec::new();
+    let path = parsed.path().trim_start_matches('/');
+    if let Some(host) = parsed.host_str() {
+        let mut candidate = fixtures.join(host);
+        candidate.extend(path.split('/').filter(|segment| !segment.is_empty()));
+        candidates.push(candidate);
+    }
+    if let Some(name) = path.rsplit('/').next().filter(|name| !name.is_empty()) {
+        candidates.push(fixtures.join(name));
+    }
+    Ok(candidates)
+}
+
+/// User-Agent sent when the config doesn't set one
+fn default_user_agent() -> String {
+    format!(
+        "ical-merge/{} (+https://github.com/user/ical-merge)",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Headers the config sends with every fetch
+fn config_headers(config: &FetcherConfig) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &config.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| Error::Config(format!("Invalid header name '{}'", name)))?;
+        let mut value = HeaderValue::from_str(value)
+            .map_err(|_| Error::Config(format!("Invalid value for header '{}'", name)))?;
+        value.set_sensitive(true);
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+/// Start building a client with the configured settings. Redirects are followed
+/// by the fetcher itself so credentials can be kept from other origins
+fn client_builder(config: &FetcherConfig, timeout: Duration) -> Result<ClientBuilder> {
+    let mut builder = Client::builder()
+        .timeout(timeout)
+        .user_agent(config.user_agent.clone().unwrap_or_else(default_user_agent))
+        .redirect(Policy::none());
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(Proxy::all(proxy)?);
+    }
+    if config.ip_family != IpFamily::Any {
+        builder = builder.dns_resolver(Arc::new(FamilyResolver(config.ip_family)));
+    }
+    for (host, address) in &config.resolve {
+        // The port comes from the URL, the one given here is ignored
+        builder = builder.resolve(host, SocketAddr::new(*address, 0));
+    }
+    if let Some(version) = config.min_tls_version {
+        builder = builder.min_tls_version(match version {
+            TlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+            TlsVersion::Tls13 => reqwest::tls::Version::TLS_1_3,
+        });
+    }
+    Ok(builder)
 }
 
 impl Fetcher {
     pub fn new() -> Result<Self> {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(30))
-            .user_agent(format!(
-                "ical-merge/{} (+https://github.com/user/ical-merge)",
-                env!("CARGO_PKG_VERSION")
-            ))
-            .build()?;
-
-        Ok(Self { client })
+        Self::from_config(&FetcherConfig::default())
     }
 
     pub fn with_timeout(timeout: Duration) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(timeout)
-            .user_agent(format!(
-                "ical-merge/{} (+https://github.com/user/ical-merge)",
-                env!("CARGO_PKG_VERSION")
-            ))
-            .build()?;
+        Self::build(&FetcherConfig::default(), timeout)
+    }
+
+    /// Build a fetcher with the configured timeout, User-Agent, proxy, headers, TLS policy,
+    /// host addresses, IP versions, redirect limit, concurrency limit and response size limit
+    pub fn from_config(config: &FetcherConfig) -> Result<Self> {
+        Self::build(config, config.timeout())
+    }
+
+    fn build(config: &FetcherConfig, timeout: Duration) -> Result<Self> {
+        Ok(Self {
+            client: client_builder(config, timeout)?.build()?,
+            headers: config_headers(config)?,
+            fixtures: None,
+            validated: Mutex::new(HashMap::new()),
+            tokens: Mutex::new(HashMap::new()),
+            config: config.clone(),
+            timeout,
+            clients: Mutex::new(HashMap::new()),
+            fetches: config.max_concurrent_fetches.map(Semaphore::new),
+            providers: Providers::new(),
+            checkouts: git::Checkouts::new(),
+            rate_limiter: HostRateLimiter::new(),
+            disk_cache: config.cache_dir.clone().map(DiskCache::new),
+            refreshing: Mutex::new(HashSet::new()),
+            shared: Weak::new(),
+        })
+    }
+
+    /// Share the fetcher, letting it serve stale copies of kept calendars while it
+    /// fetches them again in the background. A fetcher that isn't shared fetches
+    /// them again before returning
+    pub fn into_shared(self) -> Arc<Self> {
+        Arc::new_cyclic(|shared| Self {
+            shared: shared.clone(),
+            ..self
+        })
+    }
 
-        Ok(Self { client })
+    /// Read every calendar from files in `fixtures` instead of fetching it
+    pub fn with_fixtures(mut self, fixtures: Option<PathBuf>) -> Self {
+        self.fixtures = fixtures;
+        self
+    }
+
+    /// The fixtures directory when running offline
+    pub fn fixtures(&self) -> Option<&Path> {
+        self.fixtures.as_deref()
+    }
+
+    /// Read sources whose URLs use `scheme`, e.g. `db` for `db:team-rota`, with
+    /// `provider` instead of fetching them
+    pub fn with_provider(mut self, scheme: &str, provider: impl SourceProvider + 'static) -> Self {
+        self.providers
+            .insert(scheme.to_ascii_lowercase(), Arc::new(provider));
+        self
+    }
+
+    /// Use the same providers as another fetcher, e.g. the one this replaces
+    pub fn with_providers(mut self, providers: Providers) -> Self {
+        self.providers = providers;
+        self
+    }
+
+    /// The registered providers, by scheme
+    pub fn providers(&self) -> &Providers {
+        &self.providers
     }
 
     pub async fn fetch(&self, url: &str) -> Result<String> {
-        let normalized_url = normalize_calendar_url(url);
-        let response = self.client.get(&normalized_url).send().await?;
-        let text = response.error_for_status()?.text().await?;
+        self.fetch_with_auth(url, Credentials::default(), None)
+            .await
+    }
+
+    /// Fetch a calendar, sending the source's credentials and waiting `timeout`
+    /// instead of the configured timeout if given
+    pub async fn fetch_with_auth(
+        &self,
+        url: &str,
+        credentials: Credentials<'_>,
+        timeout: Option<Duration>,
+    ) -> Result<String> {
+        // Registered providers and local files are read even in offline mode
+        if let Some(provider) = provider::scheme(url)
+            .and_then(|scheme| self.providers.get(&scheme.to_ascii_lowercase()))
+        {
+            return provider.fetch(url).await;
+        }
+        if let Some(path) = local_path(url) {
+            return tokio::fs::read_to_string(&path).await.map_err(|e| {
+                Error::Io(std::io::Error::new(
+                    e.kind(),
+                    format!("Failed to read {}: {}", path.display(), e),
+                ))
+            });
+        }
+        if let Some(fixtures) = &self.fixtures {
+            return self.fetch_fixture(fixtures, url).await;
+        }
+
+        let Some(cache) = &self.disk_cache else {
+            return self.fetch_http(url, credentials, timeout).await;
+        };
+        let key = format!(
+            "{} {:?}",
+            normalize_calendar_url(url),
+            credentials.identity()
+        );
+        if let Some((body, age)) = cache.get(&key).await {
+            let ttl = self.config.cache_ttl();
+            if age < ttl {
+                tracing::debug!("Using the copy of {} kept {}s ago", url, age.as_secs());
+                return Ok(body);
+            }
+            if age < ttl + self.config.cache_max_stale()
+                && let Some(fetcher) = self.shared.upgrade()
+            {
+                tracing::debug!(
+                    "Serving the copy of {} kept {}s ago while fetching it again",
+                    url,
+                    age.as_secs()
+                );
+                fetcher.refresh_in_background(url, credentials, timeout, key);
+                return Ok(body);
+            }
+        }
+
+        let text = self.fetch_http(url, credentials, timeout).await?;
+        cache.put(&key, url, &text).await;
         Ok(text)
     }
+
+    /// Fetch a kept calendar again without waiting for it, unless it's already being
+    /// fetched
+    fn refresh_in_background(
+        self: Arc<Self>,
+        url: &str,
+        credentials: Credentials<'_>,
+        timeout: Option<Duration>,
+        key: String,
+    ) {
+        if !self.refreshing.lock().unwrap().insert(key.clone()) {
+            return;
+        }
+        let url = url.to_string();
+        let credentials = OwnedCredentials::new(credentials);
+        tokio::spawn(async move {
+            match self.fetch_http(&url, credentials.borrow(), timeout).await {
+                Ok(text) => {
+                    if let Some(cache) = &self.disk_cache {
+                        cache.put(&key, &url, &text).await;
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to fetch {} again: {}", url, e),
+            }
+            self.refreshing.lock().unwrap().remove(&key);
+        });
+    }
+
+    /// Fetch a calendar from the network
+    async fn fetch_http(
+        &self,
+        url: &str,
+        credentials: Credentials<'_>,
+        timeout: Option<Duration>,
+    ) -> Result<String> {
+        // Held until the body has been read, the semaphore is never closed
+        let _permit = match &self.fetches {
+            Some(fetches) => fetches.acquire().await.ok(),
+            None => None,
+        };
+
+        let normalized_url = match credentials.auth {
+            Some(SourceAuth::S3 {
+                region, endpoint, ..
+            }) if url.starts_with("s3:") => s3::object_url(
+                url,
+                region.as_deref().unwrap_or(s3::DEFAULT_REGION),
+                endpoint.as_deref(),
+            )?,
+            _ => normalize_calendar_url(url),
+        };
+        let client = self.client_for(credentials)?;
+        let key = (normalized_url, credentials.identity());
+        let mut conditional = HeaderMap::new();
+        {
+            let validated = self.validated.lock().unwrap();
+            if let Some(previous) = validated.get(&key) {
+                if let Some(etag) = &previous.etag {
+                    conditional.insert(IF_NONE_MATCH, etag.clone());
+                }
+                if let Some(last_modified) = &previous.last_modified {
+                    conditional.insert(IF_MODIFIED_SINCE, last_modified.clone());
+                }
+            }
+        }
+
+        let response = self
+            .send_following(&client, &key.0, credentials, timeout, |client, url| {
+                client.get(url).headers(conditional.clone())
+            })
+            .await?;
+        if response.status() == StatusCode::UNAUTHORIZED
+            && let Some(SourceAuth::Oauth2 {
+                token_url,
+                client_id,
+                scopes,
+                ..
+            }) = credentials.auth
+        {
+            // The token may have been revoked early, so fetch a fresh one next time
+            self.tokens.lock().unwrap().remove(&(
+                token_url.clone(),
+                client_id.clone(),
+                scopes.join(" "),
+            ));
+        }
+        if response.status() == StatusCode::NOT_MODIFIED
+            && let Some(previous) = self.validated.lock().unwrap().get(&key)
+        {
+            tracing::debug!("{} not modified, reusing the last response", key.0);
+            return Ok(previous.body.clone());
+        }
+
+        let response = response.error_for_status()?;
+        let etag = response.headers().get(ETAG).cloned();
+        let last_modified = response.headers().get(LAST_MODIFIED).cloned();
+        let text = self.read_body(response, &key.0).await?;
+
+        let mut validated = self.validated.lock().unwrap();
+        if etag.is_some() || last_modified.is_some() {
+            validated.insert(
+                key,
+                Validated {
+                    etag,
+                    last_modified,
+                    body: text.clone(),
+                },
+            );
+        } else {
+            validated.remove(&key);
+        }
+        Ok(text)
+    }
+
+    /// Fetch the vCards of an address book. A `.vcf` file, or one that isn't on
+    /// HTTP, is fetched like a calendar, and a CardDAV collection is asked for every
+    /// contact with a REPORT
+    pub async fn fetch_address_book(
+        &self,
+        url: &str,
+        credentials: Credentials<'_>,
+        timeout: Option<Duration>,
+    ) -> Result<String> {
+        if !birthdays::is_collection(url) || self.fixtures.is_some() {
+            return self.fetch_with_auth(url, credentials, timeout).await;
+        }
+
+        // Held until the body has been read, the semaphore is never closed
+        let _permit = match &self.fetches {
+            Some(fetches) => fetches.acquire().await.ok(),
+            None => None,
+        };
+
+        let client = self.client_for(credentials)?;
+        let report = Method::from_bytes(b"REPORT").expect("REPORT is a valid method");
+        let response = self
+            .send_following(&client, url, credentials, timeout, |client, url| {
+                client
+                    .request(report.clone(), url)
+                    .header("Depth", "1")
+                    .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+                    .body(birthdays::ADDRESSBOOK_QUERY)
+            })
+            .await?
+            .error_for_status()?;
+        let multistatus = self.read_body(response, url).await?;
+        Ok(birthdays::address_data(&multistatus))
+    }
+
+    /// Send the request `build` makes for `url`, following redirects up to the
+    /// configured limit. Credentials, the configured headers and any client
+    /// certificate or relaxed certificate checks only apply to the origin the source
+    /// names, never to wherever it redirects to
+    async fn send_following(
+        &self,
+        client: &Client,
+        url: &str,
+        credentials: Credentials<'_>,
+        timeout: Option<Duration>,
+        build: impl Fn(&Client, reqwest::Url) -> RequestBuilder,
+    ) -> Result<Response> {
+        let mut current = reqwest::Url::parse(url)
+            .map_err(|e| Error::Config(format!("Invalid URL '{}': {}", url, e)))?;
+        let origin = current.origin();
+        let max_redirects = self.config.max_redirects();
+
+        let mut redirects = 0;
+        loop {
+            let same_origin = current.origin() == origin;
+            let mut request = build(
+                if same_origin { client } else { &self.client },
+                current.clone(),
+            );
+            if same_origin {
+                request = self
+                    .authorize(request.headers(self.headers.clone()), &current, credentials)
+                    .await?;
+            }
+            if let Some(timeout) = timeout {
+                request = request.timeout(timeout);
+            }
+
+            self.wait_for_turn(&current, timeout.unwrap_or(self.timeout))
+                .await?;
+            let response = request.send().await?;
+            let redirected = matches!(
+                response.status(),
+                StatusCode::MOVED_PERMANENTLY
+                    | StatusCode::FOUND
+                    | StatusCode::SEE_OTHER
+                    | StatusCode::TEMPORARY_REDIRECT
+                    | StatusCode::PERMANENT_REDIRECT
+            );
+            let Some(location) = response.headers().get(LOCATION).filter(|_| redirected) else {
+                return Ok(response);
+            };
+
+            if redirects >= max_redirects {
+                return Err(Error::Redirect(format!(
+                    "{} redirected more than {} times",
+                    url, max_redirects
+                )));
+            }
+            let next = location
+                .to_str()
+                .ok()
+                .and_then(|location| current.join(location).ok())
+                .filter(|next| matches!(next.scheme(), "http" | "https"))
+                .ok_or_else(|| {
+                    Error::Redirect(format!("{} redirected to an invalid location", current))
+                })?;
+            tracing::debug!("{} redirected to {}", current, next);
+            current = next;
+            redirects += 1;
+        }
+    }
+
+    /// Wait until a request can be sent to the URL's host without going over its
+    /// rate limit, failing rather than waiting longer than `max_wait`
+    async fn wait_for_turn(&self, url: &reqwest::Url, max_wait: Duration) -> Result<()> {
+        let Some(host) = url.host_str() else {
+            return Ok(());
+        };
+        let Some(per_minute) = self.config.host_rate_limit(host) else {
+            return Ok(());
+        };
+
+        let host = host.to_ascii_lowercase();
+        match self
+            .rate_limiter
+            .reserve(&host, per_minute, max_wait, Instant::now())
+        {
+            Some(wait) if wait.is_zero() => Ok(()),
+            Some(wait) => {
+                tracing::debug!(
+                    "Waiting {:?} to fetch {} to stay under {}'s rate limit",
+                    wait,
+                    url,
+                    host
+                );
+                tokio::time::sleep(wait).await;
+                Ok(())
+            }
+            None => Err(Error::RateLimited(format!(
+                "{} has been sent its {} requests a minute",
+                host, per_minute
+            ))),
+        }
+    }
+
+    /// Read a response's body, giving up as soon as it's larger than the configured limit
+    async fn read_body(&self, mut response: Response, url: &str) -> Result<String> {
+        let limit = self.config.max_response_size();
+        let too_large = || Error::ResponseTooLarge(url.to_string(), limit);
+        if response
+            .content_length()
+            .is_some_and(|length| length > limit)
+        {
+            return Err(too_large());
+        }
+
+        // The length isn't always given, or may be wrong, so count as it arrives
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if (body.len() + chunk.len()) as u64 > limit {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Get the client to fetch with, one presenting the source's client certificate
+    /// or accepting any certificate if the source needs it
+    fn client_for(&self, credentials: Credentials<'_>) -> Result<Client> {
+        if credentials.client_cert.is_none() && !credentials.insecure_tls {
+            return Ok(self.client.clone());
+        }
+
+        let (pem, identity) = match credentials.client_cert {
+            Some((cert, key)) => {
+                let (pem, identity) = load_identity(cert, key)?;
+                (pem, Some(identity))
+            }
+            None => (Vec::new(), None),
+        };
+        let settings = TlsSettings {
+            client_cert: credentials
+                .client_cert
+                .map(|(cert, key)| (cert.to_path_buf(), key.map(Path::to_path_buf))),
+            insecure_tls: credentials.insecure_tls,
+        };
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(built) = clients.get(&settings)
+            && built.pem == pem
+        {
+            return Ok(built.client.clone());
+        }
+
+        let mut builder = client_builder(&self.config, self.timeout)?;
+        if let Some(identity) = identity {
+            builder = builder.identity(identity);
+        }
+        if credentials.insecure_tls {
+            tracing::warn!("Building a client that accepts any TLS certificate");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        let client = builder.build()?;
+        clients.insert(
+            settings,
+            SourceClient {
+                pem,
+                client: client.clone(),
+            },
+        );
+        Ok(client)
+    }
+
+    /// Add the source's credentials to a request for `url`
+    async fn authorize(
+        &self,
+        mut request: RequestBuilder,
+        url: &reqwest::Url,
+        credentials: Credentials<'_>,
+    ) -> Result<RequestBuilder> {
+        if let Some((username, password)) = credentials.basic {
+            request = request.basic_auth(username, password);
+        }
+        match credentials.auth {
+            Some(SourceAuth::Bearer(token)) => {
+                request = request.bearer_auth(token);
+            }
+            Some(SourceAuth::Header { name, value }) => {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| Error::Config(format!("Invalid header name '{}'", name)))?;
+                let mut value = HeaderValue::from_str(value)
+                    .map_err(|_| Error::Config(format!("Invalid value for header '{}'", name)))?;
+                value.set_sensitive(true);
+                request = request.header(name, value);
+            }
+            Some(SourceAuth::Oauth2 {
+                token_url,
+                client_id,
+                client_secret,
+                scopes,
+            }) => {
+                let token = self
+                    .oauth2_token(token_url, client_id, client_secret, scopes)
+                    .await?;
+                request = request.bearer_auth(token);
+            }
+            Some(SourceAuth::S3 {
+                access_key_id,
+                secret_access_key,
+                session_token,
+                region,
+                ..
+            }) => {
+                let credentials = S3Credentials {
+                    access_key_id,
+                    secret_access_key,
+                    session_token: session_token.as_deref(),
+                    region: region.as_deref().unwrap_or(s3::DEFAULT_REGION),
+                };
+                for (name, value) in s3::signed_headers(url, credentials, chrono::Utc::now()) {
+                    let mut value = HeaderValue::from_str(&value).map_err(|_| {
+                        Error::Config(format!("Invalid value for header '{}'", name))
+                    })?;
+                    value.set_sensitive(name != "x-amz-date");
+                    request = request.header(name, value);
+                }
+            }
+            None => {}
+        }
+        Ok(request)
+    }
+
+    /// Get an access token with the client credentials grant, reusing the last
+    /// one until it is about to expire
+    async fn oauth2_token(
+        &self,
+        token_url: &str,
+        client_id: &str,
+        client_secret: &str,
+        scopes: &[String],
+    ) -> Result<String> {
+        let key = (
+            token_url.to_string(),
+            client_id.to_string(),
+            scopes.join(" "),
+        );
+        if let Some(token) = self.tokens.lock().unwrap().get(&key)
+            && token.expires > Instant::now()
+        {
+            return Ok(token.token.clone());
+        }
+
+        tracing::debug!("Fetching an OAuth2 token from {}", token_url);
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+        ];
+        if !key.2.is_empty() {
+            form.push(("scope", &key.2));
+        }
+        let text = self
+            .client
+            .post(token_url)
+            .headers(self.headers.clone())
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let response: TokenResponse = serde_json::from_str(&text).map_err(|e| {
+            Error::Parse(format!(
+                "Invalid OAuth2 token response from {}: {}",
+                token_url, e
+            ))
+        })?;
+
+        // Tokens that don't say when they expire are fetched again each time
+        if let Some(expires_in) = response.expires_in {
+            let lifetime = Duration::from_secs(expires_in).saturating_sub(TOKEN_EXPIRY_MARGIN);
+            self.tokens.lock().unwrap().insert(
+                key,
+                AccessToken {
+                    token: response.access_token.clone(),
+                    expires: Instant::now() + lifetime,
+                },
+            );
+        }
+        Ok(response.access_token)
+    }
+
+    /// Run a command source's program and read the calendar it prints, waiting
+    /// `timeout` instead of the configured timeout if given. Programs are run even
+    /// in offline mode, like local files
+    pub async fn run_command(
+        &self,
+        command: &str,
+        args: &[String],
+        timeout: Option<Duration>,
+    ) -> Result<String> {
+        use tokio::io::AsyncReadExt;
+
+        let _permit = match &self.fetches {
+            Some(fetches) => fetches.acquire().await.ok(),
+            None => None,
+        };
+
+        let mut child = tokio::process::Command::new(command)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| Error::Command(format!("Failed to run {}: {}", command, e)))?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+
+        // Read stderr alongside so a chatty program can't block on a full pipe
+        let errors = tokio::spawn(async move {
+            let mut errors = Vec::new();
+            let _ = stderr
+                .take(MAX_COMMAND_ERRORS)
+                .read_to_end(&mut errors)
+                .await;
+            String::from_utf8_lossy(&errors).into_owned()
+        });
+
+        let limit = self.config.max_response_size();
+        let run = async {
+            let mut output = Vec::new();
+            (&mut stdout)
+                .take(limit + 1)
+                .read_to_end(&mut output)
+                .await?;
+            if output.len() as u64 > limit {
+                return Err(Error::ResponseTooLarge(command.to_string(), limit));
+            }
+            Ok((child.wait().await?, output))
+        };
+        let timeout = timeout.unwrap_or(self.timeout);
+        // The program is killed when it's dropped on the way out
+        let (status, output) = tokio::time::timeout(timeout, run)
+            .await
+            .map_err(|_| Error::Command(format!("{} timed out after {:?}", command, timeout)))??;
+
+        if !status.success() {
+            let errors = errors.await.unwrap_or_default();
+            return Err(Error::Command(match errors.trim().lines().last() {
+                Some(line) => format!("{} failed with {}: {}", command, status, line),
+                None => format!("{} failed with {}", command, status),
+            }));
+        }
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    /// Read `files` from the checkout of a git repository, cloning it first or
+    /// pulling it if it was last pulled more than `interval` ago. Offline, only
+    /// an existing checkout is read
+    pub async fn fetch_git(
+        &self,
+        repo: &str,
+        branch: Option<&str>,
+        files: &[String],
+        interval: Duration,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<(String, String)>> {
+        let _permit = match &self.fetches {
+            Some(fetches) => fetches.acquire().await.ok(),
+            None => None,
+        };
+
+        let dir = git::checkout_dir(&self.config.git_dir(), repo, branch);
+        self.checkouts
+            .sync(
+                &dir,
+                repo,
+                branch,
+                interval,
+                timeout.unwrap_or(self.timeout),
+                self.fixtures.is_some(),
+            )
+            .await?;
+        git::read_files(&dir, files, self.config.max_response_size()).await
+    }
+
+    async fn fetch_fixture(&self, fixtures: &Path, url: &str) -> Result<String> {
+        let candidates = fixture_candidates(fixtures, url)?;
+        for candidate in &candidates {
+            if tokio::fs::try_exists(candidate).await? {
+                tracing::debug!("Reading {} from {}", url, candidate.display());
+                return Ok(tokio::fs::read_to_string(candidate).await?);
+            }
+        }
+
+        let tried: Vec<String> = candidates.iter().map(|c| c.display().to_string()).collect();
+        Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No fixture for {}, tried {}", url, tried.join(", ")),
+        )))
+    }
+}
+
+/// The fetcher is the provider of HTTP, `webcal:` and `file:` sources
+impl SourceProvider for Fetcher {
+    fn fetch<'a>(&'a self, identifier: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(Fetcher::fetch(self, identifier))
+    }
 }
 
 impl Default for Fetcher {
@@ -62,7 +1036,7 @@ impl Default for Fetcher {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use wiremock::matchers::{method, path};
+    use wiremock::matchers::{body_string_contains, header, header_exists, method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     const SAMPLE_ICAL: &str = r#"BEGIN:VCALENDAR
@@ -132,6 +1106,881 @@ END:VCALENDAR"#;
         let result = fetcher.fetch(&url).await;
 
         assert!(result.is_err());
+
+        // A source's own timeout replaces the fetcher's
+        let result = fetcher
+            .fetch_with_auth(&url, Credentials::default(), Some(Duration::from_secs(5)))
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_fetches() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(SAMPLE_ICAL)
+                    .set_delay(Duration::from_millis(300)),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let fetcher = Fetcher::from_config(&FetcherConfig {
+            max_concurrent_fetches: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+        let urls: Vec<String> = (0..4)
+            .map(|i| format!("{}/{}.ics", mock_server.uri(), i))
+            .collect();
+
+        // Four fetches two at a time take two rounds of the delay
+        let started = Instant::now();
+        let results = futures::future::join_all(urls.iter().map(|url| fetcher.fetch(url))).await;
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert!(started.elapsed() >= Duration::from_millis(600));
+    }
+
+    #[tokio::test]
+    async fn test_max_response_size() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        let fetcher = |max_response_size| {
+            Fetcher::from_config(&FetcherConfig {
+                max_response_size: Some(max_response_size),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let url = format!("{}/calendar.ics", mock_server.uri());
+        assert_eq!(
+            fetcher(SAMPLE_ICAL.len() as u64).fetch(&url).await.unwrap(),
+            SAMPLE_ICAL
+        );
+        let err = fetcher(100).fetch(&url).await.unwrap_err();
+        assert!(matches!(err, Error::ResponseTooLarge(_, 100)));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_command() {
+        let fetcher = Fetcher::from_config(&FetcherConfig {
+            max_response_size: Some(SAMPLE_ICAL.len() as u64),
+            ..Default::default()
+        })
+        .unwrap();
+        let script = |script: &str| vec!["-c".to_string(), script.to_string()];
+
+        let output = fetcher
+            .run_command("echo", &["-n".to_string(), SAMPLE_ICAL.to_string()], None)
+            .await
+            .unwrap();
+        assert_eq!(output, SAMPLE_ICAL);
+
+        // The last line of what the program complained about is reported
+        let err = fetcher
+            .run_command(
+                "sh",
+                &script("echo starting >&2; echo 'no login' >&2; exit 3"),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(&err, Error::Command(message) if message.ends_with(": no login")));
+
+        let err = fetcher
+            .run_command("sh", &script("sleep 5"), Some(Duration::from_millis(200)))
+            .await
+            .unwrap_err();
+        assert!(matches!(&err, Error::Command(message) if message.contains("timed out")));
+
+        let err = fetcher
+            .run_command("sh", &script("yes"), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::ResponseTooLarge(_, _)));
+
+        let err = fetcher
+            .run_command("/nonexistent/program", &[], None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Command(_)));
+    }
+
+    #[tokio::test]
+    async fn test_redirects() {
+        let upstream = MockServer::start().await;
+        let tracker = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/moved.ics"))
+            .respond_with(ResponseTemplate::new(301).insert_header("location", "/calendar.ics"))
+            .mount(&upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/calendar.ics"))
+            .and(header("authorization", "Bearer t0ken"))
+            .and(header("x-team", "ops"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/tracked.ics"))
+            .respond_with(
+                ResponseTemplate::new(302)
+                    .insert_header("location", format!("{}/track", tracker.uri())),
+            )
+            .mount(&upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/track"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&tracker)
+            .await;
+
+        let fetcher = |max_redirects| {
+            Fetcher::from_config(&FetcherConfig {
+                headers: [("X-Team".to_string(), "ops".to_string())].into(),
+                max_redirects,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let auth = SourceAuth::Bearer("t0ken".to_string());
+        let credentials = Credentials {
+            auth: Some(&auth),
+            ..Default::default()
+        };
+        let fetch = |fetcher: Fetcher, file: &str| {
+            let url = format!("{}/{}", upstream.uri(), file);
+            async move { fetcher.fetch_with_auth(&url, credentials, None).await }
+        };
+
+        // Credentials follow a redirect within the same origin
+        assert!(fetch(fetcher(None), "moved.ics").await.is_ok());
+
+        // But not to another one
+        assert!(fetch(fetcher(None), "tracked.ics").await.is_ok());
+        let requests = tracker.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(!requests[0].headers.contains_key("authorization"));
+        assert!(!requests[0].headers.contains_key("x-team"));
+
+        let err = fetch(fetcher(Some(0)), "moved.ics").await.unwrap_err();
+        assert!(matches!(err, Error::Redirect(_)));
+    }
+
+    #[tokio::test]
+    async fn test_host_rate_limit() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+        let host = reqwest::Url::parse(&mock_server.uri())
+            .unwrap()
+            .host_str()
+            .unwrap()
+            .to_string();
+
+        let fetcher = Fetcher::from_config(&FetcherConfig {
+            timeout: Some(1),
+            host_rate_limit: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        let url = format!("{}/team.ics", mock_server.uri());
+        assert!(fetcher.fetch(&url).await.is_ok());
+        // The next turn is longer away than the fetch would wait
+        let err = fetcher.fetch(&url).await.unwrap_err();
+        assert!(matches!(err, Error::RateLimited(_)));
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+
+        // Hosts can be given limits of their own
+        let fetcher = Fetcher::from_config(&FetcherConfig {
+            timeout: Some(1),
+            host_rate_limit: Some(1),
+            host_rate_limits: [(host.to_uppercase(), 10)].into(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(fetcher.fetch(&url).await.is_ok());
+        assert!(fetcher.fetch(&url).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL.replace("Test", "Moved")),
+            )
+            .mount(&mock_server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("{}/team.ics", mock_server.uri());
+        async fn refreshed(fetcher: &Fetcher) {
+            for _ in 0..50 {
+                if fetcher.refreshing.lock().unwrap().is_empty() {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+        let config = |cache_ttl| FetcherConfig {
+            cache_dir: Some(dir.path().to_path_buf()),
+            cache_ttl: Some(cache_ttl),
+            ..Default::default()
+        };
+
+        let fetcher = Fetcher::from_config(&config(300)).unwrap();
+        assert!(fetcher.fetch(&url).await.unwrap().contains("Test"));
+        // Fresh copies are used without fetching, even by a fetcher built after a restart
+        let fetcher = Fetcher::from_config(&config(300)).unwrap();
+        assert!(fetcher.fetch(&url).await.unwrap().contains("Test"));
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+
+        // Stale copies are served while they're fetched again
+        let fetcher = Fetcher::from_config(&config(0)).unwrap().into_shared();
+        assert!(fetcher.fetch(&url).await.unwrap().contains("Test"));
+        refreshed(&fetcher).await;
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 2);
+        assert!(fetcher.fetch(&url).await.unwrap().contains("Moved"));
+        refreshed(&fetcher).await;
+
+        // Unless the fetcher isn't shared, when it fetches them first
+        let fetcher = Fetcher::from_config(&config(0)).unwrap();
+        assert!(fetcher.fetch(&url).await.unwrap().contains("Moved"));
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_config() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/private.ics"))
+            .and(header("authorization", "Bearer secret"))
+            .and(header("user-agent", "calendar-bot/1.0"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        let config = FetcherConfig {
+            user_agent: Some("calendar-bot/1.0".to_string()),
+            headers: [("Authorization".to_string(), "Bearer secret".to_string())].into(),
+            ..Default::default()
+        };
+        let fetcher = Fetcher::from_config(&config).unwrap();
+        let url = format!("{}/private.ics", mock_server.uri());
+        assert!(fetcher.fetch(&url).await.is_ok());
+
+        // Without the configured header the upstream refuses the request
+        let fetcher = Fetcher::new().unwrap();
+        assert!(fetcher.fetch(&url).await.is_err());
+
+        let invalid = FetcherConfig {
+            headers: [("Bad Header".to_string(), "value".to_string())].into(),
+            ..Default::default()
+        };
+        assert!(Fetcher::from_config(&invalid).is_err());
+
+        let invalid = FetcherConfig {
+            proxy: Some("not a url".to_string()),
+            ..Default::default()
+        };
+        assert!(Fetcher::from_config(&invalid).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_resolve() {
+        let mock_server = MockServer::start().await;
+        let address = mock_server.address();
+
+        Mock::given(method("GET"))
+            .and(path("/calendar.ics"))
+            .and(header(
+                "host",
+                format!("calendar.invalid:{}", address.port()).as_str(),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        let config: FetcherConfig = serde_json::from_value(serde_json::json!({
+            "resolve": { "calendar.invalid": address.ip().to_string() }
+        }))
+        .unwrap();
+        let fetcher = Fetcher::from_config(&config).unwrap();
+        let url = format!("http://calendar.invalid:{}/calendar.ics", address.port());
+        assert!(fetcher.fetch(&url).await.is_ok());
+
+        assert!(serde_json::from_str::<FetcherConfig>(r#"{ "resolve": { "a": "b" } }"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ip_family() {
+        let v4: SocketAddr = "192.0.2.1:0".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:0".parse().unwrap();
+        let addresses = vec![v6, v4];
+        assert_eq!(
+            order_addresses(IpFamily::Any, addresses.clone()),
+            vec![v6, v4]
+        );
+        assert_eq!(order_addresses(IpFamily::Ipv4, addresses.clone()), vec![v4]);
+        assert_eq!(order_addresses(IpFamily::Ipv6, addresses.clone()), vec![v6]);
+        assert_eq!(
+            order_addresses(IpFamily::PreferIpv4, addresses.clone()),
+            vec![v4, v6]
+        );
+        assert_eq!(
+            order_addresses(IpFamily::PreferIpv6, addresses),
+            vec![v6, v4]
+        );
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/calendar.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        // The mock server only listens on IPv4
+        let url = format!(
+            "http://localhost:{}/calendar.ics",
+            mock_server.address().port()
+        );
+        let fetcher = |ip_family| {
+            Fetcher::from_config(&FetcherConfig {
+                ip_family,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        assert!(fetcher(IpFamily::Ipv4).fetch(&url).await.is_ok());
+        assert!(fetcher(IpFamily::Ipv6).fetch(&url).await.is_err());
+    }
+
+    #[test]
+    fn test_min_tls_version() {
+        let config: FetcherConfig =
+            serde_json::from_str(r#"{ "min_tls_version": "1.3" }"#).unwrap();
+        assert_eq!(config.min_tls_version, Some(TlsVersion::Tls13));
+        assert!(Fetcher::from_config(&config).is_ok());
+
+        assert!(serde_json::from_str::<FetcherConfig>(r#"{ "min_tls_version": "1.1" }"#).is_err());
+    }
+
+    #[test]
+    fn test_credentials_identity() {
+        assert_eq!(Credentials::default().identity(), None);
+
+        let bearer = |token: &str| SourceAuth::Bearer(token.to_string());
+        let (first, second) = (bearer("t0ken"), bearer("other"));
+        let with_auth = |auth| Credentials {
+            auth: Some(auth),
+            ..Default::default()
+        };
+        let identity = with_auth(&first).identity().unwrap();
+        assert!(!identity.contains("t0ken"));
+        assert_eq!(with_auth(&first).identity(), Some(identity.clone()));
+        assert_ne!(with_auth(&second).identity(), Some(identity));
+
+        let basic = |password| Credentials {
+            basic: Some(("alice", Some(password))),
+            ..Default::default()
+        };
+        assert_ne!(basic("secret").identity(), basic("other").identity());
+        assert!(!basic("secret").identity().unwrap().contains("secret"));
+        assert!(!format!("{:?}", basic("secret")).contains("secret"));
+        assert!(!format!("{:?}", with_auth(&first)).contains("t0ken"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_basic_auth() {
+        let mock_server = MockServer::start().await;
+
+        // "alice:secret" and "bob" with no password
+        Mock::given(method("GET"))
+            .and(path("/private.ics"))
+            .and(header("authorization", "Basic YWxpY2U6c2VjcmV0"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/private.ics"))
+            .and(header("authorization", "Basic Ym9iOg=="))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/private.ics"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+
+        let fetcher = Fetcher::new().unwrap();
+        let url = format!("{}/private.ics", mock_server.uri());
+        let basic = |username, password| Credentials {
+            basic: Some((username, password)),
+            auth: None,
+            client_cert: None,
+            insecure_tls: false,
+        };
+        assert!(
+            fetcher
+                .fetch_with_auth(&url, basic("alice", Some("secret")), None)
+                .await
+                .is_ok()
+        );
+        assert!(
+            fetcher
+                .fetch_with_auth(&url, basic("bob", None), None)
+                .await
+                .is_ok()
+        );
+        assert!(
+            fetcher
+                .fetch_with_auth(&url, basic("alice", Some("wrong")), None)
+                .await
+                .is_err()
+        );
+        assert!(fetcher.fetch(&url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_token() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/bearer.ics"))
+            .and(header("authorization", "Bearer t0ken"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/key.ics"))
+            .and(header("x-api-key", "k3y"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+
+        let fetcher = Fetcher::new().unwrap();
+        let fetch = |file: &str, auth: SourceAuth| {
+            let url = format!("{}/{}", mock_server.uri(), file);
+            let fetcher = &fetcher;
+            async move {
+                let credentials = Credentials {
+                    basic: None,
+                    auth: Some(&auth),
+                    client_cert: None,
+                    insecure_tls: false,
+                };
+                fetcher.fetch_with_auth(&url, credentials, None).await
+            }
+        };
+
+        assert!(
+            fetch("bearer.ics", SourceAuth::Bearer("t0ken".to_string()))
+                .await
+                .is_ok()
+        );
+        assert!(
+            fetch("bearer.ics", SourceAuth::Bearer("wrong".to_string()))
+                .await
+                .is_err()
+        );
+        let api_key = |value: &str| SourceAuth::Header {
+            name: "X-Api-Key".to_string(),
+            value: value.to_string(),
+        };
+        assert!(fetch("key.ics", api_key("k3y")).await.is_ok());
+        assert!(fetch("key.ics", api_key("wrong")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_from_s3() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/exports/team/rota%202025.ics"))
+            .and(header_exists("x-amz-date"))
+            .and(header_exists("x-amz-content-sha256"))
+            .and(header("x-amz-security-token", "session"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let fetcher = Fetcher::new().unwrap();
+        let auth = SourceAuth::S3 {
+            access_key_id: "AKIA".to_string(),
+            secret_access_key: "s3cret".to_string(),
+            session_token: Some("session".to_string()),
+            region: Some("eu-west-2".to_string()),
+            endpoint: Some(mock_server.uri()),
+        };
+        let credentials = Credentials {
+            basic: None,
+            auth: Some(&auth),
+            client_cert: None,
+            insecure_tls: false,
+        };
+        let text = fetcher
+            .fetch_with_auth("s3://exports/team/rota 2025.ics", credentials, None)
+            .await
+            .unwrap();
+        assert_eq!(text, SAMPLE_ICAL);
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let authorization = requests[0].headers["authorization"].to_str().unwrap();
+        assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIA/"));
+        assert!(authorization.contains("/eu-west-2/s3/aws4_request, "));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_oauth2() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("grant_type=client_credentials"))
+            .and(body_string_contains("client_id=ical-merge"))
+            .and(body_string_contains("client_secret=s3cret"))
+            .and(body_string_contains("scope=calendars.read+offline"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{ "access_token": "t0ken", "token_type": "Bearer", "expires_in": 3600 }"#,
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/private.ics"))
+            .and(header("authorization", "Bearer t0ken"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let fetcher = Fetcher::new().unwrap();
+        let auth = SourceAuth::Oauth2 {
+            token_url: format!("{}/token", mock_server.uri()),
+            client_id: "ical-merge".to_string(),
+            client_secret: "s3cret".to_string(),
+            scopes: vec!["calendars.read".to_string(), "offline".to_string()],
+        };
+        let credentials = Credentials {
+            basic: None,
+            auth: Some(&auth),
+            client_cert: None,
+            insecure_tls: false,
+        };
+        let url = format!("{}/private.ics", mock_server.uri());
+
+        // The token is only fetched once while it is still good
+        assert!(
+            fetcher
+                .fetch_with_auth(&url, credentials, None)
+                .await
+                .is_ok()
+        );
+        assert!(
+            fetcher
+                .fetch_with_auth(&url, credentials, None)
+                .await
+                .is_ok()
+        );
+
+        // A client the token endpoint doesn't know can't fetch anything
+        let auth = SourceAuth::Oauth2 {
+            token_url: format!("{}/missing", mock_server.uri()),
+            client_id: "ical-merge".to_string(),
+            client_secret: "wrong".to_string(),
+            scopes: vec![],
+        };
+        let credentials = Credentials {
+            basic: None,
+            auth: Some(&auth),
+            client_cert: None,
+            insecure_tls: false,
+        };
+        assert!(
+            fetcher
+                .fetch_with_auth(&url, credentials, None)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_client_cert() {
+        let cert = Path::new("tests/fixtures/tls/client.crt");
+        let key = Path::new("tests/fixtures/tls/client.key");
+        assert!(load_identity(cert, Some(key)).is_ok());
+        // Without its key the certificate is no use
+        assert!(load_identity(cert, None).is_err());
+        assert!(load_identity(Path::new("tests/fixtures/tls/missing.crt"), Some(key)).is_err());
+
+        // The key can be in the same file
+        let dir = tempfile::tempdir().unwrap();
+        let combined = dir.path().join("client.pem");
+        let pem = [std::fs::read(cert).unwrap(), std::fs::read(key).unwrap()].concat();
+        std::fs::write(&combined, pem).unwrap();
+        assert!(load_identity(&combined, None).is_ok());
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/calendar.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        let fetcher = Fetcher::new().unwrap();
+        let credentials = Credentials {
+            client_cert: Some((&combined, None)),
+            ..Default::default()
+        };
+        let url = format!("{}/calendar.ics", mock_server.uri());
+        assert!(
+            fetcher
+                .fetch_with_auth(&url, credentials, None)
+                .await
+                .is_ok()
+        );
+        assert!(
+            fetcher
+                .fetch_with_auth(&url, credentials, None)
+                .await
+                .is_ok()
+        );
+        assert_eq!(fetcher.clients.lock().unwrap().len(), 1);
+
+        // Accepting any certificate needs a client of its own
+        let insecure = Credentials {
+            insecure_tls: true,
+            ..Default::default()
+        };
+        assert!(fetcher.fetch_with_auth(&url, insecure, None).await.is_ok());
+        assert_eq!(fetcher.clients.lock().unwrap().len(), 2);
+        assert!(fetcher.fetch(&url).await.is_ok());
+        assert_eq!(fetcher.clients.lock().unwrap().len(), 2);
+    }
+
+    /// Serve `SAMPLE_ICAL` over TLS with a self-signed certificate, returning the
+    /// server's URL
+    async fn self_signed_server() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_rustls::rustls::pki_types::pem::PemObject;
+        use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+        use tokio_rustls::rustls::{ServerConfig, crypto::ring};
+
+        let certs = CertificateDer::pem_file_iter("tests/fixtures/tls/server.crt")
+            .unwrap()
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .unwrap();
+        let key = PrivateKeyDer::from_pem_file("tests/fixtures/tls/server.key").unwrap();
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    // Handshakes refused by clients that check the certificate fail here
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(read) => request.extend_from_slice(&buf[..read]),
+                        }
+                    }
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        SAMPLE_ICAL.len(),
+                        SAMPLE_ICAL
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_insecure_tls() {
+        let secure = self_signed_server().await;
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/tracked.ics"))
+            .respond_with(
+                ResponseTemplate::new(302)
+                    .insert_header("location", format!("{}/calendar.ics", secure)),
+            )
+            .mount(&upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/calendar.ics"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("etag", "\"v1\"")
+                    .set_body_string(SAMPLE_ICAL),
+            )
+            .mount(&upstream)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let fetcher = Fetcher::from_config(&FetcherConfig {
+            cache_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        })
+        .unwrap();
+        let insecure = Credentials {
+            insecure_tls: true,
+            ..Default::default()
+        };
+
+        // Only sources accepting any certificate can fetch from the self-signed server
+        let direct = format!("{}/calendar.ics", secure);
+        assert!(fetcher.fetch(&direct).await.is_err());
+        assert!(
+            fetcher
+                .fetch_with_auth(&direct, insecure, None)
+                .await
+                .is_ok()
+        );
+
+        // But not once it's been redirected to from another origin
+        let tracked = format!("{}/tracked.ics", upstream.uri());
+        assert!(
+            fetcher
+                .fetch_with_auth(&tracked, insecure, None)
+                .await
+                .is_err()
+        );
+
+        // A verified copy and its validators are never shared with an unverified one
+        let url = format!("{}/calendar.ics", upstream.uri());
+        assert!(fetcher.fetch(&url).await.is_ok());
+        assert!(fetcher.fetch_with_auth(&url, insecure, None).await.is_ok());
+        let requests: Vec<_> = upstream
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|request| request.url.path() == "/calendar.ics")
+            .collect();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[1].headers.contains_key("if-none-match"));
+    }
+
+    #[tokio::test]
+    async fn test_conditional_fetch() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/etag.ics"))
+            .and(header("if-none-match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/etag.ics"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("etag", "\"v1\"")
+                    .set_body_string(SAMPLE_ICAL),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/dated.ics"))
+            .and(header_exists("if-modified-since"))
+            .respond_with(ResponseTemplate::new(304))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/dated.ics"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("last-modified", "Mon, 03 Mar 2025 09:00:00 GMT")
+                    .set_body_string(SAMPLE_ICAL),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let fetcher = Fetcher::new().unwrap();
+        for file in ["etag.ics", "dated.ics"] {
+            let url = format!("{}/{}", mock_server.uri(), file);
+            let first = fetcher.fetch(&url).await.unwrap();
+            let second = fetcher.fetch(&url).await.unwrap();
+            assert!(first.contains("Test Event"));
+            assert_eq!(first, second);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_fixtures() {
+        let dir = std::env::temp_dir().join(format!("ical-merge-fixtures-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("calendar.example.com/team")).unwrap();
+        std::fs::write(dir.join("calendar.example.com/team/work.ics"), SAMPLE_ICAL).unwrap();
+        std::fs::write(dir.join("personal.ics"), "BEGIN:VCALENDAR\nEND:VCALENDAR\n").unwrap();
+
+        let fetcher = Fetcher::new().unwrap().with_fixtures(Some(dir.clone()));
+        assert_eq!(fetcher.fixtures(), Some(dir.as_path()));
+
+        // Host and path map onto the directory tree
+        let text = fetcher
+            .fetch("webcal://calendar.example.com/team/work.ics?token=abc")
+            .await
+            .unwrap();
+        assert!(text.contains("Test Event"));
+
+        // Falling back to the file name
+        let text = fetcher
+            .fetch("https://other.example.com/feeds/personal.ics")
+            .await
+            .unwrap();
+        assert!(text.starts_with("BEGIN:VCALENDAR"));
+
+        let err = fetcher
+            .fetch("https://other.example.com/missing.ics")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No fixture"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
@@ -154,6 +2003,79 @@ END:VCALENDAR"#;
         );
     }
 
+    #[tokio::test]
+    async fn test_fetch_address_book() {
+        let mock_server = MockServer::start().await;
+        let vcard = "BEGIN:VCARD\r\nFN:Ada Lovelace\r\nBDAY:18151210\r\nEND:VCARD\r\n";
+
+        Mock::given(method("REPORT"))
+            .and(path("/contacts/"))
+            .and(header("depth", "1"))
+            .and(body_string_contains("addressbook-query"))
+            .and(header("authorization", "Basic YWxpY2U6c2VjcmV0"))
+            .respond_with(ResponseTemplate::new(207).set_body_string(format!(
+                "<d:multistatus xmlns:d=\"DAV:\" xmlns:c=\"urn:ietf:params:xml:ns:carddav\">\
+                 <d:response><d:propstat><d:prop><c:address-data>{}</c:address-data>\
+                 </d:prop></d:propstat></d:response></d:multistatus>",
+                vcard
+            )))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/contacts.vcf"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(vcard))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let fetcher = Fetcher::new().unwrap();
+        let credentials = Credentials {
+            basic: Some(("alice", Some("secret"))),
+            ..Credentials::default()
+        };
+        let collection = fetcher
+            .fetch_address_book(
+                &format!("{}/contacts/", mock_server.uri()),
+                credentials,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(collection.trim_end(), vcard.trim_end());
+
+        let file = fetcher
+            .fetch_address_book(
+                &format!("{}/contacts.vcf", mock_server.uri()),
+                Credentials::default(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(file, vcard);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_local_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("local calendar.ics");
+        std::fs::write(&file, SAMPLE_ICAL).unwrap();
+
+        let url = reqwest::Url::from_file_path(&file).unwrap().to_string();
+        assert_eq!(local_path(&url), Some(file.clone()));
+        assert_eq!(local_path("https://example.com/calendar.ics"), None);
+
+        // Read from disk even when everything else comes from fixtures
+        let fetcher = Fetcher::new()
+            .unwrap()
+            .with_fixtures(Some(dir.path().join("fixtures")));
+        assert_eq!(fetcher.fetch(&url).await.unwrap(), SAMPLE_ICAL);
+
+        std::fs::remove_file(&file).unwrap();
+        let err = fetcher.fetch(&url).await.unwrap_err();
+        assert!(err.to_string().contains("local calendar.ics"));
+    }
+
     #[tokio::test]
     async fn test_fetch_webcal_url() {
         let mock_server = MockServer::start().await;
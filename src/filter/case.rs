@@ -0,0 +1,292 @@
+use regex::{Captures, Regex};
+
+use crate::config::CaseTransform;
+use crate::error::Result;
+
+/// Words left lowercase by title case (except at the start or end) for each language
+fn minor_words(language: &str) -> &'static [&'static str] {
+    match language {
+        "en" => &[
+            "a", "an", "and", "as", "at", "but", "by", "for", "in", "nor", "of", "on", "or", "per",
+            "the", "to", "via", "vs",
+        ],
+        "de" => &[
+            "am", "der", "die", "das", "dem", "den", "des", "im", "mit", "oder", "und", "von", "zu",
+        ],
+        "es" => &[
+            "a", "de", "del", "el", "en", "la", "las", "los", "o", "un", "una", "y",
+        ],
+        "fr" => &[
+            "à", "au", "aux", "de", "des", "du", "en", "et", "la", "le", "les", "ou", "un", "une",
+        ],
+        "nl" => &["de", "een", "en", "het", "in", "of", "op", "te", "van"],
+        _ => &[],
+    }
+}
+
+/// Language settings for case transformations
+struct Language {
+    code: Option<String>,
+    turkic: bool,
+}
+
+impl Language {
+    /// Parse a locale such as `tr`, `tr-TR` or `de_DE` down to its language code
+    fn new(locale: Option<&str>) -> Self {
+        let code = locale
+            .and_then(|l| l.split(['-', '_']).next())
+            .map(|l| l.to_lowercase());
+        let turkic = matches!(code.as_deref(), Some("tr") | Some("az"));
+        Self { code, turkic }
+    }
+
+    fn is_minor_word(&self, word: &str) -> bool {
+        let Some(code) = &self.code else {
+            return false;
+        };
+        let bare = word
+            .trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase();
+        minor_words(code).contains(&bare.as_str())
+    }
+
+    /// Lowercase the whole text at once, as `str::to_lowercase` needs the letters
+    /// around a sigma to know whether it ends a word. Only the Turkic letters are
+    /// mapped by hand first
+    fn lower(&self, text: &str) -> String {
+        if self.turkic {
+            text.replace('I', "ı").replace('İ', "i").to_lowercase()
+        } else {
+            text.to_lowercase()
+        }
+    }
+
+    fn upper(&self, text: &str) -> String {
+        if self.turkic {
+            text.replace('i', "İ").to_uppercase()
+        } else {
+            text.to_uppercase()
+        }
+    }
+
+    /// Titlecase differs from uppercase for a few characters like `ß` and the
+    /// Latin digraphs. Without a locale they're uppercased like everything else
+    fn title(&self, c: char) -> String {
+        match c {
+            _ if self.code.is_none() => c.to_uppercase().collect(),
+            'ß' => "Ss".to_string(),
+            'Ǆ' | 'ǅ' | 'ǆ' => 'ǅ'.to_string(),
+            'Ǉ' | 'ǈ' | 'ǉ' => 'ǈ'.to_string(),
+            'Ǌ' | 'ǋ' | 'ǌ' => 'ǋ'.to_string(),
+            'Ǳ' | 'ǲ' | 'ǳ' => 'ǲ'.to_string(),
+            _ => self.upper(c.encode_utf8(&mut [0; 4])),
+        }
+    }
+
+    /// Uppercase the first character and lowercase the rest
+    fn capitalize(&self, text: &str) -> String {
+        let mut chars = text.chars();
+        match chars.next() {
+            None => String::new(),
+            Some(first) => self.title(first) + &self.lower(chars.as_str()),
+        }
+    }
+}
+
+/// Apply a case transformation to text, using language specific rules when a
+/// locale is given
+pub fn transform_case(text: &str, transform: &CaseTransform, locale: Option<&str>) -> String {
+    let language = Language::new(locale);
+
+    match transform {
+        CaseTransform::Lower => language.lower(text),
+        CaseTransform::Upper => language.upper(text),
+        CaseTransform::Sentence => language.capitalize(text),
+        CaseTransform::Title => {
+            let words: Vec<&str> = text.split_whitespace().collect();
+            let last = words.len().saturating_sub(1);
+            words
+                .iter()
+                .enumerate()
+                .map(|(idx, word)| {
+                    if idx != 0 && idx != last && language.is_minor_word(word) {
+                        language.lower(word)
+                    } else {
+                        language.capitalize(word)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+    }
+}
+
+/// Words whose spelling is restored after a case transformation (e.g. `PTO`, `iOS`)
+#[derive(Debug)]
+pub struct CaseExceptions {
+    regex: Regex,
+    words: Vec<String>,
+}
+
+impl CaseExceptions {
+    /// Compile the exception list, returning `None` when there are no exceptions
+    pub fn new(words: &[String]) -> Result<Option<Self>> {
+        let alternatives: Vec<String> = words
+            .iter()
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                // Only anchor on word boundaries where the word starts/ends with a word character
+                let is_word_char =
+                    |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+                let start = if is_word_char(word.chars().next()) {
+                    r"\b"
+                } else {
+                    ""
+                };
+                let end = if is_word_char(word.chars().last()) {
+                    r"\b"
+                } else {
+                    ""
+                };
+                format!("{}{}{}", start, regex::escape(word), end)
+            })
+            .collect();
+
+        if alternatives.is_empty() {
+            return Ok(None);
+        }
+
+        let regex = Regex::new(&format!("(?i){}", alternatives.join("|")))?;
+        Ok(Some(Self {
+            regex,
+            words: words.to_vec(),
+        }))
+    }
+
+    /// Replace any case variant of an exception word with its configured spelling
+    pub fn apply(&self, text: &str) -> String {
+        self.regex
+            .replace_all(text, |caps: &Captures| {
+                let found = &caps[0];
+                self.words
+                    .iter()
+                    .find(|word| word.to_lowercase() == found.to_lowercase())
+                    .cloned()
+                    .unwrap_or_else(|| found.to_string())
+            })
+            .into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exceptions_restore_spelling() {
+        let exceptions =
+            CaseExceptions::new(&["PTO".to_string(), "iOS".to_string(), "C++".to_string()])
+                .unwrap()
+                .unwrap();
+
+        let text = transform_case("PTO and iOS review", &CaseTransform::Title, None);
+        assert_eq!(text, "Pto And Ios Review");
+        assert_eq!(exceptions.apply(&text), "PTO And iOS Review");
+
+        let text = transform_case("C++ CLASS ON PTO", &CaseTransform::Sentence, None);
+        assert_eq!(exceptions.apply(&text), "C++ class on PTO");
+
+        // Only whole words are restored
+        assert_eq!(exceptions.apply("Ptolemy"), "Ptolemy");
+    }
+
+    #[test]
+    fn test_no_exceptions() {
+        assert!(CaseExceptions::new(&[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_title_without_locale_capitalizes_every_word() {
+        assert_eq!(
+            transform_case("lord OF the rings", &CaseTransform::Title, None),
+            "Lord Of The Rings"
+        );
+    }
+
+    #[test]
+    fn test_title_leaves_minor_words_lowercase() {
+        assert_eq!(
+            transform_case("lord OF the rings", &CaseTransform::Title, Some("en")),
+            "Lord of the Rings"
+        );
+        // First and last words are always capitalized
+        assert_eq!(
+            transform_case("the end of", &CaseTransform::Title, Some("en-GB")),
+            "The End Of"
+        );
+    }
+
+    #[test]
+    fn test_turkish_dotted_i() {
+        assert_eq!(
+            transform_case("istanbul", &CaseTransform::Upper, Some("tr")),
+            "İSTANBUL"
+        );
+        assert_eq!(
+            transform_case("ISPARTA", &CaseTransform::Lower, Some("tr_TR")),
+            "ısparta"
+        );
+        assert_eq!(
+            transform_case("izmir toplantısı", &CaseTransform::Title, Some("tr")),
+            "İzmir Toplantısı"
+        );
+        assert_eq!(
+            transform_case("istanbul", &CaseTransform::Upper, None),
+            "ISTANBUL"
+        );
+    }
+
+    #[test]
+    fn test_german_sharp_s() {
+        assert_eq!(
+            transform_case("straße", &CaseTransform::Upper, Some("de")),
+            "STRASSE"
+        );
+        assert_eq!(
+            transform_case("STRASSE", &CaseTransform::Lower, Some("de")),
+            "strasse"
+        );
+    }
+
+    #[test]
+    fn test_titlecase_digraphs() {
+        assert_eq!(
+            transform_case("ǆungla", &CaseTransform::Title, Some("hr")),
+            "ǅungla"
+        );
+        assert_eq!(
+            transform_case("ßtraße", &CaseTransform::Sentence, Some("de")),
+            "Sstraße"
+        );
+    }
+
+    #[test]
+    fn test_without_locale_matches_std() {
+        // A sigma ending a word has a form of its own
+        assert_eq!(transform_case("ΟΔΟΣ", &CaseTransform::Lower, None), "οδος");
+        assert_eq!(transform_case("ΟΔΟΣ", &CaseTransform::Title, None), "Οδος");
+        assert_eq!(
+            transform_case("ΟΔΟΣ", &CaseTransform::Lower, Some("el")),
+            "οδος"
+        );
+        // Titlecase letters are only used with a locale
+        assert_eq!(
+            transform_case("ßtraße", &CaseTransform::Sentence, None),
+            "SStraße"
+        );
+        assert_eq!(
+            transform_case("ǆungla", &CaseTransform::Title, None),
+            "Ǆungla"
+        );
+    }
+}
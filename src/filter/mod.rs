@@ -1,3 +1,9 @@
+pub mod case;
 pub mod steps;
 
-pub use steps::{CompiledStep, StepResult, apply_steps, process_events};
+pub use case::{CaseExceptions, transform_case};
+pub use steps::{
+    BUFFER_PROPERTY, CompiledStep, PipelineStats, StepResult, StepStats, WOULD_FILTER_PROPERTY,
+    apply_steps, apply_steps_with_default, decorate_events, process_events,
+    process_events_with_stats, process_tagged_events_with_stats,
+};
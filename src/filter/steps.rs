@@ -1,8 +1,25 @@
-use regex::Regex;
-
-use crate::config::{CaseTransform, MatchMode, Step};
-use crate::error::Result;
+use std::sync::LazyLock;
+
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, Weekday};
+use icalendar::{Component, DatePerhapsTime, EventLike};
+use regex::{NoExpand, Regex};
+use serde::Serialize;
+
+use super::case::{CaseExceptions, transform_case};
+use crate::config::{
+    CaseTransform, DefaultAction, FilterAction, HoursMode, MatchMode, NoticePosition, Step,
+    working_hours,
+};
+use crate::error::{Error, Result};
 use crate::ical::Event;
+use crate::merge::{SOURCE_LABEL_PROPERTY, SOURCE_TAGS_PROPERTY};
+use crate::parallel;
+use crate::recurrence::shift;
+
+/// Links written out in text, ending before whitespace, quotes or angle brackets
+/// and leaving off punctuation that ends the sentence around them
+static LINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"https?://[^\s<>"]*[^\s<>".,;:!?')\]]"#).unwrap());
 
 /// A compiled pattern with associated fields
 #[derive(Debug)]
@@ -11,30 +28,40 @@ pub struct CompiledPattern {
     fields: Vec<String>,
 }
 
+/// Compile a pattern, treating it as plain text when `literal` is set
+fn build_regex(pattern: &str, literal: bool) -> Result<Regex> {
+    if literal {
+        Ok(Regex::new(&regex::escape(pattern))?)
+    } else {
+        Ok(Regex::new(pattern)?)
+    }
+}
+
 impl CompiledPattern {
-    pub fn new(pattern: &str, fields: Vec<String>) -> Result<Self> {
-        let regex = Regex::new(pattern)?;
+    pub fn new(pattern: &str, fields: Vec<String>, literal: bool) -> Result<Self> {
+        let regex = build_regex(pattern, literal)?;
         Ok(Self { regex, fields })
     }
 
+    /// Compile several patterns that search the same fields
+    pub fn compile_many(
+        patterns: &[String],
+        fields: &[String],
+        literal: bool,
+    ) -> Result<Vec<Self>> {
+        patterns
+            .iter()
+            .map(|p| Self::new(p, fields.to_vec(), literal))
+            .collect()
+    }
+
     /// Check if this pattern matches any of the specified fields in the event
     pub fn matches(&self, event: &Event) -> bool {
-        for field in &self.fields {
-            let text = match field.as_str() {
-                "summary" => event.summary(),
-                "description" => event.description(),
-                "location" => event.location(),
-                _ => None,
-            };
-
-            if let Some(text) = text
-                && self.regex.is_match(text)
-            {
-                return true;
-            }
-        }
-
-        false
+        self.fields.iter().any(|field| {
+            event
+                .field(field)
+                .is_some_and(|text| self.regex.is_match(&text))
+        })
     }
 }
 
@@ -51,26 +78,125 @@ pub enum CompiledStep {
     Allow {
         patterns: Vec<CompiledPattern>,
         mode: MatchMode,
+        action: FilterAction,
+        unless: Vec<CompiledPattern>,
     },
     Deny {
         patterns: Vec<CompiledPattern>,
         mode: MatchMode,
+        action: FilterAction,
+        unless: Vec<CompiledPattern>,
     },
     Replace {
         regex: Regex,
         replacement: String,
         field: String,
+        literal: bool,
     },
     Strip {
         field: String,
     },
+    Alarms {
+        /// Uppercase actions to keep, any when empty
+        actions: Vec<String>,
+        min_before: Option<Duration>,
+        max_before: Option<Duration>,
+    },
     Case {
         transform: CaseTransform,
         field: String,
+        locale: Option<String>,
+        exceptions: Option<CaseExceptions>,
+    },
+    Extract {
+        regex: Regex,
+        field: String,
+    },
+    Classify {
+        rules: Vec<(CompiledPattern, String)>,
+        property: Option<String>,
+    },
+    Hours {
+        start: NaiveTime,
+        end: NaiveTime,
+        days: Vec<Weekday>,
+        mode: HoursMode,
+    },
+    Buffer {
+        patterns: Vec<CompiledPattern>,
+        location: bool,
+        before: Duration,
+        after: Duration,
+        summary: String,
+    },
+    Attendees {
+        field: String,
+        format: String,
+        min: usize,
+    },
+    Organizer {
+        /// New address as a `mailto:` URI
+        address: Option<String>,
+        name: Option<String>,
+        remove: bool,
+    },
+    /// Applied when serving by `decorate_events`, so does nothing when merging
+    Soon,
+    Notice {
+        /// Text with `{source}` and `{tags}` still to be filled in for each event
+        text: String,
+        position: NoticePosition,
+    },
+    Links {
+        regex: Regex,
+        replacement: String,
+        literal: bool,
+    },
+    RawReplace {
+        regex: Regex,
+        replacement: String,
+        literal: bool,
     },
 }
 
 impl CompiledStep {
+    /// Name of the step type as used in config
+    pub fn step_type(&self) -> &'static str {
+        match self {
+            Self::Allow { .. } => "allow",
+            Self::Deny { .. } => "deny",
+            Self::Replace { .. } => "replace",
+            Self::Strip { .. } => "strip",
+            Self::Alarms { .. } => "alarms",
+            Self::Case { .. } => "case",
+            Self::Extract { .. } => "extract",
+            Self::Classify { .. } => "classify",
+            Self::Hours { .. } => "hours",
+            Self::Buffer { .. } => "buffer",
+            Self::Attendees { .. } => "attendees",
+            Self::Organizer { .. } => "organizer",
+            Self::Soon => "soon",
+            Self::Notice { .. } => "notice",
+            Self::Links { .. } => "links",
+            Self::RawReplace { .. } => "raw_replace",
+        }
+    }
+
+    /// Whether applying this step can change the event
+    fn may_modify(&self) -> bool {
+        !matches!(
+            self,
+            Self::Allow {
+                action: FilterAction::Enforce,
+                ..
+            } | Self::Deny {
+                action: FilterAction::Enforce,
+                ..
+            } | Self::Buffer { .. }
+                | Self::Soon
+        )
+    }
+
     /// Compile a single step
     pub fn compile(step: &Step) -> Result<Self> {
         match step {
@@ -78,48 +204,173 @@ impl CompiledStep {
                 patterns,
                 mode,
                 fields,
-            } => {
-                let compiled = patterns
-                    .iter()
-                    .map(|p| CompiledPattern::new(p, fields.clone()))
-                    .collect::<Result<Vec<_>>>()?;
-                Ok(Self::Allow {
-                    patterns: compiled,
-                    mode: mode.clone(),
-                })
-            }
+                action,
+                unless,
+                literal,
+            } => Ok(Self::Allow {
+                patterns: CompiledPattern::compile_many(patterns, fields, *literal)?,
+                mode: mode.clone(),
+                action: action.clone(),
+                unless: CompiledPattern::compile_many(unless, fields, *literal)?,
+            }),
             Step::Deny {
                 patterns,
                 mode,
                 fields,
-            } => {
-                let compiled = patterns
-                    .iter()
-                    .map(|p| CompiledPattern::new(p, fields.clone()))
-                    .collect::<Result<Vec<_>>>()?;
-                Ok(Self::Deny {
-                    patterns: compiled,
-                    mode: mode.clone(),
-                })
-            }
+                action,
+                unless,
+                literal,
+            } => Ok(Self::Deny {
+                patterns: CompiledPattern::compile_many(patterns, fields, *literal)?,
+                mode: mode.clone(),
+                action: action.clone(),
+                unless: CompiledPattern::compile_many(unless, fields, *literal)?,
+            }),
             Step::Replace {
                 pattern,
                 replacement,
                 field,
+                literal,
+            } => Ok(Self::Replace {
+                regex: build_regex(pattern, *literal)?,
+                replacement: replacement.clone(),
+                field: field.clone(),
+                literal: *literal,
+            }),
+            Step::Strip { field } => Ok(Self::Strip {
+                field: field.clone(),
+            }),
+            Step::Alarms {
+                actions,
+                min_before,
+                max_before,
             } => {
-                let regex = Regex::new(pattern)?;
-                Ok(Self::Replace {
-                    regex,
-                    replacement: replacement.clone(),
-                    field: field.clone(),
+                let minutes = |minutes: &Option<i64>| {
+                    minutes
+                        .map(|minutes| {
+                            Duration::try_minutes(minutes).ok_or_else(|| {
+                                Error::Config(format!(
+                                    "Alarms step {} minutes is too long to hold",
+                                    minutes
+                                ))
+                            })
+                        })
+                        .transpose()
+                };
+                Ok(Self::Alarms {
+                    actions: actions.iter().map(|action| action.to_uppercase()).collect(),
+                    min_before: minutes(min_before)?,
+                    max_before: minutes(max_before)?,
                 })
             }
-            Step::Strip { field } => Ok(Self::Strip {
+            Step::Case {
+                transform,
+                field,
+                locale,
+                exceptions,
+            } => Ok(Self::Case {
+                transform: transform.clone(),
                 field: field.clone(),
+                locale: locale.clone(),
+                exceptions: CaseExceptions::new(exceptions)?,
             }),
-            Step::Case { transform, field } => Ok(Self::Case {
-                transform: transform.clone(),
+            Step::Extract { pattern, field } => Ok(Self::Extract {
+                regex: Regex::new(pattern)?,
+                field: field.clone(),
+            }),
+            Step::Classify {
+                rules,
+                fields,
+                property,
+                literal,
+            } => {
+                let compiled = rules
+                    .iter()
+                    .map(|rule| {
+                        CompiledPattern::new(&rule.pattern, fields.clone(), *literal)
+                            .map(|pattern| (pattern, rule.category.clone()))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Self::Classify {
+                    rules: compiled,
+                    property: property
+                        .as_ref()
+                        .filter(|p| !p.eq_ignore_ascii_case("categories"))
+                        .cloned(),
+                })
+            }
+            Step::Hours {
+                start,
+                end,
+                days,
+                mode,
+            } => {
+                let (start, end, days) = working_hours(start, end, days)
+                    .map_err(|e| Error::Config(format!("Hours step {}", e)))?;
+                Ok(Self::Hours {
+                    start,
+                    end,
+                    days,
+                    mode: mode.clone(),
+                })
+            }
+            Step::Buffer {
+                patterns,
+                fields,
+                literal,
+                location,
+                before,
+                after,
+                summary,
+            } => Ok(Self::Buffer {
+                patterns: CompiledPattern::compile_many(patterns, fields, *literal)?,
+                location: *location,
+                before: Duration::minutes((*before).into()),
+                after: Duration::minutes((*after).into()),
+                summary: summary.clone(),
+            }),
+            Step::Attendees { field, format, min } => Ok(Self::Attendees {
                 field: field.clone(),
+                format: format.clone(),
+                min: *min as usize,
+            }),
+            Step::Organizer {
+                email,
+                name,
+                remove,
+            } => Ok(Self::Organizer {
+                address: email.as_ref().map(|email| {
+                    if email.to_lowercase().starts_with("mailto:") {
+                        email.clone()
+                    } else {
+                        format!("mailto:{}", email)
+                    }
+                }),
+                name: name.clone(),
+                remove: *remove,
+            }),
+            Step::Soon { .. } => Ok(Self::Soon),
+            Step::Notice { text, position } => Ok(Self::Notice {
+                text: text.clone(),
+                position: *position,
+            }),
+            Step::Links {
+                pattern,
+                replacement,
+                literal,
+            } => Ok(Self::Links {
+                regex: build_regex(pattern, *literal)?,
+                replacement: replacement.clone(),
+                literal: *literal,
+            }),
+            Step::RawReplace {
+                pattern,
+                replacement,
+                literal,
+            } => Ok(Self::RawReplace {
+                regex: build_regex(pattern, *literal)?,
+                replacement: replacement.clone(),
+                literal: *literal,
             }),
         }
     }
@@ -129,33 +380,48 @@ impl CompiledStep {
         steps.iter().map(Self::compile).collect()
     }
 
+    /// Compile steps, substituting `{name}` template variables in replacement text
+    pub fn compile_many_with_vars(steps: &[Step], vars: &[(&str, &str)]) -> Result<Vec<Self>> {
+        steps
+            .iter()
+            .map(|step| {
+                let mut compiled = Self::compile(step)?;
+                if let Self::Replace { replacement, .. }
+                | Self::Links { replacement, .. }
+                | Self::RawReplace { replacement, .. } = &mut compiled
+                {
+                    for (name, value) in vars {
+                        *replacement = replacement.replace(&format!("{{{}}}", name), value);
+                    }
+                }
+                Ok(compiled)
+            })
+            .collect()
+    }
+
     /// Apply this step to an event
     pub fn apply(&self, event: &mut Event) -> StepResult {
         match self {
-            Self::Allow { patterns, mode } => {
-                let matches: Vec<bool> = patterns.iter().map(|p| p.matches(event)).collect();
-
-                let passes = match mode {
-                    MatchMode::Any => matches.iter().any(|&m| m),
-                    MatchMode::All => matches.iter().all(|&m| m),
-                };
-
-                if passes {
+            Self::Allow {
+                patterns,
+                mode,
+                action,
+                unless,
+            } => {
+                if filter_matches(event, patterns, mode, unless) {
                     StepResult::Keep
                 } else {
-                    StepResult::Reject
+                    filter_out(event, action, "allow")
                 }
             }
-            Self::Deny { patterns, mode } => {
-                let matches: Vec<bool> = patterns.iter().map(|p| p.matches(event)).collect();
-
-                let blocked = match mode {
-                    MatchMode::Any => matches.iter().any(|&m| m),
-                    MatchMode::All => matches.iter().all(|&m| m),
-                };
-
-                if blocked {
-                    StepResult::Reject
+            Self::Deny {
+                patterns,
+                mode,
+                action,
+                unless,
+            } => {
+                if filter_matches(event, patterns, mode, unless) {
+                    filter_out(event, action, "deny")
                 } else {
                     StepResult::Keep
                 }
@@ -164,22 +430,18 @@ impl CompiledStep {
                 regex,
                 replacement,
                 field,
+                literal,
             } => {
-                let text = match field.as_str() {
-                    "summary" => event.summary().map(|s| s.to_string()),
-                    "description" => event.description().map(|s| s.to_string()),
-                    "location" => event.location().map(|s| s.to_string()),
-                    _ => None,
-                };
+                let text = event.field(field).map(|s| s.into_owned());
 
                 if let Some(text) = text {
-                    let new_text = regex.replace_all(&text, replacement);
-                    match field.as_str() {
-                        "summary" => event.set_summary(&new_text),
-                        "description" => event.set_description(&new_text),
-                        "location" => event.set_location(&new_text),
-                        _ => {}
-                    }
+                    // Literal replacements don't expand `$1` style capture references
+                    let new_text = if *literal {
+                        regex.replace_all(&text, NoExpand(replacement))
+                    } else {
+                        regex.replace_all(&text, replacement)
+                    };
+                    event.set_field(field, &new_text);
                 }
 
                 StepResult::Keep
@@ -191,85 +453,678 @@ impl CompiledStep {
 
                 StepResult::Keep
             }
-            Self::Case { transform, field } => {
-                let text = match field.as_str() {
-                    "summary" => event.summary().map(|s| s.to_string()),
-                    "description" => event.description().map(|s| s.to_string()),
-                    "location" => event.location().map(|s| s.to_string()),
-                    _ => None,
-                };
+            Self::Alarms {
+                actions,
+                min_before,
+                max_before,
+            } => {
+                if event.has_alarms() {
+                    event.retain_alarms(|action, before| {
+                        let action_kept = actions.is_empty()
+                            || action.is_some_and(|action| {
+                                actions.iter().any(|kept| kept.eq_ignore_ascii_case(action))
+                            });
+                        // Reminders whose time can't be worked out only pass without a range
+                        let in_range = match before {
+                            Some(before) => {
+                                min_before.is_none_or(|min| before >= min)
+                                    && max_before.is_none_or(|max| before <= max)
+                            }
+                            None => min_before.is_none() && max_before.is_none(),
+                        };
+                        action_kept && in_range
+                    });
+                }
+
+                StepResult::Keep
+            }
+            Self::Case {
+                transform,
+                field,
+                locale,
+                exceptions,
+            } => {
+                let text = event.field(field).map(|s| s.into_owned());
 
                 if let Some(text) = text {
-                    let new_text = match transform {
-                        CaseTransform::Lower => text.to_lowercase(),
-                        CaseTransform::Upper => text.to_uppercase(),
-                        CaseTransform::Sentence => {
-                            let mut chars = text.chars();
-                            match chars.next() {
-                                None => String::new(),
-                                Some(first) => {
-                                    first.to_uppercase().collect::<String>()
-                                        + &chars.as_str().to_lowercase()
-                                }
+                    let mut new_text = transform_case(&text, transform, locale.as_deref());
+                    if let Some(exceptions) = exceptions {
+                        new_text = exceptions.apply(&new_text);
+                    }
+                    event.set_field(field, &new_text);
+                }
+
+                StepResult::Keep
+            }
+            Self::Extract { regex, field } => {
+                let text = event.field(field).map(|s| s.into_owned());
+
+                if let Some(caps) = text.as_deref().and_then(|text| regex.captures(text)) {
+                    for name in regex.capture_names().flatten() {
+                        if let Some(value) = caps.name(name) {
+                            let property = format!("X-{}", name.replace('_', "-"));
+                            event.set_property(&property, value.as_str());
+                        }
+                    }
+                }
+
+                StepResult::Keep
+            }
+            Self::Classify { rules, property } => {
+                let categories: Vec<&str> = rules
+                    .iter()
+                    .filter(|(pattern, _)| pattern.matches(event))
+                    .map(|(_, category)| category.as_str())
+                    .collect();
+
+                if !categories.is_empty() {
+                    match property {
+                        Some(property) => event.set_property(property, &categories.join(",")),
+                        None => {
+                            for category in categories {
+                                event.add_category(category);
                             }
                         }
-                        CaseTransform::Title => text
-                            .split_whitespace()
-                            .map(|word| {
-                                let mut chars = word.chars();
-                                match chars.next() {
-                                    None => String::new(),
-                                    Some(first) => {
-                                        first.to_uppercase().collect::<String>()
-                                            + &chars.as_str().to_lowercase()
-                                    }
-                                }
-                            })
-                            .collect::<Vec<_>>()
-                            .join(" "),
-                    };
-                    match field.as_str() {
-                        "summary" => event.set_summary(&new_text),
-                        "description" => event.set_description(&new_text),
-                        "location" => event.set_location(&new_text),
-                        _ => {}
                     }
                 }
 
                 StepResult::Keep
             }
+            Self::Hours {
+                start,
+                end,
+                days,
+                mode,
+            } => {
+                if event.is_all_day() {
+                    return StepResult::Keep;
+                }
+                let (Some(event_start), Some(event_end)) = (event.start_time(), event.end_time())
+                else {
+                    return StepResult::Keep;
+                };
+
+                let windows = working_windows(event_start, event_end, *start, *end, days);
+                let (Some(first), Some(last)) = (windows.first(), windows.last()) else {
+                    return StepResult::Reject;
+                };
+                if (first.0, last.1) == (event_start, event_end) && windows.len() == 1 {
+                    return StepResult::Keep;
+                }
+
+                match mode {
+                    HoursMode::Drop => StepResult::Reject,
+                    HoursMode::Clip => {
+                        clip(event, first.0 - event_start, last.1 - event_end);
+                        StepResult::Keep
+                    }
+                }
+            }
+            // Buffers are added alongside the event once it makes it through the pipeline
+            Self::Buffer { .. } => StepResult::Keep,
+            Self::Attendees { field, format, min } => {
+                let count = event.attendee_count();
+                if count >= *min && count > 0 {
+                    let note = format.replace("{count}", &count.to_string());
+                    let text = event.field(field).unwrap_or_default();
+                    // Running the pipeline again doesn't add the count twice
+                    if !text.ends_with(&note) {
+                        let text = format!("{}{}", text, note);
+                        event.set_field(field, text.trim_start());
+                    }
+                }
+                StepResult::Keep
+            }
+            Self::Organizer {
+                address,
+                name,
+                remove,
+            } => {
+                if *remove {
+                    event.remove_properties(&["ORGANIZER"]);
+                } else if let Some(address) =
+                    address.clone().or(event.organizer().map(str::to_string))
+                {
+                    event.set_organizer(&address, name.as_deref());
+                }
+                StepResult::Keep
+            }
+            // Merges are cached, so this waits until the calendar is served
+            Self::Soon => StepResult::Keep,
+            Self::Notice { text, position } => {
+                let notice = text
+                    .replace(
+                        "{source}",
+                        event.property(SOURCE_LABEL_PROPERTY).unwrap_or_default(),
+                    )
+                    .replace(
+                        "{tags}",
+                        event.property(SOURCE_TAGS_PROPERTY).unwrap_or_default(),
+                    );
+                let description = event.description().unwrap_or_default();
+
+                // Events passing through the same step again, e.g. in a calendar
+                // including this one, only get the notice once
+                if !description.contains(&notice) {
+                    let new_description = match position {
+                        _ if description.is_empty() => notice,
+                        NoticePosition::Start => format!("{}\n\n{}", notice, description),
+                        NoticePosition::End => format!("{}\n\n{}", description, notice),
+                    };
+                    event.set_description(&new_description);
+                }
+                StepResult::Keep
+            }
+            Self::Links {
+                regex,
+                replacement,
+                literal,
+            } => {
+                let rewrite = |link: &str| {
+                    if *literal {
+                        regex.replace_all(link, NoExpand(replacement)).into_owned()
+                    } else {
+                        regex.replace_all(link, replacement.as_str()).into_owned()
+                    }
+                };
+
+                if let Some(url) = event.property("URL") {
+                    let new_url = rewrite(url);
+                    if new_url != url {
+                        event.set_property("URL", &new_url);
+                    }
+                }
+                if let Some(description) = event.description() {
+                    // Only the links are rewritten, never the text around them
+                    let new_description =
+                        LINK.replace_all(description, |caps: &regex::Captures| rewrite(&caps[0]));
+                    if new_description != description {
+                        let new_description = new_description.into_owned();
+                        event.set_description(&new_description);
+                    }
+                }
+                StepResult::Keep
+            }
+            Self::RawReplace {
+                regex,
+                replacement,
+                literal,
+            } => {
+                raw_replace(event, regex, replacement, *literal);
+                StepResult::Keep
+            }
+        }
+    }
+
+    /// Buffer events to reserve time around an event, for buffer steps that match it
+    fn buffers(&self, event: &Event) -> Vec<Event> {
+        let Self::Buffer {
+            patterns,
+            location,
+            before,
+            after,
+            summary,
+        } = self
+        else {
+            return Vec::new();
+        };
+
+        let matched = patterns.iter().any(|p| p.matches(event))
+            || (*location && event.location().is_some_and(|l| !l.trim().is_empty()));
+        if !matched || event.is_all_day() || event.property(BUFFER_PROPERTY).is_some() {
+            return Vec::new();
+        }
+        let Some(start) = event.start() else {
+            return Vec::new();
+        };
+        let end = event.end().unwrap_or_else(|| start.clone());
+
+        let mut buffers = Vec::new();
+        if *before > Duration::zero() {
+            buffers.push(buffer_event(
+                event,
+                shift(&start, -*before),
+                start,
+                summary,
+                "before",
+            ));
+        }
+        if *after > Duration::zero() {
+            buffers.push(buffer_event(
+                event,
+                end.clone(),
+                shift(&end, *after),
+                summary,
+                "after",
+            ));
+        }
+        buffers
+    }
+}
+
+/// Apply the calendar's soon steps as of `now`. These depend on when the calendar
+/// is served rather than when it was merged, so run on each response
+pub fn decorate_events(mut events: Vec<Event>, steps: &[Step], now: NaiveDateTime) -> Vec<Event> {
+    let decorations: Vec<(Option<u32>, &str)> = steps
+        .iter()
+        .filter_map(|step| match step {
+            Step::Soon { within, prefix } => Some((*within, prefix.as_str())),
+            _ => None,
+        })
+        .collect();
+    if decorations.is_empty() {
+        return events;
+    }
+
+    for event in &mut events {
+        let Some(start) = event.start_time() else {
+            continue;
+        };
+        // Leave off prefixes already there so decorating again doesn't stack them
+        let original = event.summary().unwrap_or_default();
+        let mut base = original;
+        while let Some(rest) = decorations
+            .iter()
+            .find_map(|(_, prefix)| base.strip_prefix(prefix))
+        {
+            base = rest;
+        }
+
+        let mut summary = base.to_string();
+        for (within, prefix) in &decorations {
+            let soon = match within {
+                Some(hours) => {
+                    !event.is_all_day()
+                        && start >= now
+                        && start <= now + Duration::hours((*hours).into())
+                }
+                None => start.date() == now.date(),
+            };
+            if soon {
+                summary = format!("{}{}", prefix, summary);
+            }
+        }
+        if summary != original {
+            event.set_summary(&summary);
+        }
+    }
+    events
+}
+
+/// Run a replacement over every unfolded content line of the event, then read the
+/// event back. An edit that leaves something unreadable is dropped with a warning
+fn raw_replace(event: &mut Event, regex: &Regex, replacement: &str, literal: bool) {
+    let raw = event
+        .inner()
+        .to_string()
+        .replace("\r\n ", "")
+        .replace("\r\n\t", "");
+
+    let mut changed = false;
+    let lines: Vec<String> = raw
+        .lines()
+        .map(|line| {
+            let new_line = if literal {
+                regex.replace_all(line, NoExpand(replacement))
+            } else {
+                regex.replace_all(line, replacement)
+            };
+            changed |= new_line != line;
+            new_line.into_owned()
+        })
+        .filter(|line| !line.is_empty())
+        .collect();
+    if !changed {
+        return;
+    }
+
+    let text = format!(
+        "BEGIN:VCALENDAR\r\n{}\r\nEND:VCALENDAR\r\n",
+        lines.join("\r\n")
+    );
+    let parsed = crate::ical::parse_calendar(&text).map(|calendar| calendar.into_events());
+    match parsed {
+        Ok(events) if events.len() == 1 => {
+            *event = events.into_iter().next().unwrap();
+        }
+        _ => tracing::warn!(
+            uid = event.uid(),
+            "raw_replace step left the event unreadable, keeping it unchanged"
+        ),
+    }
+}
+
+/// Property marking buffer events, holding which side of their event they are on
+pub const BUFFER_PROPERTY: &str = "X-ICAL-MERGE-BUFFER";
+
+/// Build an event reserving time on one side of another
+fn buffer_event(
+    event: &Event,
+    start: DatePerhapsTime,
+    end: DatePerhapsTime,
+    summary: &str,
+    side: &str,
+) -> Event {
+    let mut inner = icalendar::Event::new();
+    inner.summary(summary).starts(start).ends(end);
+    if let Some(uid) = event.uid() {
+        inner.uid(&format!("{}-buffer-{}", uid, side));
+        inner.add_property("RELATED-TO", uid);
+    }
+    inner.add_property(BUFFER_PROPERTY, side);
+
+    let mut buffer = Event::new(inner);
+    for name in [SOURCE_LABEL_PROPERTY, SOURCE_TAGS_PROPERTY] {
+        if let Some(value) = event.property(name) {
+            buffer.set_property(name, value);
+        }
+    }
+    buffer
+}
+
+/// The parts of `from`..`to` that fall within working hours, in order. An event
+/// with no duration is within working hours if it starts within them
+fn working_windows(
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    start: NaiveTime,
+    end: NaiveTime,
+    days: &[Weekday],
+) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+    from.date()
+        .iter_days()
+        .take_while(|date| *date <= to.date())
+        .filter(|date| days.contains(&date.weekday()))
+        .filter_map(|date| {
+            let window_start = from.max(date.and_time(start));
+            let window_end = to.min(date.and_time(end));
+            let overlaps = if from == to {
+                (date.and_time(start)..date.and_time(end)).contains(&from)
+            } else {
+                window_start < window_end
+            };
+            overlaps.then_some((window_start, window_end))
+        })
+        .collect()
+}
+
+/// Move an event's start and end by the given amounts, keeping their form and timezone
+fn clip(event: &mut Event, start_offset: Duration, end_offset: Duration) {
+    let start = event.start();
+    let end = event.end();
+    let mut inner = event.clone().into_inner();
+    if let Some(start) = start {
+        inner.starts(shift(&start, start_offset));
+    }
+    if let Some(end) = end {
+        inner.ends(shift(&end, end_offset));
+    }
+    *event = Event::new(inner);
+}
+
+/// Whether an allow/deny step's patterns match an event, taking `unless`
+/// exclusions into account
+fn filter_matches(
+    event: &Event,
+    patterns: &[CompiledPattern],
+    mode: &MatchMode,
+    unless: &[CompiledPattern],
+) -> bool {
+    let matched = match mode {
+        MatchMode::Any => patterns.iter().any(|p| p.matches(event)),
+        MatchMode::All => patterns.iter().all(|p| p.matches(event)),
+    };
+
+    matched && !unless.iter().any(|p| p.matches(event))
+}
+
+/// Summary prefix added to events that an annotating step would have rejected
+pub const WOULD_FILTER_PREFIX: &str = "[WOULD FILTER] ";
+
+/// Property recording which kinds of annotating step would have rejected an event
+pub const WOULD_FILTER_PROPERTY: &str = "X-ICAL-MERGE-WOULD-FILTER";
+
+/// Reject an event, or mark it as one that would have been rejected
+fn filter_out(event: &mut Event, action: &FilterAction, step_type: &str) -> StepResult {
+    match action {
+        FilterAction::Enforce => StepResult::Reject,
+        FilterAction::Annotate => {
+            let summary = event.summary().unwrap_or_default();
+            if !summary.starts_with(WOULD_FILTER_PREFIX) {
+                event.set_summary(&format!("{}{}", WOULD_FILTER_PREFIX, summary));
+            }
+
+            let reasons = match event.property(WOULD_FILTER_PROPERTY) {
+                Some(existing) if existing.split(',').any(|r| r == step_type) => {
+                    existing.to_string()
+                }
+                Some(existing) => format!("{},{}", existing, step_type),
+                None => step_type.to_string(),
+            };
+            event.set_property(WOULD_FILTER_PROPERTY, &reasons);
+
+            StepResult::Keep
         }
     }
 }
 
 /// Apply all steps to an event, stopping at the first rejection
 pub fn apply_steps(event: &mut Event, steps: &[CompiledStep]) -> StepResult {
-    for step in steps {
-        if step.apply(event) == StepResult::Reject {
+    apply_steps_with_default(event, steps, &DefaultAction::Allow)
+}
+
+/// Counters for a single step over one run of a pipeline
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StepStats {
+    pub step_type: &'static str,
+    /// Events that reached this step
+    pub evaluated: u64,
+    /// Events this step rejected
+    pub rejected: u64,
+    /// Events this step changed
+    pub modified: u64,
+}
+
+/// Counters for one run of a step pipeline
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PipelineStats {
+    pub steps: Vec<StepStats>,
+    /// Events rejected at the end of a deny-by-default pipeline
+    pub default_rejected: u64,
+}
+
+impl PipelineStats {
+    pub fn new(steps: &[CompiledStep]) -> Self {
+        Self {
+            steps: steps
+                .iter()
+                .map(|step| StepStats {
+                    step_type: step.step_type(),
+                    ..Default::default()
+                })
+                .collect(),
+            default_rejected: 0,
+        }
+    }
+
+    /// Add the counters from another run of the same pipeline
+    fn add(&mut self, other: &PipelineStats) {
+        for (stats, other) in self.steps.iter_mut().zip(&other.steps) {
+            stats.evaluated += other.evaluated;
+            stats.rejected += other.rejected;
+            stats.modified += other.modified;
+        }
+        self.default_rejected += other.default_rejected;
+    }
+}
+
+/// Apply all steps to an event with the given default action.
+///
+/// With [`DefaultAction::Deny`] allow steps that don't match no longer reject
+/// the event; instead the event is rejected at the end of the pipeline unless at
+/// least one allow step matched it.
+pub fn apply_steps_with_default(
+    event: &mut Event,
+    steps: &[CompiledStep],
+    default: &DefaultAction,
+) -> StepResult {
+    run_steps(event, steps, default, None, &mut Vec::new())
+}
+
+/// Run an event through the steps, collecting any buffer events to add alongside
+/// it if it is kept
+fn run_steps(
+    event: &mut Event,
+    steps: &[CompiledStep],
+    default: &DefaultAction,
+    mut stats: Option<&mut PipelineStats>,
+    added: &mut Vec<Event>,
+) -> StepResult {
+    let mut allowed = *default == DefaultAction::Allow;
+    let mut buffers = Vec::new();
+
+    for (idx, step) in steps.iter().enumerate() {
+        // Only pay for the clone when counting modifications
+        let before = match stats {
+            Some(_) if step.may_modify() => Some(event.clone()),
+            _ => None,
+        };
+
+        let result = step.apply(event);
+        let rejected = match step {
+            CompiledStep::Allow {
+                action: FilterAction::Enforce,
+                ..
+            } if *default == DefaultAction::Deny => {
+                if result == StepResult::Keep {
+                    allowed = true;
+                }
+                false
+            }
+            _ => result == StepResult::Reject,
+        };
+
+        if let Some(stats) = stats.as_deref_mut() {
+            let step_stats = &mut stats.steps[idx];
+            step_stats.evaluated += 1;
+            if rejected {
+                step_stats.rejected += 1;
+            }
+            if before.is_some_and(|before| before != *event) {
+                step_stats.modified += 1;
+            }
+        }
+
+        if rejected {
             return StepResult::Reject;
         }
+        buffers.extend(step.buffers(event));
+    }
+
+    if allowed {
+        added.extend(buffers);
+        StepResult::Keep
+    } else {
+        if let Some(stats) = stats {
+            stats.default_rejected += 1;
+        }
+        StepResult::Reject
     }
-    StepResult::Keep
+}
+
+/// Run tagged events through a step pipeline, keeping the tag of each event on
+/// the buffers added alongside it. Large calendars such as transit feeds are split
+/// across threads, keeping the events in order
+fn run_pipeline<T: Clone + Send>(
+    events: Vec<(T, Event)>,
+    steps: &[CompiledStep],
+    default: &DefaultAction,
+    stats: Option<&mut PipelineStats>,
+) -> Vec<(T, Event)> {
+    let counting = stats.is_some();
+    let chunks = parallel::map_chunks(events, |chunk| {
+        let mut chunk_stats = counting.then(|| PipelineStats::new(steps));
+        let processed = run_chunk(chunk, steps, default, chunk_stats.as_mut());
+        (processed, chunk_stats)
+    });
+
+    let mut processed = Vec::new();
+    let mut stats = stats;
+    for (events, chunk_stats) in chunks {
+        processed.extend(events);
+        if let (Some(stats), Some(chunk_stats)) = (stats.as_deref_mut(), chunk_stats) {
+            stats.add(&chunk_stats);
+        }
+    }
+    processed
+}
+
+/// Run one chunk of events through a step pipeline
+fn run_chunk<T: Clone>(
+    events: Vec<(T, Event)>,
+    steps: &[CompiledStep],
+    default: &DefaultAction,
+    mut stats: Option<&mut PipelineStats>,
+) -> Vec<(T, Event)> {
+    let mut processed = Vec::with_capacity(events.len());
+    for (tag, mut event) in events {
+        let mut added = Vec::new();
+        if run_steps(&mut event, steps, default, stats.as_deref_mut(), &mut added)
+            == StepResult::Keep
+        {
+            processed.push((tag.clone(), event));
+            processed.extend(added.into_iter().map(|buffer| (tag.clone(), buffer)));
+        }
+    }
+    processed
 }
 
 /// Process events through a step pipeline, filtering and transforming them
-pub fn process_events(events: Vec<Event>, steps: &[CompiledStep]) -> Vec<Event> {
-    events
+pub fn process_events(
+    events: Vec<Event>,
+    steps: &[CompiledStep],
+    default: &DefaultAction,
+) -> Vec<Event> {
+    let events = events.into_iter().map(|event| ((), event)).collect();
+    run_pipeline(events, steps, default, None)
         .into_iter()
-        .filter_map(|mut event| {
-            if apply_steps(&mut event, steps) == StepResult::Keep {
-                Some(event)
-            } else {
-                None
-            }
-        })
+        .map(|(_, event)| event)
         .collect()
 }
 
+/// Process events through a step pipeline, counting what each step did
+pub fn process_events_with_stats(
+    events: Vec<Event>,
+    steps: &[CompiledStep],
+    default: &DefaultAction,
+) -> (Vec<Event>, PipelineStats) {
+    let mut stats = PipelineStats::new(steps);
+    let events = events.into_iter().map(|event| ((), event)).collect();
+    let events = run_pipeline(events, steps, default, Some(&mut stats))
+        .into_iter()
+        .map(|(_, event)| event)
+        .collect();
+
+    (events, stats)
+}
+
+/// Process events through a step pipeline, counting what each step did and
+/// keeping a tag alongside each surviving event
+pub fn process_tagged_events_with_stats<T: Clone + Send>(
+    events: Vec<(T, Event)>,
+    steps: &[CompiledStep],
+    default: &DefaultAction,
+) -> (Vec<(T, Event)>, PipelineStats) {
+    let mut stats = PipelineStats::new(steps);
+    let events = run_pipeline(events, steps, default, Some(&mut stats));
+
+    (events, stats)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{MatchMode, Step};
+    use crate::config::{ClassifyRule, FilterAction, HoursMode, MatchMode, Step};
     use icalendar::{Component, EventLike};
 
     fn create_event(summary: &str, description: Option<&str>) -> Event {
@@ -303,6 +1158,9 @@ mod tests {
             patterns: vec!["(?i)meeting".to_string(), "(?i)standup".to_string()],
             mode: MatchMode::Any,
             fields: vec!["summary".to_string()],
+            action: FilterAction::Enforce,
+            unless: vec![],
+            literal: false,
         };
         let compiled = CompiledStep::compile(&step).unwrap();
 
@@ -322,6 +1180,9 @@ mod tests {
             patterns: vec!["(?i)important".to_string(), "(?i)meeting".to_string()],
             mode: MatchMode::All,
             fields: vec!["summary".to_string()],
+            action: FilterAction::Enforce,
+            unless: vec![],
+            literal: false,
         };
         let compiled = CompiledStep::compile(&step).unwrap();
 
@@ -341,6 +1202,9 @@ mod tests {
             patterns: vec!["(?i)optional".to_string(), "(?i)canceled".to_string()],
             mode: MatchMode::Any,
             fields: vec!["summary".to_string()],
+            action: FilterAction::Enforce,
+            unless: vec![],
+            literal: false,
         };
         let compiled = CompiledStep::compile(&step).unwrap();
 
@@ -360,6 +1224,9 @@ mod tests {
             patterns: vec!["(?i)optional".to_string(), "(?i)meeting".to_string()],
             mode: MatchMode::All,
             fields: vec!["summary".to_string()],
+            action: FilterAction::Enforce,
+            unless: vec![],
+            literal: false,
         };
         let compiled = CompiledStep::compile(&step).unwrap();
 
@@ -374,32 +1241,339 @@ mod tests {
     }
 
     #[test]
-    fn test_replace_step() {
-        let step = Step::Replace {
-            pattern: "^Meeting:".to_string(),
-            replacement: "[WORK]".to_string(),
-            field: "summary".to_string(),
+    fn test_replace_step() {
+        let step = Step::Replace {
+            pattern: "^Meeting:".to_string(),
+            replacement: "[WORK]".to_string(),
+            field: "summary".to_string(),
+            literal: false,
+        };
+        let compiled = CompiledStep::compile(&step).unwrap();
+
+        let mut event = create_event("Meeting: Team sync", None);
+        assert_eq!(compiled.apply(&mut event), StepResult::Keep);
+        assert_eq!(event.summary(), Some("[WORK] Team sync"));
+    }
+
+    #[test]
+    fn test_replace_step_empty_replacement() {
+        // Test that empty replacement removes the matched text
+        let step = Step::Replace {
+            pattern: "🔔 ".to_string(),
+            replacement: "".to_string(),
+            field: "summary".to_string(),
+            literal: false,
+        };
+        let compiled = CompiledStep::compile(&step).unwrap();
+
+        let mut event = create_event("🔔 Important Meeting", None);
+        assert_eq!(compiled.apply(&mut event), StepResult::Keep);
+        assert_eq!(event.summary(), Some("Important Meeting"));
+    }
+
+    #[test]
+    fn test_attendees_step() {
+        let ical = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nUID:review\r\nSUMMARY:Design review\r\n\
+            DTSTART:20250303T090000Z\r\n\
+            ATTENDEE;CN=Alice:mailto:alice@example.com\r\n\
+            ATTENDEE;CN=Bob:mailto:bob@example.com\r\n\
+            ATTENDEE;CUTYPE=ROOM;CN=Room 1:mailto:room1@example.com\r\n\
+            END:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:solo\r\nSUMMARY:Focus\r\n\
+            DTSTART:20250303T090000Z\r\nEND:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        let events = crate::ical::parse_calendar(ical).unwrap().into_events();
+        assert_eq!(events[0].attendee_count(), 2);
+
+        let step = Step::Attendees {
+            field: "summary".to_string(),
+            format: " ({count} ppl)".to_string(),
+            min: 1,
+        };
+        let compiled = CompiledStep::compile(&step).unwrap();
+
+        let mut review = events[0].clone();
+        assert_eq!(compiled.apply(&mut review), StepResult::Keep);
+        assert_eq!(review.summary(), Some("Design review (2 ppl)"));
+        compiled.apply(&mut review);
+        assert_eq!(review.summary(), Some("Design review (2 ppl)"));
+
+        // Events without attendees are left alone
+        let mut solo = events[1].clone();
+        compiled.apply(&mut solo);
+        assert_eq!(solo.summary(), Some("Focus"));
+
+        // Only large meetings once there's a minimum
+        let step = Step::Attendees {
+            field: "description".to_string(),
+            format: "Attendees: {count}".to_string(),
+            min: 3,
+        };
+        let compiled = CompiledStep::compile(&step).unwrap();
+        let mut review = events[0].clone();
+        compiled.apply(&mut review);
+        assert_eq!(review.description(), None);
+    }
+
+    #[test]
+    fn test_organizer_step() {
+        let ical = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nUID:invite\r\nSUMMARY:Lunch\r\n\
+            DTSTART:20250303T120000Z\r\n\
+            ORGANIZER;CN=Jo Bloggs;SENT-BY=\"mailto:pa@example.com\":mailto:jo@example.com\r\n\
+            END:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:own\r\nSUMMARY:Gym\r\n\
+            DTSTART:20250303T180000Z\r\nEND:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        let events = crate::ical::parse_calendar(ical).unwrap().into_events();
+        let organizer = |step: Step, event: &Event| {
+            let mut event = event.clone();
+            let compiled = CompiledStep::compile(&step).unwrap();
+            assert_eq!(compiled.apply(&mut event), StepResult::Keep);
+            event.inner().properties().get("ORGANIZER").map(|prop| {
+                let name = prop.params().get("CN").map(|cn| cn.value().to_string());
+                (prop.value().to_string(), name, prop.params().len())
+            })
+        };
+
+        // Replacing the organizer drops the original name and parameters
+        let step = Step::Organizer {
+            email: Some("calendar@example.com".to_string()),
+            name: Some("Family, shared".to_string()),
+            remove: false,
+        };
+        assert_eq!(
+            organizer(step.clone(), &events[0]),
+            Some((
+                "mailto:calendar@example.com".to_string(),
+                Some("\"Family, shared\"".to_string()),
+                1
+            ))
+        );
+        // Events without one get the new organizer
+        assert_eq!(
+            organizer(step, &events[1]).map(|(address, _, _)| address),
+            Some("mailto:calendar@example.com".to_string())
+        );
+
+        // Renaming keeps the address
+        let step = Step::Organizer {
+            email: None,
+            name: Some("Someone".to_string()),
+            remove: false,
+        };
+        assert_eq!(
+            organizer(step.clone(), &events[0]),
+            Some((
+                "mailto:jo@example.com".to_string(),
+                Some("Someone".to_string()),
+                1
+            ))
+        );
+        assert_eq!(organizer(step, &events[1]), None);
+
+        let step = Step::Organizer {
+            email: None,
+            name: None,
+            remove: true,
+        };
+        assert_eq!(organizer(step, &events[0]), None);
+    }
+
+    #[test]
+    fn test_links_step() {
+        let step = Step::Links {
+            pattern: r"^https://wiki\.corp\.example\.com/([a-z/]+)$".to_string(),
+            replacement: "https://docs.example.com/$1".to_string(),
+            literal: false,
+        };
+        let compiled = CompiledStep::compile(&step).unwrap();
+
+        let mut event = create_event(
+            "Planning",
+            Some(
+                "Agenda at https://wiki.corp.example.com/team/plan, notes in https://wiki.corp.example.com/notes.\nMention wiki.corp.example.com stays",
+            ),
+        );
+        event.set_property("URL", "https://wiki.corp.example.com/meetings/planning");
+
+        assert_eq!(compiled.apply(&mut event), StepResult::Keep);
+        assert_eq!(
+            event.property("URL"),
+            Some("https://docs.example.com/meetings/planning")
+        );
+        assert_eq!(
+            event.description(),
+            Some(
+                "Agenda at https://docs.example.com/team/plan, notes in https://docs.example.com/notes.\nMention wiki.corp.example.com stays"
+            )
+        );
+        // The pattern only ever sees links, so text around them can't match
+        assert_eq!(event.summary(), Some("Planning"));
+    }
+
+    #[test]
+    fn test_decorate_events() {
+        let event = |uid: &str, start: &str| {
+            let ical = format!(
+                "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:{}\r\nSUMMARY:{}\r\n\
+                 DTSTART{}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+                uid, uid, start
+            );
+            crate::ical::parse_calendar(&ical).unwrap().into_events()[0].clone()
+        };
+        let events = vec![
+            event("Earlier", ":20250303T080000"),
+            event("Standup", ":20250303T093000"),
+            event("Lunch", ":20250303T123000"),
+            event("Holiday", ";VALUE=DATE:20250303"),
+            event("Tomorrow", ":20250304T093000"),
+        ];
+        let now = chrono::NaiveDate::from_ymd_opt(2025, 3, 3)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap();
+        let summaries = |events: Vec<Event>| -> Vec<String> {
+            events
+                .iter()
+                .map(|e| e.summary().unwrap().to_string())
+                .collect()
         };
-        let compiled = CompiledStep::compile(&step).unwrap();
 
-        let mut event = create_event("Meeting: Team sync", None);
-        assert_eq!(compiled.apply(&mut event), StepResult::Keep);
-        assert_eq!(event.summary(), Some("[WORK] Team sync"));
+        let steps = vec![
+            Step::Soon {
+                within: None,
+                prefix: "TODAY: ".to_string(),
+            },
+            Step::Soon {
+                within: Some(2),
+                prefix: "⏰ ".to_string(),
+            },
+        ];
+        let decorated = decorate_events(events.clone(), &steps, now);
+        assert_eq!(
+            summaries(decorated.clone()),
+            vec![
+                "TODAY: Earlier",
+                "⏰ TODAY: Standup",
+                "TODAY: Lunch",
+                "TODAY: Holiday",
+                "Tomorrow"
+            ]
+        );
+
+        // Decorating again doesn't stack prefixes
+        assert_eq!(
+            summaries(decorate_events(decorated.clone(), &steps, now)),
+            summaries(decorated)
+        );
+
+        // Merging leaves the events alone
+        let compiled = CompiledStep::compile_many(&steps).unwrap();
+        assert_eq!(
+            summaries(process_events(
+                events.clone(),
+                &compiled,
+                &DefaultAction::Allow
+            )),
+            summaries(events)
+        );
     }
 
     #[test]
-    fn test_replace_step_empty_replacement() {
-        // Test that empty replacement removes the matched text
-        let step = Step::Replace {
-            pattern: "🔔 ".to_string(),
+    fn test_raw_replace_step() {
+        let ical = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:raw\r\nSUMMARY:Review\r\n\
+            DTSTART:20250303T090000Z\r\n\
+            ATTENDEE;CN=Bob;ROLE=OPT-PARTICIPANT:mailto:bob@example.com\r\n\
+            X-MS-OLK-SENSITIVITY:PRIVATE\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+        let event = crate::ical::parse_calendar(ical).unwrap().into_events()[0].clone();
+        let raw = |event: &Event| event.inner().to_string();
+
+        // Parameters can be rewritten
+        let step = Step::RawReplace {
+            pattern: "ROLE=OPT-PARTICIPANT".to_string(),
+            replacement: "ROLE=REQ-PARTICIPANT".to_string(),
+            literal: true,
+        };
+        let compiled = CompiledStep::compile(&step).unwrap();
+        let mut rewritten = event.clone();
+        assert_eq!(compiled.apply(&mut rewritten), StepResult::Keep);
+        assert!(raw(&rewritten).contains("ROLE=REQ-PARTICIPANT"));
+        assert_eq!(rewritten.summary(), Some("Review"));
+
+        // Replacing a whole line with nothing removes the property
+        let step = Step::RawReplace {
+            pattern: "^X-MS-OLK-.*$".to_string(),
             replacement: "".to_string(),
-            field: "summary".to_string(),
+            literal: false,
         };
         let compiled = CompiledStep::compile(&step).unwrap();
+        let mut stripped = event.clone();
+        compiled.apply(&mut stripped);
+        assert_eq!(stripped.property("X-MS-OLK-SENSITIVITY"), None);
+        assert!(raw(&stripped).contains("ATTENDEE"));
+
+        // An edit that breaks the event is ignored
+        let step = Step::RawReplace {
+            pattern: "^BEGIN:VEVENT$".to_string(),
+            replacement: "".to_string(),
+            literal: false,
+        };
+        let compiled = CompiledStep::compile(&step).unwrap();
+        let mut broken = event.clone();
+        assert_eq!(compiled.apply(&mut broken), StepResult::Keep);
+        assert_eq!(broken.uid(), Some("raw"));
+        assert_eq!(broken.property("X-MS-OLK-SENSITIVITY"), Some("PRIVATE"));
+    }
 
-        let mut event = create_event("🔔 Important Meeting", None);
-        assert_eq!(compiled.apply(&mut event), StepResult::Keep);
-        assert_eq!(event.summary(), Some("Important Meeting"));
+    #[test]
+    fn test_literal_patterns() {
+        let steps = vec![
+            Step::Allow {
+                patterns: vec!["C++ (advanced)?".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: true,
+            },
+            Step::Replace {
+                pattern: "(advanced)?".to_string(),
+                replacement: "$1".to_string(),
+                field: "summary".to_string(),
+                literal: true,
+            },
+        ];
+        let compiled = CompiledStep::compile_many(&steps).unwrap();
+
+        let mut event1 = create_event("Training: C++ (advanced)?", None);
+        assert_eq!(apply_steps(&mut event1, &compiled), StepResult::Keep);
+        assert_eq!(event1.summary(), Some("Training: C++ $1"));
+
+        let mut event2 = create_event("Training: CCC advanced", None);
+        assert_eq!(apply_steps(&mut event2, &compiled), StepResult::Reject);
+    }
+
+    #[test]
+    fn test_template_vars() {
+        let steps = vec![Step::Replace {
+            pattern: "^".to_string(),
+            replacement: "[{label}] ".to_string(),
+            field: "summary".to_string(),
+            literal: false,
+        }];
+        let compiled = CompiledStep::compile_many_with_vars(&steps, &[("label", "Work")]).unwrap();
+
+        let mut event = create_event("Standup", None);
+        apply_steps(&mut event, &compiled);
+        assert_eq!(event.summary(), Some("[Work] Standup"));
+
+        // Unknown variables are left alone
+        let compiled = CompiledStep::compile_many_with_vars(&steps, &[]).unwrap();
+        let mut event = create_event("Standup", None);
+        apply_steps(&mut event, &compiled);
+        assert_eq!(event.summary(), Some("[{label}] Standup"));
     }
 
     #[test]
@@ -413,6 +1587,87 @@ mod tests {
         assert_eq!(compiled.apply(&mut event), StepResult::Keep);
     }
 
+    #[test]
+    fn test_alarms_step() {
+        let ical_text = include_str!("../../tests/fixtures/england_rugby.ics");
+        let event = crate::ical::parse_calendar(ical_text).unwrap().events()[0].clone();
+        let kept = |step: Step| {
+            let compiled = CompiledStep::compile(&step).unwrap();
+            let mut event = event.clone();
+            assert_eq!(compiled.apply(&mut event), StepResult::Keep);
+            event.inner().components().len()
+        };
+
+        // The fixture has display reminders two days and an hour before
+        assert_eq!(
+            kept(Step::Alarms {
+                actions: vec!["display".to_string()],
+                min_before: None,
+                max_before: None,
+            }),
+            2
+        );
+        assert_eq!(
+            kept(Step::Alarms {
+                actions: vec![],
+                min_before: Some(30),
+                max_before: Some(120),
+            }),
+            1
+        );
+        assert_eq!(
+            kept(Step::Alarms {
+                actions: vec!["AUDIO".to_string()],
+                min_before: Some(30),
+                max_before: None,
+            }),
+            0
+        );
+    }
+
+    #[test]
+    fn test_notice_step() {
+        let notice = |text: &str, position| {
+            CompiledStep::compile(&Step::Notice {
+                text: text.to_string(),
+                position,
+            })
+            .unwrap()
+        };
+        let footer = notice(
+            "Imported via ical-merge from {source}; do not edit",
+            NoticePosition::End,
+        );
+
+        let mut event = create_event("Standup", Some("Daily sync"));
+        event.set_property(SOURCE_LABEL_PROPERTY, "Team");
+        assert_eq!(footer.apply(&mut event), StepResult::Keep);
+        assert_eq!(
+            event.description(),
+            Some("Daily sync\n\nImported via ical-merge from Team; do not edit")
+        );
+        // The same notice isn't added twice
+        footer.apply(&mut event);
+        assert_eq!(
+            event.description(),
+            Some("Daily sync\n\nImported via ical-merge from Team; do not edit")
+        );
+
+        // Events without a description or source still get one
+        let mut event = create_event("Standup", None);
+        footer.apply(&mut event);
+        assert_eq!(
+            event.description(),
+            Some("Imported via ical-merge from ; do not edit")
+        );
+
+        let header = notice("[{tags}]", NoticePosition::Start);
+        let mut event = create_event("Standup", Some("Daily sync"));
+        event.set_property(SOURCE_TAGS_PROPERTY, "work,daily");
+        header.apply(&mut event);
+        assert_eq!(event.description(), Some("[work,daily]\n\nDaily sync"));
+    }
+
     #[test]
     fn test_step_ordering() {
         // Allow then replace
@@ -421,11 +1676,15 @@ mod tests {
                 patterns: vec!["(?i)meeting".to_string()],
                 mode: MatchMode::Any,
                 fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
             },
             Step::Replace {
                 pattern: "Meeting".to_string(),
                 replacement: "[WORK]".to_string(),
                 field: "summary".to_string(),
+                literal: false,
             },
         ];
         let compiled = CompiledStep::compile_many(&steps).unwrap();
@@ -446,11 +1705,15 @@ mod tests {
                 pattern: "Meeting".to_string(),
                 replacement: "Event".to_string(),
                 field: "summary".to_string(),
+                literal: false,
             },
             Step::Allow {
                 patterns: vec!["Event".to_string()],
                 mode: MatchMode::Any,
                 fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
             },
         ];
         let compiled = CompiledStep::compile_many(&steps).unwrap();
@@ -468,11 +1731,15 @@ mod tests {
                 patterns: vec!["(?i)meeting".to_string()],
                 mode: MatchMode::Any,
                 fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
             },
             Step::Replace {
                 pattern: "Meeting".to_string(),
                 replacement: "[WORK]".to_string(),
                 field: "summary".to_string(),
+                literal: false,
             },
         ];
         let compiled = CompiledStep::compile_many(&steps).unwrap();
@@ -484,19 +1751,274 @@ mod tests {
             create_event("Break", None),
         ];
 
-        let processed = process_events(events, &compiled);
+        let processed = process_events(events, &compiled, &DefaultAction::Allow);
 
         assert_eq!(processed.len(), 2);
         assert_eq!(processed[0].summary(), Some("[WORK] 1"));
         assert_eq!(processed[1].summary(), Some("[WORK] 2"));
     }
 
+    #[test]
+    fn test_unless_exclusions() {
+        let steps = vec![
+            Step::Deny {
+                patterns: vec!["(?i)optional".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec!["(?i)board".to_string(), "(?i)all hands".to_string()],
+                literal: false,
+            },
+            Step::Allow {
+                patterns: vec!["(?i)meeting".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec!["(?i)cancel".to_string()],
+                literal: false,
+            },
+        ];
+        let compiled = CompiledStep::compile_many(&steps).unwrap();
+
+        let mut event1 = create_event("Optional meeting", None);
+        assert_eq!(apply_steps(&mut event1, &compiled), StepResult::Reject);
+
+        let mut event2 = create_event("Optional board meeting", None);
+        assert_eq!(apply_steps(&mut event2, &compiled), StepResult::Keep);
+
+        let mut event3 = create_event("Team meeting", None);
+        assert_eq!(apply_steps(&mut event3, &compiled), StepResult::Keep);
+
+        let mut event4 = create_event("Cancelled: team meeting", None);
+        assert_eq!(apply_steps(&mut event4, &compiled), StepResult::Reject);
+    }
+
+    #[test]
+    fn test_annotate_action() {
+        let steps = vec![
+            Step::Deny {
+                patterns: vec!["(?i)optional".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["summary".to_string()],
+                action: FilterAction::Annotate,
+                unless: vec![],
+                literal: false,
+            },
+            Step::Allow {
+                patterns: vec!["(?i)meeting".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["summary".to_string()],
+                action: FilterAction::Annotate,
+                unless: vec![],
+                literal: false,
+            },
+        ];
+        let compiled = CompiledStep::compile_many(&steps).unwrap();
+
+        let mut event1 = create_event("Team meeting", None);
+        assert_eq!(apply_steps(&mut event1, &compiled), StepResult::Keep);
+        assert_eq!(event1.summary(), Some("Team meeting"));
+        assert_eq!(event1.property(WOULD_FILTER_PROPERTY), None);
+
+        let mut event2 = create_event("Optional lunch", None);
+        assert_eq!(apply_steps(&mut event2, &compiled), StepResult::Keep);
+        assert_eq!(event2.summary(), Some("[WOULD FILTER] Optional lunch"));
+        assert_eq!(event2.property(WOULD_FILTER_PROPERTY), Some("deny,allow"));
+
+        // Annotating allow steps don't count as a match in deny-by-default pipelines
+        let mut event3 = create_event("Team meeting", None);
+        assert_eq!(
+            apply_steps_with_default(&mut event3, &compiled, &DefaultAction::Deny),
+            StepResult::Reject
+        );
+    }
+
+    #[test]
+    fn test_default_deny_pipeline() {
+        let steps = vec![
+            Step::Allow {
+                patterns: vec!["(?i)meeting".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
+            },
+            Step::Allow {
+                patterns: vec!["(?i)standup".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
+            },
+            Step::Deny {
+                patterns: vec!["(?i)optional".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
+            },
+        ];
+        let compiled = CompiledStep::compile_many(&steps).unwrap();
+
+        let events = vec![
+            create_event("Team meeting", None),
+            create_event("Daily standup", None),
+            create_event("Optional standup", None),
+            create_event("Lunch", None),
+        ];
+
+        // By default a non-matching allow step rejects, so nothing passes both allows
+        let processed = process_events(events.clone(), &compiled, &DefaultAction::Allow);
+        assert!(processed.is_empty());
+
+        // With deny-by-default, matching any allow step is enough
+        let processed = process_events(events, &compiled, &DefaultAction::Deny);
+        assert_eq!(processed.len(), 2);
+        assert_eq!(processed[0].summary(), Some("Team meeting"));
+        assert_eq!(processed[1].summary(), Some("Daily standup"));
+
+        // Without any allow steps everything is rejected
+        let mut event = create_event("Team meeting", None);
+        assert_eq!(
+            apply_steps_with_default(&mut event, &[], &DefaultAction::Deny),
+            StepResult::Reject
+        );
+    }
+
+    #[test]
+    fn test_process_events_with_stats() {
+        let steps = vec![
+            Step::Deny {
+                patterns: vec!["(?i)optional".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
+            },
+            Step::Replace {
+                pattern: "Meeting".to_string(),
+                replacement: "[WORK]".to_string(),
+                field: "summary".to_string(),
+                literal: false,
+            },
+            Step::Allow {
+                patterns: vec!["WORK".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
+            },
+        ];
+        let compiled = CompiledStep::compile_many(&steps).unwrap();
+
+        let events = vec![
+            create_event("Meeting 1", None),
+            create_event("Optional meeting", None),
+            create_event("Lunch", None),
+            create_event("Meeting 2", None),
+        ];
+
+        let (processed, stats) =
+            process_events_with_stats(events.clone(), &compiled, &DefaultAction::Allow);
+        assert_eq!(processed.len(), 2);
+        assert_eq!(
+            stats.steps,
+            vec![
+                StepStats {
+                    step_type: "deny",
+                    evaluated: 4,
+                    rejected: 1,
+                    modified: 0,
+                },
+                StepStats {
+                    step_type: "replace",
+                    evaluated: 3,
+                    rejected: 0,
+                    modified: 2,
+                },
+                StepStats {
+                    step_type: "allow",
+                    evaluated: 3,
+                    rejected: 1,
+                    modified: 0,
+                },
+            ]
+        );
+        assert_eq!(stats.default_rejected, 0);
+
+        // Non-matching allow steps don't reject in deny-by-default pipelines
+        let (processed, stats) = process_events_with_stats(events, &compiled, &DefaultAction::Deny);
+        assert_eq!(processed.len(), 2);
+        assert_eq!(stats.steps[2].rejected, 0);
+        assert_eq!(stats.default_rejected, 1);
+    }
+
+    #[test]
+    fn test_large_pipeline() {
+        let steps = vec![
+            Step::Deny {
+                patterns: vec!["(?i)optional".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
+            },
+            Step::Replace {
+                pattern: "Meeting".to_string(),
+                replacement: "[WORK]".to_string(),
+                field: "summary".to_string(),
+                literal: false,
+            },
+        ];
+        let compiled = CompiledStep::compile_many(&steps).unwrap();
+        let events: Vec<(usize, Event)> = (0..parallel::MIN_PARALLEL * 3)
+            .map(|idx| {
+                let summary = match idx % 3 {
+                    0 => format!("Meeting {}", idx),
+                    1 => format!("Optional {}", idx),
+                    _ => format!("Lunch {}", idx),
+                };
+                (idx, create_event(&summary, None))
+            })
+            .collect();
+
+        // However the events are split across threads they come out as one run would
+        let mut expected_stats = PipelineStats::new(&compiled);
+        let expected = run_chunk(
+            events.clone(),
+            &compiled,
+            &DefaultAction::Allow,
+            Some(&mut expected_stats),
+        );
+        let (processed, stats) =
+            process_tagged_events_with_stats(events, &compiled, &DefaultAction::Allow);
+        assert_eq!(processed, expected);
+        assert_eq!(stats, expected_stats);
+        assert_eq!(processed.len(), parallel::MIN_PARALLEL * 2);
+        assert_eq!(processed[0].1.summary(), Some("[WORK] 0"));
+        assert_eq!(stats.steps[1].modified, parallel::MIN_PARALLEL as u64);
+
+        let mut combined = stats.clone();
+        combined.add(&stats);
+        assert_eq!(combined.steps[0].evaluated, stats.steps[0].evaluated * 2);
+        assert_eq!(combined.steps[0].rejected, stats.steps[0].rejected * 2);
+    }
+
     #[test]
     fn test_multi_field_matching() {
         let step = Step::Allow {
             patterns: vec!["(?i)important".to_string()],
             mode: MatchMode::Any,
             fields: vec!["summary".to_string(), "description".to_string()],
+            action: FilterAction::Enforce,
+            unless: vec![],
+            literal: false,
         };
         let compiled = CompiledStep::compile(&step).unwrap();
 
@@ -516,6 +2038,9 @@ mod tests {
             patterns: vec!["(?i)stadium".to_string()],
             mode: MatchMode::Any,
             fields: vec!["location".to_string()],
+            action: FilterAction::Enforce,
+            unless: vec![],
+            literal: false,
         };
         let compiled = CompiledStep::compile(&step).unwrap();
 
@@ -537,11 +2062,17 @@ mod tests {
                 patterns: vec!["(?i)optional".to_string()],
                 mode: MatchMode::Any,
                 fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
             },
             Step::Allow {
                 patterns: vec!["(?i)meeting".to_string()],
                 mode: MatchMode::Any,
                 fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
             },
         ];
         let compiled = CompiledStep::compile_many(&steps).unwrap();
@@ -563,11 +2094,13 @@ mod tests {
                 pattern: "Meeting".to_string(),
                 replacement: "Event".to_string(),
                 field: "summary".to_string(),
+                literal: false,
             },
             Step::Replace {
                 pattern: "Event".to_string(),
                 replacement: "Activity".to_string(),
                 field: "summary".to_string(),
+                literal: false,
             },
         ];
         let compiled = CompiledStep::compile_many(&steps).unwrap();
@@ -584,16 +2117,19 @@ mod tests {
                 pattern: "Meeting".to_string(),
                 replacement: "Event".to_string(),
                 field: "summary".to_string(),
+                literal: false,
             },
             Step::Replace {
                 pattern: "discuss".to_string(),
                 replacement: "talk about".to_string(),
                 field: "description".to_string(),
+                literal: false,
             },
             Step::Replace {
                 pattern: "Room".to_string(),
                 replacement: "Space".to_string(),
                 field: "location".to_string(),
+                literal: false,
             },
         ];
         let compiled = CompiledStep::compile_many(&steps).unwrap();
@@ -610,11 +2146,288 @@ mod tests {
         assert_eq!(event.location(), Some("Conference Space A"));
     }
 
+    #[test]
+    fn test_classify_step_categories() {
+        let step = Step::Classify {
+            rules: vec![
+                ClassifyRule {
+                    pattern: "(?i)interview|screen".to_string(),
+                    category: "Hiring".to_string(),
+                },
+                ClassifyRule {
+                    pattern: "(?i)phone".to_string(),
+                    category: "Calls".to_string(),
+                },
+            ],
+            fields: vec!["summary".to_string()],
+            property: None,
+            literal: false,
+        };
+        let compiled = CompiledStep::compile(&step).unwrap();
+
+        let mut event1 = create_event("Phone screen with candidate", None);
+        assert_eq!(compiled.apply(&mut event1), StepResult::Keep);
+        assert_eq!(event1.categories(), vec!["Hiring", "Calls"]);
+
+        let mut event2 = create_event("Lunch", None);
+        assert_eq!(compiled.apply(&mut event2), StepResult::Keep);
+        assert!(event2.categories().is_empty());
+    }
+
+    fn timed_event(start: &str, end: Option<&str>) -> Event {
+        let end = end
+            .map(|end| format!("DTEND;TZID=Europe/London:{}\r\n", end))
+            .unwrap_or_default();
+        let ical = format!(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:test\r\nSUMMARY:Busy\r\n\
+             DTSTART;TZID=Europe/London:{}\r\n{}END:VEVENT\r\nEND:VCALENDAR\r\n",
+            start, end
+        );
+        crate::ical::parse_calendar(&ical)
+            .unwrap()
+            .into_events()
+            .remove(0)
+    }
+
+    fn hours_step(mode: HoursMode) -> CompiledStep {
+        CompiledStep::compile(&Step::Hours {
+            start: "09:00".to_string(),
+            end: "17:00".to_string(),
+            days: vec!["mon".to_string(), "tue".to_string()],
+            mode,
+        })
+        .unwrap()
+    }
+
+    fn times(event: &Event) -> (String, String) {
+        let format = |time: chrono::NaiveDateTime| time.format("%Y%m%dT%H%M%S").to_string();
+        (
+            format(event.start_time().unwrap()),
+            format(event.end_time().unwrap()),
+        )
+    }
+
+    #[test]
+    fn test_hours_step_clip() {
+        let step = hours_step(HoursMode::Clip);
+
+        // 2025-03-03 is a Monday
+        let mut inside = timed_event("20250303T100000", Some("20250303T110000"));
+        assert_eq!(step.apply(&mut inside), StepResult::Keep);
+        assert_eq!(
+            times(&inside),
+            ("20250303T100000".into(), "20250303T110000".into())
+        );
+
+        let mut late = timed_event("20250303T160000", Some("20250303T230000"));
+        assert_eq!(step.apply(&mut late), StepResult::Keep);
+        assert_eq!(
+            times(&late),
+            ("20250303T160000".into(), "20250303T170000".into())
+        );
+        assert!(
+            late.inner()
+                .properties()
+                .get("DTEND")
+                .unwrap()
+                .params()
+                .contains_key("TZID")
+        );
+
+        let mut overnight = timed_event("20250303T220000", Some("20250304T100000"));
+        assert_eq!(step.apply(&mut overnight), StepResult::Keep);
+        assert_eq!(
+            times(&overnight),
+            ("20250304T090000".into(), "20250304T100000".into())
+        );
+
+        let mut night = timed_event("20250303T010000", Some("20250303T040000"));
+        assert_eq!(step.apply(&mut night), StepResult::Reject);
+
+        // Wednesday isn't a working day
+        let mut wednesday = timed_event("20250305T100000", Some("20250305T110000"));
+        assert_eq!(step.apply(&mut wednesday), StepResult::Reject);
+
+        let mut instant = timed_event("20250303T170000", None);
+        assert_eq!(step.apply(&mut instant), StepResult::Reject);
+
+        let mut holiday = icalendar::Event::new();
+        holiday.all_day(chrono::NaiveDate::from_ymd_opt(2025, 3, 8).unwrap());
+        let mut all_day = Event::new(holiday);
+        assert_eq!(step.apply(&mut all_day), StepResult::Keep);
+    }
+
+    #[test]
+    fn test_hours_step_drop() {
+        let step = hours_step(HoursMode::Drop);
+
+        let mut inside = timed_event("20250303T090000", Some("20250303T170000"));
+        assert_eq!(step.apply(&mut inside), StepResult::Keep);
+
+        let mut late = timed_event("20250303T160000", Some("20250303T230000"));
+        assert_eq!(step.apply(&mut late), StepResult::Reject);
+
+        let mut two_days = timed_event("20250303T100000", Some("20250304T100000"));
+        assert_eq!(step.apply(&mut two_days), StepResult::Reject);
+    }
+
+    #[test]
+    fn test_buffer_step() {
+        let steps = CompiledStep::compile_many(&[
+            Step::Buffer {
+                patterns: vec!["(?i)on-?site".to_string()],
+                fields: vec!["summary".to_string()],
+                literal: false,
+                location: true,
+                before: 30,
+                after: 15,
+                summary: "Commute".to_string(),
+            },
+            Step::Deny {
+                patterns: vec!["Cancelled".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["summary".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
+            },
+        ])
+        .unwrap();
+
+        let ical = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nUID:visit\r\nSUMMARY:Onsite visit\r\n\
+            DTSTART;TZID=Europe/London:20250303T100000\r\n\
+            DTEND;TZID=Europe/London:20250303T110000\r\n\
+            X-ICAL-MERGE-SOURCE:Work\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:call\r\nSUMMARY:Call\r\n\
+            DTSTART:20250303T120000Z\r\nDTEND:20250303T123000Z\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:lunch\r\nSUMMARY:Cancelled lunch\r\nLOCATION:Cafe\r\n\
+            DTSTART:20250303T130000Z\r\nDTEND:20250303T140000Z\r\nEND:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        let events = crate::ical::parse_calendar(ical).unwrap().into_events();
+        let events = process_events(events, &steps, &DefaultAction::Allow);
+
+        let uids: Vec<_> = events.iter().map(|e| e.uid().unwrap()).collect();
+        assert_eq!(
+            uids,
+            vec!["visit", "visit-buffer-before", "visit-buffer-after", "call"]
+        );
+
+        let before = &events[1];
+        assert_eq!(before.summary(), Some("Commute"));
+        assert_eq!(
+            times(before),
+            ("20250303T093000".into(), "20250303T100000".into())
+        );
+        assert_eq!(before.property("RELATED-TO"), Some("visit"));
+        assert_eq!(before.property(BUFFER_PROPERTY), Some("before"));
+        assert_eq!(before.property(SOURCE_LABEL_PROPERTY), Some("Work"));
+        assert!(
+            before
+                .inner()
+                .properties()
+                .get("DTSTART")
+                .unwrap()
+                .params()
+                .contains_key("TZID")
+        );
+        assert_eq!(
+            times(&events[2]),
+            ("20250303T110000".into(), "20250303T111500".into())
+        );
+
+        // Buffers don't get buffers of their own in a later pipeline
+        let buffers = events[1..3].to_vec();
+        let buffers = process_events(buffers, &steps, &DefaultAction::Allow);
+        assert_eq!(buffers.len(), 2);
+    }
+
+    #[test]
+    fn test_classify_step_custom_property_then_filter() {
+        let steps = vec![
+            Step::Classify {
+                rules: vec![ClassifyRule {
+                    pattern: "(?i)interview".to_string(),
+                    category: "Hiring".to_string(),
+                }],
+                fields: vec!["summary".to_string()],
+                property: Some("X-LABEL".to_string()),
+                literal: false,
+            },
+            Step::Classify {
+                rules: vec![ClassifyRule {
+                    pattern: "(?i)interview".to_string(),
+                    category: "Hiring".to_string(),
+                }],
+                fields: vec!["summary".to_string()],
+                property: None,
+                literal: false,
+            },
+            Step::Allow {
+                patterns: vec!["^Hiring$".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["categories".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
+            },
+        ];
+        let compiled = CompiledStep::compile_many(&steps).unwrap();
+
+        let mut event1 = create_event("Interview: Jane", None);
+        assert_eq!(apply_steps(&mut event1, &compiled), StepResult::Keep);
+        assert_eq!(event1.property("X-LABEL"), Some("Hiring"));
+
+        let mut event2 = create_event("Standup", None);
+        assert_eq!(apply_steps(&mut event2, &compiled), StepResult::Reject);
+        assert_eq!(event2.property("X-LABEL"), None);
+    }
+
+    #[test]
+    fn test_extract_step() {
+        let steps = vec![
+            Step::Extract {
+                pattern: r"(?P<ticket>[A-Z]+-\d+)(?: \((?P<team_name>\w+)\))?".to_string(),
+                field: "summary".to_string(),
+            },
+            Step::Deny {
+                patterns: vec!["^OPS-".to_string()],
+                mode: MatchMode::Any,
+                fields: vec!["X-TICKET".to_string()],
+                action: FilterAction::Enforce,
+                unless: vec![],
+                literal: false,
+            },
+        ];
+        let compiled = CompiledStep::compile_many(&steps).unwrap();
+
+        let mut event1 = create_event("Fix login JIRA-42 (platform)", None);
+        assert_eq!(apply_steps(&mut event1, &compiled), StepResult::Keep);
+        assert_eq!(event1.property("X-TICKET"), Some("JIRA-42"));
+        assert_eq!(event1.property("X-TEAM-NAME"), Some("platform"));
+
+        // Optional groups that don't participate are not written
+        let mut event2 = create_event("Review JIRA-7", None);
+        assert_eq!(apply_steps(&mut event2, &compiled), StepResult::Keep);
+        assert_eq!(event2.property("X-TICKET"), Some("JIRA-7"));
+        assert_eq!(event2.property("X-TEAM-NAME"), None);
+
+        // Extracted properties can be matched by later steps
+        let mut event3 = create_event("Deploy OPS-9", None);
+        assert_eq!(apply_steps(&mut event3, &compiled), StepResult::Reject);
+
+        let mut event4 = create_event("Lunch", None);
+        assert_eq!(apply_steps(&mut event4, &compiled), StepResult::Keep);
+        assert_eq!(event4.property("X-TICKET"), None);
+    }
+
     #[test]
     fn test_case_lower() {
         let step = Step::Case {
             transform: CaseTransform::Lower,
             field: "summary".to_string(),
+            locale: None,
+            exceptions: vec![],
         };
         let compiled = CompiledStep::compile(&step).unwrap();
 
@@ -628,6 +2441,8 @@ mod tests {
         let step = Step::Case {
             transform: CaseTransform::Upper,
             field: "summary".to_string(),
+            locale: None,
+            exceptions: vec![],
         };
         let compiled = CompiledStep::compile(&step).unwrap();
 
@@ -641,6 +2456,8 @@ mod tests {
         let step = Step::Case {
             transform: CaseTransform::Sentence,
             field: "summary".to_string(),
+            locale: None,
+            exceptions: vec![],
         };
         let compiled = CompiledStep::compile(&step).unwrap();
 
@@ -658,6 +2475,8 @@ mod tests {
         let step = Step::Case {
             transform: CaseTransform::Title,
             field: "summary".to_string(),
+            locale: None,
+            exceptions: vec![],
         };
         let compiled = CompiledStep::compile(&step).unwrap();
 
@@ -674,11 +2493,28 @@ mod tests {
         assert_eq!(event3.summary(), Some("Meeting With Team"));
     }
 
+    #[test]
+    fn test_case_title_with_exceptions() {
+        let step = Step::Case {
+            transform: CaseTransform::Title,
+            field: "summary".to_string(),
+            locale: None,
+            exceptions: vec!["PTO".to_string(), "QA".to_string(), "iOS".to_string()],
+        };
+        let compiled = CompiledStep::compile(&step).unwrap();
+
+        let mut event = create_event("pto: IOS qa sync", None);
+        assert_eq!(compiled.apply(&mut event), StepResult::Keep);
+        assert_eq!(event.summary(), Some("PTO: iOS QA Sync"));
+    }
+
     #[test]
     fn test_case_on_description() {
         let step = Step::Case {
             transform: CaseTransform::Upper,
             field: "description".to_string(),
+            locale: None,
+            exceptions: vec![],
         };
         let compiled = CompiledStep::compile(&step).unwrap();
 
@@ -693,6 +2529,8 @@ mod tests {
         let step = Step::Case {
             transform: CaseTransform::Lower,
             field: "location".to_string(),
+            locale: None,
+            exceptions: vec![],
         };
         let compiled = CompiledStep::compile(&step).unwrap();
 
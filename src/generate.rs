@@ -0,0 +1,262 @@
+use std::sync::LazyLock;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use icalendar::{CalendarDateTime, Component, DatePerhapsTime, EventLike};
+use regex::Regex;
+
+use crate::config::GeneratedEventConfig;
+use crate::error::{Error, Result};
+use crate::ical::Event;
+
+/// Matches an RFC 5545 duration such as `PT1H30M`, `P1D` or `P2W`
+static DURATION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[+-]?P(\d+W|(\d+D)?(T(\d+H)?(\d+M)?(\d+S)?)?)$").unwrap());
+
+/// Start of a generated event, either a date for all-day events or a local date-time
+enum Start {
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
+}
+
+fn parse_start(start: &str) -> Result<Start> {
+    if let Ok(date) = NaiveDate::parse_from_str(start, "%Y-%m-%d") {
+        return Ok(Start::Date(date));
+    }
+
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(start, format).ok())
+        .map(Start::DateTime)
+        .ok_or_else(|| {
+            Error::Config(format!(
+                "Invalid start '{}', expected YYYY-MM-DD or YYYY-MM-DDTHH:MM[:SS]",
+                start
+            ))
+        })
+}
+
+/// Parse the end of an event, which must be the same kind as its start and not before it
+fn parse_end(event: &GeneratedEventConfig, start: &Start) -> Result<Option<Start>> {
+    let Some(end_text) = &event.end else {
+        return Ok(None);
+    };
+    if event.duration.is_some() {
+        return Err(Error::Config(
+            "Generated event can't have both an end and a duration".to_string(),
+        ));
+    }
+
+    let end = parse_start(end_text)?;
+    let in_order = match (start, &end) {
+        (Start::Date(start), Start::Date(end)) => end >= start,
+        (Start::DateTime(start), Start::DateTime(end)) => end >= start,
+        _ => {
+            return Err(Error::Config(format!(
+                "End '{}' must be a date for all-day events and a date-time otherwise, like the start",
+                end_text
+            )));
+        }
+    };
+    if !in_order {
+        return Err(Error::Config(format!(
+            "End '{}' is before the start '{}'",
+            end_text, event.start
+        )));
+    }
+    Ok(Some(end))
+}
+
+/// Check that an event definition can be generated
+pub fn validate(event: &GeneratedEventConfig) -> Result<()> {
+    if event.summary.is_empty() {
+        return Err(Error::Config(
+            "Generated event has empty summary".to_string(),
+        ));
+    }
+
+    let start = parse_start(&event.start)?;
+    parse_end(event, &start)?;
+
+    if let Some(duration) = &event.duration
+        && !DURATION.is_match(duration)
+    {
+        return Err(Error::Config(format!(
+            "Invalid duration '{}', expected an RFC 5545 duration such as PT1H30M",
+            duration
+        )));
+    }
+
+    if let Some(rrule) = &event.rrule
+        && !rrule
+            .split(';')
+            .any(|part| part.to_uppercase().starts_with("FREQ="))
+    {
+        return Err(Error::Config(format!(
+            "Invalid rrule '{}', FREQ is required",
+            rrule
+        )));
+    }
+
+    Ok(())
+}
+
+/// Build the events described by a `generate` source
+pub fn generated_events(events: &[GeneratedEventConfig]) -> Result<Vec<Event>> {
+    events
+        .iter()
+        .map(|config| {
+            validate(config)?;
+
+            let to_date_time = |start| match (start, &config.timezone) {
+                (Start::Date(date), _) => DatePerhapsTime::Date(date),
+                (Start::DateTime(date_time), Some(tzid)) => {
+                    DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone {
+                        date_time,
+                        tzid: tzid.clone(),
+                    })
+                }
+                (Start::DateTime(date_time), None) => {
+                    DatePerhapsTime::DateTime(CalendarDateTime::Floating(date_time))
+                }
+            };
+            let parsed_start = parse_start(&config.start)?;
+            // The last day of an all-day event is included, where DTEND is the day after
+            let end = parse_end(config, &parsed_start)?.map(|end| match end {
+                Start::Date(date) => Start::Date(date + chrono::Days::new(1)),
+                end => end,
+            });
+            let start = to_date_time(parsed_start);
+
+            // All-day events last a day unless told otherwise
+            let duration = match (&config.duration, &start, &end) {
+                (Some(duration), _, _) => Some(duration.to_uppercase()),
+                (None, DatePerhapsTime::Date(_), None) => Some("P1D".to_string()),
+                (None, _, _) => None,
+            };
+
+            let slug: String = config
+                .summary
+                .to_lowercase()
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '-' })
+                .collect();
+            let start_key: String = config
+                .start
+                .chars()
+                .filter(|c| c.is_ascii_digit())
+                .collect();
+
+            let mut event = icalendar::Event::new();
+            event
+                .uid(&format!("{}-{}@generated.ical-merge", start_key, slug))
+                .summary(&config.summary)
+                .starts(start);
+            if let Some(end) = end {
+                event.ends(to_date_time(end));
+            }
+            if let Some(duration) = duration {
+                event.add_property("DURATION", duration);
+            }
+            if let Some(rrule) = &config.rrule {
+                event.add_property("RRULE", rrule.to_uppercase());
+            }
+            if let Some(description) = &config.description {
+                event.description(description);
+            }
+            if let Some(location) = &config.location {
+                event.location(location);
+            }
+
+            Ok(Event::new(event.done()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ical::serialize_events;
+
+    fn config(start: &str) -> GeneratedEventConfig {
+        GeneratedEventConfig {
+            summary: "Gym".to_string(),
+            start: start.to_string(),
+            duration: Some("PT1H".to_string()),
+            end: None,
+            rrule: Some("FREQ=WEEKLY;BYDAY=MO,WE,FR".to_string()),
+            timezone: Some("Europe/London".to_string()),
+            description: None,
+            location: None,
+        }
+    }
+
+    #[test]
+    fn test_generated_event() {
+        let events = generated_events(&[config("2025-01-06T07:00")]).unwrap();
+        assert_eq!(events.len(), 1);
+
+        let event = &events[0];
+        assert_eq!(event.summary(), Some("Gym"));
+        assert_eq!(event.uid(), Some("202501060700-gym@generated.ical-merge"));
+        assert_eq!(event.property("RRULE"), Some("FREQ=WEEKLY;BYDAY=MO,WE,FR"));
+        assert_eq!(event.property("DURATION"), Some("PT1H"));
+
+        let ical = serialize_events(events);
+        assert!(ical.contains("DTSTART;TZID=Europe/London:20250106T070000"));
+    }
+
+    #[test]
+    fn test_all_day_generated_event() {
+        let mut all_day = config("2025-01-06");
+        all_day.duration = None;
+        all_day.timezone = None;
+
+        let events = generated_events(&[all_day]).unwrap();
+        assert_eq!(events[0].property("DURATION"), Some("P1D"));
+        assert!(serialize_events(events).contains("DTSTART;VALUE=DATE:20250106"));
+    }
+
+    #[test]
+    fn test_generated_event_end() {
+        let mut timed = config("2025-01-06T07:00");
+        timed.duration = None;
+        timed.end = Some("2025-01-06T08:15".to_string());
+        let mut all_day = config("2025-12-24");
+        all_day.duration = None;
+        all_day.timezone = None;
+        all_day.end = Some("2025-12-26".to_string());
+
+        let events = generated_events(&[timed, all_day]).unwrap();
+        assert_eq!(events[0].property("DURATION"), None);
+        assert_eq!(events[1].property("DURATION"), None);
+        let ical = serialize_events(events);
+        assert!(ical.contains("DTEND;TZID=Europe/London:20250106T081500"));
+        // The end is the last day, so the event runs until the start of the day after
+        assert!(ical.contains("DTEND;VALUE=DATE:20251227"));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&config("2025-01-06T07:00:00")).is_ok());
+        assert!(validate(&config("next monday")).is_err());
+
+        let mut bad_duration = config("2025-01-06");
+        bad_duration.duration = Some("1 hour".to_string());
+        assert!(validate(&bad_duration).is_err());
+
+        let mut bad_rrule = config("2025-01-06");
+        bad_rrule.rrule = Some("BYDAY=MO".to_string());
+        assert!(validate(&bad_rrule).is_err());
+
+        let mut end = config("2025-01-06T07:00");
+        end.end = Some("2025-01-06T08:00".to_string());
+        // Only one of end and duration
+        assert!(validate(&end).is_err());
+        end.duration = None;
+        assert!(validate(&end).is_ok());
+        end.end = Some("2025-01-06T06:00".to_string());
+        assert!(validate(&end).is_err());
+        end.end = Some("2025-01-07".to_string());
+        assert!(validate(&end).is_err());
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+use tokio::process::Command;
+
+use crate::error::{Error, Result};
+
+/// Seconds between pulls of a git source's repository when it doesn't say
+pub const DEFAULT_INTERVAL: u64 = 300;
+
+/// Whether a path names something inside a repository rather than reaching out of it
+pub fn is_inside(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Where the checkout of `repo` at `branch` lives under `root`, named after the
+/// repository so it's easy to find
+pub fn checkout_dir(root: &Path, repo: &str, branch: Option<&str>) -> PathBuf {
+    let digest = Sha256::digest(format!("{}\n{}", repo, branch.unwrap_or_default()));
+    let hash: String = digest[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let name: String = repo
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .rsplit(['/', '\\', ':'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        .collect();
+    if name.is_empty() {
+        root.join(hash)
+    } else {
+        root.join(format!("{}-{}", name, hash))
+    }
+}
+
+/// A git command that can't stop to ask for credentials
+fn git() -> Command {
+    let mut command = Command::new("git");
+    command
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    command
+}
+
+/// Run a git command, failing with the last line it wrote to stderr
+async fn run(command: &mut Command, action: &str, timeout: Duration) -> Result<()> {
+    // The command is killed when it's dropped on the way out
+    let output = tokio::time::timeout(timeout, command.output())
+        .await
+        .map_err(|_| Error::Command(format!("git {} timed out after {:?}", action, timeout)))?
+        .map_err(|e| Error::Command(format!("Failed to run git: {}", e)))?;
+    if !output.status.success() {
+        let errors = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::Command(match errors.trim().lines().last() {
+            Some(line) => format!("git {} failed with {}: {}", action, output.status, line),
+            None => format!("git {} failed with {}", action, output.status),
+        }));
+    }
+    Ok(())
+}
+
+/// Checkouts of git sources' repositories, along with when each was last pulled
+#[derive(Debug, Default)]
+pub struct Checkouts {
+    /// Locked while the checkout is being pulled, so merges don't pull it at once
+    pulled: Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<Option<Instant>>>>>,
+}
+
+impl Checkouts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Clone `repo` into `dir`, or pull it if it was last pulled more than
+    /// `interval` ago. Offline, only an existing checkout is used
+    pub async fn sync(
+        &self,
+        dir: &Path,
+        repo: &str,
+        branch: Option<&str>,
+        interval: Duration,
+        timeout: Duration,
+        offline: bool,
+    ) -> Result<()> {
+        let pulled = self
+            .pulled
+            .lock()
+            .unwrap()
+            .entry(dir.to_path_buf())
+            .or_default()
+            .clone();
+        let mut pulled = pulled.lock().await;
+
+        let cloned = tokio::fs::try_exists(dir.join(".git")).await?;
+        if offline {
+            return match cloned {
+                true => Ok(()),
+                false => Err(Error::Command(format!(
+                    "{} hasn't been cloned, which can't be done offline",
+                    repo
+                ))),
+            };
+        }
+        if cloned && pulled.is_some_and(|at| at.elapsed() < interval) {
+            return Ok(());
+        }
+
+        if cloned {
+            tracing::debug!("Pulling {} in {}", repo, dir.display());
+            // Resetting to what was fetched copes with history being rewritten
+            run(
+                git().current_dir(dir).args([
+                    "fetch",
+                    "--depth",
+                    "1",
+                    "origin",
+                    branch.unwrap_or("HEAD"),
+                ]),
+                "fetch",
+                timeout,
+            )
+            .await?;
+            run(
+                git()
+                    .current_dir(dir)
+                    .args(["reset", "--hard", "FETCH_HEAD"]),
+                "reset",
+                timeout,
+            )
+            .await?;
+        } else {
+            tracing::debug!("Cloning {} into {}", repo, dir.display());
+            if let Some(parent) = dir.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let mut clone = git();
+            clone.args(["clone", "--quiet", "--depth", "1"]);
+            if let Some(branch) = branch {
+                clone.args(["--branch", branch]);
+            }
+            run(clone.arg("--").arg(repo).arg(dir), "clone", timeout).await?;
+        }
+
+        *pulled = Some(Instant::now());
+        Ok(())
+    }
+}
+
+/// Read the files `paths` name in a checkout, a directory standing for every
+/// `.ics` file directly inside it. Returns each file's path in the repository
+/// along with its text
+pub async fn read_files(dir: &Path, paths: &[String], limit: u64) -> Result<Vec<(String, String)>> {
+    // Symlinks in the repository mustn't lead to files outside it
+    let root = tokio::fs::canonicalize(dir).await?;
+    let mut files = Vec::new();
+    for path in paths {
+        if !is_inside(path) {
+            return Err(Error::Config(format!(
+                "'{}' isn't a path inside the repository",
+                path
+            )));
+        }
+        let full = tokio::fs::canonicalize(dir.join(path)).await.map_err(|e| {
+            Error::Io(std::io::Error::new(
+                e.kind(),
+                format!("Failed to read {} from the repository: {}", path, e),
+            ))
+        })?;
+        if !full.starts_with(&root) {
+            return Err(Error::Config(format!(
+                "'{}' leads outside the repository",
+                path
+            )));
+        }
+
+        if tokio::fs::metadata(&full).await?.is_dir() {
+            let mut entries = tokio::fs::read_dir(&full).await?;
+            let mut names = Vec::new();
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.to_ascii_lowercase().ends_with(".ics") && entry.file_type().await?.is_file()
+                {
+                    names.push(name);
+                }
+            }
+            names.sort();
+            for name in names {
+                let path = format!("{}/{}", path.trim_end_matches('/'), name);
+                files.push((path, full.join(name)));
+            }
+        } else {
+            files.push((path.clone(), full));
+        }
+    }
+
+    let mut texts = Vec::with_capacity(files.len());
+    for (path, full) in files {
+        if tokio::fs::metadata(&full).await?.len() > limit {
+            return Err(Error::ResponseTooLarge(path, limit));
+        }
+        let bytes = tokio::fs::read(&full).await?;
+        texts.push((path, String::from_utf8_lossy(&bytes).into_owned()));
+    }
+    Ok(texts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nUID:rota@example.com\r\nDTSTART:20250106T090000Z\r\nSUMMARY:On call\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+
+    /// Commit everything in `repo` as a test author
+    fn commit(repo: &Path, message: &str) {
+        for args in [
+            vec!["add", "-A"],
+            vec![
+                "-c",
+                "user.name=Test",
+                "-c",
+                "user.email=test@example.com",
+                "commit",
+                "--quiet",
+                "-m",
+                message,
+            ],
+        ] {
+            let status = std::process::Command::new("git")
+                .current_dir(repo)
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+    }
+
+    #[test]
+    fn test_paths() {
+        assert!(is_inside("team.ics"));
+        assert!(is_inside("./calendars/team.ics"));
+        assert!(is_inside("calendars/"));
+        assert!(!is_inside(""));
+        assert!(!is_inside("../team.ics"));
+        assert!(!is_inside("calendars/../../team.ics"));
+        assert!(!is_inside("/etc/passwd"));
+
+        let root = Path::new("/var/cache/git");
+        let dir = checkout_dir(root, "https://github.com/example/rotas.git", None);
+        assert!(dir.starts_with(root));
+        assert!(
+            dir.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("rotas-")
+        );
+        assert_eq!(
+            dir,
+            checkout_dir(root, "https://github.com/example/rotas.git", None)
+        );
+        assert_ne!(
+            dir,
+            checkout_dir(root, "https://github.com/example/rotas.git", Some("next"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_and_read() {
+        let upstream = tempfile::tempdir().unwrap();
+        let status = std::process::Command::new("git")
+            .args(["init", "--quiet"])
+            .current_dir(upstream.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::create_dir(upstream.path().join("calendars")).unwrap();
+        std::fs::write(upstream.path().join("calendars/team.ics"), CALENDAR).unwrap();
+        std::fs::write(
+            upstream.path().join("calendars/notes.txt"),
+            "not a calendar",
+        )
+        .unwrap();
+        commit(upstream.path(), "Add the rota");
+
+        let checkouts = Checkouts::new();
+        let root = tempfile::tempdir().unwrap();
+        let repo = upstream.path().to_str().unwrap();
+        let dir = checkout_dir(root.path(), repo, None);
+        let timeout = Duration::from_secs(30);
+
+        // Nothing to read offline until it's been cloned
+        assert!(
+            checkouts
+                .sync(&dir, repo, None, Duration::ZERO, timeout, true)
+                .await
+                .is_err()
+        );
+        checkouts
+            .sync(&dir, repo, None, Duration::ZERO, timeout, false)
+            .await
+            .unwrap();
+        let files = read_files(&dir, &["calendars".to_string()], 1024)
+            .await
+            .unwrap();
+        assert_eq!(
+            files,
+            vec![("calendars/team.ics".to_string(), CALENDAR.to_string())]
+        );
+
+        // Changes are only pulled once the interval is up
+        let changed = CALENDAR.replace("On call", "Off");
+        std::fs::write(upstream.path().join("calendars/team.ics"), &changed).unwrap();
+        commit(upstream.path(), "Change the rota");
+        checkouts
+            .sync(&dir, repo, None, Duration::from_secs(3600), timeout, false)
+            .await
+            .unwrap();
+        let paths = ["calendars/team.ics".to_string()];
+        assert_eq!(read_files(&dir, &paths, 1024).await.unwrap()[0].1, CALENDAR);
+        checkouts
+            .sync(&dir, repo, None, Duration::ZERO, timeout, false)
+            .await
+            .unwrap();
+        assert_eq!(read_files(&dir, &paths, 1024).await.unwrap()[0].1, changed);
+
+        assert!(matches!(
+            read_files(&dir, &paths, 10).await,
+            Err(Error::ResponseTooLarge(..))
+        ));
+        assert!(
+            read_files(&dir, &["missing.ics".to_string()], 1024)
+                .await
+                .is_err()
+        );
+        assert!(
+            read_files(&dir, &["../team.ics".to_string()], 1024)
+                .await
+                .is_err()
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlink_out_of_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.ics"), CALENDAR).unwrap();
+        std::os::unix::fs::symlink(
+            outside.path().join("secret.ics"),
+            dir.path().join("team.ics"),
+        )
+        .unwrap();
+
+        let err = read_files(dir.path(), &["team.ics".to_string()], 1024)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("leads outside the repository"));
+    }
+}
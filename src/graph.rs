@@ -0,0 +1,323 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::config::{Config, SourceConfig};
+use crate::error::{Error, Result};
+
+/// Whether a node is one of the configured calendars or somewhere events come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Calendar,
+    Upstream,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub label: String,
+    /// Whether some calendar includes this upstream more than once
+    pub duplicate: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    /// Steps run on the events that come along the edge
+    pub steps: usize,
+}
+
+/// How calendars include each other and their upstreams
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+/// What a source reads, so the same feed reached in different ways is one node
+fn upstream_key(config: &Config, calendar_id: &str, idx: usize, source: &SourceConfig) -> String {
+    match source {
+        SourceConfig::Source { source, .. } => match config.sources.get(source) {
+            Some(shared) => shared.url.clone(),
+            None => format!("source:{}", source),
+        },
+        SourceConfig::Generate { .. } => format!("generated:{}:{}", calendar_id, idx),
+        SourceConfig::Inline { .. } => format!("inline:{}:{}", calendar_id, idx),
+        _ => source.origin(),
+    }
+}
+
+fn upstream_label(source: &SourceConfig, key: &str) -> String {
+    match source {
+        SourceConfig::Urls { urls, .. } if urls.len() > 1 => {
+            format!("{} (+{} mirrors)", key, urls.len() - 1)
+        }
+        SourceConfig::Generate { .. } => "generated events".to_string(),
+        SourceConfig::Inline { .. } => "inline calendar".to_string(),
+        _ => key.to_string(),
+    }
+}
+
+fn steps_label(steps: usize) -> String {
+    match steps {
+        1 => "1 step".to_string(),
+        steps => format!("{} steps", steps),
+    }
+}
+
+impl Graph {
+    /// Build the graph of `root` and everything it includes, or of every calendar
+    pub fn build(config: &Config, root: Option<&str>) -> Result<Self> {
+        let roots: Vec<String> = match root {
+            Some(id) => {
+                let id = config
+                    .resolve_calendar(id)
+                    .ok_or_else(|| Error::CalendarNotFound(id.to_string()))?;
+                vec![id.to_string()]
+            }
+            None => {
+                let mut ids: Vec<String> = config.calendars.keys().cloned().collect();
+                ids.sort();
+                ids
+            }
+        };
+
+        let mut graph = Graph::default();
+        let mut indices: HashMap<String, usize> = HashMap::new();
+        let mut pending = roots.clone();
+        let mut visited = BTreeSet::new();
+
+        while let Some(id) = pending.pop() {
+            if !visited.insert(id.clone()) {
+                continue;
+            }
+            let Some(calendar) = config.calendars.get(&id) else {
+                continue;
+            };
+
+            let from = graph.calendar(&mut indices, config, &id);
+
+            for (idx, source) in calendar.sources.iter().enumerate() {
+                let to = match source {
+                    SourceConfig::Calendar { calendar, .. } => {
+                        pending.push(calendar.clone());
+                        graph.calendar(&mut indices, config, calendar)
+                    }
+                    _ => {
+                        let key = upstream_key(config, &id, idx, source);
+                        graph.node(&mut indices, &key, |key| Node {
+                            kind: NodeKind::Upstream,
+                            label: upstream_label(source, key),
+                            duplicate: false,
+                        })
+                    }
+                };
+                graph.edges.push(Edge {
+                    from,
+                    to,
+                    steps: source.steps().len(),
+                });
+            }
+        }
+
+        for id in &visited {
+            let Some(&index) = indices.get(&format!("calendar:{}", id)) else {
+                continue;
+            };
+            let mut counts = HashMap::new();
+            graph.count_upstreams(index, &mut counts);
+            for (upstream, count) in counts {
+                if count > 1 {
+                    graph.nodes[upstream].duplicate = true;
+                }
+            }
+        }
+
+        Ok(graph)
+    }
+
+    /// Get the index of the node for `key`, adding it if it's new
+    fn node(
+        &mut self,
+        indices: &mut HashMap<String, usize>,
+        key: &str,
+        make: impl FnOnce(&str) -> Node,
+    ) -> usize {
+        *indices.entry(key.to_string()).or_insert_with(|| {
+            self.nodes.push(make(key));
+            self.nodes.len() - 1
+        })
+    }
+
+    /// Get the index of a calendar's node, labelled with its id and step count
+    fn calendar(
+        &mut self,
+        indices: &mut HashMap<String, usize>,
+        config: &Config,
+        id: &str,
+    ) -> usize {
+        let steps = config
+            .calendars
+            .get(id)
+            .map_or(0, |calendar| calendar.steps.len());
+        self.node(indices, &format!("calendar:{}", id), |_| Node {
+            kind: NodeKind::Calendar,
+            label: match steps {
+                0 => id.to_string(),
+                steps => format!("{}\n{}", id, steps_label(steps)),
+            },
+            duplicate: false,
+        })
+    }
+
+    /// Count every way a calendar reaches each upstream, through the calendars it includes
+    fn count_upstreams(&self, index: usize, counts: &mut HashMap<usize, usize>) {
+        for edge in self.edges.iter().filter(|edge| edge.from == index) {
+            match self.nodes[edge.to].kind {
+                NodeKind::Calendar => self.count_upstreams(edge.to, counts),
+                NodeKind::Upstream => *counts.entry(edge.to).or_default() += 1,
+            }
+        }
+    }
+
+    /// Upstreams some calendar includes more than once
+    pub fn duplicates(&self) -> Vec<&str> {
+        self.nodes
+            .iter()
+            .filter(|node| node.duplicate)
+            .map(|node| node.label.as_str())
+            .collect()
+    }
+
+    /// Render as a Graphviz digraph
+    pub fn to_dot(&self) -> String {
+        let escape = |text: &str| {
+            text.replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+        };
+
+        let mut out = String::from("digraph calendars {\n    rankdir=LR;\n");
+        for (index, node) in self.nodes.iter().enumerate() {
+            let shape = match node.kind {
+                NodeKind::Calendar => "box",
+                NodeKind::Upstream => "ellipse",
+            };
+            let color = if node.duplicate { ", color=red" } else { "" };
+            out.push_str(&format!(
+                "    n{} [shape={}, label=\"{}\"{}];\n",
+                index,
+                shape,
+                escape(&node.label),
+                color
+            ));
+        }
+        for edge in &self.edges {
+            match edge.steps {
+                0 => out.push_str(&format!("    n{} -> n{};\n", edge.from, edge.to)),
+                steps => out.push_str(&format!(
+                    "    n{} -> n{} [label=\"{}\"];\n",
+                    edge.from,
+                    edge.to,
+                    steps_label(steps)
+                )),
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Render as a Mermaid flowchart
+    pub fn to_mermaid(&self) -> String {
+        let escape = |text: &str| text.replace('"', "#quot;").replace('\n', "<br/>");
+
+        let mut out = String::from("flowchart LR\n");
+        for (index, node) in self.nodes.iter().enumerate() {
+            let label = escape(&node.label);
+            match node.kind {
+                NodeKind::Calendar => out.push_str(&format!("    n{}[\"{}\"]\n", index, label)),
+                NodeKind::Upstream => out.push_str(&format!("    n{}([\"{}\"])\n", index, label)),
+            }
+        }
+        for edge in &self.edges {
+            match edge.steps {
+                0 => out.push_str(&format!("    n{} --> n{}\n", edge.from, edge.to)),
+                steps => out.push_str(&format!(
+                    "    n{} -->|{}| n{}\n",
+                    edge.from,
+                    steps_label(steps),
+                    edge.to
+                )),
+            }
+        }
+
+        let duplicates: Vec<String> = (0..self.nodes.len())
+            .filter(|&index| self.nodes[index].duplicate)
+            .map(|index| format!("n{}", index))
+            .collect();
+        if !duplicates.is_empty() {
+            out.push_str("    classDef duplicate stroke:#d00,stroke-width:2px\n");
+            out.push_str(&format!("    class {} duplicate\n", duplicates.join(",")));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        serde_json::from_str(
+            r#"{
+                "sources": { "team": { "url": "https://example.com/team.ics" } },
+                "calendars": {
+                    "team": {
+                        "sources": [{ "source": "team", "steps": [{ "type": "deny", "patterns": ["x"] }] }]
+                    },
+                    "all": {
+                        "sources": [
+                            { "calendar": "team" },
+                            { "url": "https://example.com/team.ics" },
+                            { "url": "https://example.com/home.ics", "label": "Home \"main\"" }
+                        ],
+                        "steps": [{ "type": "strip", "field": "reminder" }]
+                    }
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_graph() {
+        let graph = Graph::build(&config(), None).unwrap();
+        assert_eq!(graph.nodes.len(), 4);
+        assert_eq!(graph.edges.len(), 4);
+        // The team feed reaches `all` directly and through the team calendar
+        assert_eq!(graph.duplicates(), vec!["https://example.com/team.ics"]);
+
+        // Only what the team calendar includes
+        let graph = Graph::build(&config(), Some("team")).unwrap();
+        assert_eq!(graph.nodes.len(), 2);
+        assert!(graph.duplicates().is_empty());
+        assert_eq!(
+            graph.to_dot(),
+            "digraph calendars {\n    rankdir=LR;\n\
+             \x20   n0 [shape=box, label=\"team\"];\n\
+             \x20   n1 [shape=ellipse, label=\"https://example.com/team.ics\"];\n\
+             \x20   n0 -> n1 [label=\"1 step\"];\n}\n"
+        );
+
+        assert!(Graph::build(&config(), Some("missing")).is_err());
+    }
+
+    #[test]
+    fn test_mermaid() {
+        let mermaid = Graph::build(&config(), Some("all")).unwrap().to_mermaid();
+        assert!(mermaid.starts_with("flowchart LR\n    n0[\"all<br/>1 step\"]\n"));
+        assert!(mermaid.contains("n0 --> n1\n"));
+        assert!(mermaid.contains("-->|1 step|"));
+        assert!(mermaid.contains("([\"https://example.com/home.ics\"])"));
+        assert!(mermaid.ends_with("class n2 duplicate\n"));
+    }
+}
@@ -0,0 +1,467 @@
+use std::ops::RangeInclusive;
+
+use chrono::{Datelike, Days, NaiveDate, Utc, Weekday};
+use icalendar::{Component, EventLike};
+
+use crate::error::{Error, Result};
+use crate::ical::Event;
+
+/// How a date is calculated for a given year
+#[derive(Debug, Clone, Copy)]
+enum DateRule {
+    /// A fixed month and day
+    Fixed(u32, u32),
+    /// Days relative to Easter Sunday
+    Easter(i64),
+    /// The nth weekday of a month, counting from the end of the month when negative
+    Nth(u32, Weekday, i32),
+    /// The last weekday on or before a month and day
+    OnOrBefore(u32, u32, Weekday),
+}
+
+/// What happens to holidays that fall on a weekend
+#[derive(Debug, Clone, Copy)]
+enum Observance {
+    /// Nothing, the holiday is only on its actual date
+    None,
+    /// A substitute day is given on the next free weekday
+    NextWeekday,
+    /// Saturdays are observed on the Friday before and Sundays on the Monday after
+    NearestWeekday,
+}
+
+#[derive(Debug)]
+struct Holiday {
+    name: &'static str,
+    date: DateRule,
+    /// Regions the holiday applies to, empty for the whole country
+    regions: &'static [&'static str],
+    /// First year the holiday was observed
+    since: Option<i32>,
+}
+
+const fn holiday(name: &'static str, date: DateRule) -> Holiday {
+    Holiday {
+        name,
+        date,
+        regions: &[],
+        since: None,
+    }
+}
+
+const fn regional(name: &'static str, date: DateRule, regions: &'static [&'static str]) -> Holiday {
+    Holiday {
+        name,
+        date,
+        regions,
+        since: None,
+    }
+}
+
+const fn since(name: &'static str, date: DateRule, year: i32) -> Holiday {
+    Holiday {
+        name,
+        date,
+        regions: &[],
+        since: Some(year),
+    }
+}
+
+#[derive(Debug)]
+struct Country {
+    code: &'static str,
+    /// Region used when none is configured
+    default_region: Option<&'static str>,
+    regions: &'static [&'static str],
+    observance: Observance,
+    substitute_suffix: &'static str,
+    holidays: &'static [Holiday],
+}
+
+use DateRule::*;
+
+const COUNTRIES: &[Country] = &[
+    Country {
+        code: "US",
+        default_region: None,
+        regions: &[],
+        observance: Observance::NearestWeekday,
+        substitute_suffix: " (observed)",
+        holidays: &[
+            holiday("New Year's Day", Fixed(1, 1)),
+            holiday("Martin Luther King Jr. Day", Nth(1, Weekday::Mon, 3)),
+            holiday("Washington's Birthday", Nth(2, Weekday::Mon, 3)),
+            holiday("Memorial Day", Nth(5, Weekday::Mon, -1)),
+            since("Juneteenth National Independence Day", Fixed(6, 19), 2021),
+            holiday("Independence Day", Fixed(7, 4)),
+            holiday("Labor Day", Nth(9, Weekday::Mon, 1)),
+            holiday("Columbus Day", Nth(10, Weekday::Mon, 2)),
+            holiday("Veterans Day", Fixed(11, 11)),
+            holiday("Thanksgiving Day", Nth(11, Weekday::Thu, 4)),
+            holiday("Christmas Day", Fixed(12, 25)),
+        ],
+    },
+    Country {
+        code: "GB",
+        default_region: Some("ENG"),
+        regions: &["ENG", "WLS", "SCT", "NIR"],
+        observance: Observance::NextWeekday,
+        substitute_suffix: " (substitute day)",
+        holidays: &[
+            holiday("New Year's Day", Fixed(1, 1)),
+            regional("2nd January", Fixed(1, 2), &["SCT"]),
+            regional("St Patrick's Day", Fixed(3, 17), &["NIR"]),
+            holiday("Good Friday", Easter(-2)),
+            regional("Easter Monday", Easter(1), &["ENG", "WLS", "NIR"]),
+            holiday("Early May bank holiday", Nth(5, Weekday::Mon, 1)),
+            holiday("Spring bank holiday", Nth(5, Weekday::Mon, -1)),
+            regional("Battle of the Boyne", Fixed(7, 12), &["NIR"]),
+            regional("Summer bank holiday", Nth(8, Weekday::Mon, 1), &["SCT"]),
+            regional(
+                "Summer bank holiday",
+                Nth(8, Weekday::Mon, -1),
+                &["ENG", "WLS", "NIR"],
+            ),
+            regional("St Andrew's Day", Fixed(11, 30), &["SCT"]),
+            holiday("Christmas Day", Fixed(12, 25)),
+            holiday("Boxing Day", Fixed(12, 26)),
+        ],
+    },
+    Country {
+        code: "CA",
+        default_region: None,
+        regions: &[],
+        observance: Observance::NextWeekday,
+        substitute_suffix: " (observed)",
+        holidays: &[
+            holiday("New Year's Day", Fixed(1, 1)),
+            holiday("Good Friday", Easter(-2)),
+            holiday("Victoria Day", OnOrBefore(5, 24, Weekday::Mon)),
+            holiday("Canada Day", Fixed(7, 1)),
+            holiday("Labour Day", Nth(9, Weekday::Mon, 1)),
+            since(
+                "National Day for Truth and Reconciliation",
+                Fixed(9, 30),
+                2021,
+            ),
+            holiday("Thanksgiving", Nth(10, Weekday::Mon, 2)),
+            holiday("Remembrance Day", Fixed(11, 11)),
+            holiday("Christmas Day", Fixed(12, 25)),
+            holiday("Boxing Day", Fixed(12, 26)),
+        ],
+    },
+    Country {
+        code: "DE",
+        default_region: None,
+        regions: &["BW", "BY", "BE", "HH", "NW", "SN"],
+        observance: Observance::None,
+        substitute_suffix: "",
+        holidays: &[
+            holiday("Neujahr", Fixed(1, 1)),
+            regional("Heilige Drei Könige", Fixed(1, 6), &["BW", "BY"]),
+            regional("Internationaler Frauentag", Fixed(3, 8), &["BE"]),
+            holiday("Karfreitag", Easter(-2)),
+            holiday("Ostermontag", Easter(1)),
+            holiday("Tag der Arbeit", Fixed(5, 1)),
+            holiday("Christi Himmelfahrt", Easter(39)),
+            holiday("Pfingstmontag", Easter(50)),
+            regional("Fronleichnam", Easter(60), &["BW", "BY", "NW"]),
+            holiday("Tag der Deutschen Einheit", Fixed(10, 3)),
+            regional("Reformationstag", Fixed(10, 31), &["HH", "SN"]),
+            regional("Allerheiligen", Fixed(11, 1), &["BW", "BY", "NW"]),
+            holiday("1. Weihnachtstag", Fixed(12, 25)),
+            holiday("2. Weihnachtstag", Fixed(12, 26)),
+        ],
+    },
+    Country {
+        code: "FR",
+        default_region: None,
+        regions: &[],
+        observance: Observance::None,
+        substitute_suffix: "",
+        holidays: &[
+            holiday("Jour de l'an", Fixed(1, 1)),
+            holiday("Lundi de Pâques", Easter(1)),
+            holiday("Fête du Travail", Fixed(5, 1)),
+            holiday("Victoire 1945", Fixed(5, 8)),
+            holiday("Ascension", Easter(39)),
+            holiday("Lundi de Pentecôte", Easter(50)),
+            holiday("Fête nationale", Fixed(7, 14)),
+            holiday("Assomption", Fixed(8, 15)),
+            holiday("Toussaint", Fixed(11, 1)),
+            holiday("Armistice 1918", Fixed(11, 11)),
+            holiday("Noël", Fixed(12, 25)),
+        ],
+    },
+];
+
+/// Find the holiday rules for a country and optional region such as `GB` and `SCT`
+fn find_country(country: &str, region: Option<&str>) -> Result<(&'static Country, Option<String>)> {
+    let found = COUNTRIES
+        .iter()
+        .find(|c| c.code.eq_ignore_ascii_case(country))
+        .ok_or_else(|| Error::Config(format!("No holiday data for country '{}'", country)))?;
+
+    let region = match region {
+        Some(region) => {
+            let region = region.to_uppercase();
+            if !found.regions.contains(&region.as_str()) {
+                return Err(Error::Config(format!(
+                    "No holiday data for region '{}' of country '{}'",
+                    region, found.code
+                )));
+            }
+            Some(region)
+        }
+        None => found.default_region.map(str::to_string),
+    };
+
+    Ok((found, region))
+}
+
+/// Check that holidays can be generated for a country and region
+pub fn validate(country: &str, region: Option<&str>) -> Result<()> {
+    find_country(country, region).map(|_| ())
+}
+
+/// The years holidays are generated for, from last year to two years ahead
+pub fn default_years() -> RangeInclusive<i32> {
+    let year = Utc::now().year();
+    (year - 1)..=(year + 2)
+}
+
+/// Easter Sunday using the anonymous Gregorian algorithm
+fn easter(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+impl DateRule {
+    fn date(&self, year: i32) -> Option<NaiveDate> {
+        match *self {
+            Fixed(month, day) => NaiveDate::from_ymd_opt(year, month, day),
+            Easter(offset) => easter(year).checked_add_signed(chrono::Duration::days(offset)),
+            Nth(month, weekday, n) if n < 0 => {
+                let last = NaiveDate::from_ymd_opt(year, month + 1, 1)
+                    .or_else(|| NaiveDate::from_ymd_opt(year + 1, 1, 1))?
+                    .pred_opt()?;
+                let back = (7 + last.weekday().num_days_from_monday()
+                    - weekday.num_days_from_monday())
+                    % 7;
+                last.checked_sub_days(Days::new(back as u64 + 7 * (-n - 1) as u64))
+            }
+            Nth(month, weekday, n) => {
+                NaiveDate::from_weekday_of_month_opt(year, month, weekday, n as u8)
+            }
+            OnOrBefore(month, day, weekday) => {
+                let date = NaiveDate::from_ymd_opt(year, month, day)?;
+                let back = (7 + date.weekday().num_days_from_monday()
+                    - weekday.num_days_from_monday())
+                    % 7;
+                date.checked_sub_days(Days::new(back as u64))
+            }
+        }
+    }
+}
+
+fn is_weekend(date: NaiveDate) -> bool {
+    matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+}
+
+/// Calculate the holidays for a year as (date, name) pairs
+fn holidays_for_year(
+    country: &Country,
+    region: Option<&str>,
+    year: i32,
+) -> Vec<(NaiveDate, String)> {
+    let mut dates: Vec<(NaiveDate, &str)> = country
+        .holidays
+        .iter()
+        .filter(|h| h.since.is_none_or(|since| year >= since))
+        .filter(|h| h.regions.is_empty() || region.is_some_and(|r| h.regions.contains(&r)))
+        .filter_map(|h| Some((h.date.date(year)?, h.name)))
+        .collect();
+    dates.sort();
+
+    let mut holidays: Vec<(NaiveDate, String)> = dates
+        .iter()
+        .map(|(date, name)| (*date, name.to_string()))
+        .collect();
+
+    let mut taken: Vec<NaiveDate> = dates.iter().map(|(date, _)| *date).collect();
+    for (date, name) in dates.into_iter().filter(|(date, _)| is_weekend(*date)) {
+        let observed = match country.observance {
+            Observance::None => continue,
+            Observance::NearestWeekday if date.weekday() == Weekday::Sat => date.pred_opt(),
+            Observance::NearestWeekday => date.succ_opt(),
+            Observance::NextWeekday => {
+                // Find the next weekday that isn't already a holiday
+                let mut next = date.succ_opt();
+                while let Some(day) = next.filter(|d| is_weekend(*d) || taken.contains(d)) {
+                    next = day.succ_opt();
+                }
+                next
+            }
+        };
+
+        if let Some(observed) = observed {
+            taken.push(observed);
+            holidays.push((observed, format!("{}{}", name, country.substitute_suffix)));
+        }
+    }
+
+    holidays.sort();
+    holidays
+}
+
+/// Generate all-day public holiday events for a country and optional region
+pub fn holiday_events(
+    country: &str,
+    region: Option<&str>,
+    years: RangeInclusive<i32>,
+) -> Result<Vec<Event>> {
+    let (country, region) = find_country(country, region)?;
+    let code = match &region {
+        Some(region) => format!("{}-{}", country.code, region),
+        None => country.code.to_string(),
+    };
+
+    let mut events = Vec::new();
+    for year in years {
+        for (date, name) in holidays_for_year(country, region.as_deref(), year) {
+            let Some(end) = date.succ_opt() else {
+                continue;
+            };
+
+            let slug: String = name
+                .to_lowercase()
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '-' })
+                .collect();
+            let event = icalendar::Event::new()
+                .uid(&format!(
+                    "{}-{}-{}@holidays.ical-merge",
+                    date.format("%Y%m%d"),
+                    code.to_lowercase(),
+                    slug
+                ))
+                .summary(&name)
+                .starts(date)
+                .ends(end)
+                .add_property("TRANSP", "TRANSPARENT")
+                .done();
+            events.push(Event::new(event));
+        }
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn names_on(holidays: &[(NaiveDate, String)], day: NaiveDate) -> Vec<&str> {
+        holidays
+            .iter()
+            .filter(|(d, _)| *d == day)
+            .map(|(_, name)| name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_easter() {
+        assert_eq!(easter(2024), date(2024, 3, 31));
+        assert_eq!(easter(2025), date(2025, 4, 20));
+        assert_eq!(easter(2026), date(2026, 4, 5));
+    }
+
+    #[test]
+    fn test_date_rules() {
+        assert_eq!(
+            Nth(11, Weekday::Thu, 4).date(2025),
+            Some(date(2025, 11, 27))
+        );
+        assert_eq!(Nth(5, Weekday::Mon, -1).date(2025), Some(date(2025, 5, 26)));
+        assert_eq!(
+            Nth(12, Weekday::Wed, -1).date(2025),
+            Some(date(2025, 12, 31))
+        );
+        assert_eq!(
+            OnOrBefore(5, 24, Weekday::Mon).date(2025),
+            Some(date(2025, 5, 19))
+        );
+    }
+
+    #[test]
+    fn test_us_observed_days() {
+        let (country, region) = find_country("us", None).unwrap();
+        let holidays = holidays_for_year(country, region.as_deref(), 2026);
+        // Independence Day 2026 is a Saturday
+        assert_eq!(
+            names_on(&holidays, date(2026, 7, 3)),
+            vec!["Independence Day (observed)"]
+        );
+        assert_eq!(holidays.len(), 12);
+    }
+
+    #[test]
+    fn test_gb_substitute_days() {
+        let (country, region) = find_country("GB", None).unwrap();
+        assert_eq!(region.as_deref(), Some("ENG"));
+        let holidays = holidays_for_year(country, region.as_deref(), 2027);
+        // Christmas 2027 is a Saturday and Boxing Day a Sunday
+        assert_eq!(
+            names_on(&holidays, date(2027, 12, 27)),
+            vec!["Christmas Day (substitute day)"]
+        );
+        assert_eq!(
+            names_on(&holidays, date(2027, 12, 28)),
+            vec!["Boxing Day (substitute day)"]
+        );
+    }
+
+    #[test]
+    fn test_regions() {
+        let (country, region) = find_country("GB", Some("sct")).unwrap();
+        let holidays = holidays_for_year(country, region.as_deref(), 2025);
+        assert_eq!(
+            names_on(&holidays, date(2025, 11, 30)),
+            vec!["St Andrew's Day"]
+        );
+        assert!(names_on(&holidays, date(2025, 4, 21)).is_empty());
+
+        assert!(validate("GB", Some("XX")).is_err());
+        assert!(validate("ZZ", None).is_err());
+    }
+
+    #[test]
+    fn test_holiday_events() {
+        let events = holiday_events("FR", None, 2025..=2026).unwrap();
+        assert_eq!(events.len(), 22);
+
+        let event = &events[0];
+        assert_eq!(event.summary(), Some("Jour de l'an"));
+        assert_eq!(
+            event.uid(),
+            Some("20250101-fr-jour-de-l-an@holidays.ical-merge")
+        );
+    }
+}
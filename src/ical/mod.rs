@@ -1,5 +1,7 @@
 pub mod parser;
 pub mod types;
 
-pub use parser::{parse_calendar, serialize_events};
+pub use parser::{
+    ParseWarning, parse_calendar, parse_calendar_bytes, serialize_events, serialize_events_with,
+};
 pub use types::{Calendar, Event};
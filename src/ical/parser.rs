@@ -1,26 +1,167 @@
+use std::fmt;
+
+use serde::Serialize;
+
 use super::types::{Calendar, Event};
+use crate::config::OutputConfig;
 use crate::error::{Error, Result};
+use crate::timezone::Zone;
 
-/// Sanitize iCal text to fix common malformed data issues
-fn sanitize_ical(ical_text: &str) -> String {
-    ical_text
-        .lines()
-        .map(|line| {
-            // Fix malformed TRIGGER values like "TRIGGER:-P2DT" (empty time component)
-            // These should be "TRIGGER:-P2D" (duration without time)
-            if line.starts_with("TRIGGER:") && line.ends_with('T') {
-                line.trim_end_matches('T').to_string()
-            } else {
-                line.to_string()
+/// Something the parser had to fix or leave out to read a calendar
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParseWarning {
+    /// Line the problem was found on, counting from 1, when it has one
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ParseWarning {
+    fn new(line: Option<usize>, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Longest content line read, in bytes once its folded lines are joined. Longer
+/// lines are left out rather than held in memory while they're parsed
+pub const MAX_CONTENT_LINE: usize = 1 << 20;
+
+/// Deepest components may be nested. Real calendars go no deeper than an alarm in
+/// an event in the calendar, and the parser recurses into each level on the stack
+pub const MAX_NESTING: usize = 16;
+
+/// Sanitize iCal text to fix common malformed data issues, recording what was
+/// changed. Folded lines are joined here, and text beyond the limits the parser
+/// can safely handle is left out or rejected
+fn sanitize_ical(ical_text: &str) -> Result<(String, Vec<ParseWarning>)> {
+    let mut warnings = Vec::new();
+
+    // Some feeds start with a UTF-8 byte order mark or end lines with a bare LF
+    // or CR rather than CRLF
+    let ical_text = match ical_text.strip_prefix('\u{feff}') {
+        Some(rest) => {
+            warnings.push(ParseWarning::new(Some(1), "Removed UTF-8 byte order mark"));
+            rest
+        }
+        None => ical_text,
+    };
+    for (ending, name) in [("\n", "LF"), ("\r", "CR")] {
+        let bare = ical_text.match_indices(ending).find(|(idx, _)| {
+            let before = ical_text[..*idx].ends_with('\r');
+            let after = ical_text[*idx + 1..].starts_with('\n');
+            if ending == "\n" { !before } else { !after }
+        });
+        if let Some((idx, _)) = bare {
+            let line = ical_text[..idx]
+                .replace("\r\n", "\n")
+                .split(['\n', '\r'])
+                .count();
+            warnings.push(ParseWarning::new(
+                Some(line),
+                format!("Lines end with a bare {} instead of CRLF", name),
+            ));
+        }
+    }
+    let ical_text = ical_text.replace("\r\n", "\n").replace('\r', "\n");
+
+    // Content lines along with the line each starts on
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (idx, line) in ical_text.split('\n').enumerate() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some((_, last))) => {
+                // Stop growing a line that will be left out anyway
+                if last.len() <= MAX_CONTENT_LINE {
+                    last.push_str(continuation);
+                }
             }
-        })
-        .collect::<Vec<_>>()
-        .join("\n")
+            _ if line.is_empty() => {}
+            _ => lines.push((idx + 1, line.to_string())),
+        }
+    }
+
+    let mut depth = 0;
+    let mut sanitized = Vec::with_capacity(lines.len());
+    for (line_number, line) in lines {
+        if line.len() > MAX_CONTENT_LINE {
+            warnings.push(ParseWarning::new(
+                Some(line_number),
+                format!(
+                    "Left out a line longer than {} bytes starting '{}'",
+                    MAX_CONTENT_LINE,
+                    line.chars().take(20).collect::<String>()
+                ),
+            ));
+            continue;
+        }
+
+        let name = line.split([':', ';']).next().unwrap_or_default();
+        if name.eq_ignore_ascii_case("BEGIN") {
+            depth += 1;
+            if depth > MAX_NESTING {
+                return Err(Error::Parse(format!(
+                    "Components are nested more than {} deep at line {}",
+                    MAX_NESTING, line_number
+                )));
+            }
+        } else if name.eq_ignore_ascii_case("END") {
+            depth = depth.saturating_sub(1);
+        }
+
+        // Fix malformed TRIGGER values like "TRIGGER:-P2DT" (empty time component)
+        // These should be "TRIGGER:-P2D" (duration without time)
+        if line.starts_with("TRIGGER:") && line.ends_with('T') {
+            warnings.push(ParseWarning::new(
+                Some(line_number),
+                format!("Removed empty time from '{}'", line),
+            ));
+            sanitized.push(line.trim_end_matches('T').to_string());
+        } else {
+            sanitized.push(line);
+        }
+    }
+
+    Ok((sanitized.join("\n"), warnings))
 }
 
-/// Parse iCal text into a Calendar with Events
+/// Parse iCal text into a Calendar with Events, keeping warnings about anything
+/// that had to be fixed or couldn't be read
 pub fn parse_calendar(ical_text: &str) -> Result<Calendar> {
-    let sanitized = sanitize_ical(ical_text);
+    parse_with_warnings(ical_text, Vec::new())
+}
+
+/// Parse iCal bytes however they arrived, replacing anything that isn't UTF-8
+/// rather than rejecting the whole calendar. Nothing an upstream sends makes this
+/// panic, which the `fuzz` targets check
+pub fn parse_calendar_bytes(bytes: &[u8]) -> Result<Calendar> {
+    let mut warnings = Vec::new();
+    if let Err(e) = std::str::from_utf8(bytes) {
+        let line = bytes[..e.valid_up_to()]
+            .iter()
+            .filter(|byte| **byte == b'\n')
+            .count()
+            + 1;
+        warnings.push(ParseWarning::new(
+            Some(line),
+            "Replaced bytes that aren't valid UTF-8",
+        ));
+    }
+    parse_with_warnings(&String::from_utf8_lossy(bytes), warnings)
+}
+
+fn parse_with_warnings(ical_text: &str, mut warnings: Vec<ParseWarning>) -> Result<Calendar> {
+    let (sanitized, sanitize_warnings) = sanitize_ical(ical_text)?;
+    warnings.extend(sanitize_warnings);
 
     let parsed = sanitized
         .parse::<icalendar::Calendar>()
@@ -28,7 +169,23 @@ pub fn parse_calendar(ical_text: &str) -> Result<Calendar> {
 
     let events = extract_events(&parsed);
 
-    Ok(Calendar::new(parsed, events))
+    // The parser is lenient and quietly skips components it can't make sense of
+    let declared = sanitized
+        .lines()
+        .filter(|line| line.trim_end().eq_ignore_ascii_case("BEGIN:VEVENT"))
+        .count();
+    if declared > events.len() {
+        warnings.push(ParseWarning::new(
+            None,
+            format!(
+                "{} of {} events couldn't be read",
+                declared - events.len(),
+                declared
+            ),
+        ));
+    }
+
+    Ok(Calendar::new(parsed, events).with_warnings(warnings))
 }
 
 /// Extract events from an icalendar::Calendar
@@ -46,15 +203,105 @@ fn extract_events(calendar: &icalendar::Calendar) -> Vec<Event> {
         .collect()
 }
 
+/// Longest content line in octets before it must be folded (RFC 5545 section 3.1)
+const MAX_LINE_OCTETS: usize = 75;
+
 /// Serialize a list of events back to valid iCal string
 pub fn serialize_events(events: Vec<Event>) -> String {
-    let mut calendar = icalendar::Calendar::new();
+    serialize_events_with(events, &OutputConfig::default(), None)
+}
+
+/// PRODID written when the config doesn't override it
+pub const DEFAULT_PRODID: &str = concat!(
+    "-//ical-merge//ical-merge ",
+    env!("CARGO_PKG_VERSION"),
+    "//EN"
+);
+
+/// Property recording when a calendar was written
+pub const GENERATED_PROPERTY: &str = "X-ICAL-MERGE-GENERATED";
+
+/// Serialize a list of events as configured, always with CRLF line endings,
+/// describing `timezone` when the events were rewritten into it
+pub fn serialize_events_with(
+    events: Vec<Event>,
+    output: &OutputConfig,
+    timezone: Option<&Zone>,
+) -> String {
+    // Start empty so only the properties chosen here are written, in a fixed order
+    let mut calendar = icalendar::Calendar::empty();
+    calendar.append_property(("VERSION", "2.0"));
+    match output.prodid.as_deref() {
+        Some("") => {}
+        Some(prodid) => {
+            calendar.append_property(("PRODID", prodid));
+        }
+        None => {
+            calendar.append_property(("PRODID", DEFAULT_PRODID));
+        }
+    }
+    calendar.append_property(("CALSCALE", "GREGORIAN"));
+    if output.generated_timestamp {
+        let now = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        calendar.append_property((GENERATED_PROPERTY, now.as_str()));
+    }
 
     for event in events {
         calendar.push(event.into_inner());
     }
 
-    calendar.to_string()
+    // Refold ourselves rather than relying on however the crate wrapped lines
+    let mut lines = unfold_lines(&calendar.to_string());
+    if let Some(zone) = timezone {
+        // The crate can't build a VTIMEZONE, so it goes in ahead of the events
+        let at = lines
+            .iter()
+            .position(|line| line == "BEGIN:VEVENT" || line == "END:VCALENDAR")
+            .unwrap_or(lines.len());
+        lines.splice(at..at, zone.vtimezone());
+    }
+
+    let mut out = String::new();
+    for line in lines {
+        if output.fold_lines {
+            out.push_str(&fold_line(&line));
+        } else {
+            out.push_str(&line);
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// Join folded continuation lines back onto the content line they belong to
+fn unfold_lines(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if line.is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Fold a content line so no line is longer than 75 octets, splitting only
+/// between characters and starting each continuation with a space
+fn fold_line(line: &str) -> String {
+    let mut out = String::with_capacity(line.len() + line.len() / MAX_LINE_OCTETS * 3);
+    let mut width = 0;
+
+    for c in line.chars() {
+        if width + c.len_utf8() > MAX_LINE_OCTETS {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out
 }
 
 #[cfg(test)]
@@ -133,6 +380,93 @@ END:VCALENDAR"#;
         );
     }
 
+    #[test]
+    fn test_line_folding() {
+        use icalendar::Component;
+
+        let mut event = icalendar::Event::new();
+        event.summary(&"Long ".repeat(30));
+        event.description(&"Café ☕ ".repeat(20));
+        let events = vec![Event::new(event)];
+
+        let folded = serialize_events(events.clone());
+        assert!(folded.ends_with("END:VCALENDAR\r\n"));
+        assert!(!folded.replace("\r\n", "").contains('\n'));
+        for line in folded.split("\r\n") {
+            assert!(line.len() <= MAX_LINE_OCTETS, "{:?} is too long", line);
+        }
+        assert!(folded.contains("\r\n "));
+
+        // Folding is undone when parsing
+        let reparsed = parse_calendar(&folded).unwrap();
+        assert_eq!(
+            reparsed.events()[0].summary(),
+            Some("Long ".repeat(30).as_str())
+        );
+
+        let output = OutputConfig {
+            fold_lines: false,
+            ..Default::default()
+        };
+        let unfolded = serialize_events_with(events, &output, None);
+        assert!(!unfolded.contains("\r\n "));
+        assert!(unfolded.contains(&format!("SUMMARY:{}\r\n", "Long ".repeat(30))));
+        assert!(unfolded.ends_with("END:VCALENDAR\r\n"));
+    }
+
+    #[test]
+    fn test_calendar_properties() {
+        let ical = serialize_events(vec![]);
+        assert!(ical.starts_with(&format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:{}\r\nCALSCALE:GREGORIAN\r\n",
+            DEFAULT_PRODID
+        )));
+        assert!(!ical.contains(GENERATED_PROPERTY));
+        // Output is byte-stable unless a timestamp is asked for
+        assert_eq!(ical, serialize_events(vec![]));
+
+        let output = OutputConfig {
+            prodid: Some("-//Example//Family//EN".to_string()),
+            generated_timestamp: true,
+            ..Default::default()
+        };
+        let ical = serialize_events_with(vec![], &output, None);
+        assert!(ical.contains("PRODID:-//Example//Family//EN\r\n"));
+        assert!(ical.contains(&format!("{}:", GENERATED_PROPERTY)));
+
+        let output = OutputConfig {
+            prodid: Some(String::new()),
+            ..Default::default()
+        };
+        assert!(!serialize_events_with(vec![], &output, None).contains("PRODID"));
+    }
+
+    #[test]
+    fn test_timezone_written_before_events() {
+        let Ok(zone) = Zone::load("Europe/London") else {
+            return;
+        };
+
+        let calendar = parse_calendar(SIMPLE_ICAL).unwrap();
+        let events = zone.convert_events(calendar.into_events());
+        let ical = serialize_events_with(events, &OutputConfig::default(), Some(&zone));
+
+        let vtimezone = ical.find("BEGIN:VTIMEZONE").unwrap();
+        assert!(vtimezone < ical.find("BEGIN:VEVENT").unwrap());
+        assert!(ical.contains("TZID:Europe/London\r\n"));
+        assert!(ical.contains("DTSTART;TZID=Europe/London:20231201T140000\r\n"));
+    }
+
+    #[test]
+    fn test_fold_line_multibyte() {
+        let line = format!("SUMMARY:{}", "é".repeat(80));
+        let folded = fold_line(&line);
+        for part in folded.split("\r\n") {
+            assert!(part.len() <= MAX_LINE_OCTETS);
+        }
+        assert_eq!(folded.replace("\r\n ", ""), line);
+    }
+
     #[test]
     fn test_parse_empty_ical() {
         // The icalendar crate is permissive, so we test that we can handle
@@ -166,6 +500,137 @@ END:VCALENDAR"#;
         assert_eq!(events[0].summary(), Some("Test Event"));
     }
 
+    #[test]
+    fn test_parse_line_endings_and_bom() {
+        let folded = SIMPLE_ICAL.replace(
+            "DESCRIPTION:This is a test event",
+            "DESCRIPTION:This is a\n  test event",
+        );
+
+        for ical in [
+            folded.clone(),
+            folded.replace('\n', "\r\n"),
+            folded.replace('\n', "\r"),
+            format!("\u{feff}{}", folded.replace('\n', "\r\n")),
+            format!("\u{feff}{}", folded.replace('\n', "\r")),
+        ] {
+            let calendar = parse_calendar(&ical).unwrap();
+            assert_eq!(calendar.events().len(), 1, "{:?}", ical);
+
+            let event = &calendar.events()[0];
+            assert_eq!(event.uid(), Some("event1@example.com"));
+            assert_eq!(event.summary(), Some("Test Event"));
+            assert_eq!(event.description(), Some("This is a test event"));
+        }
+    }
+
+    #[test]
+    fn test_parse_warnings() {
+        let calendar = parse_calendar(&SIMPLE_ICAL.replace('\n', "\r\n")).unwrap();
+        assert!(calendar.warnings().is_empty());
+
+        let ical = format!(
+            "\u{feff}{}",
+            SIMPLE_ICAL
+                .replace('\n', "\r\n")
+                .replace("SUMMARY:Test Event\r\n", "SUMMARY:Test Event\n")
+                .replace(
+                    "END:VEVENT",
+                    "BEGIN:VALARM\r\nTRIGGER:-P2DT\r\nACTION:DISPLAY\r\nEND:VALARM\r\nEND:VEVENT"
+                )
+        );
+        let calendar = parse_calendar(&ical).unwrap();
+        assert_eq!(
+            calendar.warnings(),
+            &[
+                ParseWarning::new(Some(1), "Removed UTF-8 byte order mark"),
+                ParseWarning::new(Some(9), "Lines end with a bare LF instead of CRLF"),
+                ParseWarning::new(Some(12), "Removed empty time from 'TRIGGER:-P2DT'"),
+            ]
+        );
+        assert_eq!(
+            calendar.warnings()[1].to_string(),
+            "line 9: Lines end with a bare LF instead of CRLF"
+        );
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let nested = |depth| {
+            let mut ical = String::from("BEGIN:VCALENDAR\r\n");
+            ical.push_str(&"BEGIN:VEVENT\r\n".repeat(depth - 1));
+            ical.push_str("UID:nested@example.com\r\n");
+            ical.push_str(&"END:VEVENT\r\n".repeat(depth - 1));
+            ical.push_str("END:VCALENDAR\r\n");
+            ical
+        };
+        assert!(parse_calendar(&nested(MAX_NESTING)).is_ok());
+        let err = parse_calendar(&nested(MAX_NESTING + 1)).unwrap_err();
+        assert!(err.to_string().contains("nested more than 16 deep"));
+        // Deep enough to overflow the stack if it reached the parser
+        assert!(parse_calendar(&"BEGIN:VEVENT\n".repeat(100_000)).is_err());
+
+        // Gigantic lines are left out whether or not they're folded
+        let long = "x".repeat(MAX_CONTENT_LINE);
+        let folded = "\r\n x".repeat(MAX_CONTENT_LINE);
+        for summary in [long, folded] {
+            let ical = SIMPLE_ICAL
+                .replace('\n', "\r\n")
+                .replace("SUMMARY:Test Event", &format!("SUMMARY:{}", summary));
+            let calendar = parse_calendar(&ical).unwrap();
+            assert_eq!(calendar.events().len(), 1);
+            assert!(calendar.events()[0].summary().is_none());
+            assert_eq!(
+                calendar.warnings(),
+                &[ParseWarning::new(
+                    Some(9),
+                    "Left out a line longer than 1048576 bytes starting 'SUMMARY:xxxxxxxxxxxx'"
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_calendar_bytes() {
+        let ical = SIMPLE_ICAL
+            .replace('\n', "\r\n")
+            .replace("Test Event", "Test \u{1}Event");
+        let mut bytes = ical.into_bytes();
+        let invalid = bytes.iter().position(|byte| *byte == 1).unwrap();
+        bytes[invalid] = 0xff;
+
+        let calendar = parse_calendar_bytes(&bytes).unwrap();
+        assert_eq!(calendar.events()[0].summary(), Some("Test \u{fffd}Event"));
+        assert_eq!(
+            calendar.warnings(),
+            &[ParseWarning::new(
+                Some(9),
+                "Replaced bytes that aren't valid UTF-8"
+            )]
+        );
+
+        // Mangled calendars can fail to parse, but never panic
+        let mut state: u32 = 1;
+        let mut next = |below: usize| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 8) as usize % below
+        };
+        for _ in 0..500 {
+            let mut mangled = SIMPLE_ICAL.as_bytes().to_vec();
+            for _ in 0..8 {
+                let at = next(mangled.len());
+                match next(3) {
+                    0 => mangled[at] = next(256) as u8,
+                    1 => mangled.insert(at, b"\r\n :;=\"\\"[next(8)]),
+                    _ => {
+                        mangled.remove(at);
+                    }
+                }
+            }
+            let _ = parse_calendar_bytes(&mangled);
+        }
+    }
+
     #[test]
     fn test_parse_england_rugby_fixture() {
         let ical_text = include_str!("../../tests/fixtures/england_rugby.ics");
@@ -1,17 +1,36 @@
+use std::borrow::Cow;
 use std::fmt;
 
 use icalendar::{Component, EventLike};
 
+use super::parser::ParseWarning;
+
 /// Wrapper around icalendar::Calendar
 #[derive(Debug)]
 pub struct Calendar {
     inner: icalendar::Calendar,
     events: Vec<Event>,
+    warnings: Vec<ParseWarning>,
 }
 
 impl Calendar {
     pub fn new(inner: icalendar::Calendar, events: Vec<Event>) -> Self {
-        Self { inner, events }
+        Self {
+            inner,
+            events,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Attach warnings about what had to be fixed to read the calendar
+    pub fn with_warnings(mut self, warnings: Vec<ParseWarning>) -> Self {
+        self.warnings = warnings;
+        self
+    }
+
+    /// What had to be fixed or was left out when reading the calendar
+    pub fn warnings(&self) -> &[ParseWarning] {
+        &self.warnings
     }
 
     pub fn events(&self) -> &[Event] {
@@ -33,8 +52,16 @@ impl fmt::Display for Calendar {
     }
 }
 
+/// Whether a field name refers to a non-standard `X-` property
+fn is_extension_property(field: &str) -> bool {
+    field.len() > 2
+        && field
+            .get(..2)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("x-"))
+}
+
 /// Wrapper around icalendar::Event with convenient accessors
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Event {
     inner: icalendar::Event,
 }
@@ -73,6 +100,138 @@ impl Event {
             .map(|(_, prop)| prop.value())
     }
 
+    /// Get the value of an arbitrary single-valued property (e.g. `X-TICKET`)
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.inner.property_value(&name.to_uppercase())
+    }
+
+    /// Set an arbitrary single-valued property, replacing any existing value
+    pub fn set_property(&mut self, name: &str, value: &str) {
+        self.inner.add_property(name.to_uppercase(), value);
+    }
+
+    /// Remove properties by name, whether they hold one value or many
+    pub fn remove_properties(&mut self, names: &[&str]) {
+        let keep = |prop: &&icalendar::Property| {
+            !names
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(prop.key()))
+        };
+
+        // The icalendar crate can't remove properties so rebuild the event without them
+        let mut new_event = icalendar::Event::new();
+        for prop in self.inner.properties().values().filter(keep) {
+            new_event.append_property(prop.clone());
+        }
+        for prop in self
+            .inner
+            .multi_properties()
+            .values()
+            .flatten()
+            .filter(keep)
+        {
+            new_event.append_multi_property(prop.clone());
+        }
+        for component in self.inner.components() {
+            new_event.append_component(component.clone());
+        }
+
+        self.inner = new_event;
+    }
+
+    /// Get all categories, flattening comma-separated CATEGORIES properties
+    pub fn categories(&self) -> Vec<&str> {
+        self.inner
+            .multi_properties()
+            .get("CATEGORIES")
+            .into_iter()
+            .flatten()
+            .flat_map(|prop| prop.value().split(','))
+            .map(str::trim)
+            .filter(|category| !category.is_empty())
+            .collect()
+    }
+
+    /// Add a category unless the event already has it (compared case-insensitively)
+    pub fn add_category(&mut self, category: &str) {
+        if self
+            .categories()
+            .iter()
+            .any(|existing| existing.eq_ignore_ascii_case(category))
+        {
+            return;
+        }
+
+        self.inner.add_multi_property("CATEGORIES", category);
+    }
+
+    /// Get the ORGANIZER address, usually a `mailto:` URI
+    pub fn organizer(&self) -> Option<&str> {
+        self.inner.property_value("ORGANIZER")
+    }
+
+    /// Replace the ORGANIZER, dropping any parameters the old one had
+    pub fn set_organizer(&mut self, address: &str, name: Option<&str>) {
+        let mut organizer = icalendar::Property::new("ORGANIZER", address);
+        if let Some(name) = name {
+            // icalendar only quotes parameter values holding a colon
+            let name = name.replace('"', "");
+            let name = if !name.contains(':') && name.contains([';', ',']) {
+                format!("\"{}\"", name)
+            } else {
+                name
+            };
+            organizer.add_parameter("CN", &name);
+        }
+        self.inner.append_property(organizer);
+    }
+
+    /// Count the people invited, leaving out rooms and equipment booked as attendees
+    pub fn attendee_count(&self) -> usize {
+        let single = self.inner.properties().get("ATTENDEE");
+        let multi = self.inner.multi_properties().get("ATTENDEE");
+        single
+            .into_iter()
+            .chain(multi.into_iter().flatten())
+            .filter(|prop| {
+                !prop.params().get("CUTYPE").is_some_and(|cutype| {
+                    cutype.value().eq_ignore_ascii_case("ROOM")
+                        || cutype.value().eq_ignore_ascii_case("RESOURCE")
+                })
+            })
+            .count()
+    }
+
+    /// Get the text of a named field as used by processing steps
+    pub fn field(&self, field: &str) -> Option<Cow<'_, str>> {
+        match field {
+            "summary" => self.summary().map(Cow::Borrowed),
+            "description" => self.description().map(Cow::Borrowed),
+            "location" => self.location().map(Cow::Borrowed),
+            "categories" => {
+                let categories = self.categories();
+                if categories.is_empty() {
+                    None
+                } else {
+                    Some(Cow::Owned(categories.join(",")))
+                }
+            }
+            _ if is_extension_property(field) => self.property(field).map(Cow::Borrowed),
+            _ => None,
+        }
+    }
+
+    /// Set the text of a named field, ignoring fields that can't be written
+    pub fn set_field(&mut self, field: &str, value: &str) {
+        match field {
+            "summary" => self.set_summary(value),
+            "description" => self.set_description(value),
+            "location" => self.set_location(value),
+            _ if is_extension_property(field) => self.set_property(field, value),
+            _ => {}
+        }
+    }
+
     pub fn set_summary(&mut self, summary: &str) {
         self.inner.summary(summary);
     }
@@ -85,6 +244,11 @@ impl Event {
         self.inner.location(location);
     }
 
+    /// Roughly how much memory the event takes, going by the length of its iCal text
+    pub fn approximate_size(&self) -> usize {
+        self.inner.to_string().len()
+    }
+
     /// Check if this event has any alarms/reminders
     pub fn has_alarms(&self) -> bool {
         // Check if the event's components include any alarms
@@ -102,11 +266,69 @@ impl Event {
         for prop in self.inner.properties().values() {
             new_event.append_property(prop.clone());
         }
+        for prop in self.inner.multi_properties().values().flatten() {
+            new_event.append_multi_property(prop.clone());
+        }
 
         // Replace the inner event
         self.inner = new_event;
     }
 
+    /// Remove the alarm components `keep` returns false for. It's given each alarm's
+    /// `ACTION` and how long before the event starts it goes off (negative once the
+    /// event has started), when its `TRIGGER` can be read
+    pub fn retain_alarms(
+        &mut self,
+        mut keep: impl FnMut(Option<&str>, Option<chrono::Duration>) -> bool,
+    ) {
+        let mut new_event = icalendar::Event::new();
+        for prop in self.inner.properties().values() {
+            new_event.append_property(prop.clone());
+        }
+        for prop in self.inner.multi_properties().values().flatten() {
+            new_event.append_multi_property(prop.clone());
+        }
+        for component in self.inner.components() {
+            if component.component_kind() != "VALARM"
+                || keep(
+                    component.property_value("ACTION"),
+                    self.alarm_before_start(component.properties().get("TRIGGER")),
+                )
+            {
+                new_event.append_component(component.clone());
+            }
+        }
+        self.inner = new_event;
+    }
+
+    /// How long before the event starts an alarm with this `TRIGGER` goes off
+    fn alarm_before_start(
+        &self,
+        trigger: Option<&icalendar::Property>,
+    ) -> Option<chrono::Duration> {
+        use icalendar::{DatePerhapsTime, Trigger};
+
+        let trigger = trigger?;
+        // icalendar can't read the negative durations nearly every alarm has
+        if let Some(offset) = parse_duration(trigger.value()) {
+            let related_end = trigger
+                .params()
+                .get("RELATED")
+                .is_some_and(|related| related.value().eq_ignore_ascii_case("END"));
+            return if related_end {
+                (self.start_time()? - self.end_time()?).checked_sub(&offset)
+            } else {
+                Some(-offset)
+            };
+        }
+        match Trigger::try_from(trigger).ok()? {
+            Trigger::DateTime(at) => {
+                Some(self.start_time()? - to_naive(&DatePerhapsTime::DateTime(at)))
+            }
+            Trigger::Duration(..) => None,
+        }
+    }
+
     pub fn start(&self) -> Option<icalendar::DatePerhapsTime> {
         self.inner.get_start()
     }
@@ -114,6 +336,79 @@ impl Event {
     pub fn end(&self) -> Option<icalendar::DatePerhapsTime> {
         self.inner.get_end()
     }
+
+    /// Whether the event lasts whole days rather than starting at a time
+    pub fn is_all_day(&self) -> bool {
+        matches!(self.start(), Some(icalendar::DatePerhapsTime::Date(_)))
+    }
+
+    /// Start as a local time, ignoring any timezone like the rest of the merge does
+    pub fn start_time(&self) -> Option<chrono::NaiveDateTime> {
+        self.start().map(|start| to_naive(&start))
+    }
+
+    /// End as a local time, falling back to the start (or the next day for all-day
+    /// events) when the event has no end
+    pub fn end_time(&self) -> Option<chrono::NaiveDateTime> {
+        match self.end() {
+            Some(end) => Some(to_naive(&end)),
+            None if self.is_all_day() => self
+                .start_time()
+                .map(|start| start + chrono::Duration::days(1)),
+            None => self.start_time(),
+        }
+    }
+}
+
+/// Parse an RFC 5545 duration such as `-PT15M` or `P1W`. A trailing `T` with no
+/// time after it, which some feeds write, is allowed. Durations too long to hold
+/// are `None`
+fn parse_duration(text: &str) -> Option<chrono::Duration> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    let mut total = chrono::Duration::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+    let mut any = false;
+    for c in text.strip_prefix('P')?.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' if !in_time && number.is_empty() => in_time = true,
+            _ => {
+                let count: i64 = number.parse().ok()?;
+                number.clear();
+                let part = match (c, in_time) {
+                    ('W', false) => chrono::Duration::try_weeks(count),
+                    ('D', false) => chrono::Duration::try_days(count),
+                    ('H', true) => chrono::Duration::try_hours(count),
+                    ('M', true) => chrono::Duration::try_minutes(count),
+                    ('S', true) => chrono::Duration::try_seconds(count),
+                    _ => return None,
+                };
+                total = total.checked_add(&part?)?;
+                any = true;
+            }
+        }
+    }
+    if !any || !number.is_empty() {
+        return None;
+    }
+    Some(if negative { -total } else { total })
+}
+
+/// Convert an iCal date or date-time to a naive local time, dates become midnight
+pub(crate) fn to_naive(value: &icalendar::DatePerhapsTime) -> chrono::NaiveDateTime {
+    use icalendar::{CalendarDateTime, DatePerhapsTime};
+
+    match value {
+        DatePerhapsTime::DateTime(CalendarDateTime::Floating(naive)) => *naive,
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(utc)) => utc.naive_utc(),
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, .. }) => *date_time,
+        DatePerhapsTime::Date(date) => date.and_hms_opt(0, 0, 0).unwrap(),
+    }
 }
 
 #[cfg(test)]
@@ -136,6 +431,44 @@ mod tests {
         assert_eq!(event.uid(), Some("test-uid-123"));
     }
 
+    #[test]
+    fn test_event_times() {
+        let date = chrono::NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+
+        let mut all_day = icalendar::Event::new();
+        all_day.starts(date);
+        let all_day = Event::new(all_day);
+        assert!(all_day.is_all_day());
+        assert_eq!(all_day.start_time(), date.and_hms_opt(0, 0, 0));
+        assert_eq!(
+            all_day.end_time(),
+            date.and_hms_opt(0, 0, 0)
+                .map(|d| d + chrono::Duration::days(1))
+        );
+
+        let start = date.and_hms_opt(9, 30, 0).unwrap();
+        let mut timed = icalendar::Event::new();
+        timed.starts(start);
+        let timed = Event::new(timed);
+        assert!(!timed.is_all_day());
+        assert_eq!(timed.start_time(), Some(start));
+        assert_eq!(timed.end_time(), Some(start));
+    }
+
+    #[test]
+    fn test_event_remove_properties() {
+        let ical_text = include_str!("../../tests/fixtures/england_rugby.ics");
+        let calendar = crate::ical::parse_calendar(ical_text).unwrap();
+        let mut event = calendar.events()[0].clone();
+        event.add_category("Rugby");
+
+        event.remove_properties(&["location", "CATEGORIES"]);
+        assert_eq!(event.location(), None);
+        assert!(event.categories().is_empty());
+        assert!(event.summary().is_some());
+        assert!(event.has_alarms());
+    }
+
     #[test]
     fn test_event_set_summary() {
         let mut event = icalendar::Event::new();
@@ -147,6 +480,41 @@ mod tests {
         assert_eq!(event.summary(), Some("Modified"));
     }
 
+    #[test]
+    fn test_event_categories() {
+        let mut event = icalendar::Event::new();
+        event.summary("Test Event");
+        let mut event = Event::new(event);
+        assert!(event.categories().is_empty());
+        assert_eq!(event.field("categories"), None);
+
+        event.add_category("Hiring");
+        event.add_category("hiring");
+        event.add_category("Work");
+
+        assert_eq!(event.categories(), vec!["Hiring", "Work"]);
+        assert_eq!(event.field("categories").as_deref(), Some("Hiring,Work"));
+    }
+
+    #[test]
+    fn test_event_extension_fields() {
+        let mut event = Event::new(icalendar::Event::new());
+        assert_eq!(event.field("X-TICKET"), None);
+
+        event.set_field("x-ticket", "ABC-123");
+        assert_eq!(event.property("X-TICKET"), Some("ABC-123"));
+        assert_eq!(event.field("X-Ticket").as_deref(), Some("ABC-123"));
+
+        // Unknown non-extension fields are ignored
+        event.set_field("organizer", "someone");
+        assert_eq!(event.field("organizer"), None);
+
+        // As are names starting with characters that take more than a byte
+        event.set_field("€x", "someone");
+        assert_eq!(event.field("€x"), None);
+        assert_eq!(event.field("x€"), None);
+    }
+
     #[test]
     fn test_event_has_alarms() {
         // Event without alarms
@@ -172,4 +540,69 @@ mod tests {
         event_copy.strip_alarms();
         assert!(!event_copy.has_alarms());
     }
+
+    #[test]
+    fn test_event_retain_alarms() {
+        let ical_text = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:alarms\r\n\
+            DTSTART:20250303T090000Z\r\nDTEND:20250303T100000Z\r\nSUMMARY:Review\r\n\
+            BEGIN:VALARM\r\nACTION:DISPLAY\r\nTRIGGER:-P2DT\r\nEND:VALARM\r\n\
+            BEGIN:VALARM\r\nACTION:AUDIO\r\nTRIGGER:-PT15M\r\nEND:VALARM\r\n\
+            BEGIN:VALARM\r\nACTION:DISPLAY\r\nTRIGGER;RELATED=END:-PT90M\r\nEND:VALARM\r\n\
+            BEGIN:VALARM\r\nACTION:EMAIL\r\n\
+            TRIGGER;VALUE=DATE-TIME:20250303T083000Z\r\nEND:VALARM\r\n\
+            BEGIN:VALARM\r\nACTION:DISPLAY\r\nTRIGGER:soon\r\nEND:VALARM\r\n\
+            END:VEVENT\r\nEND:VCALENDAR\r\n";
+        let mut event = crate::ical::parse_calendar(ical_text)
+            .unwrap()
+            .into_events()[0]
+            .clone();
+
+        let mut seen = Vec::new();
+        event.retain_alarms(|action, before| {
+            seen.push((action.map(str::to_string), before.map(|d| d.num_minutes())));
+            action == Some("AUDIO")
+        });
+        assert_eq!(
+            seen,
+            vec![
+                (Some("DISPLAY".to_string()), Some(2880)),
+                (Some("AUDIO".to_string()), Some(15)),
+                (Some("DISPLAY".to_string()), Some(30)),
+                (Some("EMAIL".to_string()), Some(30)),
+                (Some("DISPLAY".to_string()), None),
+            ]
+        );
+        assert!(event.has_alarms());
+        assert_eq!(event.inner().components().len(), 1);
+        assert_eq!(event.summary(), Some("Review"));
+    }
+
+    #[test]
+    fn test_huge_alarm_triggers() {
+        assert_eq!(
+            parse_duration("-PT15M"),
+            Some(chrono::Duration::minutes(-15))
+        );
+        assert!(parse_duration("P15000000000W").is_some());
+        assert_eq!(parse_duration("-P99999999999999W"), None);
+        assert_eq!(parse_duration("PT99999999999999999999S"), None);
+        assert_eq!(parse_duration("P15000000000W15000000000W"), None);
+
+        // Triggers too far off to hold are never said to be before the start
+        let ical_text = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:huge\r\n\
+            DTSTART:20250303T100000Z\r\nDTEND:20250303T090000Z\r\nSUMMARY:Backwards\r\n\
+            BEGIN:VALARM\r\nACTION:DISPLAY\r\nTRIGGER:-P99999999999999W\r\nEND:VALARM\r\n\
+            BEGIN:VALARM\r\nACTION:DISPLAY\r\nTRIGGER;RELATED=END:-P15250284452WT79H\r\nEND:VALARM\r\n\
+            END:VEVENT\r\nEND:VCALENDAR\r\n";
+        let mut event = crate::ical::parse_calendar(ical_text)
+            .unwrap()
+            .into_events()[0]
+            .clone();
+        let mut seen = Vec::new();
+        event.retain_alarms(|_, before| {
+            seen.push(before);
+            true
+        });
+        assert_eq!(seen, vec![None, None]);
+    }
 }
@@ -0,0 +1,320 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use icalendar::{CalendarDateTime, Component, DatePerhapsTime, EventLike};
+use serde_json::Value;
+
+use crate::config::JsonFieldsConfig;
+use crate::error::{Error, Result};
+use crate::ical::{Event, ParseWarning};
+
+/// Timestamps at least this large are taken to be in milliseconds rather than
+/// seconds, which would put them thousands of years away
+const MILLISECOND_TIMESTAMPS: f64 = 1e11;
+
+/// When an event happens, as read from a JSON value
+enum Time {
+    Date(NaiveDate),
+    Utc(DateTime<Utc>),
+    Local(NaiveDateTime),
+}
+
+/// Check that a pointer is an RFC 6901 JSON pointer, empty or starting with `/`
+fn validate_pointer(name: &str, pointer: &str) -> Result<()> {
+    if pointer.is_empty() || pointer.starts_with('/') {
+        Ok(())
+    } else {
+        Err(Error::Config(format!(
+            "Invalid {} pointer '{}', expected a path such as '/{}'",
+            name, pointer, pointer
+        )))
+    }
+}
+
+/// Check that a `json` source's pointers can be looked up
+pub fn validate(items: Option<&str>, fields: &JsonFieldsConfig) -> Result<()> {
+    if let Some(items) = items {
+        validate_pointer("items", items)?;
+    }
+    for (name, pointer) in [
+        ("summary", Some(&fields.summary)),
+        ("start", Some(&fields.start)),
+    ]
+    .into_iter()
+    .chain([
+        ("end", fields.end.as_ref()),
+        ("uid", fields.uid.as_ref()),
+        ("description", fields.description.as_ref()),
+        ("location", fields.location.as_ref()),
+        ("url", fields.url.as_ref()),
+    ]) {
+        if let Some(pointer) = pointer {
+            validate_pointer(name, pointer)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read a time from an RFC 3339 string, a date, a local date-time or a Unix timestamp
+fn parse_time(value: &Value) -> Option<Time> {
+    let text = match value {
+        Value::Number(number) => {
+            let mut seconds = number.as_f64()?;
+            if seconds.abs() >= MILLISECOND_TIMESTAMPS {
+                seconds /= 1000.0;
+            }
+            return DateTime::from_timestamp(seconds.trunc() as i64, 0).map(Time::Utc);
+        }
+        Value::String(text) => text.trim(),
+        _ => return None,
+    };
+
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(text) {
+        return Some(Time::Utc(date_time.with_timezone(&Utc)));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Some(Time::Date(date));
+    }
+    [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    .map(Time::Local)
+}
+
+/// Text of a string, number or boolean value
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) if !text.trim().is_empty() => Some(text.trim().to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Build an event from an item, or say why it can't be
+fn item_event(
+    item: &Value,
+    fields: &JsonFieldsConfig,
+    timezone: Option<&str>,
+) -> std::result::Result<Event, String> {
+    let lookup = |pointer: Option<&String>| {
+        pointer
+            .and_then(|pointer| item.pointer(pointer))
+            .filter(|value| !value.is_null())
+    };
+
+    let summary = lookup(Some(&fields.summary))
+        .and_then(text)
+        .ok_or_else(|| format!("no summary at '{}'", fields.summary))?;
+    let start_value =
+        lookup(Some(&fields.start)).ok_or_else(|| format!("no start at '{}'", fields.start))?;
+    let start =
+        parse_time(start_value).ok_or_else(|| format!("unreadable start {}", start_value))?;
+    let end = match lookup(fields.end.as_ref()) {
+        Some(value) => Some(parse_time(value).ok_or_else(|| format!("unreadable end {}", value))?),
+        None => None,
+    };
+
+    let to_date_time = |time| match time {
+        Time::Date(date) => DatePerhapsTime::Date(date),
+        Time::Utc(date_time) => DatePerhapsTime::DateTime(CalendarDateTime::Utc(date_time)),
+        Time::Local(date_time) => match timezone {
+            Some(tzid) => DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone {
+                date_time,
+                tzid: tzid.to_string(),
+            }),
+            None => DatePerhapsTime::DateTime(CalendarDateTime::Floating(date_time)),
+        },
+    };
+
+    let start_key: String = start_value
+        .to_string()
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect();
+    let mut event = icalendar::Event::new();
+    match (start, end) {
+        // An all-day end is the day after the last day, as in iCal
+        (Time::Date(start), Some(Time::Date(end))) if end > start => {
+            event.starts(start).ends(end);
+        }
+        (Time::Date(start), None | Some(Time::Date(_))) => {
+            event.starts(start).add_property("DURATION", "P1D");
+        }
+        (Time::Date(_), Some(_)) | (_, Some(Time::Date(_))) => {
+            return Err("the start and end must both be dates or both be times".to_string());
+        }
+        (start, end) => {
+            event.starts(to_date_time(start));
+            if let Some(end) = end {
+                event.ends(to_date_time(end));
+            }
+        }
+    }
+
+    let uid = match lookup(fields.uid.as_ref()).and_then(text) {
+        Some(uid) if uid.contains('@') => uid,
+        Some(uid) => format!("{}@json.ical-merge", uid),
+        None => {
+            let slug: String = summary
+                .to_lowercase()
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '-' })
+                .collect();
+            format!("{}-{}@json.ical-merge", start_key, slug)
+        }
+    };
+    event.uid(&uid).summary(&summary);
+    if let Some(description) = lookup(fields.description.as_ref()).and_then(text) {
+        event.description(&description);
+    }
+    if let Some(location) = lookup(fields.location.as_ref()).and_then(text) {
+        event.location(&location);
+    }
+    if let Some(url) = lookup(fields.url.as_ref()).and_then(text) {
+        event.add_property("URL", url);
+    }
+    Ok(Event::new(event.done()))
+}
+
+/// Map the items of a JSON document to events, `items` pointing at the array of
+/// them. Items that can't be mapped are left out with a warning
+pub fn json_events(
+    text: &str,
+    items: Option<&str>,
+    fields: &JsonFieldsConfig,
+    timezone: Option<&str>,
+) -> Result<(Vec<Event>, Vec<ParseWarning>)> {
+    let document: Value =
+        serde_json::from_str(text).map_err(|e| Error::Parse(format!("Invalid JSON: {}", e)))?;
+    let list = match items {
+        Some(pointer) => document
+            .pointer(pointer)
+            .ok_or_else(|| Error::Parse(format!("Nothing at '{}' in the JSON", pointer)))?,
+        None => &document,
+    };
+    let list = list.as_array().ok_or_else(|| {
+        Error::Parse(format!(
+            "Expected an array of events at '{}' in the JSON",
+            items.unwrap_or_default()
+        ))
+    })?;
+
+    let mut events = Vec::with_capacity(list.len());
+    let mut warnings = Vec::new();
+    for (idx, item) in list.iter().enumerate() {
+        match item_event(item, fields, timezone) {
+            Ok(event) => events.push(event),
+            Err(reason) => {
+                tracing::warn!("Left out JSON item {}: {}", idx, reason);
+                warnings.push(ParseWarning {
+                    line: None,
+                    message: format!("Left out item {}: {}", idx, reason),
+                });
+            }
+        }
+    }
+    Ok((events, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ical::serialize_events;
+
+    fn fields() -> JsonFieldsConfig {
+        JsonFieldsConfig {
+            summary: "/title".to_string(),
+            start: "/when/start".to_string(),
+            end: Some("/when/end".to_string()),
+            uid: Some("/id".to_string()),
+            description: None,
+            location: Some("/venue/name".to_string()),
+            url: Some("/link".to_string()),
+        }
+    }
+
+    const EVENTS: &str = r#"{
+        "data": {
+            "events": [
+                {
+                    "id": 42,
+                    "title": "Gig",
+                    "when": { "start": "2025-03-01T19:30:00+01:00", "end": "2025-03-01T22:00:00Z" },
+                    "venue": { "name": "Town Hall" },
+                    "link": "https://tickets.example.com/42"
+                },
+                { "id": "fair@example.com", "title": "Fair", "when": { "start": "2025-06-07", "end": "2025-06-09" } },
+                { "title": "Market", "when": { "start": "2025-05-03 09:00", "end": null } },
+                { "title": "Launch", "when": { "start": 1767225600000 } },
+                { "title": "No start" },
+                { "title": "Mixed", "when": { "start": "2025-06-07", "end": "2025-06-07T12:00" } }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn test_json_events() {
+        let (events, warnings) = json_events(
+            EVENTS,
+            Some("/data/events"),
+            &fields(),
+            Some("Europe/London"),
+        )
+        .unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|warning| warning.message.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "Left out item 4: no start at '/when/start'",
+                "Left out item 5: the start and end must both be dates or both be times",
+            ]
+        );
+
+        assert_eq!(events[0].uid(), Some("42@json.ical-merge"));
+        assert_eq!(events[0].location(), Some("Town Hall"));
+        assert_eq!(
+            events[0].property("URL"),
+            Some("https://tickets.example.com/42")
+        );
+        assert_eq!(events[1].uid(), Some("fair@example.com"));
+        assert_eq!(events[2].uid(), Some("202505030900-market@json.ical-merge"));
+
+        let ical = serialize_events(events);
+        assert!(ical.contains("DTSTART:20250301T183000Z"));
+        assert!(ical.contains("DTEND:20250301T220000Z"));
+        assert!(ical.contains("DTSTART;VALUE=DATE:20250607"));
+        assert!(ical.contains("DTEND;VALUE=DATE:20250609"));
+        assert!(ical.contains("DTSTART;TZID=Europe/London:20250503T090000"));
+        // Milliseconds since the epoch
+        assert!(ical.contains("DTSTART:20260101T000000Z"));
+    }
+
+    #[test]
+    fn test_json_events_errors() {
+        assert!(json_events("not json", None, &fields(), None).is_err());
+        assert!(json_events(EVENTS, Some("/data/missing"), &fields(), None).is_err());
+        // The items must be an array
+        assert!(json_events(EVENTS, Some("/data"), &fields(), None).is_err());
+        let (events, _) = json_events("[]", None, &fields(), None).unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(Some("/data/events"), &fields()).is_ok());
+        assert!(validate(Some(""), &fields()).is_ok());
+        assert!(validate(Some("data.events"), &fields()).is_err());
+
+        let mut bad = fields();
+        bad.location = Some("venue".to_string());
+        let err = validate(None, &bad).unwrap_err();
+        assert!(err.to_string().contains("location pointer 'venue'"));
+    }
+}
@@ -1,8 +1,35 @@
+pub mod audit;
+pub mod bench;
+pub mod birthdays;
+pub mod cache;
+pub mod coalesce;
 pub mod config;
+pub mod conflicts;
+pub mod daemon;
+pub mod diskcache;
 pub mod error;
+pub mod export;
 pub mod fetcher;
 pub mod filter;
+pub mod generate;
+pub mod git;
+pub mod graph;
+pub mod holidays;
 pub mod ical;
+pub mod json;
+pub mod logging;
 pub mod merge;
+pub mod metrics;
+pub mod parallel;
+pub mod provider;
+pub mod qr;
+pub mod query;
+pub mod ratelimit;
+pub mod recurrence;
+pub mod s3;
+pub mod selftest;
 pub mod server;
+pub mod supervisor;
+pub mod systemd;
+pub mod timezone;
 pub mod watcher;
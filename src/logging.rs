@@ -0,0 +1,85 @@
+use std::sync::{Arc, Mutex};
+
+use tracing_subscriber::{EnvFilter, Registry, reload};
+
+use crate::error::{Error, Result};
+
+/// Filter used when neither `RUST_LOG` nor the config sets one
+pub const DEFAULT_LOG_FILTER: &str = "ical_merge=info,tower_http=info";
+
+/// Layer filtering what gets logged, installed by whoever sets up tracing
+pub type LogFilterLayer = reload::Layer<EnvFilter, Registry>;
+
+/// Handle for changing the tracing filter while the server is running
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// Filter to return to when the config stops setting one
+    default: Arc<String>,
+    current: Arc<Mutex<String>>,
+}
+
+impl LogFilter {
+    /// Create a reloadable filter layer starting from `default`
+    pub fn new(default: &str) -> Result<(LogFilterLayer, Self)> {
+        let (layer, handle) = reload::Layer::new(parse_filter(default)?);
+        let filter = Self {
+            handle,
+            default: Arc::new(default.to_string()),
+            current: Arc::new(Mutex::new(default.to_string())),
+        };
+        Ok((layer, filter))
+    }
+
+    /// Get the filter directives currently in effect
+    pub fn current(&self) -> String {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// Replace the filter, e.g. `ical_merge::fetcher=debug`
+    pub fn set(&self, directives: &str) -> Result<()> {
+        let filter = parse_filter(directives)?;
+        self.handle
+            .reload(filter)
+            .map_err(|e| Error::Config(format!("Failed to change log filter: {}", e)))?;
+        *self.current.lock().unwrap() = directives.to_string();
+
+        tracing::info!("Log filter set to '{}'", directives);
+        Ok(())
+    }
+
+    /// Apply the filter from the config, or go back to the default when it has none
+    pub fn apply_config(&self, log_level: Option<&str>) -> Result<()> {
+        self.set(log_level.unwrap_or(&self.default))
+    }
+}
+
+/// Parse tracing filter directives
+pub fn parse_filter(directives: &str) -> Result<EnvFilter> {
+    EnvFilter::try_new(directives)
+        .map_err(|e| Error::Config(format!("Invalid log filter '{}': {}", directives, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_set_filter() {
+        let (layer, filter) = LogFilter::new(DEFAULT_LOG_FILTER).unwrap();
+        // The handle only works while the subscriber holding the layer is alive
+        let _subscriber = tracing_subscriber::registry().with(layer);
+
+        assert_eq!(filter.current(), DEFAULT_LOG_FILTER);
+
+        filter.set("ical_merge::fetcher=debug").unwrap();
+        assert_eq!(filter.current(), "ical_merge::fetcher=debug");
+
+        assert!(filter.set("ical_merge=nonsense").is_err());
+        assert_eq!(filter.current(), "ical_merge::fetcher=debug");
+
+        filter.apply_config(None).unwrap();
+        assert_eq!(filter.current(), DEFAULT_LOG_FILTER);
+    }
+}
@@ -1,14 +1,18 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::{Path, PathBuf};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use ical_merge::config::Config;
+use ical_merge::config::{Config, DefaultAction};
 use ical_merge::error::{Error, Result};
+use ical_merge::export;
 use ical_merge::fetcher::Fetcher;
-use ical_merge::ical::serialize_events;
-use ical_merge::merge::merge_calendars;
-use ical_merge::server::{AppState, create_router};
-use ical_merge::watcher::start_config_watcher;
+use ical_merge::graph::Graph;
+use ical_merge::ical::serialize_events_with;
+use ical_merge::logging::{DEFAULT_LOG_FILTER, LogFilter};
+use ical_merge::merge::{merge_calendars, served_events};
+use ical_merge::server::{AppState, create_router, route_table};
+use ical_merge::systemd;
+use ical_merge::watcher::{start_config_watcher, start_source_watcher};
 
 #[derive(Parser)]
 #[command(name = "ical-merge")]
@@ -18,10 +22,27 @@ struct Cli {
         short,
         long,
         env = "ICAL_MERGE_CONFIG",
-        help = "Path to config file (auto-detects config.toml or config.json if not specified)"
+        help = "Path to config file (auto-detects config.toml, config.yaml or config.json if not specified)"
     )]
     config: Option<PathBuf>,
 
+    /// Read URL sources from local fixture files instead of the network
+    #[arg(long, global = true)]
+    offline: bool,
+
+    /// Where offline mode looks for fixtures, as `<host>/<path>` or just the file name
+    #[arg(
+        long,
+        global = true,
+        default_value = "tests/fixtures",
+        requires = "offline"
+    )]
+    fixtures_dir: PathBuf,
+
+    /// Write logs to this file instead of standard output
+    #[arg(long, global = true, env = "ICAL_MERGE_LOG_FILE")]
+    log_file: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Option<Command>,
 }
@@ -35,6 +56,20 @@ enum Command {
 
         #[arg(short, long, env = "ICAL_MERGE_PORT")]
         port: Option<u16>,
+
+        /// Check the config and print what would be served, then exit
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Detach from the terminal and keep running in the background
+        #[cfg(unix)]
+        #[arg(long, conflicts_with = "dry_run")]
+        daemonize: bool,
+
+        /// Write the background server's process ID to this file
+        #[cfg(unix)]
+        #[arg(long, requires = "daemonize")]
+        pid_file: Option<PathBuf>,
     },
     /// Show merged events for a calendar
     Show {
@@ -46,11 +81,107 @@ enum Command {
         /// Calendar ID from config
         calendar_id: String,
     },
+    /// Export merged calendar in another format
+    Export {
+        /// Calendar ID from config
+        calendar_id: String,
+
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+
+        /// Comma-separated CSV columns: start, end, summary, location, description, source, uid
+        #[arg(long)]
+        columns: Option<String>,
+    },
+    /// Work with the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Draw how calendars include each other and their sources
+    Graph {
+        /// Calendar ID to draw with everything it includes, every calendar if not given
+        calendar_id: Option<String>,
+
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+    /// Time parsing, steps, deduplication and serialization of a large calendar
+    Bench {
+        /// Events in the generated sample calendar
+        #[arg(long, default_value_t = ical_merge::bench::DEFAULT_EVENTS, conflicts_with = "file")]
+        events: usize,
+
+        /// Times to run each stage, the median is reported
+        #[arg(long, default_value_t = 10)]
+        iterations: usize,
+
+        /// Benchmark this iCal file instead of the generated sample
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Run the web server as a Windows service
+    #[cfg(windows)]
+    Service {
+        #[command(subcommand)]
+        command: ServiceCommand,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Csv,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the config in another format, with every default spelled out
+    Convert {
+        #[arg(long, value_enum)]
+        to: ConfigFormat,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ConfigFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+#[cfg(windows)]
+#[derive(Subcommand)]
+enum ServiceCommand {
+    /// Register a service that starts with Windows and serves the current config
+    Install {
+        #[arg(long)]
+        bind: Option<String>,
+
+        #[arg(short, long)]
+        port: Option<u16>,
+    },
+    /// Stop and remove the service
+    Uninstall,
+    /// Run under the service manager, which is how Windows starts the service
+    #[command(hide = true)]
+    Run {
+        #[arg(long)]
+        bind: Option<String>,
+
+        #[arg(short, long)]
+        port: Option<u16>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Mermaid,
 }
 
 /// Find a config file by searching for default names in order
 fn find_config_file() -> Result<PathBuf> {
-    let candidates = ["config.toml", "config.json"];
+    let candidates = ["config.toml", "config.yaml", "config.yml", "config.json"];
 
     for candidate in &candidates {
         let path = Path::new(candidate);
@@ -66,17 +197,19 @@ fn find_config_file() -> Result<PathBuf> {
     )))
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "ical_merge=info,tower_http=info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
+fn main() -> Result<()> {
     let cli = Cli::parse();
+    let log_filter = init_logging(cli.log_file.as_deref())?;
+
+    // Benchmarks don't need a config
+    if let Some(Command::Bench {
+        events,
+        iterations,
+        file,
+    }) = &cli.command
+    {
+        return run_bench(*events, *iterations, file.as_deref());
+    }
 
     // Find config file: use explicit config if provided, otherwise search for defaults
     let config_path = match cli.config {
@@ -84,64 +217,453 @@ async fn main() -> Result<()> {
         None => find_config_file()?,
     };
 
-    match cli.command.unwrap_or_else(|| {
+    let fixtures = cli.offline.then_some(cli.fixtures_dir);
+    if let Some(fixtures) = &fixtures {
+        tracing::info!("Offline, reading sources from {}", fixtures.display());
+    }
+
+    let command = cli.command.unwrap_or_else(|| {
         // When no command is specified, default to Serve and check environment variables
         Command::Serve {
             bind: std::env::var("ICAL_MERGE_BIND").ok(),
             port: std::env::var("ICAL_MERGE_PORT")
                 .ok()
                 .and_then(|s| s.parse().ok()),
+            dry_run: false,
+            #[cfg(unix)]
+            daemonize: false,
+            #[cfg(unix)]
+            pid_file: None,
+        }
+    });
+
+    // Forking has to happen before the runtime starts its threads
+    #[cfg(unix)]
+    if let Command::Serve {
+        daemonize: true,
+        pid_file,
+        ..
+    } = &command
+    {
+        // Report config mistakes while there's still a terminal to see them
+        Config::load(&config_path)?.validate()?;
+        ical_merge::daemon::daemonize(pid_file.as_deref(), cli.log_file.as_deref())?;
+    }
+
+    match command {
+        Command::Serve {
+            bind,
+            port,
+            dry_run,
+            ..
+        } => block_on(run_serve(
+            config_path,
+            fixtures,
+            bind,
+            port,
+            dry_run,
+            log_filter,
+            std::future::pending(),
+        )),
+        Command::Show { calendar_id } => block_on(run_show(config_path, fixtures, calendar_id)),
+        Command::Ical { calendar_id } => block_on(run_ical(config_path, fixtures, calendar_id)),
+        Command::Export {
+            calendar_id,
+            format,
+            columns,
+        } => block_on(run_export(
+            config_path,
+            fixtures,
+            calendar_id,
+            format,
+            columns,
+        )),
+        Command::Config {
+            command: ConfigCommand::Convert { to },
+        } => run_convert(config_path, to),
+        Command::Graph {
+            calendar_id,
+            format,
+        } => run_graph(config_path, calendar_id, format),
+        Command::Bench { .. } => unreachable!("benchmarks run before the config is loaded"),
+        #[cfg(windows)]
+        Command::Service { command } => {
+            run_service(command, config_path, fixtures, cli.log_file, log_filter)
         }
-    }) {
-        Command::Serve { bind, port } => run_serve(config_path, bind, port).await,
-        Command::Show { calendar_id } => run_show(config_path, calendar_id).await,
-        Command::Ical { calendar_id } => run_ical(config_path, calendar_id).await,
     }
 }
 
-async fn run_serve(config_path: PathBuf, bind: Option<String>, port: Option<u16>) -> Result<()> {
+/// Log to standard output, or to `log_file` when given
+fn init_logging(log_file: Option<&Path>) -> Result<LogFilter> {
+    use tracing_subscriber::Layer;
+
+    // RUST_LOG takes the place of the built-in default, the config can still override it
+    let default_filter =
+        std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_LOG_FILTER.to_string());
+    let (filter_layer, log_filter) =
+        LogFilter::new(&default_filter).or_else(|_| LogFilter::new(DEFAULT_LOG_FILTER))?;
+    let output = match log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::sync::Mutex::new(file))
+                .with_ansi(false)
+                .boxed()
+        }
+        None => tracing_subscriber::fmt::layer().boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(output)
+        .init();
+    Ok(log_filter)
+}
+
+/// Run a command on a fresh async runtime
+fn block_on(command: impl Future<Output = Result<()>>) -> Result<()> {
+    tokio::runtime::Runtime::new()?.block_on(command)
+}
+
+#[cfg(windows)]
+fn run_service(
+    command: ServiceCommand,
+    config_path: PathBuf,
+    fixtures: Option<PathBuf>,
+    log_file: Option<PathBuf>,
+    log_filter: LogFilter,
+) -> Result<()> {
+    use ical_merge::daemon::service;
+
+    match command {
+        ServiceCommand::Install { bind, port } => {
+            // Services start in the system directory, so everything is passed in full
+            Config::load(&config_path)?.validate()?;
+            let mut arguments = vec![
+                "--config".into(),
+                std::path::absolute(&config_path)?.into_os_string(),
+            ];
+            if let Some(fixtures) = fixtures {
+                arguments.push("--offline".into());
+                arguments.push("--fixtures-dir".into());
+                arguments.push(std::path::absolute(fixtures)?.into_os_string());
+            }
+            if let Some(log_file) = log_file {
+                arguments.push("--log-file".into());
+                arguments.push(std::path::absolute(log_file)?.into_os_string());
+            }
+            arguments.extend(["service".into(), "run".into()]);
+            if let Some(bind) = bind {
+                arguments.extend(["--bind".into(), bind.into()]);
+            }
+            if let Some(port) = port {
+                arguments.extend(["--port".into(), port.to_string().into()]);
+            }
+            service::install(arguments)?;
+            println!("Installed the {} service", service::SERVICE_NAME);
+            Ok(())
+        }
+        ServiceCommand::Uninstall => {
+            service::uninstall()?;
+            println!("Removed the {} service", service::SERVICE_NAME);
+            Ok(())
+        }
+        ServiceCommand::Run { bind, port } => {
+            // Relative paths in the config are taken from the config file's directory
+            if let Some(dir) = config_path
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+            {
+                std::env::set_current_dir(dir)?;
+            }
+            service::run(move |shutdown| {
+                block_on(run_serve(
+                    config_path,
+                    fixtures,
+                    bind,
+                    port,
+                    false,
+                    log_filter,
+                    async {
+                        let _ = shutdown.await;
+                    },
+                ))
+            })
+        }
+    }
+}
+
+async fn run_serve(
+    config_path: PathBuf,
+    fixtures: Option<PathBuf>,
+    bind: Option<String>,
+    port: Option<u16>,
+    dry_run: bool,
+    log_filter: LogFilter,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
     let config = Config::load(&config_path)?;
     config.validate()?;
 
+    if let Some(log_level) = &config.server.log_level {
+        log_filter.set(log_level)?;
+    }
+
     let bind_address = bind.unwrap_or_else(|| "127.0.0.1".to_string());
     let port = port.unwrap_or(8080);
 
     let bind_addr = format!("{}:{}", bind_address, port);
 
+    if dry_run {
+        // Resolve the address without binding, the real server may still be using it
+        let resolved = tokio::net::lookup_host(&bind_addr).await.map_err(|e| {
+            Error::Config(format!("Can't resolve bind address '{}': {}", bind_addr, e))
+        })?;
+        let resolved: Vec<String> = resolved.map(|addr| addr.to_string()).collect();
+        let tenants = config
+            .server
+            .tenants
+            .keys()
+            .map(|id| Ok((id.as_str(), config.load_tenant(&config_path, id)?)))
+            .collect::<Result<Vec<_>>>()?;
+        print_dry_run(&config, &config_path, &bind_addr, &resolved, &tenants);
+        return Ok(());
+    }
+
     tracing::info!("Starting server on {}", bind_addr);
     tracing::info!(
         "Configured calendars: {:?}",
         config.calendars.keys().collect::<Vec<_>>()
     );
 
-    let fetcher = Fetcher::new()?;
-    let state = AppState::new(config, config_path.clone(), fetcher);
+    let fetcher = Fetcher::from_config(&config.fetcher)?.with_fixtures(fixtures);
+    let state = AppState::new(config, config_path.clone(), fetcher).with_log_filter(log_filter);
+    state.load_tenants()?;
     let app = create_router(state.clone());
 
+    let listener = match systemd::activated_listener()? {
+        Some(listener) => {
+            tracing::info!("Listening on the socket passed in by systemd");
+            tokio::net::TcpListener::from_std(listener)?
+        }
+        None => {
+            let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
+            tracing::info!("Server listening on {}", bind_addr);
+            listener
+        }
+    };
+
+    // Warm up in the background, /readyz and systemd are told when it's done
+    if state.is_ready() {
+        notify_systemd("READY=1");
+    } else {
+        let warm_state = state.clone();
+        state.supervisor.spawn("warm-up", move || {
+            let state = warm_state.clone();
+            async move {
+                state.warm_up().await;
+                tracing::info!("Warm-up finished, server is ready");
+                notify_systemd("READY=1");
+                Ok(())
+            }
+        });
+    }
+
+    // Ping systemd's watchdog well within its timeout while the runtime is responsive
+    if let Some(timeout) = systemd::watchdog() {
+        state.supervisor.spawn("watchdog", move || async move {
+            let mut interval = tokio::time::interval(timeout / 2);
+            loop {
+                interval.tick().await;
+                notify_systemd("WATCHDOG=1");
+            }
+        });
+    }
+
     // Start config file watcher
     start_config_watcher(state.clone())?;
     tracing::info!("Config file watcher started");
+    start_source_watcher(state.clone())?;
 
-    let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
-    tracing::info!("Server listening on {}", bind_addr);
-
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown)
+    .await?;
 
     Ok(())
 }
 
-async fn run_show(config_path: PathBuf, calendar_id: String) -> Result<()> {
+/// Tell systemd about the server's state when running under it
+fn notify_systemd(state: &str) {
+    if let Err(err) = systemd::notify(state) {
+        tracing::warn!("Failed to notify systemd of {}: {}", state, err);
+    }
+}
+
+/// Print the settings, routes and calendars the server would run with
+fn print_dry_run(
+    config: &Config,
+    config_path: &Path,
+    bind_addr: &str,
+    resolved: &[String],
+    tenants: &[(&str, Config)],
+) {
+    let server = &config.server;
+    let or_none = |value: Option<String>| value.unwrap_or_else(|| "none".to_string());
+
+    println!("Config: {}", config_path.display());
+    println!("Listen: {} ({})", bind_addr, resolved.join(", "));
+    println!(
+        "Base URL: {}",
+        server
+            .base_url
+            .as_deref()
+            .unwrap_or("taken from request headers")
+    );
+    println!(
+        "Path prefix: {}",
+        or_none(Some(server.path_prefix()).filter(|prefix| !prefix.is_empty()))
+    );
+    println!("Request timeout: {}s", server.request_timeout().as_secs());
+    let limits = server.cache_limits();
+    println!(
+        "Cache: {}",
+        server
+            .cache_ttl()
+            .map(|ttl| {
+                let mut cache = format!("{}s", ttl.as_secs());
+                if let Some(bytes) = limits.max_bytes {
+                    cache.push_str(&format!(", at most {} bytes", bytes));
+                }
+                if let Some(events) = limits.max_events {
+                    cache.push_str(&format!(", at most {} events", events));
+                }
+                cache
+            })
+            .unwrap_or_else(|| "disabled".to_string())
+    );
+    let warmup = config.warmup_calendars();
+    println!(
+        "Warm-up: {}",
+        or_none(Some(warmup.join(", ")).filter(|warmup| !warmup.is_empty()))
+    );
+    println!(
+        "Log level: {}",
+        server.log_level.as_deref().unwrap_or("default")
+    );
+    println!(
+        "Admin endpoints: {}",
+        if server.admin_token.is_some() {
+            "enabled"
+        } else {
+            "disabled (no admin_token)"
+        }
+    );
+    println!(
+        "Audit log: {}",
+        or_none(
+            server
+                .audit_log
+                .as_ref()
+                .map(|audit| audit.path.display().to_string())
+        )
+    );
+
+    println!();
+    println!("Routes:");
+    for (methods, path) in route_table(config) {
+        println!("  {:<8} {}", methods, path);
+    }
+
+    if !config.sources.is_empty() {
+        println!();
+        println!("Shared sources:");
+        let mut sources: Vec<_> = config.sources.iter().collect();
+        sources.sort_by_key(|(id, _)| *id);
+        for (id, source) in sources {
+            println!("  {}: {}", id, source.url);
+        }
+    }
+
+    println!();
+    println!("Calendars:");
+    let mut calendars: Vec<_> = config.calendars.iter().collect();
+    calendars.sort_by_key(|(id, _)| *id);
+    for (id, calendar) in calendars {
+        if calendar.aliases.is_empty() {
+            println!("  {}", id);
+        } else {
+            println!("  {} (aliases: {})", id, calendar.aliases.join(", "));
+        }
+        for (idx, source) in calendar.sources.iter().enumerate() {
+            println!(
+                "    source {}: {}, {}",
+                idx,
+                source.identifier(),
+                describe_steps(source.steps().len(), source.default_action())
+            );
+            for mirror in source.urls().iter().skip(1) {
+                println!("      mirror: {}", mirror);
+            }
+        }
+        println!(
+            "    calendar: {}",
+            describe_steps(calendar.steps.len(), &calendar.default)
+        );
+    }
+
+    if !tenants.is_empty() {
+        println!();
+        println!("Tenants:");
+        for (id, tenant) in tenants {
+            let mut calendars: Vec<_> = tenant.calendars.keys().map(String::as_str).collect();
+            calendars.sort();
+            println!(
+                "  {} ({}): {}",
+                id,
+                if config.server.tenants[*id].token.is_some() {
+                    "token required"
+                } else {
+                    "open"
+                },
+                calendars.join(", ")
+            );
+        }
+    }
+}
+
+fn describe_steps(count: usize, default: &DefaultAction) -> String {
+    let default = match default {
+        DefaultAction::Allow => "allow",
+        DefaultAction::Deny => "deny",
+    };
+    format!(
+        "{} step{}, default {}",
+        count,
+        if count == 1 { "" } else { "s" },
+        default
+    )
+}
+
+async fn run_show(
+    config_path: PathBuf,
+    fixtures: Option<PathBuf>,
+    calendar_id: String,
+) -> Result<()> {
     let config = Config::load(&config_path)?;
     config.validate()?;
 
-    // Verify calendar exists
-    if !config.calendars.contains_key(&calendar_id) {
-        return Err(ical_merge::error::Error::CalendarNotFound(
-            calendar_id.clone(),
-        ));
-    }
+    // Verify calendar exists, resolving aliases to the calendar they name
+    let calendar_id = match config.resolve_calendar(&calendar_id) {
+        Some(id) => id.to_string(),
+        None => return Err(ical_merge::error::Error::CalendarNotFound(calendar_id)),
+    };
 
-    let fetcher = Fetcher::new()?;
+    let fetcher = Fetcher::from_config(&config.fetcher)?.with_fixtures(fixtures);
     let result = merge_calendars(&calendar_id, &config, &fetcher).await?;
 
     // Report any errors
@@ -156,7 +678,7 @@ async fn run_show(config_path: PathBuf, calendar_id: String) -> Result<()> {
     }
 
     // Sort events by start time
-    let mut events = result.events;
+    let mut events = served_events(result.events, &config.calendars[&calendar_id]);
     events.sort_by(|a, b| {
         use std::cmp::Ordering;
         match (a.start(), b.start()) {
@@ -229,18 +751,21 @@ fn compare_date_perhaps_time(
     to_timestamp(a).cmp(&to_timestamp(b))
 }
 
-async fn run_ical(config_path: PathBuf, calendar_id: String) -> Result<()> {
+async fn run_ical(
+    config_path: PathBuf,
+    fixtures: Option<PathBuf>,
+    calendar_id: String,
+) -> Result<()> {
     let config = Config::load(&config_path)?;
     config.validate()?;
 
-    // Verify calendar exists
-    if !config.calendars.contains_key(&calendar_id) {
-        return Err(ical_merge::error::Error::CalendarNotFound(
-            calendar_id.clone(),
-        ));
-    }
+    // Verify calendar exists, resolving aliases to the calendar they name
+    let calendar_id = match config.resolve_calendar(&calendar_id) {
+        Some(id) => id.to_string(),
+        None => return Err(ical_merge::error::Error::CalendarNotFound(calendar_id)),
+    };
 
-    let fetcher = Fetcher::new()?;
+    let fetcher = Fetcher::from_config(&config.fetcher)?.with_fixtures(fixtures);
     let result = merge_calendars(&calendar_id, &config, &fetcher).await?;
 
     // Report any errors to stderr
@@ -249,7 +774,8 @@ async fn run_ical(config_path: PathBuf, calendar_id: String) -> Result<()> {
     }
 
     // Sort events by start time
-    let mut events = result.events;
+    let timezone = result.timezone;
+    let mut events = served_events(result.events, &config.calendars[&calendar_id]);
     events.sort_by(|a, b| {
         use std::cmp::Ordering;
         match (a.start(), b.start()) {
@@ -261,8 +787,98 @@ async fn run_ical(config_path: PathBuf, calendar_id: String) -> Result<()> {
     });
 
     // Serialize to iCal format and output to stdout
-    let ical_output = serialize_events(events);
-    println!("{}", ical_output);
+    let ical_output = serialize_events_with(events, &config.output, timezone.as_ref());
+    print!("{}", ical_output);
 
     Ok(())
 }
+
+async fn run_export(
+    config_path: PathBuf,
+    fixtures: Option<PathBuf>,
+    calendar_id: String,
+    format: ExportFormat,
+    columns: Option<String>,
+) -> Result<()> {
+    let config = Config::load(&config_path)?;
+    config.validate()?;
+
+    let columns = match &columns {
+        Some(list) => export::parse_columns(list)?,
+        None => export::DEFAULT_CSV_COLUMNS.to_vec(),
+    };
+
+    // Verify calendar exists, resolving aliases to the calendar they name
+    let calendar_id = match config.resolve_calendar(&calendar_id) {
+        Some(id) => id.to_string(),
+        None => return Err(ical_merge::error::Error::CalendarNotFound(calendar_id)),
+    };
+
+    let fetcher = Fetcher::from_config(&config.fetcher)?.with_fixtures(fixtures);
+    let result = merge_calendars(&calendar_id, &config, &fetcher).await?;
+
+    // Report any errors to stderr
+    for (url, error) in &result.errors {
+        eprintln!("Error fetching {}: {}", url, error);
+    }
+
+    // Exported as the server would serve the calendar now
+    let events = served_events(result.events, &config.calendars[&calendar_id]);
+    match format {
+        ExportFormat::Csv => print!("{}", export::to_csv(&events, &columns)),
+    }
+
+    Ok(())
+}
+
+fn run_convert(config_path: PathBuf, to: ConfigFormat) -> Result<()> {
+    let config = Config::load(&config_path)?;
+    config.validate()?;
+
+    match to {
+        ConfigFormat::Toml => print!("{}", config.to_toml()?),
+        ConfigFormat::Json => println!("{}", config.to_json()?),
+        ConfigFormat::Yaml => print!("{}", config.to_yaml()?),
+    }
+    Ok(())
+}
+
+fn run_bench(events: usize, iterations: usize, file: Option<&Path>) -> Result<()> {
+    let ical_text = match file {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => ical_merge::bench::sample_calendar(events),
+    };
+    let count = ical_merge::ical::parse_calendar(&ical_text)?
+        .into_events()
+        .len();
+    println!(
+        "{} events, {} KiB, {} iterations",
+        count,
+        ical_text.len() / 1024,
+        iterations.max(1)
+    );
+
+    for timing in ical_merge::bench::run(&ical_text, iterations)? {
+        println!(
+            "{:<10} {:>10.2?} median {:>10.2?} fastest",
+            timing.stage, timing.median, timing.fastest
+        );
+    }
+    Ok(())
+}
+
+fn run_graph(config_path: PathBuf, calendar_id: Option<String>, format: GraphFormat) -> Result<()> {
+    let config = Config::load(&config_path)?;
+    config.validate()?;
+
+    let graph = Graph::build(&config, calendar_id.as_deref())?;
+    for upstream in graph.duplicates() {
+        eprintln!("Warning: {} is included more than once", upstream);
+    }
+
+    match format {
+        GraphFormat::Dot => print!("{}", graph.to_dot()),
+        GraphFormat::Mermaid => print!("{}", graph.to_mermaid()),
+    }
+    Ok(())
+}
@@ -1,23 +1,115 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
+use bytes::Bytes;
+use chrono::NaiveDateTime;
 use futures::future::join_all;
-
-use crate::config::{Config, SourceConfig};
+use icalendar::Component;
+use serde::Serialize;
+use tokio::sync::OnceCell;
+use tracing::{Instrument, field};
+
+use crate::cache::MergeCache;
+use crate::config::{
+    CalendarConfig, Config, DtstampMode, OutputConfig, SequenceMode, SourceConfig,
+};
+use crate::conflicts;
 use crate::error::{Error, Result};
-use crate::fetcher::Fetcher;
-use crate::filter::{CompiledStep, process_events};
-use crate::ical::{Event, parse_calendar};
+use crate::fetcher::{Credentials, Fetcher};
+use crate::filter::{
+    CompiledStep, PipelineStats, decorate_events, process_events_with_stats,
+    process_tagged_events_with_stats,
+};
+use crate::ical::{Event, ParseWarning, parse_calendar, serialize_events_with};
+use crate::parallel;
+use crate::recurrence;
+use crate::timezone::Zone;
+
+/// Property recording the label of the source an event came from
+pub const SOURCE_LABEL_PROPERTY: &str = "X-ICAL-MERGE-SOURCE";
+
+/// Property recording the tags of the source an event came from
+pub const SOURCE_TAGS_PROPERTY: &str = "X-ICAL-MERGE-TAGS";
+
+/// Step counters for one pipeline run during a merge
+#[derive(Debug, Clone)]
+pub struct PipelineReport {
+    /// Calendar the pipeline belongs to
+    pub calendar: String,
+    /// Which pipeline ran, `source:<index>` or `calendar`
+    pub pipeline: String,
+    pub stats: PipelineStats,
+}
+
+/// What deduplication removed during a merge
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DedupeStats {
+    /// Events dropped because an earlier event had the same start and end
+    pub removed: u64,
+    /// Dropped events keyed by the source whose event was kept, then the
+    /// source whose event was dropped
+    pub collisions: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+impl DedupeStats {
+    /// Add the counts from another merge to these
+    pub fn add(&mut self, other: &DedupeStats) {
+        self.removed += other.removed;
+        for (kept, dropped) in &other.collisions {
+            let totals = self.collisions.entry(kept.clone()).or_default();
+            for (source, count) in dropped {
+                *totals.entry(source.clone()).or_default() += count;
+            }
+        }
+    }
+}
 
 /// Result of merging multiple calendar sources
 #[derive(Debug)]
 pub struct MergeResult {
     pub events: Vec<Event>,
     pub errors: Vec<(String, Error)>,
+    /// Step counters for every pipeline run, including referenced calendars
+    pub pipelines: Vec<PipelineReport>,
+    /// What deduplication removed from this calendar
+    pub dedupe: DedupeStats,
+    /// Zone the event times were rewritten into, to describe when serializing
+    pub timezone: Option<Zone>,
+    /// What had to be fixed to read each source, including referenced calendars'
+    pub warnings: Vec<(String, Vec<ParseWarning>)>,
+    /// The events as iCal text, written the first time they're served as they are
+    serialized: OnceLock<Bytes>,
 }
 
 impl MergeResult {
     pub fn new(events: Vec<Event>, errors: Vec<(String, Error)>) -> Self {
-        Self { events, errors }
+        Self {
+            events,
+            errors,
+            pipelines: Vec::new(),
+            dedupe: DedupeStats::default(),
+            timezone: None,
+            warnings: Vec::new(),
+            serialized: OnceLock::new(),
+        }
+    }
+
+    /// The events serialized as `output` asks, written once and then shared by
+    /// every request for this merge. `output` must be the same on every call,
+    /// which holds as a merge only lives as long as the config it came from
+    pub fn serialized(&self, output: &OutputConfig) -> Bytes {
+        self.serialized
+            .get_or_init(|| {
+                serialize_events_with(self.events.clone(), output, self.timezone.as_ref()).into()
+            })
+            .clone()
+    }
+
+    /// Whether sources failed and nothing was left to serve
+    pub fn is_failed(&self) -> bool {
+        self.events.is_empty() && !self.errors.is_empty()
     }
 }
 
@@ -48,19 +140,120 @@ fn extract_time_boundary(event: &Event) -> EventTimeBoundary {
 }
 
 /// Deduplicate events by (start, end) time, keeping only the first occurrence
-fn deduplicate_events(events: Vec<Event>) -> Vec<Event> {
-    let mut seen = HashSet::new();
+/// and counting which sources the duplicates came from
+pub fn deduplicate_events(events: Vec<(String, Event)>) -> (Vec<Event>, DedupeStats) {
+    // Reading the times is the slow part, and can be spread across threads
+    let boundaries = parallel::map_chunks(events.iter().collect(), |chunk| {
+        chunk
+            .into_iter()
+            .map(|(_, event)| extract_time_boundary(event))
+            .collect::<Vec<_>>()
+    });
+
+    let mut seen: HashMap<EventTimeBoundary, String> = HashMap::new();
     let mut deduplicated = Vec::new();
+    let mut stats = DedupeStats::default();
+
+    for ((source, event), time_boundary) in events.into_iter().zip(boundaries.into_iter().flatten())
+    {
+        match seen.get(&time_boundary) {
+            Some(kept) => {
+                stats.removed += 1;
+                *stats
+                    .collisions
+                    .entry(kept.clone())
+                    .or_default()
+                    .entry(source)
+                    .or_default() += 1;
+            }
+            None => {
+                seen.insert(time_boundary, source);
+                deduplicated.push(event);
+            }
+        }
+    }
 
-    for event in events {
-        let time_boundary = extract_time_boundary(&event);
+    (deduplicated, stats)
+}
+
+/// Step pipelines of every calendar compiled ahead of merging, so their regexes are
+/// compiled once per config rather than on every merge
+#[derive(Debug, Default)]
+pub struct CompiledPipelines {
+    /// Fingerprint of the calendars the pipelines were compiled from
+    key: u64,
+    calendars: HashMap<String, CalendarPipelines>,
+}
 
-        if seen.insert(time_boundary) {
-            deduplicated.push(event);
+#[derive(Debug)]
+struct CalendarPipelines {
+    steps: Vec<CompiledStep>,
+    /// Steps of each source, in the order the calendar lists them
+    sources: Vec<Vec<CompiledStep>>,
+    /// Steps of each variant, applied as it's served
+    variants: HashMap<String, Vec<CompiledStep>>,
+}
+
+impl CompiledPipelines {
+    /// Compile the pipelines of every calendar in `config`. Calendars with steps that
+    /// don't compile are left out, to be compiled and fail as they're merged
+    pub fn compile(config: &Config) -> Self {
+        let calendars = config
+            .calendars
+            .iter()
+            .filter_map(|(id, calendar)| {
+                let steps = CompiledStep::compile_many(&calendar.steps).ok()?;
+                let sources = calendar
+                    .sources
+                    .iter()
+                    .map(compile_source_steps)
+                    .collect::<Result<_>>()
+                    .ok()?;
+                let variants = calendar
+                    .variants
+                    .iter()
+                    .map(|(name, steps)| Ok((name.clone(), CompiledStep::compile_many(steps)?)))
+                    .collect::<Result<_>>()
+                    .ok()?;
+                Some((
+                    id.clone(),
+                    CalendarPipelines {
+                        steps,
+                        sources,
+                        variants,
+                    },
+                ))
+            })
+            .collect();
+        Self {
+            key: Self::key(config),
+            calendars,
         }
     }
 
-    deduplicated
+    /// Fingerprint of everything in `config` that goes into the pipelines
+    fn key(config: &Config) -> u64 {
+        let calendars: BTreeMap<_, _> = config.calendars.iter().collect();
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(&calendars)
+            .unwrap_or_default()
+            .hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Whether these pipelines were compiled from the calendars in `config`
+    pub fn is_for(&self, config: &Config) -> bool {
+        self.key == Self::key(config)
+    }
+
+    /// Compiled steps of the variant `name` of the calendar `calendar_id`
+    pub fn variant(&self, calendar_id: &str, name: &str) -> Option<&[CompiledStep]> {
+        self.calendars
+            .get(calendar_id)?
+            .variants
+            .get(name)
+            .map(Vec::as_slice)
+    }
 }
 
 /// Fetch and merge calendars according to config
@@ -69,6 +262,96 @@ pub async fn merge_calendars(
     config: &Config,
     fetcher: &Fetcher,
 ) -> Result<MergeResult> {
+    merge_calendars_with(calendar_id, config, &CompiledPipelines::default(), fetcher).await
+}
+
+/// What fetching an upstream gave, shared by every source wanting it
+type Fetched = Arc<OnceCell<std::result::Result<Arc<str>, Arc<Error>>>>;
+
+/// What is shared between the calendars merged for one request
+#[derive(Default)]
+struct MergeRun<'a> {
+    /// Upstreams fetched so far by URL and the credentials they're made with, since
+    /// different users can be shown different calendars at one URL. Sources and
+    /// referenced calendars naming the same URL share a single download
+    fetched: Mutex<HashMap<(String, Option<String>), Fetched>>,
+    /// Merged calendars kept by the server, and how long they stay fresh
+    cache: Option<(&'a MergeCache, Duration)>,
+}
+
+impl MergeRun<'_> {
+    /// Fetch `url` unless it's already been fetched with the same credentials in
+    /// this merge, waiting for that fetch if it's still going
+    async fn fetch(
+        &self,
+        url: &str,
+        credentials: Credentials<'_>,
+        timeout: Option<Duration>,
+        fetcher: &Fetcher,
+    ) -> Result<Arc<str>> {
+        let key = (url.to_string(), credentials.identity());
+        let cell = self.fetched.lock().unwrap().entry(key).or_default().clone();
+        if cell.initialized() {
+            tracing::debug!("Reusing {} fetched earlier in this merge", url);
+        }
+        cell.get_or_init(|| async {
+            fetcher
+                .fetch_with_auth(url, credentials, timeout)
+                .await
+                .map(Arc::from)
+                .map_err(Arc::new)
+        })
+        .await
+        .clone()
+        .map_err(Error::Shared)
+    }
+}
+
+/// Fetch and merge calendars according to config, using `pipelines` compiled from
+/// the same config rather than compiling the steps again
+pub async fn merge_calendars_with(
+    calendar_id: &str,
+    config: &Config,
+    pipelines: &CompiledPipelines,
+    fetcher: &Fetcher,
+) -> Result<MergeResult> {
+    merge_calendar(
+        calendar_id,
+        config,
+        pipelines,
+        fetcher,
+        &MergeRun::default(),
+    )
+    .await
+}
+
+/// Like [`merge_calendars_with`], but calendars referenced by this one are read
+/// through `cache`: merges of them fresher than `ttl` are reused rather than fetching
+/// their sources again, and new merges of them are stored
+pub async fn merge_calendars_cached(
+    calendar_id: &str,
+    config: &Config,
+    pipelines: &CompiledPipelines,
+    fetcher: &Fetcher,
+    cache: &MergeCache,
+    ttl: Duration,
+) -> Result<MergeResult> {
+    let run = MergeRun {
+        cache: Some((cache, ttl)),
+        ..MergeRun::default()
+    };
+    merge_calendar(calendar_id, config, pipelines, fetcher, &run).await
+}
+
+/// Merge a calendar, or one it references, sharing `run` with the rest of the merge
+async fn merge_calendar(
+    calendar_id: &str,
+    config: &Config,
+    pipelines: &CompiledPipelines,
+    fetcher: &Fetcher,
+    run: &MergeRun<'_>,
+) -> Result<MergeResult> {
+    let compiled = pipelines.calendars.get(calendar_id);
     let calendar_config = config
         .calendars
         .get(calendar_id)
@@ -77,78 +360,502 @@ pub async fn merge_calendars(
     let futures: Vec<_> = calendar_config
         .sources
         .iter()
-        .map(|source| fetch_and_process_source(source, config, fetcher))
+        .enumerate()
+        .map(|(idx, source)| {
+            // Fields are filled in as the source is fetched and processed
+            let span = tracing::debug_span!(
+                "source",
+                calendar = calendar_id,
+                source = %source.identifier(),
+                bytes = field::Empty,
+                parsed = field::Empty,
+                kept = field::Empty,
+            );
+            let steps = compiled.map(|compiled| compiled.sources[idx].as_slice());
+            fetch_and_process_source(source, steps, config, pipelines, fetcher, run)
+                .instrument(span)
+        })
         .collect();
 
     let results = join_all(futures).await;
 
     let mut all_events = Vec::new();
     let mut errors = Vec::new();
+    let mut pipelines = Vec::new();
+    let mut warnings = Vec::new();
 
-    for result in results {
+    for (idx, result) in results.into_iter().enumerate() {
         match result {
-            Ok(events) => all_events.extend(events),
+            Ok(output) => {
+                // Labelled sources are reported by label rather than position
+                let source = &calendar_config.sources[idx];
+                let pipeline = match source.label() {
+                    Some(label) => format!("source:{}", label),
+                    None => format!("source:{}", idx),
+                };
+                // Remember where each event came from to report dedupe collisions
+                all_events.extend(
+                    output
+                        .events
+                        .into_iter()
+                        .map(|event| (pipeline.clone(), event)),
+                );
+                pipelines.extend(output.referenced);
+                warnings.extend(output.warnings);
+                pipelines.push(PipelineReport {
+                    calendar: calendar_id.to_string(),
+                    pipeline,
+                    stats: output.stats,
+                });
+            }
             Err((identifier, err)) => errors.push((identifier, err)),
         }
     }
 
     // Apply calendar-level steps
-    let calendar_steps = CompiledStep::compile_many(&calendar_config.steps)
-        .map_err(|e| Error::Config(format!("Failed to compile calendar-level steps: {}", e)))?;
-    let processed_events = process_events(all_events, &calendar_steps);
+    let compiled_steps;
+    let calendar_steps = match compiled {
+        Some(compiled) => &compiled.steps,
+        None => {
+            compiled_steps = CompiledStep::compile_many(&calendar_config.steps).map_err(|e| {
+                Error::Config(format!("Failed to compile calendar-level steps: {}", e))
+            })?;
+            &compiled_steps
+        }
+    };
+    let (processed_events, stats) =
+        process_tagged_events_with_stats(all_events, calendar_steps, &calendar_config.default);
+    pipelines.push(PipelineReport {
+        calendar: calendar_id.to_string(),
+        pipeline: "calendar".to_string(),
+        stats,
+    });
 
     // Deduplicate events by (start, end) time
-    let deduplicated_events = deduplicate_events(processed_events);
+    let (deduplicated_events, dedupe) = deduplicate_events(processed_events);
+    tracing::debug!(
+        calendar = calendar_id,
+        events = deduplicated_events.len(),
+        duplicates = dedupe.removed,
+        errors = errors.len(),
+        "Merged calendar"
+    );
+
+    // Occurrences are expanded after deduplication, which would otherwise drop
+    // an occurrence sharing its series' start
+    let events = recurrence::apply(
+        deduplicated_events,
+        &calendar_config.recurrence,
+        chrono::Local::now().date_naive(),
+    );
+
+    let events = if calendar_config.flag_conflicts {
+        conflicts::flag_conflicts(events)
+    } else {
+        events
+    };
+    let events = normalize_revisions(events, &config.output, chrono::Utc::now());
+
+    let timezone = calendar_config
+        .output_timezone
+        .as_deref()
+        .map(Zone::load)
+        .transpose()?;
+    let events = match &timezone {
+        Some(zone) => zone.convert_events(events),
+        None => events,
+    };
+
+    Ok(MergeResult {
+        events,
+        errors,
+        pipelines,
+        dedupe,
+        timezone,
+        warnings,
+        serialized: OnceLock::new(),
+    })
+}
 
-    Ok(MergeResult::new(deduplicated_events, errors))
+/// Rewrite DTSTAMP and SEQUENCE as the output config asks, so clients that
+/// react to every bump don't re-alert for events that didn't change
+fn normalize_revisions(
+    events: Vec<Event>,
+    output: &OutputConfig,
+    merged_at: chrono::DateTime<chrono::Utc>,
+) -> Vec<Event> {
+    if output.dtstamp == DtstampMode::Keep && output.sequence == SequenceMode::Keep {
+        return events;
+    }
+
+    let dtstamp = merged_at.format("%Y%m%dT%H%M%SZ").to_string();
+    events
+        .into_iter()
+        .map(|mut event| {
+            if output.dtstamp == DtstampMode::Refresh {
+                event.set_property("DTSTAMP", &dtstamp);
+            }
+            match output.sequence {
+                SequenceMode::Keep => {}
+                SequenceMode::Drop => event.remove_properties(&["SEQUENCE"]),
+                SequenceMode::Zero => event.set_property("SEQUENCE", "0"),
+            }
+            event
+        })
+        .collect()
 }
 
-/// Fetch and process a single source
+/// Events and step counters produced by a single source
+struct SourceOutput {
+    events: Vec<Event>,
+    stats: PipelineStats,
+    /// Pipeline reports from a referenced calendar's own merge
+    referenced: Vec<PipelineReport>,
+    /// What had to be fixed to read this source, or a referenced calendar's sources
+    warnings: Vec<(String, Vec<ParseWarning>)>,
+}
+
+/// Label recorded on a source's events. Shared sources fall back to their id when
+/// they have no explicit label
+fn provenance_label(source: &SourceConfig) -> Option<&str> {
+    match source {
+        SourceConfig::Source {
+            source: source_id,
+            label: None,
+            ..
+        } => Some(source_id.as_str()),
+        _ => source.label(),
+    }
+}
+
+/// Compile a source's steps, which can refer to its label and tags
+fn compile_source_steps(source: &SourceConfig) -> Result<Vec<CompiledStep>> {
+    let tags = source.tags().join(",");
+    let vars = [
+        ("label", provenance_label(source).unwrap_or_default()),
+        ("tags", tags.as_str()),
+    ];
+    CompiledStep::compile_many_with_vars(source.steps(), &vars)
+}
+
+/// Fetch and process a single source, with its steps when they're already compiled
 async fn fetch_and_process_source(
     source: &SourceConfig,
+    steps: Option<&[CompiledStep]>,
     config: &Config,
+    pipelines: &CompiledPipelines,
     fetcher: &Fetcher,
-) -> std::result::Result<Vec<Event>, (String, Error)> {
+    run: &MergeRun<'_>,
+) -> std::result::Result<SourceOutput, (String, Error)> {
     let identifier = source.identifier();
+    let mut referenced = Vec::new();
+    let mut warnings = Vec::new();
 
     // Get events from either URL or calendar reference
-    let events = match source {
-        SourceConfig::Url { url, .. } => {
-            // Fetch calendar
-            let ical_text = fetcher
-                .fetch(url)
+    let mut events = match source {
+        SourceConfig::Url { .. } | SourceConfig::Urls { .. } => {
+            let (events, parse_warnings) = fetch_first(
+                source.urls(),
+                source.credentials(),
+                source.timeout(),
+                fetcher,
+                run,
+            )
+            .await
+            .map_err(|e| (identifier.clone(), e))?;
+            if !parse_warnings.is_empty() {
+                warnings.push((identifier.clone(), parse_warnings));
+            }
+            events
+        }
+        SourceConfig::Holidays {
+            holidays, region, ..
+        } => crate::holidays::holiday_events(
+            holidays,
+            region.as_deref(),
+            crate::holidays::default_years(),
+        )
+        .map_err(|e| (identifier.clone(), e))?,
+        SourceConfig::Generate { generate, .. } => {
+            crate::generate::generated_events(generate).map_err(|e| (identifier.clone(), e))?
+        }
+        SourceConfig::Command { command, args, .. } => {
+            let (events, parse_warnings) = run_command(command, args, source.timeout(), fetcher)
                 .await
                 .map_err(|e| (identifier.clone(), e))?;
-
-            // Parse calendar
-            let calendar = parse_calendar(&ical_text).map_err(|e| (identifier.clone(), e))?;
-
-            calendar.into_events()
+            if !parse_warnings.is_empty() {
+                warnings.push((identifier.clone(), parse_warnings));
+            }
+            events
+        }
+        SourceConfig::Birthdays { birthdays, .. } => {
+            let vcards = fetcher
+                .fetch_address_book(birthdays, source.credentials(), source.timeout())
+                .await
+                .map_err(|e| (identifier.clone(), e))?;
+            tracing::debug!(bytes = vcards.len(), "Fetched address book");
+            let contacts = crate::birthdays::parse_contacts(&vcards);
+            crate::birthdays::birthday_events(&contacts)
+        }
+        SourceConfig::Json {
+            json,
+            items,
+            fields,
+            timezone,
+            ..
+        } => {
+            let text = run
+                .fetch(json, source.credentials(), source.timeout(), fetcher)
+                .await
+                .map_err(|e| (identifier.clone(), e))?;
+            tracing::debug!(bytes = text.len(), "Fetched JSON");
+            let (events, item_warnings) =
+                crate::json::json_events(&text, items.as_deref(), fields, timezone.as_deref())
+                    .map_err(|e| (identifier.clone(), e))?;
+            if !item_warnings.is_empty() {
+                warnings.push((identifier.clone(), item_warnings));
+            }
+            events
+        }
+        SourceConfig::Git {
+            git,
+            files,
+            branch,
+            interval,
+            ..
+        } => {
+            let interval = Duration::from_secs(interval.unwrap_or(crate::git::DEFAULT_INTERVAL));
+            let texts = fetcher
+                .fetch_git(git, branch.as_deref(), files, interval, source.timeout())
+                .await
+                .map_err(|e| (identifier.clone(), e))?;
+            let mut events = Vec::new();
+            for (file, text) in texts {
+                let (file_events, parse_warnings) =
+                    parse_fetched(&text).map_err(|e| (format!("{} {}", identifier, file), e))?;
+                if !parse_warnings.is_empty() {
+                    warnings.push((format!("{} {}", identifier, file), parse_warnings));
+                }
+                events.extend(file_events);
+            }
+            events
+        }
+        SourceConfig::Inline { ics, .. } => {
+            let (events, parse_warnings) =
+                parse_fetched(ics).map_err(|e| (identifier.clone(), e))?;
+            if !parse_warnings.is_empty() {
+                warnings.push((identifier.clone(), parse_warnings));
+            }
+            events
+        }
+        SourceConfig::Source {
+            source: source_id, ..
+        } => {
+            let shared = config.sources.get(source_id).ok_or_else(|| {
+                (
+                    identifier.clone(),
+                    Error::Config(format!("Source '{}' not found", source_id)),
+                )
+            })?;
+            let (events, parse_warnings) = fetch_events(
+                &shared.url,
+                shared.credentials(),
+                shared.timeout(),
+                fetcher,
+                run,
+            )
+            .await
+            .map_err(|e| (identifier.clone(), e))?;
+            if !parse_warnings.is_empty() {
+                warnings.push((identifier.clone(), parse_warnings));
+            }
+            events
         }
         SourceConfig::Calendar {
             calendar: ref_id, ..
         } => {
             // Resolve calendar reference
-            resolve_calendar_reference(ref_id, config, fetcher)
-                .await
-                .map_err(|e| (identifier.clone(), e))?
+            let (merge_result, merged) =
+                resolve_calendar_reference(ref_id, config, pipelines, fetcher, run)
+                    .await
+                    .map_err(|e| (identifier.clone(), e))?;
+            // A cached merge's pipelines were reported when it ran, and didn't run again
+            if merged {
+                referenced = merge_result.pipelines.clone();
+            }
+            warnings = merge_result.warnings.clone();
+            merge_result.events.clone()
         }
     };
 
-    // Compile and apply source-level steps
-    let steps = CompiledStep::compile_many(source.steps()).map_err(|e| (identifier.clone(), e))?;
-    let processed_events = process_events(events, &steps);
+    let label = provenance_label(source);
+    let tags = source.tags().join(",");
+
+    // Record provenance, keeping any already set by a referenced calendar's sources
+    for event in &mut events {
+        if let Some(label) = label
+            && event.property(SOURCE_LABEL_PROPERTY).is_none()
+        {
+            event.set_property(SOURCE_LABEL_PROPERTY, label);
+        }
+        if !tags.is_empty() && event.property(SOURCE_TAGS_PROPERTY).is_none() {
+            event.set_property(SOURCE_TAGS_PROPERTY, &tags);
+        }
+    }
 
-    Ok(processed_events)
+    // Trim old events before they go through the pipeline
+    let parsed = events.len();
+    if let Some(cutoff) = source.include_cutoff(chrono::Local::now().naive_local()) {
+        events = trim_old_events(events, cutoff);
+    }
+
+    // Apply source-level steps, compiling them if they weren't already
+    let compiled_steps;
+    let steps = match steps {
+        Some(steps) => steps,
+        None => {
+            compiled_steps = compile_source_steps(source).map_err(|e| (identifier.clone(), e))?;
+            &compiled_steps
+        }
+    };
+    let included = events.len();
+    let (events, stats) = process_events_with_stats(events, steps, source.default_action());
+
+    let span = tracing::Span::current();
+    span.record("parsed", parsed);
+    span.record("kept", events.len());
+    tracing::debug!(
+        parsed,
+        trimmed = parsed - included,
+        kept = events.len(),
+        "Processed source"
+    );
+
+    Ok(SourceOutput {
+        events,
+        stats,
+        referenced,
+        warnings,
+    })
+}
+
+/// Drop events that ended before `cutoff`, keeping recurring events whose later
+/// occurrences may not have
+pub fn trim_old_events(events: Vec<Event>, cutoff: NaiveDateTime) -> Vec<Event> {
+    events
+        .into_iter()
+        .filter(|event| !is_over(event, cutoff))
+        .collect()
+}
+
+/// Apply the calendar's soon steps and hide long finished events as of now, as the
+/// server does for each request, for commands showing a calendar as it's served
+pub fn served_events(events: Vec<Event>, calendar: &CalendarConfig) -> Vec<Event> {
+    let now = chrono::Local::now().naive_local();
+    let events = match calendar.serve_cutoff(now) {
+        Some(cutoff) => trim_old_events(events, cutoff),
+        None => events,
+    };
+    decorate_events(events, &calendar.steps, now)
+}
+
+/// Whether an event ended before `cutoff`, which a recurring event never has
+pub fn is_over(event: &Event, cutoff: NaiveDateTime) -> bool {
+    event.property("RRULE").is_none()
+        && !event.inner().multi_properties().contains_key("RDATE")
+        && event.end_time().is_some_and(|end| end < cutoff)
+}
+
+/// Fetch and parse the events from the first of `urls` that can be read, so
+/// mirrors are only used when the primary fails
+async fn fetch_first(
+    urls: &[String],
+    credentials: Credentials<'_>,
+    timeout: Option<Duration>,
+    fetcher: &Fetcher,
+    run: &MergeRun<'_>,
+) -> Result<(Vec<Event>, Vec<ParseWarning>)> {
+    let mut last_error = None;
+    for url in urls {
+        if let Some(err) = &last_error {
+            tracing::warn!("Trying {} after the previous URL failed: {}", url, err);
+        }
+        match fetch_events(url, credentials, timeout, fetcher, run).await {
+            Ok(fetched) => return Ok(fetched),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| Error::Config("Source has no URLs".to_string())))
+}
+
+/// Fetch and parse the events from a calendar URL, along with anything that had
+/// to be fixed to read them
+async fn fetch_events(
+    url: &str,
+    credentials: Credentials<'_>,
+    timeout: Option<Duration>,
+    fetcher: &Fetcher,
+    run: &MergeRun<'_>,
+) -> Result<(Vec<Event>, Vec<ParseWarning>)> {
+    let ical_text = run.fetch(url, credentials, timeout, fetcher).await?;
+    parse_fetched(&ical_text)
+}
+
+/// Run a command source's program and parse the events it prints
+async fn run_command(
+    command: &str,
+    args: &[String],
+    timeout: Option<Duration>,
+    fetcher: &Fetcher,
+) -> Result<(Vec<Event>, Vec<ParseWarning>)> {
+    let ical_text = fetcher.run_command(command, args, timeout).await?;
+    parse_fetched(&ical_text)
+}
+
+/// Parse a source's iCal text, logging anything that had to be fixed to read it
+fn parse_fetched(ical_text: &str) -> Result<(Vec<Event>, Vec<ParseWarning>)> {
+    tracing::Span::current().record("bytes", ical_text.len());
+    tracing::debug!(bytes = ical_text.len(), "Fetched source");
+
+    let calendar = parse_calendar(ical_text)?;
+    let warnings = calendar.warnings().to_vec();
+    for warning in &warnings {
+        tracing::warn!(
+            line = warning.line,
+            "Fixed source data: {}",
+            warning.message
+        );
+    }
+    Ok((calendar.into_events(), warnings))
 }
 
-/// Resolve a calendar reference by recursively calling merge_calendars
+/// Resolve a calendar reference by recursively calling merge_calendars, or from the
+/// server's cache while its merge of the calendar is fresh, so it's the same as what
+/// the calendar's own URL serves. Also returns whether it was merged just now
 async fn resolve_calendar_reference(
     calendar_id: &str,
     config: &Config,
+    pipelines: &CompiledPipelines,
     fetcher: &Fetcher,
-) -> Result<Vec<Event>> {
-    let merge_result = merge_calendars(calendar_id, config, fetcher).await?;
+    run: &MergeRun<'_>,
+) -> Result<(Arc<MergeResult>, bool)> {
+    if let Some((cache, ttl)) = run.cache
+        && let Some(cached) = cache.get_fresh(calendar_id, ttl)
+    {
+        tracing::debug!(
+            "Using the cached merge of referenced calendar '{}'",
+            calendar_id
+        );
+        return Ok((cached, false));
+    }
+
+    let merge_result =
+        Arc::new(merge_calendar(calendar_id, config, pipelines, fetcher, run).await?);
+    if let Some((cache, _)) = run.cache
+        && !merge_result.is_failed()
+    {
+        cache.insert(calendar_id, merge_result.clone());
+    }
 
     // Log errors from referenced calendar
     for (identifier, err) in &merge_result.errors {
@@ -160,14 +867,18 @@ async fn resolve_calendar_reference(
         );
     }
 
-    Ok(merge_result.events)
+    Ok((merge_result, true))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{CalendarConfig, MatchMode, SourceConfig, Step};
+    use crate::config::{
+        CalendarConfig, DefaultAction, FilterAction, MatchMode, SharedSourceConfig, SourceConfig,
+        Step,
+    };
     use std::collections::HashMap;
+    use tracing::instrument::WithSubscriber;
     use wiremock::matchers::{method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
@@ -202,6 +913,75 @@ SUMMARY:Holiday
 END:VEVENT
 END:VCALENDAR"#;
 
+    #[test]
+    fn test_trim_old_events() {
+        let ical = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nUID:old\r\nDTSTART:20240101T090000\r\nDTEND:20240101T100000\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:ongoing\r\nDTSTART:20250228T090000\r\nDTEND:20250302T100000\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:weekly\r\nDTSTART:20240101T090000\r\nRRULE:FREQ=WEEKLY\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:upcoming\r\nDTSTART;VALUE=DATE:20250310\r\nEND:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        let events = parse_calendar(ical).unwrap().into_events();
+        let cutoff = chrono::NaiveDate::from_ymd_opt(2025, 3, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+
+        let kept = trim_old_events(events, cutoff);
+        let uids: Vec<_> = kept.iter().map(|e| e.uid().unwrap()).collect();
+        assert_eq!(uids, vec!["ongoing", "weekly", "upcoming"]);
+    }
+
+    #[test]
+    fn test_serialized_once() {
+        let events = parse_calendar(CALENDAR2).unwrap().into_events();
+        let result = MergeResult::new(events.clone(), vec![]);
+        let output = OutputConfig::default();
+
+        let first = result.serialized(&output);
+        assert_eq!(
+            first,
+            serialize_events_with(events, &output, None).as_bytes()
+        );
+        // Later requests share the same text rather than writing it again
+        let second = result.serialized(&output);
+        assert_eq!(first.as_ptr(), second.as_ptr());
+    }
+
+    #[test]
+    fn test_normalize_revisions() {
+        let ical = CALENDAR1.replace("SUMMARY:Meeting with team", "SUMMARY:Meeting\nSEQUENCE:4");
+        let events = parse_calendar(&ical).unwrap().into_events();
+        let merged_at = chrono::DateTime::parse_from_rfc3339("2025-03-01T08:30:00Z")
+            .unwrap()
+            .to_utc();
+
+        let output = OutputConfig::default();
+        assert_eq!(
+            normalize_revisions(events.clone(), &output, merged_at),
+            events
+        );
+
+        let output = OutputConfig {
+            dtstamp: DtstampMode::Refresh,
+            sequence: SequenceMode::Drop,
+            ..Default::default()
+        };
+        let normalized = normalize_revisions(events.clone(), &output, merged_at);
+        for event in &normalized {
+            assert_eq!(event.property("DTSTAMP"), Some("20250301T083000Z"));
+            assert_eq!(event.property("SEQUENCE"), None);
+        }
+
+        let output = OutputConfig {
+            sequence: SequenceMode::Zero,
+            ..Default::default()
+        };
+        let normalized = normalize_revisions(events, &output, merged_at);
+        assert_eq!(normalized[0].property("SEQUENCE"), Some("0"));
+        assert_eq!(normalized[0].property("DTSTAMP"), Some("20231201T120000Z"));
+    }
+
     #[tokio::test]
     async fn test_merge_multiple_calendars() {
         let mock_server = MockServer::start().await;
@@ -226,23 +1006,179 @@ END:VCALENDAR"#;
                     SourceConfig::Url {
                         url: format!("{}/cal1.ics", mock_server.uri()),
                         steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                        username: None,
+                        password: None,
+                        auth: None,
+                        timeout: None,
+                        client_cert: None,
+                        client_key: None,
+                        insecure_tls: false,
                     },
                     SourceConfig::Url {
                         url: format!("{}/cal2.ics", mock_server.uri()),
                         steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                        username: None,
+                        password: None,
+                        auth: None,
+                        timeout: None,
+                        client_cert: None,
+                        client_key: None,
+                        insecure_tls: false,
                     },
                 ],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
-        let config = Config { calendars };
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
 
         let fetcher = Fetcher::new().unwrap();
         let result = merge_calendars("test", &config, &fetcher).await.unwrap();
 
         assert_eq!(result.events.len(), 3);
         assert_eq!(result.errors.len(), 0);
+
+        // The fixtures use bare LF line endings, which is recorded against each source
+        assert_eq!(result.warnings.len(), 2);
+        assert_eq!(
+            result.warnings[0].0,
+            format!("{}/cal1.ics", mock_server.uri())
+        );
+        assert_eq!(
+            result.warnings[0].1[0].message,
+            "Lines end with a bare LF instead of CRLF"
+        );
+    }
+
+    /// Log output captured in memory
+    #[derive(Clone, Default)]
+    struct CapturedLogs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_source_log_context() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/cal1.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(CALENDAR1))
+            .mount(&mock_server)
+            .await;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/cal1.ics", mock_server.uri()),
+                    steps: vec![Step::Deny {
+                        patterns: vec!["(?i)optional".to_string()],
+                        mode: MatchMode::Any,
+                        fields: vec!["summary".to_string()],
+                        action: FilterAction::Enforce,
+                        unless: vec![],
+                        literal: false,
+                    }],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter("ical_merge=debug")
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+
+        let fetcher = Fetcher::new().unwrap();
+        let result = merge_calendars("test", &config, &fetcher)
+            .with_subscriber(subscriber)
+            .await
+            .unwrap();
+        assert_eq!(result.events.len(), 1);
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let line = output
+            .lines()
+            .find(|line| line.contains("Processed source"))
+            .expect("source should be logged");
+        assert!(line.contains("calendar=\"test\""));
+        assert!(line.contains(&format!("source={}/cal1.ics", mock_server.uri())));
+        assert!(line.contains(&format!("bytes={}", CALENDAR1.len())));
+        assert!(line.contains("parsed=2"));
+        assert!(line.contains("kept=1"));
     }
 
     #[tokio::test]
@@ -265,13 +1201,46 @@ END:VCALENDAR"#;
                         patterns: vec!["(?i)meeting".to_string()],
                         mode: MatchMode::Any,
                         fields: vec!["summary".to_string()],
+                        action: FilterAction::Enforce,
+                        unless: vec![],
+                        literal: false,
                     }],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
-        let config = Config { calendars };
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
 
         let fetcher = Fetcher::new().unwrap();
         let result = merge_calendars("test", &config, &fetcher).await.unwrap();
@@ -303,19 +1272,53 @@ END:VCALENDAR"#;
                             patterns: vec!["(?i)meeting".to_string()],
                             mode: MatchMode::Any,
                             fields: vec!["summary".to_string()],
+                            action: FilterAction::Enforce,
+                            unless: vec![],
+                            literal: false,
                         },
                         Step::Replace {
                             pattern: "^Meeting".to_string(),
                             replacement: "[WORK]".to_string(),
                             field: "summary".to_string(),
+                            literal: false,
                         },
                     ],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
-        let config = Config { calendars };
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
 
         let fetcher = Fetcher::new().unwrap();
         let result = merge_calendars("test", &config, &fetcher).await.unwrap();
@@ -349,17 +1352,59 @@ END:VCALENDAR"#;
                     SourceConfig::Url {
                         url: format!("{}/cal1.ics", mock_server.uri()),
                         steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                        username: None,
+                        password: None,
+                        auth: None,
+                        timeout: None,
+                        client_cert: None,
+                        client_key: None,
+                        insecure_tls: false,
                     },
                     SourceConfig::Url {
                         url: format!("{}/notfound.ics", mock_server.uri()),
                         steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                        username: None,
+                        password: None,
+                        auth: None,
+                        timeout: None,
+                        client_cert: None,
+                        client_key: None,
+                        insecure_tls: false,
                     },
                 ],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
-        let config = Config { calendars };
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
 
         let fetcher = Fetcher::new().unwrap();
         let result = merge_calendars("test", &config, &fetcher).await.unwrap();
@@ -370,6 +1415,126 @@ END:VCALENDAR"#;
         assert!(result.errors[0].0.contains("notfound.ics"));
     }
 
+    #[tokio::test]
+    async fn test_source_timeout() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(CALENDAR1)
+                    .set_delay(Duration::from_secs(2)),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let config: Config = serde_json::from_str(&format!(
+            r#"{{
+                "fetcher": {{ "timeout": 1 }},
+                "calendars": {{
+                    "slow": {{
+                        "sources": [
+                            {{ "url": "{0}/patient.ics", "timeout": 5 }},
+                            {{ "url": "{0}/impatient.ics" }}
+                        ]
+                    }}
+                }}
+            }}"#,
+            mock_server.uri()
+        ))
+        .unwrap();
+        config.validate().unwrap();
+
+        // The source's own timeout replaces the fetcher's, the other gives up
+        let fetcher = Fetcher::from_config(&config.fetcher).unwrap();
+        let result = merge_calendars("slow", &config, &fetcher).await.unwrap();
+        assert_eq!(result.events.len(), 2);
+        assert_eq!(result.errors.len(), 1);
+        assert!(result.errors[0].0.contains("impatient.ics"));
+    }
+
+    #[tokio::test]
+    async fn test_source_mirrors() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/cal1.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(CALENDAR1))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/down.ics"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/unused.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(CALENDAR1))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let source = |files: &[&str]| SourceConfig::Urls {
+            urls: files
+                .iter()
+                .map(|file| format!("{}/{}", mock_server.uri(), file))
+                .collect(),
+            username: None,
+            password: None,
+            auth: None,
+            steps: vec![],
+            default: DefaultAction::Allow,
+            label: None,
+            tags: vec![],
+            only_future: false,
+            skip_older_than: None,
+            timeout: None,
+            client_cert: None,
+            client_key: None,
+            insecure_tls: false,
+        };
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test".to_string(),
+            CalendarConfig {
+                sources: vec![
+                    // The mirror is used when the primary is down
+                    source(&["down.ics", "cal1.ics", "unused.ics"]),
+                    source(&["down.ics", "down.ics"]),
+                ],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        config.validate().unwrap();
+
+        let fetcher = Fetcher::new().unwrap();
+        let result = merge_calendars("test", &config, &fetcher).await.unwrap();
+
+        assert_eq!(result.events.len(), 2);
+        assert_eq!(result.errors.len(), 1);
+        assert!(result.errors[0].1.to_string().contains("503"));
+    }
+
     #[tokio::test]
     async fn test_deduplication_by_time() {
         let mock_server = MockServer::start().await;
@@ -434,17 +1599,59 @@ END:VCALENDAR"#;
                     SourceConfig::Url {
                         url: format!("{}/cal1.ics", mock_server.uri()),
                         steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                        username: None,
+                        password: None,
+                        auth: None,
+                        timeout: None,
+                        client_cert: None,
+                        client_key: None,
+                        insecure_tls: false,
                     },
                     SourceConfig::Url {
                         url: format!("{}/cal2.ics", mock_server.uri()),
                         steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                        username: None,
+                        password: None,
+                        auth: None,
+                        timeout: None,
+                        client_cert: None,
+                        client_key: None,
+                        insecure_tls: false,
                     },
                 ],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
-        let config = Config { calendars };
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
 
         let fetcher = Fetcher::new().unwrap();
         let result = merge_calendars("test", &config, &fetcher).await.unwrap();
@@ -486,6 +1693,10 @@ END:VCALENDAR"#;
                 .iter()
                 .any(|e| e.summary() == Some("Unique Event 2"))
         );
+
+        // The collision is attributed to the sources involved
+        assert_eq!(result.dedupe.removed, 1);
+        assert_eq!(result.dedupe.collisions["source:0"]["source:1"], 1);
     }
 
     #[tokio::test]
@@ -505,8 +1716,30 @@ END:VCALENDAR"#;
                 sources: vec![SourceConfig::Url {
                     url: format!("{}/base.ics", mock_server.uri()),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
         calendars.insert(
@@ -515,12 +1748,35 @@ END:VCALENDAR"#;
                 sources: vec![SourceConfig::Calendar {
                     calendar: "base".to_string(),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
-        let config = Config { calendars };
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
 
         let fetcher = Fetcher::new().unwrap();
         let result = merge_calendars("derived", &config, &fetcher).await.unwrap();
@@ -530,6 +1786,592 @@ END:VCALENDAR"#;
         assert_eq!(result.errors.len(), 0);
     }
 
+    #[tokio::test]
+    async fn test_shared_source() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/shared.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(CALENDAR1))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let mut sources = HashMap::new();
+        sources.insert(
+            "team".to_string(),
+            SharedSourceConfig {
+                url: format!("{}/shared.ics", mock_server.uri()),
+                username: None,
+                password: None,
+                auth: None,
+                timeout: None,
+                client_cert: None,
+                client_key: None,
+                insecure_tls: false,
+            },
+        );
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "all".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Source {
+                    source: "team".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        calendars.insert(
+            "required".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Source {
+                    source: "team".to_string(),
+                    steps: vec![Step::Deny {
+                        patterns: vec!["Optional".to_string()],
+                        mode: MatchMode::Any,
+                        fields: vec!["summary".to_string()],
+                        action: FilterAction::Enforce,
+                        unless: vec![],
+                        literal: false,
+                    }],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources,
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        let fetcher = Fetcher::new().unwrap();
+
+        let all = merge_calendars("all", &config, &fetcher).await.unwrap();
+        assert_eq!(all.events.len(), 2);
+
+        let required = merge_calendars("required", &config, &fetcher)
+            .await
+            .unwrap();
+        assert_eq!(required.events.len(), 1);
+        assert_eq!(required.events[0].summary(), Some("Meeting with team"));
+    }
+
+    #[tokio::test]
+    async fn test_source_labels() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/work.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(CALENDAR1))
+            .mount(&mock_server)
+            .await;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "work".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/work.ics", mock_server.uri()),
+                    steps: vec![Step::Replace {
+                        pattern: "^".to_string(),
+                        replacement: "[{label}] ".to_string(),
+                        field: "summary".to_string(),
+                        literal: false,
+                    }],
+                    default: DefaultAction::Allow,
+                    label: Some("Work".to_string()),
+                    tags: vec!["office".to_string(), "team".to_string()],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        calendars.insert(
+            "combined".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Calendar {
+                    calendar: "work".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: Some("Everything".to_string()),
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        let fetcher = Fetcher::new().unwrap();
+        let result = merge_calendars("combined", &config, &fetcher)
+            .await
+            .unwrap();
+
+        let event = &result.events[0];
+        assert_eq!(event.summary(), Some("[Work] Meeting with team"));
+        // The innermost source's label is kept
+        assert_eq!(event.property(SOURCE_LABEL_PROPERTY), Some("Work"));
+        assert_eq!(event.property(SOURCE_TAGS_PROPERTY), Some("office,team"));
+
+        let pipelines: Vec<_> = result
+            .pipelines
+            .iter()
+            .map(|r| format!("{}/{}", r.calendar, r.pipeline))
+            .collect();
+        assert_eq!(
+            pipelines,
+            vec![
+                "work/source:Work",
+                "work/calendar",
+                "combined/source:Everything",
+                "combined/calendar"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_holidays_source() {
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "holidays".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Holidays {
+                    holidays: "US".to_string(),
+                    region: None,
+                    steps: vec![Step::Allow {
+                        patterns: vec!["Christmas".to_string()],
+                        mode: MatchMode::Any,
+                        fields: vec!["summary".to_string()],
+                        action: FilterAction::Enforce,
+                        unless: vec![],
+                        literal: false,
+                    }],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        let fetcher = Fetcher::new().unwrap();
+        let result = merge_calendars("holidays", &config, &fetcher)
+            .await
+            .unwrap();
+
+        // One Christmas Day for each generated year, plus any observed days
+        assert!(result.events.len() >= crate::holidays::default_years().count());
+        assert!(result.errors.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_source() {
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "work".to_string(),
+            CalendarConfig {
+                sources: vec![
+                    SourceConfig::Command {
+                        command: "cat".to_string(),
+                        args: vec!["tests/fixtures/work.ics".to_string()],
+                        timeout: None,
+                        steps: vec![Step::Deny {
+                            patterns: vec!["^Optional".to_string()],
+                            mode: MatchMode::Any,
+                            fields: vec!["summary".to_string()],
+                            action: FilterAction::Enforce,
+                            unless: vec![],
+                            literal: false,
+                        }],
+                        default: DefaultAction::Allow,
+                        label: Some("Portal".to_string()),
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                    },
+                    SourceConfig::Command {
+                        command: "false".to_string(),
+                        args: vec![],
+                        timeout: None,
+                        steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                    },
+                ],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        let fetcher = Fetcher::new().unwrap();
+        let result = merge_calendars("work", &config, &fetcher).await.unwrap();
+
+        assert_eq!(result.events.len(), 2);
+        assert_eq!(
+            result.events[0].property(SOURCE_LABEL_PROPERTY),
+            Some("Portal")
+        );
+        // A program that fails is reported like an upstream that's down
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].0, "command:false");
+    }
+
+    #[tokio::test]
+    async fn test_inline_source() {
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "work".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Inline {
+                    ics: CALENDAR1.to_string(),
+                    steps: vec![Step::Deny {
+                        patterns: vec!["^Optional".to_string()],
+                        mode: MatchMode::Any,
+                        fields: vec!["summary".to_string()],
+                        action: FilterAction::Enforce,
+                        unless: vec![],
+                        literal: false,
+                    }],
+                    default: DefaultAction::Allow,
+                    label: Some("Closures".to_string()),
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        let fetcher = Fetcher::new().unwrap();
+        let result = merge_calendars("work", &config, &fetcher).await.unwrap();
+
+        assert!(result.errors.is_empty());
+        assert_eq!(result.events.len(), 1);
+        assert_eq!(result.events[0].summary(), Some("Meeting with team"));
+        assert_eq!(
+            result.events[0].property(SOURCE_LABEL_PROPERTY),
+            Some("Closures")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_birthdays_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("contacts.vcf");
+        std::fs::write(
+            &file,
+            "BEGIN:VCARD\r\nFN:Ada Lovelace\r\nBDAY:1815-12-10\r\nEND:VCARD\r\n\
+             BEGIN:VCARD\r\nFN:Grace Hopper\r\nBDAY:--1209\r\nEND:VCARD\r\n",
+        )
+        .unwrap();
+        let url = reqwest::Url::from_file_path(&file).unwrap().to_string();
+
+        let config: Config = serde_json::from_str(&format!(
+            r#"{{
+                "calendars": {{
+                    "family": {{
+                        "sources": [{{
+                            "birthdays": "{}",
+                            "steps": [{{ "type": "deny", "patterns": ["^Grace"] }}]
+                        }}]
+                    }}
+                }}
+            }}"#,
+            url
+        ))
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.local_files(), vec![file]);
+
+        let fetcher = Fetcher::new().unwrap();
+        let result = merge_calendars("family", &config, &fetcher).await.unwrap();
+        assert!(result.errors.is_empty());
+        assert_eq!(result.events.len(), 1);
+        assert_eq!(result.events[0].summary(), Some("Ada Lovelace's birthday"));
+        assert_eq!(result.events[0].property("RRULE"), Some("FREQ=YEARLY"));
+    }
+
+    #[tokio::test]
+    async fn test_json_source() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/events"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{ "results": [
+                    { "id": 1, "name": "Quiz night", "starts": "2025-03-06T19:00:00Z" },
+                    { "id": 2, "name": "CANCELLED: Open mic", "starts": "2025-03-07T19:00:00Z" },
+                    { "id": 3, "name": "No date" }
+                ] }"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let config: Config = serde_json::from_str(&format!(
+            r#"{{
+                "calendars": {{
+                    "pub": {{
+                        "sources": [{{
+                            "json": "{}/api/events",
+                            "items": "/results",
+                            "fields": {{ "summary": "/name", "start": "/starts", "uid": "/id" }},
+                            "steps": [{{ "type": "deny", "patterns": ["^CANCELLED"] }}]
+                        }}]
+                    }}
+                }}
+            }}"#,
+            mock_server.uri()
+        ))
+        .unwrap();
+        config.validate().unwrap();
+
+        let fetcher = Fetcher::new().unwrap();
+        let result = merge_calendars("pub", &config, &fetcher).await.unwrap();
+        assert!(result.errors.is_empty());
+        assert_eq!(result.events.len(), 1);
+        assert_eq!(result.events[0].summary(), Some("Quiz night"));
+        assert_eq!(result.events[0].uid(), Some("1@json.ical-merge"));
+        assert_eq!(result.warnings.len(), 1);
+        assert_eq!(
+            result.warnings[0].1[0].message,
+            "Left out item 2: no start at '/starts'"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetches_shared_within_merge() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/cal1.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(CALENDAR1))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/down.ics"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let config: Config = serde_json::from_str(&format!(
+            r#"{{
+                "calendars": {{
+                    "work": {{
+                        "sources": [
+                            {{ "url": "{uri}/cal1.ics" }},
+                            {{ "url": "{uri}/down.ics" }}
+                        ]
+                    }},
+                    "everything": {{
+                        "sources": [
+                            {{ "url": "{uri}/cal1.ics", "label": "Mine" }},
+                            {{ "url": "{uri}/down.ics" }},
+                            {{ "calendar": "work" }}
+                        ]
+                    }}
+                }}
+            }}"#,
+            uri = mock_server.uri()
+        ))
+        .unwrap();
+        config.validate().unwrap();
+
+        let fetcher = Fetcher::new().unwrap();
+        let result = merge_calendars("everything", &config, &fetcher)
+            .await
+            .unwrap();
+        assert!(!result.events.is_empty());
+        assert_eq!(result.errors.len(), 1);
+        assert!(result.errors[0].1.to_string().contains("503"));
+    }
+
+    #[tokio::test]
+    async fn test_compiled_pipelines() {
+        let mut config: Config = serde_json::from_str(&format!(
+            r#"{{
+                "calendars": {{
+                    "work": {{
+                        "sources": [{{
+                            "ics": {},
+                            "steps": [{{ "type": "deny", "patterns": ["^Optional"] }}]
+                        }}],
+                        "steps": [{{ "type": "replace", "pattern": "team", "replacement": "squad" }}],
+                        "variants": {{
+                            "private": [{{ "type": "deny", "patterns": ["squad"] }}]
+                        }}
+                    }},
+                    "broken": {{
+                        "sources": [],
+                        "steps": [{{ "type": "allow", "patterns": ["("] }}]
+                    }}
+                }}
+            }}"#,
+            serde_json::to_string(CALENDAR1).unwrap()
+        ))
+        .unwrap();
+
+        let pipelines = CompiledPipelines::compile(&config);
+        assert!(pipelines.is_for(&config));
+        assert!(pipelines.is_for(&config.clone()));
+        // Steps that don't compile are left to fail when the calendar is merged
+        assert!(pipelines.calendars.contains_key("work"));
+        assert!(!pipelines.calendars.contains_key("broken"));
+        // Variants are compiled along with the rest of the calendar
+        assert_eq!(pipelines.variant("work", "private").unwrap().len(), 1);
+        assert!(pipelines.variant("work", "missing").is_none());
+        assert!(pipelines.variant("broken", "private").is_none());
+
+        let fetcher = Fetcher::new().unwrap();
+        let result = merge_calendars_with("work", &config, &pipelines, &fetcher)
+            .await
+            .unwrap();
+        assert_eq!(result.events.len(), 1);
+        assert_eq!(result.events[0].summary(), Some("Meeting with squad"));
+        assert!(
+            merge_calendars_with("broken", &config, &pipelines, &fetcher)
+                .await
+                .is_err()
+        );
+
+        config.calendars.get_mut("work").unwrap().steps.clear();
+        assert!(!pipelines.is_for(&config));
+    }
+
     #[tokio::test]
     async fn test_calendar_reference_with_steps() {
         let mock_server = MockServer::start().await;
@@ -547,8 +2389,30 @@ END:VCALENDAR"#;
                 sources: vec![SourceConfig::Url {
                     url: format!("{}/base.ics", mock_server.uri()),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
         calendars.insert(
@@ -560,13 +2424,37 @@ END:VCALENDAR"#;
                         pattern: "^".to_string(),
                         replacement: "[WORK] ".to_string(),
                         field: "summary".to_string(),
+                        literal: false,
                     }],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
-        let config = Config { calendars };
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
 
         let fetcher = Fetcher::new().unwrap();
         let result = merge_calendars("derived", &config, &fetcher).await.unwrap();
@@ -601,8 +2489,30 @@ END:VCALENDAR"#;
                 sources: vec![SourceConfig::Url {
                     url: format!("{}/cal1.ics", mock_server.uri()),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
         calendars.insert(
@@ -612,17 +2522,52 @@ END:VCALENDAR"#;
                     SourceConfig::Calendar {
                         calendar: "base".to_string(),
                         steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
                     },
                     SourceConfig::Url {
                         url: format!("{}/cal2.ics", mock_server.uri()),
                         steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                        username: None,
+                        password: None,
+                        auth: None,
+                        timeout: None,
+                        client_cert: None,
+                        client_key: None,
+                        insecure_tls: false,
                     },
                 ],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
-        let config = Config { calendars };
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
 
         let fetcher = Fetcher::new().unwrap();
         let result = merge_calendars("combined", &config, &fetcher)
@@ -651,8 +2596,30 @@ END:VCALENDAR"#;
                 sources: vec![SourceConfig::Url {
                     url: format!("{}/base.ics", mock_server.uri()),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
         calendars.insert(
@@ -661,8 +2628,23 @@ END:VCALENDAR"#;
                 sources: vec![SourceConfig::Calendar {
                     calendar: "level1".to_string(),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
         calendars.insert(
@@ -671,12 +2653,35 @@ END:VCALENDAR"#;
                 sources: vec![SourceConfig::Calendar {
                     calendar: "level2".to_string(),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
-        let config = Config { calendars };
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
 
         let fetcher = Fetcher::new().unwrap();
         let result = merge_calendars("level3", &config, &fetcher).await.unwrap();
@@ -684,5 +2689,23 @@ END:VCALENDAR"#;
         // Should resolve through all levels
         assert_eq!(result.events.len(), 2);
         assert_eq!(result.errors.len(), 0);
+
+        // Every pipeline that ran is reported, including referenced calendars
+        let pipelines: Vec<(&str, &str)> = result
+            .pipelines
+            .iter()
+            .map(|report| (report.calendar.as_str(), report.pipeline.as_str()))
+            .collect();
+        assert_eq!(
+            pipelines,
+            vec![
+                ("level1", "source:0"),
+                ("level1", "calendar"),
+                ("level2", "source:0"),
+                ("level2", "calendar"),
+                ("level3", "source:0"),
+                ("level3", "calendar"),
+            ]
+        );
     }
 }
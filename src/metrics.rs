@@ -0,0 +1,442 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::cache::CacheStats;
+use crate::filter::{PipelineStats, StepStats};
+use crate::ical::ParseWarning;
+use crate::merge::{DedupeStats, MergeResult};
+
+/// Seconds since the unix epoch
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Metrics collected for a single calendar
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CalendarMetrics {
+    /// Number of merges served for this calendar
+    pub merges: u64,
+    /// Unix timestamp of the most recent merge
+    pub last_merge: Option<u64>,
+    /// Events served by the most recent merge
+    pub events: usize,
+    /// Sources that failed in the most recent merge
+    pub errors: usize,
+    /// What had to be fixed to read each source in the most recent merge
+    pub warnings: BTreeMap<String, Vec<ParseWarning>>,
+    /// Step counters from the most recent run of each pipeline
+    pub pipelines: BTreeMap<String, PipelineStats>,
+    /// Duplicates removed by the most recent merge
+    pub dedupe: DedupeStats,
+    /// Step counters accumulated over every run of each pipeline
+    #[serde(skip)]
+    totals: BTreeMap<String, PipelineStats>,
+    /// Duplicates removed over every merge
+    #[serde(skip)]
+    dedupe_totals: DedupeStats,
+}
+
+/// In-memory metrics registry shared by the server
+#[derive(Debug, Default)]
+pub struct Metrics {
+    calendars: Mutex<BTreeMap<String, CalendarMetrics>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the outcome of merging a calendar
+    pub fn record_merge(&self, calendar_id: &str, result: &MergeResult) {
+        let mut calendars = self.calendars.lock().unwrap();
+
+        let calendar = calendars.entry(calendar_id.to_string()).or_default();
+        calendar.merges += 1;
+        calendar.last_merge = Some(unix_now());
+        calendar.events = result.events.len();
+        calendar.errors = result.errors.len();
+        calendar.warnings = result.warnings.iter().cloned().collect();
+        calendar.dedupe = result.dedupe.clone();
+        calendar.dedupe_totals.add(&result.dedupe);
+
+        // Reports may belong to referenced calendars as well as this one
+        for report in &result.pipelines {
+            let calendar = calendars.entry(report.calendar.clone()).or_default();
+            calendar
+                .pipelines
+                .insert(report.pipeline.clone(), report.stats.clone());
+
+            let totals = calendar.totals.entry(report.pipeline.clone()).or_default();
+            add_stats(totals, &report.stats);
+        }
+    }
+
+    /// Snapshot of the metrics for every calendar that has been merged
+    pub fn calendars(&self) -> BTreeMap<String, CalendarMetrics> {
+        self.calendars.lock().unwrap().clone()
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let calendars = self.calendars.lock().unwrap();
+        let mut out = String::new();
+
+        write_header(
+            &mut out,
+            "ical_merge_merges_total",
+            "counter",
+            "Number of merges served per calendar",
+        );
+        for (id, calendar) in calendars.iter() {
+            let _ = writeln!(
+                out,
+                "ical_merge_merges_total{{calendar=\"{}\"}} {}",
+                escape_label(id),
+                calendar.merges
+            );
+        }
+
+        write_header(
+            &mut out,
+            "ical_merge_last_merge_events",
+            "gauge",
+            "Events served by the most recent merge",
+        );
+        for (id, calendar) in calendars.iter() {
+            let _ = writeln!(
+                out,
+                "ical_merge_last_merge_events{{calendar=\"{}\"}} {}",
+                escape_label(id),
+                calendar.events
+            );
+        }
+
+        write_header(
+            &mut out,
+            "ical_merge_last_merge_errors",
+            "gauge",
+            "Sources that failed in the most recent merge",
+        );
+        for (id, calendar) in calendars.iter() {
+            let _ = writeln!(
+                out,
+                "ical_merge_last_merge_errors{{calendar=\"{}\"}} {}",
+                escape_label(id),
+                calendar.errors
+            );
+        }
+
+        write_header(
+            &mut out,
+            "ical_merge_last_merge_parse_warnings",
+            "gauge",
+            "Problems fixed while reading sources in the most recent merge",
+        );
+        for (id, calendar) in calendars.iter() {
+            let _ = writeln!(
+                out,
+                "ical_merge_last_merge_parse_warnings{{calendar=\"{}\"}} {}",
+                escape_label(id),
+                calendar.warnings.values().map(Vec::len).sum::<usize>()
+            );
+        }
+
+        write_header(
+            &mut out,
+            "ical_merge_deduplicated_events_total",
+            "counter",
+            "Events removed because another event had the same start and end",
+        );
+        for (id, calendar) in calendars.iter() {
+            let _ = writeln!(
+                out,
+                "ical_merge_deduplicated_events_total{{calendar=\"{}\"}} {}",
+                escape_label(id),
+                calendar.dedupe_totals.removed
+            );
+        }
+
+        write_header(
+            &mut out,
+            "ical_merge_dedupe_collisions_total",
+            "counter",
+            "Events removed as duplicates by the source kept and the source dropped",
+        );
+        for (id, calendar) in calendars.iter() {
+            for (kept, dropped) in &calendar.dedupe_totals.collisions {
+                for (source, count) in dropped {
+                    let _ = writeln!(
+                        out,
+                        "ical_merge_dedupe_collisions_total{{calendar=\"{}\",kept=\"{}\",dropped=\"{}\"}} {}",
+                        escape_label(id),
+                        escape_label(kept),
+                        escape_label(source),
+                        count
+                    );
+                }
+            }
+        }
+
+        write_header(
+            &mut out,
+            "ical_merge_step_events_total",
+            "counter",
+            "Events evaluated, rejected or modified by each step",
+        );
+        for (id, calendar) in calendars.iter() {
+            for (pipeline, stats) in &calendar.totals {
+                for (idx, step) in stats.steps.iter().enumerate() {
+                    for (outcome, value) in [
+                        ("evaluated", step.evaluated),
+                        ("rejected", step.rejected),
+                        ("modified", step.modified),
+                    ] {
+                        let _ = writeln!(
+                            out,
+                            "ical_merge_step_events_total{{calendar=\"{}\",pipeline=\"{}\",step=\"{}\",type=\"{}\",outcome=\"{}\"}} {}",
+                            escape_label(id),
+                            escape_label(pipeline),
+                            idx,
+                            step.step_type,
+                            outcome,
+                            value
+                        );
+                    }
+                }
+            }
+        }
+
+        write_header(
+            &mut out,
+            "ical_merge_default_rejected_total",
+            "counter",
+            "Events rejected because no allow step matched in a deny-by-default pipeline",
+        );
+        for (id, calendar) in calendars.iter() {
+            for (pipeline, stats) in &calendar.totals {
+                let _ = writeln!(
+                    out,
+                    "ical_merge_default_rejected_total{{calendar=\"{}\",pipeline=\"{}\"}} {}",
+                    escape_label(id),
+                    escape_label(pipeline),
+                    stats.default_rejected
+                );
+            }
+        }
+
+        out
+    }
+}
+
+/// Render the merge cache's memory use in the Prometheus text exposition format
+pub fn render_cache(stats: &CacheStats) -> String {
+    let mut out = String::new();
+    for (name, kind, help, value) in [
+        (
+            "ical_merge_cache_entries",
+            "gauge",
+            "Calendars with a cached merge",
+            stats.entries as u64,
+        ),
+        (
+            "ical_merge_cache_bytes",
+            "gauge",
+            "Approximate bytes of cached events",
+            stats.bytes,
+        ),
+        (
+            "ical_merge_cache_events",
+            "gauge",
+            "Events held in the cache",
+            stats.events as u64,
+        ),
+        (
+            "ical_merge_cache_evictions_total",
+            "counter",
+            "Cached merges dropped to stay within the cache limits",
+            stats.evictions,
+        ),
+    ] {
+        write_header(&mut out, name, kind, help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+    out
+}
+
+/// Accumulate one pipeline run into running totals
+fn add_stats(totals: &mut PipelineStats, stats: &PipelineStats) {
+    // The pipeline changed shape (e.g. after a config reload) so start again
+    let same_shape = totals.steps.len() == stats.steps.len()
+        && totals
+            .steps
+            .iter()
+            .zip(&stats.steps)
+            .all(|(a, b)| a.step_type == b.step_type);
+    if !same_shape {
+        *totals = PipelineStats {
+            steps: stats
+                .steps
+                .iter()
+                .map(|step| StepStats {
+                    step_type: step.step_type,
+                    ..Default::default()
+                })
+                .collect(),
+            default_rejected: 0,
+        };
+    }
+
+    for (total, step) in totals.steps.iter_mut().zip(&stats.steps) {
+        total.evaluated += step.evaluated;
+        total.rejected += step.rejected;
+        total.modified += step.modified;
+    }
+    totals.default_rejected += stats.default_rejected;
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escape a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::PipelineReport;
+
+    fn report(calendar: &str, pipeline: &str, rejected: u64) -> PipelineReport {
+        PipelineReport {
+            calendar: calendar.to_string(),
+            pipeline: pipeline.to_string(),
+            stats: PipelineStats {
+                steps: vec![StepStats {
+                    step_type: "deny",
+                    evaluated: 5,
+                    rejected,
+                    modified: 0,
+                }],
+                default_rejected: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_record_merge_accumulates_totals() {
+        let metrics = Metrics::new();
+
+        let mut result = MergeResult::new(vec![], vec![]);
+        result.pipelines = vec![report("work", "source:0", 2), report("base", "calendar", 1)];
+        metrics.record_merge("work", &result);
+
+        result.pipelines = vec![report("work", "source:0", 3)];
+        metrics.record_merge("work", &result);
+
+        let calendars = metrics.calendars();
+        let work = &calendars["work"];
+        assert_eq!(work.merges, 2);
+        assert!(work.last_merge.is_some());
+        assert_eq!(work.pipelines["source:0"].steps[0].rejected, 3);
+        assert_eq!(work.totals["source:0"].steps[0].rejected, 5);
+        assert_eq!(work.totals["source:0"].steps[0].evaluated, 10);
+
+        // Referenced calendars get step counters without counting as a merge
+        let base = &calendars["base"];
+        assert_eq!(base.merges, 0);
+        assert_eq!(base.pipelines["calendar"].steps[0].rejected, 1);
+
+        let text = metrics.render_prometheus();
+        assert!(text.contains("ical_merge_merges_total{calendar=\"work\"} 2"));
+        assert!(text.contains(
+            "ical_merge_step_events_total{calendar=\"work\",pipeline=\"source:0\",step=\"0\",type=\"deny\",outcome=\"rejected\"} 5"
+        ));
+    }
+
+    #[test]
+    fn test_record_merge_warnings() {
+        let metrics = Metrics::new();
+
+        let mut result = MergeResult::new(vec![], vec![]);
+        result.warnings = vec![(
+            "https://example.com/feed.ics".to_string(),
+            vec![ParseWarning {
+                line: Some(1),
+                message: "Removed UTF-8 byte order mark".to_string(),
+            }],
+        )];
+        metrics.record_merge("work", &result);
+
+        let calendars = metrics.calendars();
+        assert_eq!(
+            calendars["work"].warnings["https://example.com/feed.ics"][0].message,
+            "Removed UTF-8 byte order mark"
+        );
+        let text = metrics.render_prometheus();
+        assert!(text.contains("ical_merge_last_merge_parse_warnings{calendar=\"work\"} 1"));
+
+        // Warnings only describe the most recent merge
+        metrics.record_merge("work", &MergeResult::new(vec![], vec![]));
+        assert!(metrics.calendars()["work"].warnings.is_empty());
+    }
+
+    #[test]
+    fn test_record_merge_dedupe() {
+        let metrics = Metrics::new();
+
+        let mut result = MergeResult::new(vec![], vec![]);
+        result.dedupe.removed = 2;
+        result
+            .dedupe
+            .collisions
+            .entry("source:work".to_string())
+            .or_default()
+            .insert("source:1".to_string(), 2);
+        metrics.record_merge("all", &result);
+        metrics.record_merge("all", &result);
+
+        let calendars = metrics.calendars();
+        assert_eq!(calendars["all"].dedupe.removed, 2);
+        assert_eq!(calendars["all"].dedupe_totals.removed, 4);
+
+        let text = metrics.render_prometheus();
+        assert!(text.contains("ical_merge_deduplicated_events_total{calendar=\"all\"} 4"));
+        assert!(text.contains(
+            "ical_merge_dedupe_collisions_total{calendar=\"all\",kept=\"source:work\",dropped=\"source:1\"} 4"
+        ));
+    }
+
+    #[test]
+    fn test_render_cache() {
+        let rendered = render_cache(&CacheStats {
+            entries: 2,
+            bytes: 4096,
+            events: 30,
+            evictions: 1,
+        });
+        assert!(
+            rendered.contains("# TYPE ical_merge_cache_bytes gauge\nical_merge_cache_bytes 4096\n")
+        );
+        assert!(rendered.contains("ical_merge_cache_events 30\n"));
+        assert!(rendered.contains("# TYPE ical_merge_cache_evictions_total counter\n"));
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,187 @@
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+/// Batches smaller than this are processed on the calling thread, as spreading them
+/// across threads costs more than it saves
+pub const MIN_PARALLEL: usize = 4096;
+
+/// Threads beyond the calling one that chunks can run on, shared by every merge
+/// running at once so together they never start more than one thread per CPU
+static SPARE_THREADS: LazyLock<ThreadBudget> = LazyLock::new(|| {
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    ThreadBudget::new(threads - 1)
+});
+
+/// A limit on how many threads are running chunks at once
+struct ThreadBudget {
+    limit: usize,
+    busy: AtomicUsize,
+}
+
+/// Threads taken from a [`ThreadBudget`], given back when dropped
+struct Reserved<'a> {
+    budget: &'a ThreadBudget,
+    threads: usize,
+}
+
+impl ThreadBudget {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            busy: AtomicUsize::new(0),
+        }
+    }
+
+    /// Take as many of the threads as are free, which may be none
+    fn reserve(&self) -> Reserved<'_> {
+        let mut busy = self.busy.load(Ordering::Relaxed);
+        loop {
+            let threads = self.limit.saturating_sub(busy);
+            if threads == 0 {
+                return Reserved {
+                    budget: self,
+                    threads,
+                };
+            }
+            match self.busy.compare_exchange_weak(
+                busy,
+                busy + threads,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Reserved {
+                        budget: self,
+                        threads,
+                    };
+                }
+                Err(current) => busy = current,
+            }
+        }
+    }
+}
+
+impl Drop for Reserved<'_> {
+    fn drop(&mut self) {
+        self.budget.busy.fetch_sub(self.threads, Ordering::AcqRel);
+    }
+}
+
+/// Split `items` into chunks and run `f` on each, one on the calling thread and the
+/// rest on threads of their own, returning the results in the order of the chunks.
+/// Batches under [`MIN_PARALLEL`] are a single chunk run on the calling thread, as
+/// are batches when other merges already have a thread on every CPU. Called from a
+/// multi-threaded tokio runtime, its other tasks move off this worker meanwhile
+pub fn map_chunks<T, U>(items: Vec<T>, f: impl Fn(Vec<T>) -> U + Sync) -> Vec<U>
+where
+    T: Send,
+    U: Send,
+{
+    if items.len() < MIN_PARALLEL {
+        return vec![f(items)];
+    }
+    let run = || map_chunks_across(items, &SPARE_THREADS, MIN_PARALLEL, f);
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(run)
+        }
+        _ => run(),
+    }
+}
+
+fn map_chunks_across<T, U>(
+    items: Vec<T>,
+    budget: &ThreadBudget,
+    min_parallel: usize,
+    f: impl Fn(Vec<T>) -> U + Sync,
+) -> Vec<U>
+where
+    T: Send,
+    U: Send,
+{
+    if items.len() < min_parallel {
+        return vec![f(items)];
+    }
+    let reserved = budget.reserve();
+    if reserved.threads == 0 {
+        return vec![f(items)];
+    }
+
+    let chunk_size = items.len().div_ceil(reserved.threads + 1);
+    let mut items = items.into_iter();
+    let mut chunks = std::iter::from_fn(|| {
+        let chunk: Vec<T> = items.by_ref().take(chunk_size).collect();
+        (!chunk.is_empty()).then_some(chunk)
+    });
+    let Some(first) = chunks.next() else {
+        return vec![f(Vec::new())];
+    };
+    let rest: Vec<Vec<T>> = chunks.collect();
+
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = rest
+            .into_iter()
+            .map(|chunk| scope.spawn(move || f(chunk)))
+            .collect();
+        let mut results = vec![f(first)];
+        results.extend(handles.into_iter().map(|handle| {
+            handle
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        }));
+        results
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_chunks() {
+        let items: Vec<u32> = (0..10).collect();
+        let budget = ThreadBudget::new(3);
+        let doubled = map_chunks_across(items.clone(), &budget, 5, |chunk| {
+            chunk.into_iter().map(|item| item * 2).collect::<Vec<_>>()
+        });
+        assert_eq!(doubled.len(), 4);
+        assert_eq!(
+            doubled.concat(),
+            items.iter().map(|item| item * 2).collect::<Vec<_>>()
+        );
+        // The threads are given back once the chunks are done
+        assert_eq!(budget.busy.load(Ordering::SeqCst), 0);
+
+        // Small batches stay on the calling thread
+        let caller = std::thread::current().id();
+        let ran_on = map_chunks_across(items.clone(), &budget, 11, |_| std::thread::current().id());
+        assert_eq!(ran_on, vec![caller]);
+        let ran_on = map_chunks_across(items.clone(), &ThreadBudget::new(0), 5, |_| {
+            std::thread::current().id()
+        });
+        assert_eq!(ran_on, vec![caller]);
+
+        // As do batches while other merges have all the threads
+        let reserved = budget.reserve();
+        assert_eq!(reserved.threads, 3);
+        let ran_on = map_chunks_across(items.clone(), &budget, 5, |_| std::thread::current().id());
+        assert_eq!(ran_on, vec![caller]);
+        drop(reserved);
+        let ran_on = map_chunks_across(items, &budget, 5, |_| std::thread::current().id());
+        assert_eq!(ran_on.len(), 4);
+        assert_eq!(ran_on[0], caller);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_map_chunks_in_runtime() {
+        let items: Vec<usize> = (0..MIN_PARALLEL * 2).collect();
+        let sums = map_chunks(items, |chunk| chunk.into_iter().sum::<usize>());
+        assert_eq!(
+            sums.into_iter().sum::<usize>(),
+            (0..MIN_PARALLEL * 2).sum::<usize>()
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+
+use crate::error::Result;
+
+/// Somewhere calendars are read from. Programs embedding ical-merge can register
+/// their own with [`Fetcher::with_provider`](crate::fetcher::Fetcher::with_provider)
+/// to supply events from a database or internal API
+pub trait SourceProvider: Send + Sync {
+    /// Read the iCal text of the calendar at `identifier`, the source's whole URL
+    /// such as `db:team-rota`
+    fn fetch<'a>(&'a self, identifier: &'a str) -> BoxFuture<'a, Result<String>>;
+}
+
+/// Registered providers by the URL scheme they handle
+pub type Providers = HashMap<String, Arc<dyn SourceProvider>>;
+
+/// The scheme of a source URL, which picks the provider that reads it
+pub fn scheme(identifier: &str) -> Option<&str> {
+    let (scheme, _) = identifier.split_once(':')?;
+    let valid = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then_some(scheme)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scheme() {
+        assert_eq!(scheme("db:team-rota"), Some("db"));
+        assert_eq!(scheme("https://example.com/a.ics"), Some("https"));
+        assert_eq!(scheme("x-api+v2:rota"), Some("x-api+v2"));
+        assert_eq!(scheme("no scheme here"), None);
+        assert_eq!(scheme("1db:rota"), None);
+        assert_eq!(scheme(":rota"), None);
+    }
+}
@@ -0,0 +1,591 @@
+use std::collections::BTreeMap;
+
+use chrono::{Duration, Locale, NaiveDate, NaiveDateTime};
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::ical::Event;
+use crate::merge::SOURCE_LABEL_PROPERTY;
+
+/// Largest compiled size allowed for a user-supplied search pattern
+const SEARCH_REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// An event as returned by the JSON endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct EventView {
+    pub uid: Option<String>,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    /// Local start time, `YYYY-MM-DD` for all-day events
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub all_day: bool,
+}
+
+impl From<&Event> for EventView {
+    fn from(event: &Event) -> Self {
+        let all_day = event.is_all_day();
+        let format = |time: NaiveDateTime| {
+            if all_day {
+                time.format("%Y-%m-%d").to_string()
+            } else {
+                time.format("%Y-%m-%dT%H:%M:%S").to_string()
+            }
+        };
+
+        Self {
+            uid: event.uid().map(str::to_string),
+            summary: event.summary().map(str::to_string),
+            description: event.description().map(str::to_string),
+            location: event.location().map(str::to_string),
+            start: event.start_time().map(format),
+            end: event.end_time().map(format),
+            all_day,
+        }
+    }
+}
+
+/// Parse a `YYYY-MM-DD` date (as midnight) or `YYYY-MM-DDTHH:MM[:SS]` local time
+pub fn parse_time(value: &str) -> Result<NaiveDateTime> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap());
+    }
+
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+        .map_err(|_| {
+            Error::Config(format!(
+                "Invalid time '{}', expected YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS",
+                value
+            ))
+        })
+}
+
+/// Build the case-insensitive pattern for a search, `q` is plain text unless `regex` is set
+pub fn search_pattern(q: &str, regex: bool) -> Result<Regex> {
+    let pattern = if regex {
+        q.to_string()
+    } else {
+        regex::escape(q)
+    };
+
+    Ok(RegexBuilder::new(&pattern)
+        .case_insensitive(true)
+        .size_limit(SEARCH_REGEX_SIZE_LIMIT)
+        .build()?)
+}
+
+/// Whether an event overlaps the range, either end of which may be open
+pub fn in_range(event: &Event, from: Option<NaiveDateTime>, to: Option<NaiveDateTime>) -> bool {
+    let (Some(start), Some(end)) = (event.start_time(), event.end_time()) else {
+        // Events without a start can't be placed so only match unbounded searches
+        return from.is_none() && to.is_none();
+    };
+
+    // Events without a duration still match a range starting at them
+    let ends_after_from = from.is_none_or(|from| end > from || start >= from);
+    let starts_before_to = to.is_none_or(|to| start < to);
+    ends_after_from && starts_before_to
+}
+
+/// Find events whose summary, description or location match within the range,
+/// ordered by start
+pub fn search<'a>(
+    events: &'a [Event],
+    pattern: &Regex,
+    from: Option<NaiveDateTime>,
+    to: Option<NaiveDateTime>,
+) -> Vec<&'a Event> {
+    let mut matches: Vec<&Event> = events
+        .iter()
+        .filter(|event| {
+            [event.summary(), event.description(), event.location()]
+                .into_iter()
+                .flatten()
+                .any(|text| pattern.is_match(text))
+        })
+        .filter(|event| in_range(event, from, to))
+        .collect();
+
+    matches.sort_by_key(|event| event.start_time());
+    matches
+}
+
+/// The first event starting at or after `now`
+pub fn next_event(events: &[Event], now: NaiveDateTime) -> Option<&Event> {
+    events
+        .iter()
+        .filter(|event| event.start_time().is_some_and(|start| start >= now))
+        .min_by_key(|event| event.start_time())
+}
+
+/// Events taking place at any time on `date`, ordered by start
+pub fn events_on(events: &[Event], date: NaiveDate) -> Vec<&Event> {
+    let from = date.and_hms_opt(0, 0, 0).unwrap();
+    let to = from + chrono::Duration::days(1);
+
+    let mut matches: Vec<&Event> = events
+        .iter()
+        .filter(|event| in_range(event, Some(from), Some(to)))
+        .collect();
+    matches.sort_by_key(|event| event.start_time());
+    matches
+}
+
+/// Describe when an event starts, e.g. `2025-03-10 09:00` or just the date for all-day events
+pub fn start_label(event: &Event) -> String {
+    match event.start_time() {
+        Some(start) if event.is_all_day() => start.format("%Y-%m-%d").to_string(),
+        Some(start) => start.format("%Y-%m-%d %H:%M").to_string(),
+        None => "Unscheduled".to_string(),
+    }
+}
+
+/// Describe when an event happens on its own day, e.g. `09:00-10:00` or `All day`
+pub fn time_of_day(event: &Event) -> String {
+    match (event.start_time(), event.end_time()) {
+        _ if event.is_all_day() => "All day".to_string(),
+        (Some(start), Some(end)) if end > start => {
+            format!("{}-{}", start.format("%H:%M"), end.format("%H:%M"))
+        }
+        (Some(start), _) => start.format("%H:%M").to_string(),
+        (None, _) => "Unscheduled".to_string(),
+    }
+}
+
+/// Events grouped by the days they take place on, skipping days without events
+pub fn agenda(events: &[Event], from: NaiveDate, days: u32) -> Vec<(NaiveDate, Vec<&Event>)> {
+    from.iter_days()
+        .take(days as usize)
+        .map(|date| (date, events_on(events, date)))
+        .filter(|(_, events)| !events.is_empty())
+        .collect()
+}
+
+/// Find a locale by name, either as `fr_FR` or the `fr-FR` of a language tag. A bare
+/// language picks the country sharing its code, e.g. `de` is `de_DE`
+pub fn parse_locale(name: &str) -> Option<Locale> {
+    let name = name.trim().replace('-', "_");
+    let (language, country) = name.split_once('_').unwrap_or((&name, ""));
+    let language = language.to_lowercase();
+    let candidates = [
+        format!("{}_{}", language, country.to_uppercase()),
+        format!("{}_{}", language, language.to_uppercase()),
+        language.clone(),
+    ];
+    candidates
+        .iter()
+        .find_map(|candidate| Locale::try_from(candidate.as_str()).ok())
+        .or_else(|| (language == "en").then_some(Locale::en_US))
+}
+
+/// The most preferred locale in an `Accept-Language` header that's known
+pub fn preferred_locale(accept_language: &str) -> Option<Locale> {
+    let mut languages: Vec<(&str, f32)> = accept_language
+        .split(',')
+        .filter_map(|part| {
+            let mut params = part.split(';');
+            let tag = params.next()?.trim();
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    // Stable, so equally preferred languages stay in the order given
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages.into_iter().find_map(|(tag, _)| parse_locale(tag))
+}
+
+/// Format an agenda day heading, with day and month names in the locale
+fn agenda_day(date: NaiveDate, locale: Locale) -> String {
+    date.format_localized("%A %-d %B %Y", locale).to_string()
+}
+
+/// Render an agenda as plain text, one indented line per event under each day
+pub fn agenda_text(agenda: &[(NaiveDate, Vec<&Event>)], locale: Locale) -> String {
+    let mut out = String::new();
+    for (date, events) in agenda {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("{}\n", agenda_day(*date, locale)));
+        for event in events {
+            out.push_str(&format!(
+                "  {:<11} {}\n",
+                time_of_day(event),
+                describe(event)
+            ));
+        }
+    }
+    out
+}
+
+/// Render an agenda as Markdown, a heading per day with a list of its events
+pub fn agenda_markdown(agenda: &[(NaiveDate, Vec<&Event>)], locale: Locale) -> String {
+    let mut out = String::new();
+    for (date, events) in agenda {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("## {}\n\n", agenda_day(*date, locale)));
+        for event in events {
+            out.push_str(&format!(
+                "- **{}** {}\n",
+                time_of_day(event),
+                escape_markdown(&describe(event))
+            ));
+        }
+    }
+    out
+}
+
+/// Aggregate figures over the events overlapping a range
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    pub events: usize,
+    pub all_day_events: usize,
+    /// Hours covered by timed events within the range, counting overlapping time once
+    pub busy_hours: f64,
+    /// Busy hours as a percentage of the whole range
+    pub busy_percent: f64,
+    pub by_category: BTreeMap<String, usize>,
+    /// Events per source label, for events from labelled sources
+    pub by_source: BTreeMap<String, usize>,
+    /// The day with the most busy time, `None` when no events fall in the range
+    pub busiest_day: Option<BusiestDay>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BusiestDay {
+    pub date: String,
+    pub busy_hours: f64,
+    pub events: usize,
+}
+
+/// Compute statistics over the events overlapping `from` to `to`. All-day events
+/// are counted but don't make anyone busy
+pub fn stats(events: &[Event], from: NaiveDateTime, to: NaiveDateTime) -> Stats {
+    let matching: Vec<&Event> = events
+        .iter()
+        .filter(|event| in_range(event, Some(from), Some(to)))
+        .collect();
+
+    let mut by_category = BTreeMap::new();
+    let mut by_source = BTreeMap::new();
+    for event in &matching {
+        for category in event.categories() {
+            *by_category.entry(category.to_string()).or_insert(0) += 1;
+        }
+        if let Some(source) = event.property(SOURCE_LABEL_PROPERTY) {
+            *by_source.entry(source.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    let timed: Vec<(NaiveDateTime, NaiveDateTime)> = matching
+        .iter()
+        .filter(|event| !event.is_all_day())
+        .filter_map(|event| event.start_time().zip(event.end_time()))
+        .collect();
+    let busy = busy_time(&timed, from, to);
+
+    let mut busiest_day: Option<BusiestDay> = None;
+    let mut busiest_time = Duration::zero();
+    for date in from
+        .date()
+        .iter_days()
+        .take_while(|date| date.and_time(from.time()) < to)
+    {
+        let day_start = date.and_hms_opt(0, 0, 0).unwrap().max(from);
+        let day_end = (date.and_hms_opt(0, 0, 0).unwrap() + Duration::days(1)).min(to);
+        let day_events = matching
+            .iter()
+            .filter(|event| in_range(event, Some(day_start), Some(day_end)))
+            .count();
+        if day_events == 0 {
+            continue;
+        }
+
+        let day_busy = busy_time(&timed, day_start, day_end);
+        if busiest_day.is_none() || day_busy > busiest_time {
+            busiest_time = day_busy;
+            busiest_day = Some(BusiestDay {
+                date: date.format("%Y-%m-%d").to_string(),
+                busy_hours: hours(day_busy),
+                events: day_events,
+            });
+        }
+    }
+
+    let range = (to - from).num_seconds();
+    Stats {
+        events: matching.len(),
+        all_day_events: matching.iter().filter(|event| event.is_all_day()).count(),
+        busy_hours: hours(busy),
+        busy_percent: if range > 0 {
+            round(busy.num_seconds() as f64 * 100.0 / range as f64)
+        } else {
+            0.0
+        },
+        by_category,
+        by_source,
+        busiest_day,
+    }
+}
+
+/// Time covered by any of the intervals within `from` to `to`
+fn busy_time(
+    intervals: &[(NaiveDateTime, NaiveDateTime)],
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Duration {
+    let mut clipped: Vec<(NaiveDateTime, NaiveDateTime)> = intervals
+        .iter()
+        .map(|(start, end)| ((*start).max(from), (*end).min(to)))
+        .filter(|(start, end)| start < end)
+        .collect();
+    clipped.sort();
+
+    let mut total = Duration::zero();
+    let mut current: Option<(NaiveDateTime, NaiveDateTime)> = None;
+    for (start, end) in clipped {
+        match current {
+            Some((current_start, current_end)) if start <= current_end => {
+                current = Some((current_start, current_end.max(end)));
+            }
+            _ => {
+                if let Some((current_start, current_end)) = current {
+                    total += current_end - current_start;
+                }
+                current = Some((start, end));
+            }
+        }
+    }
+    if let Some((current_start, current_end)) = current {
+        total += current_end - current_start;
+    }
+    total
+}
+
+fn hours(duration: Duration) -> f64 {
+    round(duration.num_seconds() as f64 / 3600.0)
+}
+
+/// Round to two decimal places for display
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// Summary with the location and an alarm marker, as the `show` command prints
+fn describe(event: &Event) -> String {
+    let mut text = event.summary().unwrap_or("<no summary>").to_string();
+    if let Some(location) = event.location().filter(|l| !l.is_empty()) {
+        text.push_str(&format!(" ({})", location));
+    }
+    if event.has_alarms() {
+        text.push_str(" ⏰");
+    }
+    text
+}
+
+/// Escape characters Markdown would treat as formatting
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use icalendar::{Component, EventLike};
+
+    fn event(summary: &str, location: Option<&str>, start: &str) -> Event {
+        let mut event = icalendar::Event::new();
+        event.summary(summary);
+        if let Some(location) = location {
+            event.location(location);
+        }
+        match NaiveDate::parse_from_str(start, "%Y-%m-%d") {
+            Ok(date) => event.starts(date),
+            Err(_) => event.starts(parse_time(start).unwrap()),
+        };
+        Event::new(event.done())
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("2025-03-01").unwrap().to_string(),
+            "2025-03-01 00:00:00"
+        );
+        assert_eq!(
+            parse_time("2025-03-01T09:30").unwrap().to_string(),
+            "2025-03-01 09:30:00"
+        );
+        assert!(parse_time("tomorrow").is_err());
+    }
+
+    #[test]
+    fn test_search() {
+        let events = vec![
+            event("Dentist", None, "2025-03-10T09:00:00"),
+            event("Team sync", Some("Room 1"), "2025-03-03T10:00:00"),
+            event("Lunch", Some("Dentist's cafe"), "2025-03-01T12:00:00"),
+        ];
+
+        let pattern = search_pattern("dentist", false).unwrap();
+        let found = search(&events, &pattern, None, None);
+        let summaries: Vec<_> = found.iter().map(|e| e.summary().unwrap()).collect();
+        assert_eq!(summaries, vec!["Lunch", "Dentist"]);
+
+        let from = parse_time("2025-03-02").ok();
+        let found = search(&events, &pattern, from, None);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].summary(), Some("Dentist"));
+
+        let to = parse_time("2025-03-02").ok();
+        assert_eq!(search(&events, &pattern, None, to).len(), 1);
+
+        // Plain text isn't treated as a pattern
+        let literal = search_pattern("room 1.*", false).unwrap();
+        assert!(search(&events, &literal, None, None).is_empty());
+        let regex = search_pattern("^(team|lunch)", true).unwrap();
+        assert_eq!(search(&events, &regex, None, None).len(), 2);
+        assert!(search_pattern("(", true).is_err());
+    }
+
+    #[test]
+    fn test_next_and_today() {
+        let events = vec![
+            event("Dentist", None, "2025-03-10T09:00:00"),
+            event("Team sync", None, "2025-03-03T10:00:00"),
+            event("Lunch", None, "2025-03-03T12:00:00"),
+            event("Holiday", None, "2025-03-03"),
+        ];
+
+        let now = parse_time("2025-03-03T11:00:00").unwrap();
+        assert_eq!(next_event(&events, now).unwrap().summary(), Some("Lunch"));
+        let later = parse_time("2025-03-11").unwrap();
+        assert!(next_event(&events, later).is_none());
+
+        let today = events_on(&events, now.date());
+        let summaries: Vec<_> = today.iter().map(|e| e.summary().unwrap()).collect();
+        assert_eq!(summaries, vec!["Holiday", "Team sync", "Lunch"]);
+
+        assert_eq!(time_of_day(today[0]), "All day");
+        assert_eq!(time_of_day(today[1]), "10:00");
+        assert_eq!(start_label(today[0]), "2025-03-03");
+        assert_eq!(start_label(today[1]), "2025-03-03 10:00");
+    }
+
+    #[test]
+    fn test_agenda() {
+        let events = vec![
+            event("Dentist", Some("Clinic"), "2025-03-04T09:00:00"),
+            event("Team sync", None, "2025-03-03T10:00:00"),
+            event("*Launch*", None, "2025-03-03"),
+            event("Far away", None, "2025-04-01T10:00:00"),
+        ];
+
+        let from = NaiveDate::from_ymd_opt(2025, 3, 3).unwrap();
+        let days = agenda(&events, from, 7);
+        assert_eq!(days.len(), 2);
+
+        assert_eq!(
+            agenda_text(&days, Locale::en_GB),
+            "Monday 3 March 2025\n  All day     *Launch*\n  10:00       Team sync\n\n\
+             Tuesday 4 March 2025\n  09:00       Dentist (Clinic)\n"
+        );
+        assert_eq!(
+            agenda_markdown(&days, Locale::en_GB),
+            "## Monday 3 March 2025\n\n- **All day** \\*Launch\\*\n- **10:00** Team sync\n\n\
+             ## Tuesday 4 March 2025\n\n- **09:00** Dentist (Clinic)\n"
+        );
+
+        assert!(agenda(&events, from, 1).len() == 1);
+
+        assert!(agenda_text(&days, Locale::fr_FR).starts_with("lundi 3 mars 2025\n"));
+        assert!(agenda_markdown(&days, Locale::de_DE).starts_with("## Montag 3 März 2025\n"));
+    }
+
+    #[test]
+    fn test_locales() {
+        assert_eq!(parse_locale("fr_FR"), Some(Locale::fr_FR));
+        assert_eq!(parse_locale("pt-br"), Some(Locale::pt_BR));
+        assert_eq!(parse_locale("de"), Some(Locale::de_DE));
+        assert_eq!(parse_locale("en"), Some(Locale::en_US));
+        assert_eq!(parse_locale("klingon"), None);
+
+        assert_eq!(
+            preferred_locale("xx-YY, de-CH;q=0.8, fr;q=0.9, *;q=0.1"),
+            Some(Locale::fr_FR)
+        );
+        assert_eq!(preferred_locale("en-GB,en;q=0.9"), Some(Locale::en_GB));
+        assert_eq!(preferred_locale("fr;q=0, *"), None);
+        assert_eq!(preferred_locale(""), None);
+    }
+
+    #[test]
+    fn test_stats() {
+        let timed = |summary: &str, start: &str, end: &str| {
+            let mut event = icalendar::Event::new();
+            event
+                .summary(summary)
+                .starts(parse_time(start).unwrap())
+                .ends(parse_time(end).unwrap());
+            Event::new(event.done())
+        };
+
+        let mut review = timed("Review", "2025-03-03T09:30", "2025-03-03T11:00");
+        review.add_category("Work");
+        review.set_property(SOURCE_LABEL_PROPERTY, "Work");
+        let mut standup = timed("Standup", "2025-03-03T09:00", "2025-03-03T10:00");
+        standup.add_category("Work");
+        let events = vec![
+            standup,
+            review,
+            timed("Late", "2025-03-04T23:00", "2025-03-05T01:00"),
+            event("Holiday", None, "2025-03-05"),
+            timed("Outside", "2025-03-10T09:00", "2025-03-10T10:00"),
+        ];
+
+        let from = parse_time("2025-03-03").unwrap();
+        let to = parse_time("2025-03-08").unwrap();
+        let week = stats(&events, from, to);
+        assert_eq!(week.events, 4);
+        assert_eq!(week.all_day_events, 1);
+        // Overlapping meetings count once, 2 hours plus the late event's 2
+        assert_eq!(week.busy_hours, 4.0);
+        assert_eq!(week.busy_percent, 3.33);
+        assert_eq!(week.by_category.get("Work"), Some(&2));
+        assert_eq!(week.by_source.get("Work"), Some(&1));
+        assert_eq!(
+            week.busiest_day,
+            Some(BusiestDay {
+                date: "2025-03-03".to_string(),
+                busy_hours: 2.0,
+                events: 2,
+            })
+        );
+
+        let empty = stats(&events, to, to + Duration::days(1));
+        assert_eq!(empty.events, 0);
+        assert_eq!(empty.busiest_day, None);
+    }
+
+    #[test]
+    fn test_event_view() {
+        let view = EventView::from(&event("Dentist", Some("Clinic"), "2025-03-10T09:00:00"));
+        assert_eq!(view.start.as_deref(), Some("2025-03-10T09:00:00"));
+        assert_eq!(view.location.as_deref(), Some("Clinic"));
+        assert!(!view.all_day);
+    }
+}
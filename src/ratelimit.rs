@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The period limits are counted over
+const PERIOD: Duration = Duration::from_secs(60);
+
+/// Keeps requests to each host under a number a minute. Up to a minute's worth
+/// can be sent at once, after which requests are spaced out evenly
+#[derive(Debug, Default)]
+pub struct HostRateLimiter {
+    /// When each host's allowance will be back to full, given the requests so far
+    full_at: Mutex<HashMap<String, Instant>>,
+}
+
+impl HostRateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a turn to send a request to `host`, allowing `per_minute` requests a
+    /// minute. Returns how long to wait before sending it, or `None` without
+    /// taking the turn when that would be longer than `max_wait`
+    pub fn reserve(
+        &self,
+        host: &str,
+        per_minute: u32,
+        max_wait: Duration,
+        now: Instant,
+    ) -> Option<Duration> {
+        let interval = PERIOD / per_minute.max(1);
+        let mut full_at = self.full_at.lock().unwrap();
+        let host_full_at = full_at.get(host).copied().unwrap_or(now).max(now);
+
+        // Each request uses up `interval` of the minute's allowance
+        let next = host_full_at + interval;
+        let wait = next.saturating_duration_since(now + PERIOD);
+        if wait > max_wait {
+            return None;
+        }
+
+        // Hosts whose allowance is full again are forgotten
+        full_at.retain(|_, at| *at > now);
+        full_at.insert(host.to_string(), next);
+        Some(wait)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve() {
+        let limiter = HostRateLimiter::new();
+        let now = Instant::now();
+        let max_wait = Duration::from_secs(30);
+        let reserve = |host: &str, at: Instant| limiter.reserve(host, 6, max_wait, at);
+
+        // A minute's worth go straight away
+        for _ in 0..6 {
+            assert_eq!(reserve("calendar.google.com", now), Some(Duration::ZERO));
+        }
+        // Then they're spaced out, as far ahead as the caller will wait
+        assert_eq!(
+            reserve("calendar.google.com", now),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            reserve("calendar.google.com", now),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(
+            reserve("calendar.google.com", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(reserve("calendar.google.com", now), None);
+
+        // Other hosts have allowances of their own
+        assert_eq!(reserve("outlook.office365.com", now), Some(Duration::ZERO));
+
+        // The allowance comes back over time, a turn every 10 seconds
+        let later = now + Duration::from_secs(45);
+        assert_eq!(reserve("calendar.google.com", later), Some(Duration::ZERO));
+        assert_eq!(
+            reserve("calendar.google.com", later),
+            Some(Duration::from_secs(5))
+        );
+        let much_later = now + Duration::from_secs(300);
+        assert_eq!(
+            reserve("calendar.google.com", much_later),
+            Some(Duration::ZERO)
+        );
+    }
+}
@@ -0,0 +1,745 @@
+use std::collections::HashSet;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+use icalendar::{CalendarDateTime, Component, DatePerhapsTime, EventLike};
+
+use crate::config::{RecurrenceConfig, RecurrenceMode};
+use crate::ical::Event;
+use crate::ical::types::to_naive;
+use crate::timezone::Zone;
+
+/// Most occurrences added for a single recurring event
+const MAX_OCCURRENCES: usize = 1000;
+
+/// Properties describing the recurrence set, dropped from occurrences
+const RECURRENCE_PROPERTIES: &[&str] = &["RRULE", "RDATE", "EXDATE"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// The parts of an RRULE we can expand
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<NaiveDateTime>,
+    /// Weekdays, optionally the nth (negative from the end) of the month or year
+    by_day: Vec<(Option<i32>, Weekday)>,
+    by_month_day: Vec<i32>,
+    by_month: Vec<u32>,
+    by_set_pos: Vec<i32>,
+    week_start: Weekday,
+}
+
+/// Serve recurring events as the config asks, expanding occurrences that
+/// overlap the window around `today`
+pub fn apply(events: Vec<Event>, config: &RecurrenceConfig, today: NaiveDate) -> Vec<Event> {
+    if config.mode == RecurrenceMode::Master {
+        return events;
+    }
+
+    let today = today.and_hms_opt(0, 0, 0).unwrap();
+    let window = (
+        today - Duration::days(config.past_days.into()),
+        today + Duration::days(config.future_days.into()),
+    );
+
+    // Occurrences the source already overrides are left to the override
+    let overridden: HashSet<(String, NaiveDateTime)> = events
+        .iter()
+        .filter_map(|event| {
+            let recurrence_id = event.inner().get_recurrence_id()?;
+            Some((event.uid()?.to_string(), to_naive(&recurrence_id)))
+        })
+        .collect();
+
+    let mut served = Vec::with_capacity(events.len());
+    for event in events {
+        let Some(rrule) = event.property("RRULE") else {
+            served.push(match event.inner().get_recurrence_id() {
+                Some(recurrence_id) if config.mode == RecurrenceMode::Expand => {
+                    detach(event, &recurrence_id)
+                }
+                _ => event,
+            });
+            continue;
+        };
+
+        let (Some(uid), Some(start)) = (event.uid(), event.start()) else {
+            served.push(event);
+            continue;
+        };
+        let Some(rule) = Rule::parse(rrule, &start) else {
+            tracing::debug!(uid, rrule, "Not expanding unsupported recurrence rule");
+            served.push(event);
+            continue;
+        };
+
+        let duration = event
+            .end_time()
+            .zip(event.start_time())
+            .map(|(end, start)| end - start)
+            .unwrap_or_default();
+        let first = to_naive(&start);
+        let occurrences: Vec<NaiveDateTime> = occurrences(&event, &rule, &start, window.1)
+            .into_iter()
+            .filter(|occurrence| {
+                *occurrence < window.1
+                    && (*occurrence + duration > window.0 || *occurrence >= window.0)
+            })
+            .filter(|occurrence| !overridden.contains(&(uid.to_string(), *occurrence)))
+            // Clients that ignore the rule still show the event itself at its start
+            .filter(|occurrence| config.mode == RecurrenceMode::Expand || *occurrence != first)
+            .take(MAX_OCCURRENCES)
+            .collect();
+
+        let instances: Vec<Event> = occurrences
+            .into_iter()
+            .map(|occurrence| instance(&event, &start, occurrence - first, config.mode))
+            .collect();
+        if config.mode == RecurrenceMode::Both {
+            served.push(event);
+        }
+        served.extend(instances);
+    }
+
+    served
+}
+
+/// Build the occurrence of a recurring event moved by `offset` from its start
+fn instance(
+    master: &Event,
+    start: &DatePerhapsTime,
+    offset: Duration,
+    mode: RecurrenceMode,
+) -> Event {
+    let mut event = master.clone();
+    event.remove_properties(RECURRENCE_PROPERTIES);
+
+    let occurrence = shift(start, offset);
+    let end = master.end().map(|end| shift(&end, offset));
+    let mut inner = event.into_inner();
+    inner.starts(occurrence.clone());
+    if let Some(end) = end {
+        inner.ends(end);
+    }
+
+    let event = Event::new(inner);
+    match mode {
+        RecurrenceMode::Both => {
+            // Tie the occurrence to its series so clients that understand it don't show it twice
+            let mut inner = event.into_inner();
+            inner.recurrence_id(occurrence);
+            Event::new(inner)
+        }
+        _ => detach(event, &occurrence),
+    }
+}
+
+/// Turn an occurrence into a standalone event with a UID of its own
+fn detach(mut event: Event, occurrence: &DatePerhapsTime) -> Event {
+    event.remove_properties(&["RECURRENCE-ID"]);
+    if let Some(uid) = event.uid() {
+        let uid = format!("{}-{}", to_naive(occurrence).format("%Y%m%dT%H%M%S"), uid);
+        let mut inner = event.into_inner();
+        inner.uid(&uid);
+        event = Event::new(inner);
+    }
+    event
+}
+
+/// Move a date or date-time, keeping its form and timezone
+pub(crate) fn shift(value: &DatePerhapsTime, offset: Duration) -> DatePerhapsTime {
+    match value {
+        DatePerhapsTime::Date(date) => DatePerhapsTime::Date(*date + offset),
+        DatePerhapsTime::DateTime(CalendarDateTime::Floating(date_time)) => {
+            DatePerhapsTime::DateTime(CalendarDateTime::Floating(*date_time + offset))
+        }
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(date_time)) => {
+            DatePerhapsTime::DateTime(CalendarDateTime::Utc(*date_time + offset))
+        }
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, tzid }) => {
+            DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone {
+                date_time: *date_time + offset,
+                tzid: tzid.clone(),
+            })
+        }
+    }
+}
+
+/// Read a DATE or DATE-TIME value as a local time in the same terms as `start`
+fn parse_value(value: &str, tzid: Option<&str>, start: &DatePerhapsTime) -> Option<NaiveDateTime> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+        return date.and_hms_opt(0, 0, 0);
+    }
+
+    let start_zone = match start {
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { tzid, .. }) => Some(tzid),
+        _ => None,
+    };
+    if let Some(utc) = value.strip_suffix('Z') {
+        let utc = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+        return Some(match start_zone.and_then(|tzid| Zone::load(tzid).ok()) {
+            Some(zone) => zone.from_utc(utc),
+            None => utc,
+        });
+    }
+
+    let local = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    let is_utc = matches!(start, DatePerhapsTime::DateTime(CalendarDateTime::Utc(_)));
+    Some(
+        match tzid
+            .filter(|_| is_utc)
+            .and_then(|tzid| Zone::load(tzid).ok())
+        {
+            Some(zone) => zone.to_utc(local),
+            None => local,
+        },
+    )
+}
+
+/// Values of a multi-valued date property such as EXDATE
+fn date_list(event: &Event, name: &str, start: &DatePerhapsTime) -> Vec<NaiveDateTime> {
+    event
+        .inner()
+        .multi_properties()
+        .get(name)
+        .into_iter()
+        .flatten()
+        .flat_map(|property| {
+            let tzid = property.params().get("TZID").map(|tzid| tzid.value());
+            property
+                .value()
+                .split(',')
+                .filter_map(move |value| parse_value(value, tzid, start))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Every occurrence of a recurring event, from its start until the rule ends
+/// or `horizon` is reached, in order
+fn occurrences(
+    event: &Event,
+    rule: &Rule,
+    start: &DatePerhapsTime,
+    horizon: NaiveDateTime,
+) -> Vec<NaiveDateTime> {
+    let first = to_naive(start);
+
+    let mut occurrences = vec![first];
+    let mut count = 1;
+    'periods: for period in 0.. {
+        let Some((period_start, dates)) = rule.candidates(first.date(), period) else {
+            break;
+        };
+        // Rules matching no dates at all would otherwise never end
+        if period_start > horizon.date() {
+            break;
+        }
+        for date in dates {
+            let occurrence = date.and_time(first.time());
+            if occurrence <= first {
+                continue;
+            }
+            if occurrence >= horizon
+                || rule.until.is_some_and(|until| occurrence > until)
+                || rule.count.is_some_and(|limit| count >= limit)
+            {
+                break 'periods;
+            }
+            count += 1;
+            occurrences.push(occurrence);
+        }
+    }
+
+    occurrences.extend(date_list(event, "RDATE", start));
+    let exdates = date_list(event, "EXDATE", start);
+    occurrences.retain(|occurrence| !exdates.contains(occurrence));
+    occurrences.sort();
+    occurrences.dedup();
+    occurrences
+}
+
+impl Rule {
+    /// Parse an RRULE, or `None` when it uses parts we can't expand
+    fn parse(rrule: &str, start: &DatePerhapsTime) -> Option<Self> {
+        let mut rule = Rule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+            by_set_pos: Vec::new(),
+            week_start: Weekday::Mon,
+        };
+        let mut frequency = None;
+
+        for part in rrule.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = part.split_once('=')?;
+            match name.to_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return None,
+                    })
+                }
+                "INTERVAL" => rule.interval = value.parse().ok().filter(|n| *n > 0)?,
+                "COUNT" => rule.count = Some(value.parse().ok()?),
+                "UNTIL" => rule.until = Some(parse_value(value, None, start)?),
+                "BYDAY" => {
+                    rule.by_day = value.split(',').map(parse_weekday).collect::<Option<_>>()?
+                }
+                "BYMONTHDAY" => {
+                    rule.by_month_day = parse_list(value)
+                        .map(|day| day.filter(|day: &i32| (1..=31).contains(&day.abs())))
+                        .collect::<Option<_>>()?
+                }
+                "BYMONTH" => {
+                    rule.by_month = parse_list(value)
+                        .map(|month| month.filter(|month| (1..=12).contains(month)))
+                        .collect::<Option<_>>()?
+                }
+                "BYSETPOS" => {
+                    rule.by_set_pos = parse_list(value)
+                        .map(|pos| pos.filter(|pos: &i32| *pos != 0))
+                        .collect::<Option<_>>()?
+                }
+                "WKST" => {
+                    rule.week_start = match parse_weekday(value)? {
+                        (None, day) => day,
+                        (Some(_), _) => return None,
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        rule.frequency = frequency?;
+        Some(rule)
+    }
+
+    /// The start of the `index`th period from `first` and the dates in it
+    /// matching the rule, or `None` once periods can no longer be represented
+    fn candidates(&self, first: NaiveDate, index: u32) -> Option<(NaiveDate, Vec<NaiveDate>)> {
+        let step = i64::from(index) * i64::from(self.interval);
+        let (period_start, mut dates): (NaiveDate, Vec<NaiveDate>) = match self.frequency {
+            Frequency::Daily => {
+                let date = first.checked_add_signed(Duration::try_days(step)?)?;
+                let matches = self.matches_month_day(date) && self.matches_weekday(date, None);
+                (date, if matches { vec![date] } else { Vec::new() })
+            }
+            Frequency::Weekly => {
+                let from_week_start = (first.weekday().num_days_from_monday() + 7
+                    - self.week_start.num_days_from_monday())
+                    % 7;
+                let week = first
+                    .checked_sub_signed(Duration::days(from_week_start.into()))?
+                    .checked_add_signed(Duration::try_weeks(step)?)?;
+                let days = week.iter_days().take(7);
+                let dates = if self.by_day.is_empty() {
+                    days.filter(|day| day.weekday() == first.weekday())
+                        .collect()
+                } else {
+                    days.filter(|day| self.matches_weekday(*day, None))
+                        .collect()
+                };
+                (week, dates)
+            }
+            Frequency::Monthly => {
+                let month = i64::from(first.year()) * 12 + i64::from(first.month0()) + step;
+                let year = i32::try_from(month.div_euclid(12)).ok()?;
+                let first_of_month =
+                    NaiveDate::from_ymd_opt(year, month.rem_euclid(12) as u32 + 1, 1)?;
+                (first_of_month, self.month_dates(first_of_month, first))
+            }
+            Frequency::Yearly => {
+                let year = i32::try_from(i64::from(first.year()) + step).ok()?;
+                let first_of_year = NaiveDate::from_ymd_opt(year, 1, 1)?;
+                let dates = if self.by_month.is_empty()
+                    && !self.by_day.is_empty()
+                    && self.by_month_day.is_empty()
+                {
+                    // Weekdays numbered through the whole year, e.g. the 20th Monday
+                    let days: Vec<NaiveDate> = first_of_year
+                        .iter_days()
+                        .take_while(|day| day.year() == year)
+                        .collect();
+                    days.iter()
+                        .copied()
+                        .filter(|day| self.matches_weekday(*day, Some(&days)))
+                        .collect()
+                } else {
+                    let months = if self.by_month.is_empty() {
+                        vec![first.month()]
+                    } else {
+                        self.by_month.clone()
+                    };
+                    months
+                        .into_iter()
+                        .filter_map(|month| NaiveDate::from_ymd_opt(year, month, 1))
+                        .flat_map(|first_of_month| self.month_dates(first_of_month, first))
+                        .collect()
+                };
+                (first_of_year, dates)
+            }
+        };
+
+        dates.retain(|date| self.by_month.is_empty() || self.by_month.contains(&date.month()));
+        dates.sort();
+        dates.dedup();
+        Some((period_start, self.select_positions(dates)))
+    }
+
+    /// Dates in a month matching BYMONTHDAY and BYDAY, or the start's day of the month
+    fn month_dates(&self, first_of_month: NaiveDate, first: NaiveDate) -> Vec<NaiveDate> {
+        let days: Vec<NaiveDate> = first_of_month
+            .iter_days()
+            .take_while(|day| day.month() == first_of_month.month())
+            .collect();
+
+        if self.by_month_day.is_empty() && self.by_day.is_empty() {
+            return days
+                .into_iter()
+                .filter(|day| day.day() == first.day())
+                .collect();
+        }
+
+        days.iter()
+            .copied()
+            .filter(|day| self.by_month_day.is_empty() || self.matches_month_day(*day))
+            .filter(|day| self.by_day.is_empty() || self.matches_weekday(*day, Some(&days)))
+            .collect()
+    }
+
+    fn matches_month_day(&self, date: NaiveDate) -> bool {
+        if self.by_month_day.is_empty() {
+            return true;
+        }
+        let days_in_month = date
+            .with_day(1)
+            .and_then(|first| first.checked_add_months(chrono::Months::new(1)))
+            .and_then(|next| next.pred_opt())
+            .map_or(31, |last| last.day() as i32);
+        self.by_month_day.iter().any(|day| {
+            let day = if *day < 0 {
+                days_in_month + 1 + day
+            } else {
+                *day
+            };
+            day == date.day() as i32
+        })
+    }
+
+    /// Whether a date is one of the BYDAY weekdays, counting ordinals through `period`
+    fn matches_weekday(&self, date: NaiveDate, period: Option<&[NaiveDate]>) -> bool {
+        if self.by_day.is_empty() {
+            return true;
+        }
+        self.by_day.iter().any(|(nth, weekday)| {
+            if date.weekday() != *weekday {
+                return false;
+            }
+            match (nth, period) {
+                (Some(nth), Some(period)) => {
+                    let same: Vec<&NaiveDate> = period
+                        .iter()
+                        .filter(|day| day.weekday() == *weekday)
+                        .collect();
+                    let index = same.iter().position(|day| **day == date).unwrap_or(0) as i32;
+                    *nth == index + 1 || *nth == index - same.len() as i32
+                }
+                _ => true,
+            }
+        })
+    }
+
+    /// Keep only the BYSETPOS positions of a period's dates
+    fn select_positions(&self, dates: Vec<NaiveDate>) -> Vec<NaiveDate> {
+        if self.by_set_pos.is_empty() {
+            return dates;
+        }
+        let len = dates.len() as i32;
+        let mut selected: Vec<NaiveDate> = self
+            .by_set_pos
+            .iter()
+            .filter_map(|pos| {
+                let index = if *pos > 0 { pos - 1 } else { len + pos };
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|index| dates.get(index).copied())
+            })
+            .collect();
+        selected.sort();
+        selected.dedup();
+        selected
+    }
+}
+
+/// Parse a comma-separated list, giving `None` for items that don't parse
+fn parse_list<T: std::str::FromStr>(value: &str) -> impl Iterator<Item = Option<T>> + '_ {
+    value.split(',').map(|item| item.parse().ok())
+}
+
+/// Parse a BYDAY entry such as `MO`, `2TU` or `-1FR`
+fn parse_weekday(value: &str) -> Option<(Option<i32>, Weekday)> {
+    let split = value.len().checked_sub(2)?;
+    let (nth, day) = value.split_at(split);
+    let weekday = match day.to_uppercase().as_str() {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    };
+    let nth = match nth {
+        "" => None,
+        nth => Some(
+            nth.parse()
+                .ok()
+                .filter(|n: &i32| *n != 0 && n.abs() <= 53)?,
+        ),
+    };
+    Some((nth, weekday))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calendar(events: &str) -> Vec<Event> {
+        let ical = format!("BEGIN:VCALENDAR\r\n{}END:VCALENDAR\r\n", events);
+        crate::ical::parse_calendar(&ical).unwrap().into_events()
+    }
+
+    fn config(mode: RecurrenceMode) -> RecurrenceConfig {
+        RecurrenceConfig {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    fn starts(events: &[Event]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| {
+                event
+                    .start_time()
+                    .unwrap()
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .collect()
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    fn expand(rrule: &str, start: &str) -> Vec<String> {
+        let value = if start.len() == 8 { ";VALUE=DATE" } else { "" };
+        let events = calendar(&format!(
+            "BEGIN:VEVENT\r\nUID:series\r\nDTSTART{}:{}\r\nRRULE:{}\r\nEND:VEVENT\r\n",
+            value, start, rrule
+        ));
+        let config = RecurrenceConfig {
+            mode: RecurrenceMode::Expand,
+            past_days: 0,
+            future_days: 120,
+        };
+        starts(&apply(events, &config, date("2025-01-01")))
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(
+            expand("FREQ=WEEKLY;BYDAY=MO,WE;COUNT=4", "20250106T090000"),
+            vec![
+                "2025-01-06 09:00",
+                "2025-01-08 09:00",
+                "2025-01-13 09:00",
+                "2025-01-15 09:00"
+            ]
+        );
+        assert_eq!(
+            expand(
+                "FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20250401T000000Z",
+                "20250131T180000Z"
+            ),
+            vec!["2025-01-31 18:00", "2025-02-28 18:00", "2025-03-28 18:00"]
+        );
+        assert_eq!(
+            expand("FREQ=MONTHLY;BYMONTHDAY=30;COUNT=3", "20250130T100000"),
+            vec!["2025-01-30 10:00", "2025-03-30 10:00", "2025-04-30 10:00"]
+        );
+        assert_eq!(
+            expand(
+                "FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1;COUNT=3",
+                "20250131T170000"
+            ),
+            vec!["2025-01-31 17:00", "2025-02-28 17:00", "2025-03-31 17:00"]
+        );
+        assert_eq!(
+            expand("FREQ=DAILY;INTERVAL=10;COUNT=3", "20241225"),
+            vec!["2025-01-04 00:00", "2025-01-14 00:00"]
+        );
+        assert_eq!(
+            expand("FREQ=YEARLY;BYMONTH=2;BYDAY=2SU", "20240211T120000"),
+            vec!["2025-02-09 12:00"]
+        );
+
+        // Rules we can't expand are passed through
+        assert_eq!(
+            expand("FREQ=HOURLY;COUNT=3", "20250106T090000"),
+            vec!["2025-01-06 09:00"]
+        );
+    }
+
+    #[test]
+    fn test_expand_mode() {
+        let events = calendar(
+            "BEGIN:VEVENT\r\n\
+             UID:standup\r\n\
+             SUMMARY:Standup\r\n\
+             DTSTART:20250106T090000\r\n\
+             DTEND:20250106T091500\r\n\
+             RRULE:FREQ=DAILY;COUNT=5\r\n\
+             EXDATE:20250108T090000\r\n\
+             END:VEVENT\r\n\
+             BEGIN:VEVENT\r\n\
+             UID:standup\r\n\
+             SUMMARY:Standup (moved)\r\n\
+             RECURRENCE-ID:20250109T090000\r\n\
+             DTSTART:20250109T110000\r\n\
+             END:VEVENT\r\n\
+             BEGIN:VEVENT\r\n\
+             UID:single\r\n\
+             DTSTART:20250107T120000\r\n\
+             END:VEVENT\r\n",
+        );
+
+        let expanded = apply(
+            events.clone(),
+            &config(RecurrenceMode::Expand),
+            date("2025-01-06"),
+        );
+        let mut summaries: Vec<(String, String)> = expanded
+            .iter()
+            .map(|event| {
+                (
+                    event.uid().unwrap().to_string(),
+                    starts(std::slice::from_ref(event))[0].clone(),
+                )
+            })
+            .collect();
+        summaries.sort();
+        assert_eq!(
+            summaries,
+            vec![
+                (
+                    "20250106T090000-standup".to_string(),
+                    "2025-01-06 09:00".to_string()
+                ),
+                (
+                    "20250107T090000-standup".to_string(),
+                    "2025-01-07 09:00".to_string()
+                ),
+                (
+                    "20250109T090000-standup".to_string(),
+                    "2025-01-09 11:00".to_string()
+                ),
+                (
+                    "20250110T090000-standup".to_string(),
+                    "2025-01-10 09:00".to_string()
+                ),
+                ("single".to_string(), "2025-01-07 12:00".to_string()),
+            ]
+        );
+        for event in &expanded {
+            assert!(event.property("RRULE").is_none());
+            assert!(event.inner().get_recurrence_id().is_none());
+        }
+        let first = expanded
+            .iter()
+            .find(|event| event.uid() == Some("20250106T090000-standup"))
+            .unwrap();
+        assert_eq!(
+            first.end_time().unwrap().format("%H:%M").to_string(),
+            "09:15"
+        );
+
+        // Masters are untouched by default
+        assert_eq!(
+            apply(events, &config(RecurrenceMode::Master), date("2025-01-06")).len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_both_mode() {
+        let events = calendar(
+            "BEGIN:VEVENT\r\n\
+             UID:weekly\r\n\
+             DTSTART;TZID=Europe/London:20250106T090000\r\n\
+             RRULE:FREQ=WEEKLY;COUNT=3\r\n\
+             END:VEVENT\r\n",
+        );
+
+        let served = apply(events, &config(RecurrenceMode::Both), date("2025-01-06"));
+        assert_eq!(
+            starts(&served),
+            vec!["2025-01-06 09:00", "2025-01-13 09:00", "2025-01-20 09:00"]
+        );
+
+        // The master keeps its rule and occurrences point back at it
+        assert!(served[0].property("RRULE").is_some());
+        for occurrence in &served[1..] {
+            assert_eq!(occurrence.uid(), Some("weekly"));
+            assert!(occurrence.property("RRULE").is_none());
+            assert_eq!(occurrence.inner().get_recurrence_id(), occurrence.start());
+        }
+        assert!(
+            served[1]
+                .inner()
+                .to_string()
+                .contains("DTSTART;TZID=Europe/London:20250113T090000")
+        );
+    }
+
+    #[test]
+    fn test_window() {
+        let events = calendar(
+            "BEGIN:VEVENT\r\n\
+             UID:forever\r\n\
+             DTSTART;VALUE=DATE:20200101\r\n\
+             RRULE:FREQ=MONTHLY\r\n\
+             END:VEVENT\r\n",
+        );
+        let config = RecurrenceConfig {
+            mode: RecurrenceMode::Expand,
+            past_days: 31,
+            future_days: 62,
+        };
+
+        let served = apply(events, &config, date("2025-03-15"));
+        assert_eq!(
+            starts(&served),
+            vec!["2025-03-01 00:00", "2025-04-01 00:00", "2025-05-01 00:00"]
+        );
+        assert!(served.iter().all(Event::is_all_day));
+    }
+}
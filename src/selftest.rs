@@ -0,0 +1,153 @@
+use icalendar::{Component, EventLike};
+
+use crate::config::{Config, SelfTest, SelfTestExpect, SourceConfig};
+use crate::error::{Error, Result};
+use crate::filter::{CompiledStep, StepResult, apply_steps_with_default};
+use crate::ical::Event;
+
+/// Run every self-test in the config, failing with the first one whose event
+/// doesn't come out of the steps as expected
+pub fn run(config: &Config) -> Result<()> {
+    for (idx, test) in config.selftest.iter().enumerate() {
+        let context = format!("Self-test {} ('{}')", idx, test.summary);
+        check(config, test)
+            .map_err(|message| Error::Config(format!("{}: {}", context, message)))?;
+    }
+    Ok(())
+}
+
+/// Build the example event a self-test describes
+fn example_event(test: &SelfTest) -> Event {
+    let mut event = icalendar::Event::new();
+    event.summary(&test.summary);
+    if let Some(description) = &test.description {
+        event.description(description);
+    }
+    if let Some(location) = &test.location {
+        event.location(location);
+    }
+    Event::new(event)
+}
+
+/// Run a self-test's event through the steps, describing what went wrong
+fn check(config: &Config, test: &SelfTest) -> std::result::Result<(), String> {
+    let calendar = config
+        .calendars
+        .get(&test.calendar)
+        .ok_or_else(|| format!("unknown calendar '{}'", test.calendar))?;
+    if test.expect == SelfTestExpect::Reject && test.result.is_some() {
+        return Err("a rejected event can't have a result".to_string());
+    }
+
+    let mut event = example_event(test);
+    let mut outcome = StepResult::Keep;
+
+    if let Some(idx) = test.source {
+        let source = calendar
+            .sources
+            .get(idx)
+            .ok_or_else(|| format!("calendar '{}' has no source {}", test.calendar, idx))?;
+        // Shared sources fall back to their id when they have no explicit label
+        let label = match source {
+            SourceConfig::Source {
+                source: source_id,
+                label: None,
+                ..
+            } => Some(source_id.as_str()),
+            _ => source.label(),
+        };
+        let tags = source.tags().join(",");
+        let vars = [
+            ("label", label.unwrap_or_default()),
+            ("tags", tags.as_str()),
+        ];
+        let steps = CompiledStep::compile_many_with_vars(source.steps(), &vars)
+            .map_err(|e| e.to_string())?;
+        outcome = apply_steps_with_default(&mut event, &steps, source.default_action());
+    }
+
+    if outcome == StepResult::Keep {
+        let steps = CompiledStep::compile_many(&calendar.steps).map_err(|e| e.to_string())?;
+        outcome = apply_steps_with_default(&mut event, &steps, &calendar.default);
+    }
+
+    match (test.expect, outcome) {
+        (SelfTestExpect::Keep, StepResult::Reject) => {
+            Err("expected the event to be kept but it was rejected".to_string())
+        }
+        (SelfTestExpect::Reject, StepResult::Keep) => Err(format!(
+            "expected the event to be rejected but it was kept as '{}'",
+            event.summary().unwrap_or_default()
+        )),
+        (SelfTestExpect::Keep, StepResult::Keep) => match &test.result {
+            Some(result) if event.summary() != Some(result.as_str()) => Err(format!(
+                "expected the summary '{}' but got '{}'",
+                result,
+                event.summary().unwrap_or_default()
+            )),
+            _ => Ok(()),
+        },
+        (SelfTestExpect::Reject, StepResult::Reject) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(selftest: &str) -> Config {
+        let config = format!(
+            r#"{{
+                "calendars": {{
+                    "work": {{
+                        "sources": [{{
+                            "url": "https://example.com/work.ics",
+                            "steps": [{{ "type": "deny", "patterns": ["^Lunch"] }}]
+                        }}],
+                        "steps": [{{ "type": "replace", "pattern": "^Team ", "replacement": "" }}]
+                    }}
+                }},
+                "selftest": {}
+            }}"#,
+            selftest
+        );
+        serde_json::from_str(&config).unwrap()
+    }
+
+    #[test]
+    fn test_selftest() {
+        let passing = config(
+            r#"[
+                { "calendar": "work", "summary": "Team standup", "result": "standup" },
+                { "calendar": "work", "source": 0, "summary": "Lunch", "expect": "reject" },
+                { "calendar": "work", "summary": "Lunch" }
+            ]"#,
+        );
+        passing.validate().unwrap();
+
+        let failing = [
+            // Wrong result
+            r#"[{ "calendar": "work", "summary": "Team standup", "result": "Standup" }]"#,
+            // Kept when it should have been rejected
+            r#"[{ "calendar": "work", "summary": "Review", "expect": "reject" }]"#,
+            // Rejected when it should have been kept
+            r#"[{ "calendar": "work", "source": 0, "summary": "Lunch" }]"#,
+            r#"[{ "calendar": "home", "summary": "Lunch" }]"#,
+            r#"[{ "calendar": "work", "source": 1, "summary": "Lunch" }]"#,
+            r#"[{ "calendar": "work", "summary": "Lunch", "expect": "reject", "result": "Lunch" }]"#,
+        ];
+        for selftest in failing {
+            let err = config(selftest).validate().unwrap_err();
+            assert!(err.to_string().contains("Self-test 0"), "{}", err);
+        }
+
+        let err =
+            config(r#"[{ "calendar": "work", "summary": "Team standup", "result": "Standup" }]"#)
+                .validate()
+                .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Configuration error: Self-test 0 ('Team standup'): expected the summary 'Standup' but got 'standup'"
+        );
+    }
+}
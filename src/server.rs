@@ -1,149 +1,3721 @@
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path as FilePath, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use axum::{
-    Router,
-    extract::{Path, State},
-    http::{StatusCode, header},
-    response::{IntoResponse, Response},
-    routing::get,
+    Json, Router,
+    body::Bytes,
+    extract::{ConnectInfo, DefaultBodyLimit, FromRequestParts, Path, Query, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header, request::Parts},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{MethodRouter, any, get, post},
 };
+use icalendar::{Component, EventLike};
+use serde::{Deserialize, Serialize};
+use tower::util::ServiceExt;
 
-use crate::config::Config;
+use crate::audit::{AuditEntry, AuditLog};
+use crate::cache::{CacheStats, MergeCache};
+use crate::coalesce::Coalescer;
+use crate::config::{CalendarConfig, Config, Step};
+use crate::conflicts;
+use crate::error::Error;
+use crate::export;
 use crate::fetcher::Fetcher;
-use crate::ical::parser::serialize_events;
-use crate::merge::merge_calendars;
+use crate::filter::{CompiledStep, decorate_events, process_events};
+use crate::ical::Event;
+use crate::ical::parser::serialize_events_with;
+use crate::logging::LogFilter;
+use crate::merge::{
+    CompiledPipelines, MergeResult, is_over, merge_calendars_cached, merge_calendars_with,
+};
+use crate::metrics::{CalendarMetrics, Metrics, render_cache};
+use crate::qr::QrCode;
+use crate::query::{self, EventView};
+use crate::supervisor::Supervisor;
+
+/// Outcome of a merge, shared between every request waiting on it
+type SharedMerge = std::result::Result<Arc<MergeResult>, Arc<Error>>;
+
+/// A merged calendar ready to be served
+struct ServedMerge {
+    result: Arc<MergeResult>,
+    /// Sources that failed in the latest merge attempt
+    errors: usize,
+    /// How old the merge is when an older one is served because the latest one failed
+    stale: Option<Duration>,
+}
+
+/// A tenant's own server state, along with the routes serving it
+#[derive(Clone)]
+struct Tenant {
+    state: AppState,
+    router: Router,
+    token: Option<String>,
+}
+
+/// A tenant's config, loaded and checked but not yet served
+struct LoadedTenant {
+    id: String,
+    config: Config,
+    /// Where the config was read from
+    path: PathBuf,
+    token: Option<String>,
+}
+
+/// Application state shared across handlers
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<RwLock<Config>>,
+    pub config_path: Arc<PathBuf>,
+    /// Replaced when reloading changes the fetcher settings
+    fetcher: Arc<RwLock<Arc<Fetcher>>>,
+    pub metrics: Arc<Metrics>,
+    /// Merges in flight, so concurrent requests for a calendar share one merge
+    merges: Arc<Coalescer<SharedMerge>>,
+    /// Recently merged calendars, used when `server.cache_ttl` is set
+    pub cache: Arc<MergeCache>,
+    /// Step pipelines compiled from the current config
+    pipelines: Arc<Mutex<Arc<CompiledPipelines>>>,
+    /// Cleared until startup warm-up has finished
+    ready: Arc<AtomicBool>,
+    /// Runtime control of the tracing filter, when tracing was set up with one
+    pub log_filter: Option<LogFilter>,
+    /// Writer for `server.audit_log`
+    audit: Arc<AuditLog>,
+    /// Background tasks such as the config watcher, restarted when they crash
+    pub supervisor: Arc<Supervisor>,
+    /// States of the tenants served under `/t/{id}`, each with its own config,
+    /// caches and metrics
+    tenants: Arc<RwLock<BTreeMap<String, Tenant>>>,
+}
+
+impl AppState {
+    pub fn new(config: Config, config_path: PathBuf, fetcher: Fetcher) -> Self {
+        let ready = config.warmup_calendars().is_empty();
+        let cache_limits = config.server.cache_limits();
+        let pipelines = CompiledPipelines::compile(&config);
+        Self {
+            config: Arc::new(RwLock::new(config)),
+            config_path: Arc::new(config_path),
+            fetcher: Arc::new(RwLock::new(fetcher.into_shared())),
+            metrics: Arc::new(Metrics::new()),
+            merges: Arc::new(Coalescer::new()),
+            cache: Arc::new(MergeCache::with_limits(cache_limits)),
+            pipelines: Arc::new(Mutex::new(Arc::new(pipelines))),
+            ready: Arc::new(AtomicBool::new(ready)),
+            log_filter: None,
+            audit: Arc::new(AuditLog::new()),
+            supervisor: Arc::new(Supervisor::new()),
+            tenants: Arc::new(RwLock::new(BTreeMap::new())),
+        }
+    }
+
+    /// Allow the tracing filter to be changed through config and `/admin/log-level`
+    pub fn with_log_filter(mut self, log_filter: LogFilter) -> Self {
+        self.log_filter = Some(log_filter);
+        self
+    }
+
+    /// The fetcher built from the current config
+    pub fn fetcher(&self) -> Arc<Fetcher> {
+        self.fetcher.read().unwrap().clone()
+    }
+
+    /// Step pipelines compiled from `config`. A merge can start with the config from
+    /// before a reload, so the pipelines are compiled again if they don't match it
+    fn pipelines(&self, config: &Config) -> Arc<CompiledPipelines> {
+        let current = self.pipelines.lock().unwrap().clone();
+        if current.is_for(config) {
+            return current;
+        }
+        let pipelines = Arc::new(CompiledPipelines::compile(config));
+        *self.pipelines.lock().unwrap() = pipelines.clone();
+        pipelines
+    }
+
+    /// Whether the server is ready to serve subscribers
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Merge the configured warm-up calendars into the cache, then mark the server ready
+    pub async fn warm_up(&self) {
+        let (ids, config) = {
+            let config = self.config.read().unwrap();
+            (config.warmup_calendars(), config.clone())
+        };
+
+        let merges = ids.iter().map(|id| {
+            let config = config.clone();
+            async move {
+                match self.merge(id, config).await {
+                    Ok(_) => tracing::info!("Warmed up calendar '{}'", id),
+                    Err(err) => tracing::warn!("Failed to warm up calendar '{}': {}", id, err),
+                }
+            }
+        });
+        futures::future::join_all(merges).await;
+
+        self.ready.store(true, Ordering::SeqCst);
+    }
+
+    /// Merge a calendar, reusing a cached merge or joining one that is already in flight
+    async fn merge(
+        &self,
+        id: &str,
+        config: Config,
+    ) -> std::result::Result<ServedMerge, Arc<Error>> {
+        let cache_ttl = config.server.cache_ttl();
+        if let Some(cached) = cache_ttl.and_then(|ttl| self.cache.get_fresh(id, ttl)) {
+            return Ok(ServedMerge {
+                errors: cached.errors.len(),
+                result: cached,
+                stale: None,
+            });
+        }
+
+        let merged = self.run_merge(id, config).await;
+
+        // Fall back to the last good merge when this one failed outright
+        let failed = merged.as_ref().map_or(true, |result| result.is_failed());
+        if failed
+            && cache_ttl.is_some()
+            && let Some(entry) = self.cache.get(id)
+        {
+            tracing::warn!("Merge of '{}' failed, serving stale data", id);
+            return Ok(ServedMerge {
+                result: entry.result,
+                errors: merged.map_or(1, |result| result.errors.len()),
+                stale: Some(entry.merged_at.elapsed()),
+            });
+        }
+
+        let result = merged?;
+        Ok(ServedMerge {
+            errors: result.errors.len(),
+            result,
+            stale: None,
+        })
+    }
+
+    /// Merge a calendar again now rather than when its cached merge expires, and drop
+    /// the cached merges of calendars including it. Returns the calendars dropped
+    async fn refresh(
+        &self,
+        id: &str,
+        config: Config,
+    ) -> std::result::Result<(Arc<MergeResult>, Vec<String>), Arc<Error>> {
+        let dependents: Vec<String> = config
+            .dependent_calendars(id)
+            .into_iter()
+            .filter(|dependent| dependent != id)
+            .collect();
+
+        // The last good merge is replaced by the new one, or kept if this one fails
+        let result = self.run_merge(id, config).await?;
+        for dependent in &dependents {
+            self.cache.remove(dependent);
+        }
+        Ok((result, dependents))
+    }
+
+    /// Merge a calendar, joining a merge of it that is already in flight
+    async fn run_merge(&self, id: &str, config: Config) -> SharedMerge {
+        let cache_ttl = config.server.cache_ttl();
+        self.merges
+            .run(id, || {
+                let state = self.clone();
+                let id = id.to_string();
+                async move {
+                    let fetcher = state.fetcher();
+                    let pipelines = state.pipelines(&config);
+                    // Referenced calendars are read through the cache when there is one
+                    let merge_result = match cache_ttl {
+                        Some(ttl) => {
+                            merge_calendars_cached(
+                                &id,
+                                &config,
+                                &pipelines,
+                                &fetcher,
+                                &state.cache,
+                                ttl,
+                            )
+                            .await?
+                        }
+                        None => merge_calendars_with(&id, &config, &pipelines, &fetcher).await?,
+                    };
+                    state.metrics.record_merge(&id, &merge_result);
+
+                    // Log any errors but still serve partial data
+                    for (url, err) in &merge_result.errors {
+                        tracing::error!("Failed to fetch calendar from {}: {}", url, err);
+                    }
+
+                    let merge_result = Arc::new(merge_result);
+                    // Keep the last good merge around to fall back to
+                    if cache_ttl.is_some() && !merge_result.is_failed() {
+                        state.cache.insert(&id, merge_result.clone());
+                    }
+                    Ok(merge_result)
+                }
+            })
+            .await
+    }
+
+    /// The state of the tenant `id`, when the config lists it
+    pub fn tenant(&self, id: &str) -> Option<AppState> {
+        self.tenants
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|tenant| tenant.state.clone())
+    }
+
+    /// Files read by `file://` sources of this server's calendars and its tenants'
+    pub fn local_files(&self) -> Vec<PathBuf> {
+        let mut files = self.config.read().unwrap().local_files();
+        for tenant in self.tenants.read().unwrap().values() {
+            for file in tenant.state.local_files() {
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+        }
+        files
+    }
+
+    /// Drop the cached merges of this server's calendars and its tenants'
+    pub fn clear_caches(&self) {
+        self.cache.clear();
+        for tenant in self.tenants.read().unwrap().values() {
+            tenant.state.clear_caches();
+        }
+    }
+
+    /// Load the configs of the tenants the current config lists
+    pub fn load_tenants(&self) -> crate::error::Result<()> {
+        let config = self.config.read().unwrap().clone();
+        let tenants = self.load_tenant_configs(&config)?;
+        self.apply_tenants(tenants)
+    }
+
+    /// Load and check the config of every tenant `config` lists
+    fn load_tenant_configs(&self, config: &Config) -> crate::error::Result<Vec<LoadedTenant>> {
+        config
+            .server
+            .tenants
+            .iter()
+            .map(|(id, tenant)| {
+                Ok(LoadedTenant {
+                    id: id.clone(),
+                    config: config.load_tenant(&self.config_path, id)?,
+                    path: tenant.path(&self.config_path),
+                    token: tenant.token.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Serve the tenants with configs that have been loaded, keeping the caches and
+    /// metrics of tenants that were already being served
+    fn apply_tenants(&self, loaded: Vec<LoadedTenant>) -> crate::error::Result<()> {
+        let current = self.tenants.read().unwrap().clone();
+        let mut tenants = BTreeMap::new();
+        for LoadedTenant {
+            id,
+            config,
+            path,
+            token,
+        } in loaded
+        {
+            let state = match current.get(&id) {
+                Some(tenant) if *tenant.state.config_path == path => {
+                    tenant.state.apply_config(config)?;
+                    tenant.state.clone()
+                }
+                _ => {
+                    tracing::info!("Serving tenant '{}' from {:?}", id, path);
+                    let main = self.fetcher();
+                    let fetcher = Fetcher::from_config(&config.fetcher)?
+                        .with_fixtures(main.fixtures().map(FilePath::to_path_buf))
+                        .with_providers(main.providers().clone());
+                    let state = AppState::new(config, path, fetcher);
+                    if !state.is_ready() && tokio::runtime::Handle::try_current().is_ok() {
+                        let warm_state = state.clone();
+                        tokio::spawn(async move { warm_state.warm_up().await });
+                    }
+                    state
+                }
+            };
+            tenants.insert(
+                id,
+                Tenant {
+                    router: tenant_router(state.clone()),
+                    state,
+                    token,
+                },
+            );
+        }
+        for id in current.keys() {
+            if !tenants.contains_key(id) {
+                tracing::info!("Stopped serving tenant '{}'", id);
+            }
+        }
+        *self.tenants.write().unwrap() = tenants;
+        Ok(())
+    }
+
+    /// Reload configuration from file
+    pub fn reload_config(&self) -> crate::error::Result<()> {
+        tracing::info!("Reloading configuration from {:?}", self.config_path);
+
+        // Load and validate new config
+        let new_config = Config::load(&*self.config_path)?;
+        new_config.validate()?;
+        // Every tenant's config is checked first so a bad one leaves them all as they were
+        let tenants = self.load_tenant_configs(&new_config)?;
+
+        self.apply_config(new_config)?;
+        self.apply_tenants(tenants)?;
+
+        tracing::info!("Configuration reloaded successfully");
+        Ok(())
+    }
+
+    /// Start using a config that has been loaded and checked
+    fn apply_config(&self, new_config: Config) -> crate::error::Result<()> {
+        // Merges already running finish with the fetcher they started with
+        if new_config.fetcher != self.config.read().unwrap().fetcher {
+            let old = self.fetcher();
+            let fixtures = old.fixtures().map(|dir| dir.to_path_buf());
+            let fetcher = Fetcher::from_config(&new_config.fetcher)?
+                .with_fixtures(fixtures)
+                .with_providers(old.providers().clone());
+            *self.fetcher.write().unwrap() = fetcher.into_shared();
+            tracing::info!("Fetcher settings changed, rebuilt the fetcher");
+        }
+
+        // Swap in new config
+        let mut config = self.config.write().unwrap();
+        let old_config = std::mem::replace(&mut *config, new_config);
+
+        // Leave a filter set through /admin/log-level alone unless the config changed it
+        if let Some(log_filter) = &self.log_filter
+            && old_config.server.log_level != config.server.log_level
+        {
+            log_filter.apply_config(config.server.log_level.as_deref())?;
+        }
+
+        // Cached merges and compiled steps were built from the old config
+        self.cache.clear();
+        self.cache.set_limits(config.server.cache_limits());
+        *self.pipelines.lock().unwrap() = Arc::new(CompiledPipelines::compile(&config));
+        Ok(())
+    }
+}
+
+/// Create the router with all routes
+///
+/// Routes are mounted under the configured path prefix, changing it requires a restart.
+pub fn create_router(state: AppState) -> Router {
+    let prefix = state.config.read().unwrap().server.path_prefix();
+
+    let routes = service_routes().route("/t/{tenant}/{*path}", any(serve_tenant));
+
+    let router = if prefix.is_empty() {
+        routes
+    } else {
+        Router::new().nest(&prefix, routes)
+    };
+
+    with_layers(router, state)
+}
+
+/// The routes of a tenant, which its requests reach with `/t/{tenant}` taken off
+fn tenant_router(state: AppState) -> Router {
+    with_layers(service_routes(), state)
+}
+
+fn service_routes() -> Router<AppState> {
+    routes()
+        .into_iter()
+        .fold(Router::new(), |router, (path, _, handler)| {
+            router.route(path, handler)
+        })
+}
+
+fn with_layers(router: Router<AppState>, state: AppState) -> Router {
+    router
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_request_timeout,
+        ))
+        // Requests carry at most a short admin setting so there's no reason to accept large bodies
+        .layer(DefaultBodyLimit::max(MAX_REQUEST_BODY))
+        .with_state(state)
+}
+
+/// Every route the server handles, with the methods it accepts
+fn routes() -> Vec<(&'static str, &'static str, MethodRouter<AppState>)> {
+    vec![
+        ("/ical/{id}", "GET", get(get_calendar)),
+        ("/ical/{id}/event/{uid}", "GET", get(get_event)),
+        ("/search/{id}", "GET", get(get_search)),
+        ("/next/{id}", "GET", get(get_next)),
+        ("/today/{id}", "GET", get(get_today)),
+        ("/agenda/{id}", "GET", get(get_agenda)),
+        ("/csv/{id}", "GET", get(get_csv)),
+        ("/stats/{id}", "GET", get(get_stats)),
+        ("/conflicts/{id}", "GET", get(get_conflicts)),
+        ("/hooks/refresh/{id}", "POST", post(post_refresh_hook)),
+        ("/status", "GET", get(get_status)),
+        ("/metrics", "GET", get(get_metrics)),
+        ("/subscribe/{id}", "GET", get(get_subscribe)),
+        ("/healthz", "GET", get(get_healthz)),
+        ("/readyz", "GET", get(get_readyz)),
+        (
+            "/admin/log-level",
+            "GET, PUT",
+            get(get_log_level).put(put_log_level),
+        ),
+    ]
+}
+
+/// Methods and full paths of the routes served with this config
+pub fn route_table(config: &Config) -> Vec<(&'static str, String)> {
+    let prefix = config.server.path_prefix();
+    let mut table: Vec<_> = routes()
+        .into_iter()
+        .map(|(path, methods, _)| (methods, format!("{}{}", prefix, path)))
+        .collect();
+    if !config.server.tenants.is_empty() {
+        table.push(("ANY", format!("{}/t/{{tenant}}/...", prefix)));
+    }
+    table
+}
+
+/// A token given in a URL, for clients that can't send an `Authorization` header
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// Handler for every route under /t/{tenant}, which hands the request to the
+/// tenant's own routes once it has shown the tenant's token
+async fn serve_tenant(
+    State(state): State<AppState>,
+    Path((id, _)): Path<(String, String)>,
+    Query(query): Query<TokenQuery>,
+    request: Request,
+) -> Result<Response, AppError> {
+    let tenant = state.tenants.read().unwrap().get(&id).cloned();
+    let Some(tenant) = tenant else {
+        return Err(AppError::NotFound(format!("Tenant '{}' not found", id)));
+    };
+
+    if let Some(token) = &tenant.token {
+        let in_query = query
+            .token
+            .is_some_and(|provided| constant_time_eq(provided.as_bytes(), token.as_bytes()));
+        if !in_query {
+            authorize_bearer(request.headers(), &[token])?;
+        }
+    }
+
+    // The path is taken from the URI as it was sent, keeping it percent-encoded
+    let tenant_prefix = format!("/t/{}", id);
+    let path = request
+        .uri()
+        .path()
+        .strip_prefix(&tenant_prefix)
+        .unwrap_or("/");
+    let uri = match request.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+
+    // A fresh request, as the tenant's routes would be confused by the parameters
+    // matched here
+    let (parts, body) = request.into_parts();
+    let mut forwarded = Request::new(body);
+    *forwarded.method_mut() = parts.method;
+    *forwarded.uri_mut() = uri
+        .parse()
+        .map_err(|_| AppError::BadRequest("Invalid request path".to_string()))?;
+    *forwarded.version_mut() = parts.version;
+    *forwarded.headers_mut() = parts.headers;
+    if let Some(connect_info) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+        forwarded.extensions_mut().insert(*connect_info);
+    }
+
+    let response = tenant.router.oneshot(forwarded).await;
+    Ok(response.into_response())
+}
+
+/// Largest request body accepted
+const MAX_REQUEST_BODY: usize = 64 * 1024;
+
+/// Fail requests that take longer than the configured timeout, e.g. because upstreams are slow
+async fn enforce_request_timeout(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let timeout = state.config.read().unwrap().server.request_timeout();
+
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!("Request timed out after {}s", timeout.as_secs());
+            (
+                StatusCode::GATEWAY_TIMEOUT,
+                format!(
+                    "Request timed out after {}s waiting for upstream calendars",
+                    timeout.as_secs()
+                ),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Who made a request, as far as the server can tell
+struct ClientInfo {
+    /// Address of the connection's peer, which is the proxy when behind one
+    ip: Option<String>,
+    /// `X-Forwarded-For` as the client sent it, which anyone can set
+    forwarded_for: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header_value = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip().to_string());
+
+        Ok(ClientInfo {
+            ip: peer,
+            forwarded_for: header_value("x-forwarded-for"),
+            user_agent: header_value("user-agent"),
+        })
+    }
+}
+
+/// A merged calendar along with the config it was merged under
+struct RequestedMerge {
+    /// Calendar id, resolved from any alias used in the URL
+    id: String,
+    config: Config,
+    served: ServedMerge,
+}
+
+impl RequestedMerge {
+    fn calendar(&self) -> &CalendarConfig {
+        &self.config.calendars[&self.id]
+    }
+
+    fn events(&self) -> &[Event] {
+        &self.served.result.events
+    }
+
+    /// Whether the merged events are served exactly as they were cached, so their
+    /// serialized text can be shared between requests
+    fn served_as_merged(&self, variant: Option<&str>) -> bool {
+        let calendar = self.calendar();
+        variant.is_none()
+            && calendar.hide_ended_after.is_none()
+            && !calendar
+                .steps
+                .iter()
+                .any(|step| matches!(step, Step::Soon { .. }))
+            && !(calendar.warning_event && (self.served.errors > 0 || self.served.stale.is_some()))
+            && !self.config.output.generated_timestamp
+    }
+
+    /// Copies of `events` as the requested variant shows them, with soon steps
+    /// applied and long finished events hidden as of now. Hidden events are never copied
+    fn decorated<'a>(
+        &self,
+        state: &AppState,
+        events: impl IntoIterator<Item = &'a Event>,
+        variant: Option<&str>,
+    ) -> Result<Vec<Event>, AppError> {
+        let calendar = self.calendar();
+        let now = chrono::Local::now().naive_local();
+        let cutoff = calendar.serve_cutoff(now);
+        let events: Vec<Event> = events
+            .into_iter()
+            .filter(|event| cutoff.is_none_or(|cutoff| !is_over(event, cutoff)))
+            .cloned()
+            .collect();
+        let mut steps = calendar.steps.clone();
+        let events = match variant {
+            None => events,
+            Some(name) => {
+                let Some(variant_steps) = calendar.variants.get(name) else {
+                    return Err(AppError::BadRequest(format!(
+                        "Calendar '{}' has no variant '{}'",
+                        self.id, name
+                    )));
+                };
+                steps.extend(variant_steps.iter().cloned());
+                let pipelines = state.pipelines(&self.config);
+                let uncompiled;
+                let compiled = match pipelines.variant(&self.id, name) {
+                    Some(compiled) => compiled,
+                    // Only left out of the pipelines when some step doesn't compile
+                    None => {
+                        uncompiled = CompiledStep::compile_many(variant_steps)?;
+                        &uncompiled
+                    }
+                };
+                process_events(events, compiled, &calendar.default)
+            }
+        };
+        Ok(decorate_events(events, &steps, now))
+    }
+}
+
+/// Resolve the calendar a request names, record the access and merge it
+async fn merge_requested(
+    state: &AppState,
+    requested: &str,
+    client: &ClientInfo,
+) -> Result<RequestedMerge, AppError> {
+    // Acquire read lock and clone the full config
+    let (id, config) = {
+        let config_guard = state.config.read().unwrap();
+        // Verify calendar exists, resolving aliases to the calendar they name
+        let Some(calendar_id) = config_guard.resolve_calendar(requested) else {
+            return Err(AppError::NotFound(format!(
+                "Calendar '{}' not found",
+                requested
+            )));
+        };
+        (calendar_id.to_string(), config_guard.clone())
+    };
+
+    if let Some(audit_log) = &config.server.audit_log {
+        let entry = AuditEntry {
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            calendar: &id,
+            requested,
+            ip: client.ip.as_deref(),
+            forwarded_for: client.forwarded_for.as_deref(),
+            user_agent: client.user_agent.as_deref(),
+        };
+        if let Err(err) = state.audit.record(audit_log, &entry) {
+            tracing::warn!("Failed to write audit log {:?}: {}", audit_log.path, err);
+        }
+    }
+
+    // Merge calendars (lock is released here)
+    let served = state.merge(&id, config.clone()).await?;
+
+    // Some would rather see an obviously broken calendar than one weeks out of date
+    if let Some(age) = served.stale
+        && let Some(max_stale) = config.calendars[&id].max_stale()
+        && age > max_stale
+    {
+        tracing::error!(
+            "Merge of '{}' is {}s old, past its max_stale, refusing to serve it",
+            id,
+            age.as_secs()
+        );
+        return Err(AppError::Stale(age));
+    }
+    Ok(RequestedMerge { id, config, served })
+}
+
+/// Serialize events as iCal for a text/calendar response
+fn serialized_events(events: Vec<Event>, merged: &RequestedMerge) -> Bytes {
+    serialize_events_with(
+        events,
+        &merged.config.output,
+        merged.served.result.timezone.as_ref(),
+    )
+    .into()
+}
+
+/// Build a text/calendar response, flagging incomplete data
+fn calendar_response(ical_text: Bytes, merged: &RequestedMerge) -> Response {
+    let served = &merged.served;
+
+    let mut response = (
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        ical_text,
+    )
+        .into_response();
+    let headers = response.headers_mut();
+    if let Some(age) = served.stale {
+        headers.insert("x-ical-merge-stale", HeaderValue::from_static("true"));
+        headers.insert("x-ical-merge-age", HeaderValue::from(age.as_secs()));
+    }
+    if served.errors > 0 {
+        headers.insert("x-ical-merge-errors", HeaderValue::from(served.errors));
+    }
+
+    response
+}
+
+/// Query string for the calendar endpoints
+#[derive(Debug, Deserialize)]
+struct VariantParams {
+    /// Name of one of the calendar's variants to serve
+    variant: Option<String>,
+}
+
+/// Handler for GET /ical/{id}
+async fn get_calendar(
+    Path(requested): Path<String>,
+    Query(params): Query<VariantParams>,
+    State(state): State<AppState>,
+    client: ClientInfo,
+) -> Result<Response, AppError> {
+    let merged = merge_requested(&state, &requested, &client).await?;
+    let served = &merged.served;
+
+    // Popular calendars are polled constantly, so avoid copying and serializing
+    // their events for every request when nothing about them changes per request
+    if merged.served_as_merged(params.variant.as_deref()) {
+        let ical_text = served.result.serialized(&merged.config.output);
+        return Ok(calendar_response(ical_text, &merged));
+    }
+
+    let mut events = merged.decorated(&state, merged.events(), params.variant.as_deref())?;
+    if merged.calendar().warning_event && (served.errors > 0 || served.stale.is_some()) {
+        events.push(create_warning_event(
+            &merged.id,
+            served.errors,
+            served.stale.is_some(),
+        ));
+    }
+
+    let ical_text = serialized_events(events, &merged);
+    Ok(calendar_response(ical_text, &merged))
+}
+
+/// Handler for GET /ical/{id}/event/{uid}.ics
+async fn get_event(
+    Path((requested, file)): Path<(String, String)>,
+    Query(params): Query<VariantParams>,
+    State(state): State<AppState>,
+    client: ClientInfo,
+) -> Result<Response, AppError> {
+    let Some(uid) = file.strip_suffix(".ics") else {
+        return Err(AppError::NotFound(format!("Event '{}' not found", file)));
+    };
+
+    let merged = merge_requested(&state, &requested, &client).await?;
+
+    // Overridden occurrences of a recurring event share its UID so come along too
+    let events = merged
+        .events()
+        .iter()
+        .filter(|event| event.uid() == Some(uid));
+    // The variant may leave the event out, or add buffers that aren't asked for
+    let mut events = merged.decorated(&state, events, params.variant.as_deref())?;
+    events.retain(|event| event.uid() == Some(uid));
+    if events.is_empty() {
+        return Err(AppError::NotFound(format!("Event '{}' not found", uid)));
+    }
+
+    let ical_text = serialized_events(events, &merged);
+    Ok(calendar_response(ical_text, &merged))
+}
+
+/// Query string for GET /search/{id}
+#[derive(Debug, Deserialize)]
+struct SearchParams {
+    /// Text to look for in the summary, description and location
+    #[serde(default)]
+    q: String,
+    /// Treat `q` as a regular expression
+    #[serde(default)]
+    regex: bool,
+    from: Option<String>,
+    to: Option<String>,
+    /// Most matches to return
+    limit: Option<usize>,
+    /// Matches to skip, for fetching later pages
+    offset: Option<usize>,
+}
+
+/// Default and largest number of results in a page of JSON results
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Where a page of JSON results sits among all of them
+#[derive(Debug, PartialEq, Serialize)]
+struct Page {
+    /// Results there are across every page
+    total: usize,
+    offset: usize,
+    /// Offset to ask for to get the next page, if there is one
+    next_offset: Option<usize>,
+}
+
+/// Take the page of `items` starting at `offset`, with between one and `limit` of them
+fn paginate<T>(items: Vec<T>, offset: Option<usize>, limit: Option<usize>) -> (Vec<T>, Page) {
+    let total = items.len();
+    let offset = offset.unwrap_or(0);
+    let limit = limit.unwrap_or(MAX_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let items: Vec<T> = items.into_iter().skip(offset).take(limit).collect();
+    let end = offset.saturating_add(items.len());
+    let page = Page {
+        total,
+        offset,
+        // An empty page would send clients following `next_offset` round forever
+        next_offset: (!items.is_empty() && end < total).then_some(end),
+    };
+    (items, page)
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+    calendar: String,
+    events: Vec<EventView>,
+    #[serde(flatten)]
+    page: Page,
+}
+
+/// Handler for GET /search/{id}
+async fn get_search(
+    Path(requested): Path<String>,
+    Query(params): Query<SearchParams>,
+    State(state): State<AppState>,
+    client: ClientInfo,
+) -> Result<Json<SearchResponse>, AppError> {
+    let bad_request = |e: Error| AppError::BadRequest(e.to_string());
+    let pattern = query::search_pattern(&params.q, params.regex).map_err(bad_request)?;
+    let from = params
+        .from
+        .as_deref()
+        .map(query::parse_time)
+        .transpose()
+        .map_err(bad_request)?;
+    let to = params
+        .to
+        .as_deref()
+        .map(query::parse_time)
+        .transpose()
+        .map_err(bad_request)?;
+
+    let merged = merge_requested(&state, &requested, &client).await?;
+
+    let matches = query::search(merged.events(), &pattern, from, to);
+    let (events, page) = paginate(matches, params.offset, params.limit);
+
+    Ok(Json(SearchResponse {
+        calendar: merged.id.clone(),
+        events: events.into_iter().map(EventView::from).collect(),
+        page,
+    }))
+}
+
+/// Output format for the convenience endpoints
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    #[default]
+    Json,
+    Txt,
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatParams {
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(Serialize)]
+struct NextResponse {
+    calendar: String,
+    event: Option<EventView>,
+}
+
+/// Handler for GET /next/{id}
+async fn get_next(
+    Path(requested): Path<String>,
+    Query(params): Query<FormatParams>,
+    State(state): State<AppState>,
+    client: ClientInfo,
+) -> Result<Response, AppError> {
+    let merged = merge_requested(&state, &requested, &client).await?;
+    let now = chrono::Local::now().naive_local();
+    let event = query::next_event(merged.events(), now);
+
+    if params.format == Format::Txt {
+        let text = match event {
+            Some(event) => format!(
+                "{} {}\n",
+                query::start_label(event),
+                event.summary().unwrap_or("<no summary>")
+            ),
+            None => "No upcoming events\n".to_string(),
+        };
+        return Ok(text.into_response());
+    }
+
+    Ok(Json(NextResponse {
+        calendar: merged.id.clone(),
+        event: event.map(EventView::from),
+    })
+    .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct TodayParams {
+    #[serde(default)]
+    format: Format,
+    /// Most events to return as JSON
+    limit: Option<usize>,
+    /// Events to skip, for fetching later pages
+    offset: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct TodayResponse {
+    calendar: String,
+    date: String,
+    events: Vec<EventView>,
+    #[serde(flatten)]
+    page: Page,
+}
+
+/// Handler for GET /today/{id}
+async fn get_today(
+    Path(requested): Path<String>,
+    Query(params): Query<TodayParams>,
+    State(state): State<AppState>,
+    client: ClientInfo,
+) -> Result<Response, AppError> {
+    let merged = merge_requested(&state, &requested, &client).await?;
+    let today = chrono::Local::now().date_naive();
+    let events = query::events_on(merged.events(), today);
+
+    if params.format == Format::Txt {
+        if events.is_empty() {
+            return Ok("No events today\n".into_response());
+        }
+        let text: String = events
+            .iter()
+            .map(|event| {
+                format!(
+                    "{} {}\n",
+                    query::time_of_day(event),
+                    event.summary().unwrap_or("<no summary>")
+                )
+            })
+            .collect();
+        return Ok(text.into_response());
+    }
+
+    let (events, page) = paginate(events, params.offset, params.limit);
+    Ok(Json(TodayResponse {
+        calendar: merged.id.clone(),
+        date: today.format("%Y-%m-%d").to_string(),
+        events: events.into_iter().map(EventView::from).collect(),
+        page,
+    })
+    .into_response())
+}
+
+/// Output format for GET /agenda/{id}
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum AgendaFormat {
+    #[default]
+    Txt,
+    Md,
+}
+
+#[derive(Debug, Deserialize)]
+struct AgendaParams {
+    #[serde(default)]
+    format: AgendaFormat,
+    /// Days to cover starting from today
+    days: Option<u32>,
+}
+
+/// Default and largest number of days an agenda covers
+const DEFAULT_AGENDA_DAYS: u32 = 7;
+const MAX_AGENDA_DAYS: u32 = 366;
+
+/// Handler for GET /agenda/{id}
+async fn get_agenda(
+    Path(requested): Path<String>,
+    Query(params): Query<AgendaParams>,
+    State(state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let days = params
+        .days
+        .unwrap_or(DEFAULT_AGENDA_DAYS)
+        .clamp(1, MAX_AGENDA_DAYS);
+
+    let merged = merge_requested(&state, &requested, &client).await?;
+    let today = chrono::Local::now().date_naive();
+    let agenda = query::agenda(merged.events(), today, days);
+
+    // The client's languages come first, then the configured locale
+    let locale = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(query::preferred_locale)
+        .or_else(|| {
+            merged
+                .config
+                .output
+                .locale
+                .as_deref()
+                .and_then(query::parse_locale)
+        })
+        .unwrap_or(chrono::Locale::POSIX);
+
+    let (content_type, mut text) = match params.format {
+        AgendaFormat::Txt => (
+            "text/plain; charset=utf-8",
+            query::agenda_text(&agenda, locale),
+        ),
+        AgendaFormat::Md => (
+            "text/markdown; charset=utf-8",
+            query::agenda_markdown(&agenda, locale),
+        ),
+    };
+    if agenda.is_empty() {
+        text = format!(
+            "No events in the next {} day{}\n",
+            days,
+            if days == 1 { "" } else { "s" }
+        );
+    }
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::VARY, "accept-language"),
+        ],
+        text,
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct CsvParams {
+    /// Comma-separated columns, defaults to start, end, summary, location and source
+    columns: Option<String>,
+}
+
+/// Handler for GET /csv/{id}
+async fn get_csv(
+    Path(requested): Path<String>,
+    Query(params): Query<CsvParams>,
+    State(state): State<AppState>,
+    client: ClientInfo,
+) -> Result<Response, AppError> {
+    let columns = match &params.columns {
+        Some(list) => {
+            export::parse_columns(list).map_err(|e| AppError::BadRequest(e.to_string()))?
+        }
+        None => export::DEFAULT_CSV_COLUMNS.to_vec(),
+    };
+
+    let merged = merge_requested(&state, &requested, &client).await?;
+    // Rows for the events as the calendar is served now, as the export command writes
+    let events = merged.decorated(&state, merged.events(), None)?;
+    let csv = export::to_csv(&events, &columns);
+
+    // Calendar ids come from the config but keep odd ones out of the header anyway
+    let filename = if merged
+        .id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        format!("{}.csv", merged.id)
+    } else {
+        "calendar.csv".to_string()
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        csv,
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsParams {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// Default and longest number of days statistics cover
+const DEFAULT_STATS_DAYS: i64 = 30;
+const MAX_STATS_DAYS: i64 = 366;
+
+#[derive(Serialize)]
+struct StatsResponse {
+    calendar: String,
+    from: String,
+    to: String,
+    #[serde(flatten)]
+    stats: query::Stats,
+}
+
+/// Handler for GET /stats/{id}
+async fn get_stats(
+    Path(requested): Path<String>,
+    Query(params): Query<StatsParams>,
+    State(state): State<AppState>,
+    client: ClientInfo,
+) -> Result<Json<StatsResponse>, AppError> {
+    let bad_request = |e: Error| AppError::BadRequest(e.to_string());
+    let from = match params.from.as_deref() {
+        Some(from) => query::parse_time(from).map_err(bad_request)?,
+        None => chrono::Local::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .unwrap(),
+    };
+    let to = match params.to.as_deref() {
+        Some(to) => query::parse_time(to).map_err(bad_request)?,
+        None => from + chrono::Duration::days(DEFAULT_STATS_DAYS),
+    };
+    if to <= from {
+        return Err(AppError::BadRequest(
+            "'to' must be after 'from'".to_string(),
+        ));
+    }
+    if to - from > chrono::Duration::days(MAX_STATS_DAYS) {
+        return Err(AppError::BadRequest(format!(
+            "Statistics can cover at most {} days",
+            MAX_STATS_DAYS
+        )));
+    }
+
+    let merged = merge_requested(&state, &requested, &client).await?;
+
+    Ok(Json(StatsResponse {
+        calendar: merged.id.clone(),
+        from: from.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        to: to.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        stats: query::stats(merged.events(), from, to),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ConflictsParams {
+    from: Option<String>,
+    to: Option<String>,
+    /// Most conflicts to return
+    limit: Option<usize>,
+    /// Conflicts to skip, for fetching later pages
+    offset: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct ConflictsResponse {
+    calendar: String,
+    conflicts: Vec<conflicts::Conflict>,
+    #[serde(flatten)]
+    page: Page,
+}
+
+/// Handler for GET /conflicts/{id}
+async fn get_conflicts(
+    Path(requested): Path<String>,
+    Query(params): Query<ConflictsParams>,
+    State(state): State<AppState>,
+    client: ClientInfo,
+) -> Result<Json<ConflictsResponse>, AppError> {
+    let bad_request = |e: Error| AppError::BadRequest(e.to_string());
+    let from = params
+        .from
+        .as_deref()
+        .map(query::parse_time)
+        .transpose()
+        .map_err(bad_request)?;
+    let to = params
+        .to
+        .as_deref()
+        .map(query::parse_time)
+        .transpose()
+        .map_err(bad_request)?;
+
+    let merged = merge_requested(&state, &requested, &client).await?;
+    let events: Vec<Event> = merged
+        .events()
+        .iter()
+        .filter(|event| query::in_range(event, from, to))
+        .cloned()
+        .collect();
+
+    let (conflicts, page) = paginate(
+        conflicts::find_conflicts(&events),
+        params.offset,
+        params.limit,
+    );
+    Ok(Json(ConflictsResponse {
+        calendar: merged.id.clone(),
+        conflicts,
+        page,
+    }))
+}
+
+/// Build an all-day event for today telling subscribers the calendar may be incomplete
+fn create_warning_event(calendar_id: &str, errors: usize, stale: bool) -> Event {
+    let today = chrono::Utc::now().date_naive();
+
+    let mut description = Vec::new();
+    if errors > 0 {
+        description.push(format!(
+            "{} source{} of this calendar could not be fetched, events may be missing.",
+            errors,
+            if errors == 1 { "" } else { "s" }
+        ));
+    }
+    if stale {
+        description.push("Events are from an earlier successful update.".to_string());
+    }
+
+    let event = icalendar::Event::new()
+        .uid(&format!("warning-{}@ical-merge", calendar_id))
+        .summary("⚠ Some sources failed")
+        .description(&description.join(" "))
+        .all_day(today)
+        .add_property("TRANSP", "TRANSPARENT")
+        .done();
+    Event::new(event)
+}
+
+/// Response body for GET /status
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    /// Every configured calendar, `null` until it has been merged
+    calendars: BTreeMap<String, Option<CalendarMetrics>>,
+    /// What the merge cache is holding
+    cache: CacheStats,
+}
+
+/// Handler for GET /status
+async fn get_status(State(state): State<AppState>) -> Json<StatusResponse> {
+    let ids: Vec<String> = {
+        let config = state.config.read().unwrap();
+        config.calendars.keys().cloned().collect()
+    };
+
+    let mut metrics = state.metrics.calendars();
+    let calendars = ids
+        .into_iter()
+        .map(|id| {
+            let calendar = metrics.remove(&id);
+            (id, calendar)
+        })
+        .collect();
+
+    Json(StatusResponse {
+        calendars,
+        cache: state.cache.stats(),
+    })
+}
+
+/// Check the request carries the admin token, the admin endpoints are hidden when none is configured
+fn authorize_admin(state: &AppState, headers: &HeaderMap) -> Result<(), AppError> {
+    let token = state.config.read().unwrap().server.admin_token.clone();
+    let Some(token) = token else {
+        return Err(AppError::NotFound("Not found".to_string()));
+    };
+
+    authorize_bearer(headers, &[&token])
+}
+
+/// Check the request's bearer token is one of `tokens`
+fn authorize_bearer(headers: &HeaderMap, tokens: &[&str]) -> Result<(), AppError> {
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match provided {
+        Some(provided)
+            if tokens
+                .iter()
+                .any(|token| constant_time_eq(provided.as_bytes(), token.as_bytes())) =>
+        {
+            Ok(())
+        }
+        _ => Err(AppError::Unauthorized),
+    }
+}
+
+#[derive(Serialize)]
+struct RefreshResponse {
+    calendar: String,
+    events: usize,
+    /// Sources that failed in the new merge
+    errors: usize,
+    /// Calendars including this one whose cached merges were dropped
+    invalidated: Vec<String>,
+}
+
+/// Handler for POST /hooks/refresh/{id}, for upstream systems to say a calendar
+/// changed. Takes the calendar's `refresh_token` or the admin token, and is hidden
+/// for calendars without either
+async fn post_refresh_hook(
+    Path(requested): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<RefreshResponse>, AppError> {
+    let not_found = || AppError::NotFound(format!("Calendar '{}' not found", requested));
+    let (id, config) = {
+        let config = state.config.read().unwrap();
+        let id = config.resolve_calendar(&requested).ok_or_else(not_found)?;
+        (id.to_string(), config.clone())
+    };
+
+    let tokens: Vec<&str> = [
+        config.calendars[&id].refresh_token.as_deref(),
+        config.server.admin_token.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if tokens.is_empty() {
+        return Err(not_found());
+    }
+    authorize_bearer(&headers, &tokens)?;
+
+    tracing::info!("Refreshing '{}' on request from its upstream", id);
+    let (result, invalidated) = state.refresh(&id, config).await?;
+    Ok(Json(RefreshResponse {
+        calendar: id,
+        events: result.events.len(),
+        errors: result.errors.len(),
+        invalidated,
+    }))
+}
+
+/// Compare secrets without leaking how much of them matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Handler for GET /admin/log-level
+async fn get_log_level(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<String, AppError> {
+    authorize_admin(&state, &headers)?;
+
+    let log_filter = state
+        .log_filter
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("Log filter can't be changed at runtime".to_string()))?;
+    Ok(log_filter.current())
+}
+
+/// Handler for PUT /admin/log-level, the body holds the new filter directives
+async fn put_log_level(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<String, AppError> {
+    authorize_admin(&state, &headers)?;
+
+    let log_filter = state
+        .log_filter
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("Log filter can't be changed at runtime".to_string()))?;
+    log_filter
+        .set(body.trim())
+        .map_err(|e| AppError::BadRequest(e.to_string()))?;
+    Ok(log_filter.current())
+}
+
+/// Handler for GET /healthz
+async fn get_healthz() -> &'static str {
+    "ok"
+}
+
+/// Handler for GET /readyz
+async fn get_readyz(State(state): State<AppState>) -> Response {
+    let failing = state.supervisor.failing();
+    if !failing.is_empty() {
+        let message = format!("restarting failed tasks: {}", failing.join(", "));
+        (StatusCode::SERVICE_UNAVAILABLE, message).into_response()
+    } else if state.is_ready() {
+        "ready".into_response()
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "warming up").into_response()
+    }
+}
+
+/// Handler for GET /metrics
+async fn get_metrics(State(state): State<AppState>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render_prometheus() + &render_cache(&state.cache.stats()),
+    )
+        .into_response()
+}
+
+/// Work out the server's base URL, preferring the configured base URL over request headers
+fn base_url(base_url: Option<&str>, headers: &HeaderMap) -> String {
+    if let Some(base_url) = base_url {
+        return base_url.trim_end_matches('/').to_string();
+    }
+
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let scheme = header("x-forwarded-proto").unwrap_or("http");
+    let host = header("x-forwarded-host")
+        .or_else(|| header(header::HOST.as_str()))
+        .unwrap_or("localhost");
+    format!("{}://{}", scheme, host)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Handler for GET /subscribe/{id}
+async fn get_subscribe(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+) -> Result<Html<String>, AppError> {
+    // Links keep using the id requested so secret slugs aren't revealed
+    let server = {
+        let config = state.config.read().unwrap();
+        if config.resolve_calendar(&id).is_none() {
+            return Err(AppError::NotFound(format!("Calendar '{}' not found", id)));
+        }
+        config.server.clone()
+    };
+
+    let base = base_url(server.base_url.as_deref(), &headers);
+    let mut https_url = format!("{}{}/ical/{}", base, server.path_prefix(), id);
+    // A tenant's token given in the URL is needed by the calendar app too
+    if let Some(token) = query.token
+        && let Ok(mut url) = reqwest::Url::parse(&https_url)
+    {
+        url.query_pairs_mut().append_pair("token", &token);
+        https_url = url.to_string();
+    }
+    let webcal_url = match https_url.split_once("://") {
+        Some((_, rest)) => format!("webcal://{}", rest),
+        None => https_url.clone(),
+    };
+    let qr = QrCode::encode(&webcal_url)
+        .map(|qr| qr.to_svg())
+        .unwrap_or_default();
+
+    let id = escape_html(&id);
+    let https_url = escape_html(&https_url);
+    let webcal_url = escape_html(&webcal_url);
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Subscribe to {id}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 32rem; margin: 2rem auto; padding: 0 1rem; }}
+input {{ width: 100%; font-family: monospace; padding: 0.25rem; }}
+.qr {{ width: 16rem; margin: 1rem 0; }}
+</style>
+</head>
+<body>
+<h1>Subscribe to {id}</h1>
+<p><a href="{webcal_url}">Open in your calendar app</a></p>
+<p><label>Calendar app link<br><input readonly value="{webcal_url}" onclick="this.select()"></label></p>
+<p><label>Web link<br><input readonly value="{https_url}" onclick="this.select()"></label></p>
+<div class="qr">{qr}</div>
+</body>
+</html>
+"#
+    )))
+}
+
+/// Application error type
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    BadRequest(String),
+    Unauthorized,
+    /// Only data older than the calendar's `max_stale` is available
+    Stale(Duration),
+    Internal(Arc<crate::error::Error>),
+}
+
+impl From<crate::error::Error> for AppError {
+    fn from(err: crate::error::Error) -> Self {
+        AppError::Internal(Arc::new(err))
+    }
+}
+
+impl From<Arc<crate::error::Error>> for AppError {
+    fn from(err: Arc<crate::error::Error>) -> Self {
+        AppError::Internal(err)
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
+            AppError::Stale(age) => {
+                let message = format!(
+                    "Calendar could not be refreshed and its last merge is {} seconds old",
+                    age.as_secs()
+                );
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [("x-ical-merge-age", age.as_secs().to_string())],
+                    message,
+                )
+                    .into_response();
+            }
+            AppError::Internal(err) => {
+                tracing::error!("Internal error: {}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal server error".to_string(),
+                )
+            }
+        };
+
+        (status, message).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        CalendarConfig, DefaultAction, FilterAction, MatchMode, SourceConfig, Step,
+    };
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use std::collections::HashMap;
+    use tower::util::ServiceExt;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const SAMPLE_ICAL: &str = r#"BEGIN:VCALENDAR
+VERSION:2.0
+PRODID:-//Test//Test//EN
+BEGIN:VEVENT
+UID:test@example.com
+DTSTAMP:20231201T120000Z
+DTSTART:20231201T140000Z
+DTEND:20231201T150000Z
+SUMMARY:Test Event
+END:VEVENT
+END:VCALENDAR"#;
+
+    #[tokio::test]
+    async fn test_get_calendar_endpoint() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/test.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test-calendar".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/test.ics", mock_server.uri()),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+
+        let fetcher = Fetcher::new().unwrap();
+        let config_path = std::env::temp_dir().join("test-config.json");
+        let state = AppState::new(config, config_path, fetcher);
+        let app = create_router(state);
+
+        let request = Request::builder()
+            .uri("/ical/test-calendar")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "text/calendar; charset=utf-8"
+        );
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body_str = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body_str.contains("Test Event"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_calendar_returns_404() {
+        let config = Config {
+            calendars: HashMap::new(),
+            sources: HashMap::new(),
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+
+        let fetcher = Fetcher::new().unwrap();
+        let config_path = std::env::temp_dir().join("test-config.json");
+        let state = AppState::new(config, config_path, fetcher);
+        let app = create_router(state);
+
+        let request = Request::builder()
+            .uri("/ical/nonexistent")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_reload_rebuilds_fetcher() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/private.ics"))
+            .and(header("authorization", "Bearer secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "calendars": {
+                "private": { "sources": [{ "url": format!("{}/private.ics", mock_server.uri()) }] }
+            }
+        }))
+        .unwrap();
+        let config_path =
+            std::env::temp_dir().join(format!("test-reload-fetcher-{}.json", std::process::id()));
+        std::fs::write(&config_path, serde_json::to_string(&config).unwrap()).unwrap();
+
+        let state = AppState::new(config.clone(), config_path.clone(), Fetcher::new().unwrap());
+        let app = create_router(state.clone());
+        let request = || {
+            Request::builder()
+                .uri("/ical/private")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.headers()["x-ical-merge-errors"], "1");
+
+        config.fetcher.headers =
+            [("Authorization".to_string(), "Bearer secret".to_string())].into();
+        std::fs::write(&config_path, serde_json::to_string(&config).unwrap()).unwrap();
+        state.reload_config().unwrap();
+
+        let response = app.oneshot(request()).await.unwrap();
+        assert!(response.headers().get("x-ical-merge-errors").is_none());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Test Event"));
+
+        let _ = std::fs::remove_file(&config_path);
+    }
+
+    #[tokio::test]
+    async fn test_reload_recompiles_steps() {
+        let rename = |to: &str| {
+            serde_json::json!({
+                "calendars": {
+                    "work": {
+                        "sources": [{ "ics": SAMPLE_ICAL }],
+                        "steps": [{ "type": "replace", "pattern": "Test", "replacement": to }]
+                    }
+                }
+            })
+        };
+        let config: Config = serde_json::from_value(rename("Trial")).unwrap();
+        let config_path =
+            std::env::temp_dir().join(format!("test-reload-steps-{}.json", std::process::id()));
+        std::fs::write(&config_path, serde_json::to_string(&config).unwrap()).unwrap();
+
+        let state = AppState::new(config.clone(), config_path.clone(), Fetcher::new().unwrap());
+        assert!(state.pipelines(&config).is_for(&config));
+        let app = create_router(state.clone());
+        let summary = |app: Router| async move {
+            let request = Request::builder()
+                .uri("/ical/work")
+                .body(Body::empty())
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+        assert!(summary(app.clone()).await.contains("SUMMARY:Trial Event"));
+
+        std::fs::write(&config_path, rename("Exam").to_string()).unwrap();
+        state.reload_config().unwrap();
+        assert!(summary(app).await.contains("SUMMARY:Exam Event"));
+
+        // A merge still running with the old config gets pipelines that match it
+        assert!(state.pipelines(&config).is_for(&config));
+
+        let _ = std::fs::remove_file(&config_path);
+    }
+
+    #[tokio::test]
+    async fn test_get_event_endpoint() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/test.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test-calendar".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/test.ics", mock_server.uri()),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+
+        let app = create_router(AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        ));
+
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(get("/ical/test-calendar/event/test%40example.com.ics"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/calendar; charset=utf-8"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("BEGIN:VCALENDAR"));
+        assert!(body.contains("UID:test@example.com"));
+        assert_eq!(body.matches("BEGIN:VEVENT").count(), 1);
+
+        for uri in [
+            "/ical/test-calendar/event/missing@example.com.ics",
+            "/ical/test-calendar/event/test@example.com",
+            "/ical/missing/event/test@example.com.ics",
+        ] {
+            let response = app.clone().oneshot(get(uri)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_calendar_variants() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/test.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        let variants = [
+            (
+                "redacted".to_string(),
+                vec![Step::Replace {
+                    pattern: ".+".to_string(),
+                    replacement: "Busy".to_string(),
+                    field: "summary".to_string(),
+                    literal: false,
+                }],
+            ),
+            (
+                "none".to_string(),
+                vec![Step::Deny {
+                    patterns: vec![".*".to_string()],
+                    mode: Default::default(),
+                    fields: vec!["summary".to_string()],
+                    action: Default::default(),
+                    unless: vec![],
+                    literal: false,
+                }],
+            ),
+        ];
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test-calendar".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/test.ics", mock_server.uri()),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: variants.into(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        config.validate().unwrap();
+
+        let mut invalid = config.clone();
+        let calendar = invalid.calendars.get_mut("test-calendar").unwrap();
+        calendar.variants.insert("no details".to_string(), vec![]);
+        assert!(invalid.validate().is_err());
+
+        let app = create_router(AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        ));
+        let get = |uri: &str| {
+            let app = app.clone();
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        let (status, body) = get("/ical/test-calendar").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("SUMMARY:Test Event"));
+
+        let (status, body) = get("/ical/test-calendar?variant=redacted").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("SUMMARY:Busy"));
+        assert!(!body.contains("Test Event"));
+
+        // The full calendar is still served after a variant was
+        let (_, body) = get("/ical/test-calendar").await;
+        assert!(body.contains("SUMMARY:Test Event"));
+
+        let (status, body) =
+            get("/ical/test-calendar/event/test@example.com.ics?variant=redacted").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("SUMMARY:Busy"));
+
+        let (status, _) = get("/ical/test-calendar/event/test@example.com.ics?variant=none").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = get("/ical/test-calendar?variant=missing").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("no variant 'missing'"));
+    }
+
+    #[tokio::test]
+    async fn test_variants_use_calendar_default() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/test.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        let allow = |pattern: &str| Step::Allow {
+            patterns: vec![pattern.to_string()],
+            mode: Default::default(),
+            fields: vec!["summary".to_string()],
+            action: Default::default(),
+            unless: vec![],
+            literal: false,
+        };
+        let redact = Step::Replace {
+            pattern: ".+".to_string(),
+            replacement: "Busy".to_string(),
+            field: "summary".to_string(),
+            literal: false,
+        };
+        let variants = [
+            ("redacted".to_string(), vec![redact.clone()]),
+            ("tests".to_string(), vec![allow("^Test"), redact]),
+        ];
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test-calendar".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/test.ics", mock_server.uri()),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![allow("Event")],
+                default: DefaultAction::Deny,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: variants.into(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        let app = create_router(AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        ));
+        let get = |uri: &str| {
+            let app = app.clone();
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        let body = get("/ical/test-calendar").await;
+        assert!(body.contains("SUMMARY:Test Event"));
+
+        // Denied by default, the variant's steps have to allow events again
+        let body = get("/ical/test-calendar?variant=redacted").await;
+        assert!(!body.contains("BEGIN:VEVENT"));
+
+        let body = get("/ical/test-calendar?variant=tests").await;
+        assert!(body.contains("SUMMARY:Busy"));
+        assert!(!body.contains("Test Event"));
+    }
+
+    #[tokio::test]
+    async fn test_search_endpoint() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/test.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test-calendar".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/test.ics", mock_server.uri()),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+
+        let app = create_router(AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        ));
+
+        let search = |query: &str| {
+            let app = app.clone();
+            let request = Request::builder()
+                .uri(format!("/search/test-calendar?{}", query))
+                .body(Body::empty())
+                .unwrap();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, serde_json::from_slice(&body).unwrap_or_default())
+            }
+        };
+
+        let (status, json): (_, serde_json::Value) = search("q=test+event").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["calendar"], "test-calendar");
+        assert_eq!(json["events"][0]["summary"], "Test Event");
+        assert_eq!(json["events"][0]["start"], "2023-12-01T14:00:00");
+        assert_eq!(json["total"], 1);
+        assert_eq!(json["next_offset"], serde_json::Value::Null);
+
+        let (_, json) = search("q=test&offset=1").await;
+        assert_eq!(json["events"].as_array().unwrap().len(), 0);
+        assert_eq!(json["total"], 1);
+        assert_eq!(json["offset"], 1);
+
+        let (_, json) = search("q=test&from=2024-01-01").await;
+        assert_eq!(json["events"].as_array().unwrap().len(), 0);
+
+        let (_, json) = search("q=%5Etest&regex=true&to=2024-01-01").await;
+        assert_eq!(json["events"].as_array().unwrap().len(), 1);
+
+        let (status, _) = search("q=(&regex=true").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = search("q=test&from=soon").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_next_today_agenda_csv_and_stats_endpoints() {
+        let today = chrono::Local::now().date_naive();
+        let tomorrow = today.succ_opt().unwrap();
+        let ical = format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Test//Test//EN\r\n\
+             BEGIN:VEVENT\r\nUID:today@example.com\r\nDTSTAMP:20231201T120000Z\r\n\
+             DTSTART;VALUE=DATE:{}\r\nSUMMARY:Bin day\r\nEND:VEVENT\r\n\
+             BEGIN:VEVENT\r\nUID:tomorrow@example.com\r\nDTSTAMP:20231201T120000Z\r\n\
+             DTSTART:{}T090000\r\nDTEND:{}T100000\r\nSUMMARY:Dentist\r\nEND:VEVENT\r\n\
+             END:VCALENDAR\r\n",
+            today.format("%Y%m%d"),
+            tomorrow.format("%Y%m%d"),
+            tomorrow.format("%Y%m%d"),
+        );
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/test.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(ical))
+            .mount(&mock_server)
+            .await;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "home".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/test.ics", mock_server.uri()),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+
+        let app = create_router(AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        ));
+
+        let get = |uri: &str| {
+            let app = app.clone();
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&get("/next/home").await).unwrap();
+        assert_eq!(json["event"]["summary"], "Dentist");
+        assert_eq!(
+            get("/next/home?format=txt").await,
+            format!("{} 09:00 Dentist\n", tomorrow.format("%Y-%m-%d"))
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&get("/today/home").await).unwrap();
+        assert_eq!(json["date"], today.format("%Y-%m-%d").to_string());
+        assert_eq!(json["events"].as_array().unwrap().len(), 1);
+        assert_eq!(json["events"][0]["summary"], "Bin day");
+        assert_eq!(get("/today/home?format=txt").await, "All day Bin day\n");
+
+        let agenda = get("/agenda/home?days=2").await;
+        assert!(agenda.contains(&today.format("%A %-d %B %Y").to_string()));
+        assert!(agenda.contains("  09:00-10:00 Dentist\n"));
+        let agenda = get("/agenda/home?format=md&days=1").await;
+        assert!(agenda.starts_with("## "));
+        assert!(agenda.contains("- **All day** Bin day"));
+        assert!(!agenda.contains("Dentist"));
+
+        // Day names follow the client's language
+        let request = Request::builder()
+            .uri("/agenda/home?days=1")
+            .header("accept-language", "fr-FR,fr;q=0.9,en;q=0.8")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["vary"], "accept-language");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(
+            String::from_utf8(body.to_vec()).unwrap().starts_with(
+                &today
+                    .format_localized("%A %-d %B %Y", chrono::Locale::fr_FR)
+                    .to_string()
+            )
+        );
+
+        let csv = get("/csv/home?columns=summary,start").await;
+        assert_eq!(
+            csv,
+            format!(
+                "summary,start\r\nBin day,{}\r\nDentist,{} 09:00:00\r\n",
+                today.format("%Y-%m-%d"),
+                tomorrow.format("%Y-%m-%d")
+            )
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&get("/stats/home").await).unwrap();
+        assert_eq!(json["events"], 2);
+        assert_eq!(json["all_day_events"], 1);
+        assert_eq!(json["busy_hours"], 1.0);
+        assert_eq!(
+            json["busiest_day"]["date"],
+            tomorrow.format("%Y-%m-%d").to_string()
+        );
+
+        let request = Request::builder()
+            .uri("/stats/home?from=2025-03-01&to=2025-02-01")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_calendar_alias() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/test.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "family".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/test.ics", mock_server.uri()),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec!["famille".to_string()],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+
+        let state = AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        );
+        let app = create_router(state.clone());
+
+        let request = Request::builder()
+            .uri("/ical/famille")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Test Event"));
+
+        // Merges through an alias count towards the calendar itself
+        assert_eq!(state.metrics.calendars()["family"].merges, 1);
+
+        let request = Request::builder()
+            .uri("/subscribe/famille")
+            .header("host", "calendar.local")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("webcal://calendar.local/ical/famille"));
+    }
+
+    #[tokio::test]
+    async fn test_admin_log_level() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "work".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: "https://example.com/work.ics".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let mut config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+
+        let (layer, log_filter) = LogFilter::new("ical_merge=info").unwrap();
+        let _subscriber = tracing_subscriber::registry().with(layer);
+
+        let put = |token: &str, body: &str| {
+            Request::builder()
+                .method("PUT")
+                .uri("/admin/log-level")
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        // Hidden without an admin token
+        let app = create_router(
+            AppState::new(
+                config.clone(),
+                std::env::temp_dir().join("test-config.json"),
+                Fetcher::new().unwrap(),
+            )
+            .with_log_filter(log_filter.clone()),
+        );
+        let response = app.oneshot(put("", "debug")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        config.server.admin_token = Some("secret".to_string());
+        let app = create_router(
+            AppState::new(
+                config,
+                std::env::temp_dir().join("test-config.json"),
+                Fetcher::new().unwrap(),
+            )
+            .with_log_filter(log_filter.clone()),
+        );
+
+        let response = app.clone().oneshot(put("wrong", "debug")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(log_filter.current(), "ical_merge=info");
+
+        let response = app
+            .clone()
+            .oneshot(put("secret", "ical_merge::fetcher=debug\n"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(log_filter.current(), "ical_merge::fetcher=debug");
+
+        let response = app
+            .clone()
+            .oneshot(put("secret", "ical_merge=nonsense"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let request = Request::builder()
+            .uri("/admin/log-level")
+            .header("authorization", "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"ical_merge::fetcher=debug");
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/test.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .mount(&mock_server)
+            .await;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "family".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/test.ics", mock_server.uri()),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec!["x7kq2p".to_string()],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let log_path =
+            std::env::temp_dir().join(format!("audit-server-test-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&log_path);
+
+        let mut config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        config.server.audit_log = Some(crate::config::AuditLogConfig {
+            path: log_path.clone(),
+            max_size: None,
+            max_files: None,
+        });
+
+        let app = create_router(AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        ));
+
+        let request = Request::builder()
+            .uri("/ical/x7kq2p")
+            .header("x-forwarded-for", "198.51.100.7, 10.0.0.1")
+            .header("user-agent", "Calendar/1.0")
+            .extension(ConnectInfo(SocketAddr::from(([192, 0, 2, 10], 51234))))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Unknown calendars aren't recorded
+        let request = Request::builder()
+            .uri("/ical/missing")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        let entries: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["calendar"], "family");
+        assert_eq!(entries[0]["requested"], "x7kq2p");
+        // The header is logged as sent, never in place of the peer's address
+        assert_eq!(entries[0]["ip"], "192.0.2.10");
+        assert_eq!(entries[0]["forwarded_for"], "198.51.100.7, 10.0.0.1");
+        assert_eq!(entries[0]["user_agent"], "Calendar/1.0");
+
+        std::fs::remove_file(&log_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_endpoint() {
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "work".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: "https://example.com/work.ics".to_string(),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let mut config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+
+        // Without a public URL the request's Host header is used
+        let app = create_router(AppState::new(
+            config.clone(),
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        ));
+        let request = Request::builder()
+            .uri("/subscribe/work")
+            .header("host", "calendar.local:8080")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("webcal://calendar.local:8080/ical/work"));
+        assert!(body.contains("http://calendar.local:8080/ical/work"));
+        assert!(body.contains("<svg"));
+
+        let request = Request::builder()
+            .uri("/subscribe/missing")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        config.server.base_url = Some("https://cal.example.com/".to_string());
+        config.server.path_prefix = Some("/calendars/".to_string());
+        let app = create_router(AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        ));
+        let request = Request::builder()
+            .uri("/calendars/subscribe/work")
+            .header("host", "internal:8080")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("webcal://cal.example.com/calendars/ical/work"));
+        assert!(body.contains("https://cal.example.com/calendars/ical/work"));
+        assert!(!body.contains("internal:8080"));
+
+        // Routes are only served under the prefix
+        let request = Request::builder()
+            .uri("/subscribe/work")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_route_table() {
+        let mut config = Config {
+            sources: HashMap::new(),
+            calendars: HashMap::new(),
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        config.server.path_prefix = Some("calendars/".to_string());
+
+        let routes = route_table(&config);
+        assert_eq!(routes[0], ("GET", "/calendars/ical/{id}".to_string()));
+        assert!(routes.contains(&("GET, PUT", "/calendars/admin/log-level".to_string())));
+        assert_eq!(routes.len(), super::routes().len());
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/slow.ics"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(SAMPLE_ICAL)
+                    .set_delay(std::time::Duration::from_secs(5)),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "slow".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/slow.ics", mock_server.uri()),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let mut config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        config.server.request_timeout = Some(1);
+
+        let app = create_router(AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        ));
+        let request = Request::builder()
+            .uri("/ical/slow")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(body.to_vec()).unwrap(),
+            "Request timed out after 1s waiting for upstream calendars"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_share_merge() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/test.ics"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(SAMPLE_ICAL)
+                    .set_delay(std::time::Duration::from_millis(200)),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test-calendar".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/test.ics", mock_server.uri()),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        let state = AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        );
+        let app = create_router(state.clone());
+
+        let requests = (0..10).map(|_| {
+            let request = Request::builder()
+                .uri("/ical/test-calendar")
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        });
+        for response in futures::future::join_all(requests).await {
+            let response = response.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert!(
+                String::from_utf8(body.to_vec())
+                    .unwrap()
+                    .contains("Test Event")
+            );
+        }
+
+        // Only one merge actually ran
+        assert_eq!(state.metrics.calendars()["test-calendar"].merges, 1);
+    }
+
+    #[tokio::test]
+    async fn test_warm_up_and_cache() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/test.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test-calendar".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/test.ics", mock_server.uri()),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+
+        let mut config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        config.server.cache_ttl = Some(300);
+        config.server.warmup = crate::config::WarmupConfig::All(true);
+
+        let state = AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        );
+        let app = create_router(state.clone());
+
+        let readyz = || {
+            Request::builder()
+                .uri("/readyz")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = app.clone().oneshot(readyz()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        state.warm_up().await;
+        let response = app.clone().oneshot(readyz()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(state.cache.stats().entries, 1);
+
+        // Served from the cache without fetching the upstream again
+        let request = Request::builder()
+            .uri("/ical/test-calendar")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(state.metrics.calendars()["test-calendar"].merges, 1);
+    }
+
+    #[tokio::test]
+    async fn test_readyz_reports_failed_tasks() {
+        let config = Config {
+            sources: HashMap::new(),
+            calendars: HashMap::new(),
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        let state = AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        );
+        let app = create_router(state.clone());
+
+        state.supervisor.spawn("broken", || async {
+            Err(crate::error::Error::Config("broken".to_string()))
+        });
+        while state.supervisor.failing().is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let request = Request::builder()
+            .uri("/readyz")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"restarting failed tasks: broken");
+    }
+
+    #[tokio::test]
+    async fn test_failed_sources_flagged() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/test.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let mut calendars = HashMap::new();
+        calendars.insert(
+            "test-calendar".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/test.ics", mock_server.uri()),
+                    steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: true,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        calendars.insert(
+            "partial".to_string(),
+            CalendarConfig {
+                sources: vec![
+                    SourceConfig::Url {
+                        url: format!("{}/test.ics", mock_server.uri()),
+                        steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                        username: None,
+                        password: None,
+                        auth: None,
+                        timeout: None,
+                        client_cert: None,
+                        client_key: None,
+                        insecure_tls: false,
+                    },
+                    SourceConfig::Url {
+                        url: format!("{}/missing.ics", mock_server.uri()),
+                        steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                        username: None,
+                        password: None,
+                        auth: None,
+                        timeout: None,
+                        client_cert: None,
+                        client_key: None,
+                        insecure_tls: false,
+                    },
+                ],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
 
-/// Application state shared across handlers
-#[derive(Clone)]
-pub struct AppState {
-    pub config: Arc<RwLock<Config>>,
-    pub config_path: Arc<PathBuf>,
-    pub fetcher: Arc<Fetcher>,
-}
+        let mut config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
+        config.server.cache_ttl = Some(1);
 
-impl AppState {
-    pub fn new(config: Config, config_path: PathBuf, fetcher: Fetcher) -> Self {
-        Self {
-            config: Arc::new(RwLock::new(config)),
-            config_path: Arc::new(config_path),
-            fetcher: Arc::new(fetcher),
-        }
+        let state = AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        );
+        let app = create_router(state);
+
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        // A healthy merge carries no indicators
+        let response = app
+            .clone()
+            .oneshot(get("/ical/test-calendar"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("x-ical-merge-stale").is_none());
+        assert!(response.headers().get("x-ical-merge-errors").is_none());
+
+        // Once the cache expires and the upstream fails the old merge is served
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let response = app
+            .clone()
+            .oneshot(get("/ical/test-calendar"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-ical-merge-stale"], "true");
+        assert_eq!(response.headers()["x-ical-merge-errors"], "1");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("Test Event"));
+        assert!(body.contains("⚠ Some sources failed"));
+        assert!(body.contains("UID:warning-test-calendar@ical-merge"));
+        assert!(!body.contains("/test.ics"));
+
+        // Failures are counted without a warning event unless it is enabled
+        let response = app.oneshot(get("/ical/partial")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("x-ical-merge-stale").is_none());
+        assert_eq!(response.headers()["x-ical-merge-errors"], "2");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("Some sources failed"));
     }
 
-    /// Reload configuration from file
-    pub fn reload_config(&self) -> crate::error::Result<()> {
-        tracing::info!("Reloading configuration from {:?}", self.config_path);
+    #[tokio::test]
+    async fn test_hide_ended_after() {
+        let mock_server = MockServer::start().await;
 
-        // Load and validate new config
-        let new_config = Config::load(&*self.config_path)?;
-        new_config.validate()?;
+        let ended = (chrono::Utc::now() - chrono::Duration::days(3)).format("%Y%m%dT%H%M%SZ");
+        let old = (chrono::Utc::now() - chrono::Duration::days(30)).format("%Y%m%dT%H%M%SZ");
+        let ical = format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n\
+             BEGIN:VEVENT\r\nUID:recent@example.com\r\nDTSTART:{ended}\r\nSUMMARY:Recent\r\nEND:VEVENT\r\n\
+             BEGIN:VEVENT\r\nUID:old@example.com\r\nDTSTART:{old}\r\nSUMMARY:Old\r\nEND:VEVENT\r\n\
+             END:VCALENDAR\r\n"
+        );
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(ical))
+            .mount(&mock_server)
+            .await;
 
-        // Swap in new config
-        let mut config = self.config.write().unwrap();
-        *config = new_config;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "calendars": {
+                "watch": {
+                    "sources": [{ "url": format!("{}/team.ics", mock_server.uri()) }],
+                    "hide_ended_after": 7
+                },
+                "all": { "sources": [{ "calendar": "watch" }] }
+            }
+        }))
+        .unwrap();
 
-        tracing::info!("Configuration reloaded successfully");
-        Ok(())
+        let state = AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        );
+        let app = create_router(state);
+        let get = |uri: &str| {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        let body = get("/ical/watch").await;
+        assert!(body.contains("SUMMARY:Recent"));
+        assert!(!body.contains("SUMMARY:Old"));
+
+        // Hidden when served but still merged into calendars including it
+        let body = get("/ical/all").await;
+        assert!(body.contains("SUMMARY:Recent"));
+        assert!(body.contains("SUMMARY:Old"));
     }
-}
 
-/// Create the router with all routes
-pub fn create_router(state: AppState) -> Router {
-    Router::new()
-        .route("/ical/{id}", get(get_calendar))
-        .with_state(state)
-}
+    #[tokio::test]
+    async fn test_refresh_hook() {
+        let mock_server = MockServer::start().await;
 
-/// Handler for GET /ical/{id}
-async fn get_calendar(
-    Path(id): Path<String>,
-    State(state): State<AppState>,
-) -> Result<Response, AppError> {
-    // Acquire read lock and clone the full config
-    let config = {
-        let config_guard = state.config.read().unwrap();
-        // Verify calendar exists
-        if !config_guard.calendars.contains_key(&id) {
-            return Err(AppError::NotFound(format!("Calendar '{}' not found", id)));
-        }
-        config_guard.clone()
-    };
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(SAMPLE_ICAL.replace("Test Event", "Moved Event")),
+            )
+            .mount(&mock_server)
+            .await;
 
-    // Merge calendars (lock is released here)
-    let merge_result = merge_calendars(&id, &config, &state.fetcher).await?;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "server": { "cache_ttl": 300 },
+            "calendars": {
+                "rota": {
+                    "sources": [{ "url": format!("{}/rota.ics", mock_server.uri()) }],
+                    "refresh_token": "s3cret"
+                },
+                "all": { "sources": [{ "calendar": "rota" }] }
+            }
+        }))
+        .unwrap();
+        config.validate().unwrap();
+
+        let app = create_router(AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        ));
+        let get = |uri: &str| {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+        let refresh = |id: &str, token: Option<&str>| {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri(format!("/hooks/refresh/{}", id));
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        // Merging "all" caches "rota" too
+        assert!(get("/ical/all").await.contains("Test Event"));
+        assert!(get("/ical/rota").await.contains("Test Event"));
 
-    // Log any errors but still serve partial data
-    for (url, err) in &merge_result.errors {
-        tracing::error!("Failed to fetch calendar from {}: {}", url, err);
+        let response = refresh("rota", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = refresh("rota", Some("wrong")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        // Calendars without a token don't have a hook
+        let response = refresh("all", Some("s3cret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = refresh("rota", Some("s3cret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["events"], 1);
+        assert_eq!(json["invalidated"], serde_json::json!(["all"]));
+
+        // Both calendars show the change without waiting for the cache to expire
+        assert!(get("/ical/rota").await.contains("Moved Event"));
+        assert!(get("/ical/all").await.contains("Moved Event"));
     }
 
-    // Serialize to iCal format
-    let ical_text = serialize_events(merge_result.events);
+    #[tokio::test]
+    async fn test_referenced_calendars_read_through_cache() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/rota.ics"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
 
-    // Return with proper content type
-    Ok((
-        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
-        ical_text,
-    )
-        .into_response())
-}
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "server": { "cache_ttl": 300 },
+            "calendars": {
+                "rota": { "sources": [{ "url": format!("{}/rota.ics", mock_server.uri()) }] },
+                "team": { "sources": [{ "calendar": "rota" }] },
+                "all": { "sources": [{ "calendar": "team" }, { "calendar": "rota" }] }
+            }
+        }))
+        .unwrap();
+        config.validate().unwrap();
 
-/// Application error type
-#[derive(Debug)]
-pub enum AppError {
-    NotFound(String),
-    Internal(crate::error::Error),
-}
+        let state = AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        );
+        let app = create_router(state.clone());
+        let get = |uri: &str| {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
 
-impl From<crate::error::Error> for AppError {
-    fn from(err: crate::error::Error) -> Self {
-        AppError::Internal(err)
+        assert!(get("/ical/rota").await.contains("Test Event"));
+        // Built from the cached merge of "rota", and cached in turn for "all"
+        assert!(get("/ical/team").await.contains("Test Event"));
+        assert!(get("/ical/all").await.contains("Test Event"));
+        assert_eq!(state.cache.stats().entries, 3);
     }
-}
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, message) = match self {
-            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
-            AppError::Internal(err) => {
-                tracing::error!("Internal error: {}", err);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Internal server error".to_string(),
-                )
+    #[tokio::test]
+    async fn test_cached_references_not_counted_again() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "server": { "cache_ttl": 300 },
+            "calendars": {
+                "rota": {
+                    "sources": [{ "ics": SAMPLE_ICAL }],
+                    "steps": [{ "type": "replace", "pattern": "Test", "replacement": "Rota" }]
+                },
+                "team": { "sources": [{ "calendar": "rota" }] }
             }
+        }))
+        .unwrap();
+        config.validate().unwrap();
+        let state = AppState::new(
+            config.clone(),
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        );
+        let counter = |state: &AppState| {
+            let metrics = state.metrics.render_prometheus();
+            metrics
+                .lines()
+                .find(|line| {
+                    line.starts_with("ical_merge_step_events_total{calendar=\"rota\"")
+                        && line.contains("outcome=\"evaluated\"")
+                })
+                .map(|line| line.rsplit(' ').next().unwrap().to_string())
         };
 
-        (status, message).into_response()
+        // The first merge of "team" runs the pipeline of "rota" and reports it
+        state.refresh("team", config.clone()).await.unwrap();
+        assert_eq!(counter(&state).as_deref(), Some("1"));
+        // Later ones use the cached merge of "rota", which ran no pipeline
+        state.refresh("team", config.clone()).await.unwrap();
+        state.refresh("team", config).await.unwrap();
+        assert_eq!(counter(&state).as_deref(), Some("1"));
+        assert_eq!(state.metrics.calendars()["team"].merges, 3);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::{CalendarConfig, SourceConfig};
-    use axum::body::Body;
-    use axum::http::{Request, StatusCode};
-    use std::collections::HashMap;
-    use tower::util::ServiceExt;
-    use wiremock::matchers::{method, path};
-    use wiremock::{Mock, MockServer, ResponseTemplate};
+    #[tokio::test]
+    async fn test_tenants() {
+        let dir = tempfile::tempdir().unwrap();
+        let tenant_config = |summary: &str| {
+            serde_json::json!({
+                "calendars": {
+                    "family": {
+                        "sources": [{ "ics": SAMPLE_ICAL.replace("Test Event", summary) }]
+                    }
+                }
+            })
+            .to_string()
+        };
+        std::fs::write(dir.path().join("smiths.json"), tenant_config("Smith Event")).unwrap();
+        std::fs::write(dir.path().join("jones.json"), tenant_config("Jones Event")).unwrap();
+        let config_path = dir.path().join("config.json");
+        let main_config = serde_json::json!({
+            "server": {
+                "base_url": "https://cal.example.com",
+                "tenants": {
+                    "smiths": { "config": "smiths.json", "token": "s3cret" },
+                    "jones": { "config": "jones.json" }
+                }
+            }
+        });
+        std::fs::write(&config_path, main_config.to_string()).unwrap();
 
-    const SAMPLE_ICAL: &str = r#"BEGIN:VCALENDAR
-VERSION:2.0
-PRODID:-//Test//Test//EN
-BEGIN:VEVENT
-UID:test@example.com
-DTSTAMP:20231201T120000Z
-DTSTART:20231201T140000Z
-DTEND:20231201T150000Z
-SUMMARY:Test Event
-END:VEVENT
-END:VCALENDAR"#;
+        let config = Config::load(&config_path).unwrap();
+        config.validate().unwrap();
+        let state = AppState::new(config, config_path.clone(), Fetcher::new().unwrap());
+        state.load_tenants().unwrap();
+        let app = create_router(state.clone());
+        let get = |uri: &str, token: Option<&str>| {
+            let mut request = Request::builder().uri(uri);
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        let (status, body) = get("/t/jones/ical/family", None).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body.contains("Jones Event"));
+
+        // Tenants with a token need it in a header or the URL
+        let (status, _) = get("/t/smiths/ical/family", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = get("/t/smiths/ical/family?token=wrong", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = get("/t/smiths/ical/family?token=s3cret", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Smith Event"));
+        let (status, _) = get("/t/smiths/ical/family", Some("s3cret")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = get("/t/browns/ical/family", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = get("/ical/family", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Links point back under the tenant, keeping the token
+        let (_, body) = get("/t/smiths/subscribe/family?token=s3cret", None).await;
+        assert!(body.contains("https://cal.example.com/t/smiths/ical/family?token=s3cret"));
+
+        // Metrics are kept apart
+        let (_, body) = get("/t/jones/status", None).await;
+        let status: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(status["calendars"]["family"]["merges"], 1);
+        assert!(state.metrics.calendars().is_empty());
+        assert_eq!(
+            state.tenant("smiths").unwrap().metrics.calendars()["family"].merges,
+            2
+        );
+
+        // Reloading picks up changed tenant configs, unless one is broken
+        std::fs::write(dir.path().join("jones.json"), tenant_config("Moved Event")).unwrap();
+        state.reload_config().unwrap();
+        let (_, body) = get("/t/jones/ical/family", None).await;
+        assert!(body.contains("Moved Event"));
+
+        std::fs::write(dir.path().join("jones.json"), "{ broken").unwrap();
+        assert!(state.reload_config().is_err());
+        let (_, body) = get("/t/jones/ical/family", None).await;
+        assert!(body.contains("Moved Event"));
+
+        let mut main_config = main_config;
+        main_config["server"]["tenants"]
+            .as_object_mut()
+            .unwrap()
+            .remove("jones");
+        std::fs::write(&config_path, main_config.to_string()).unwrap();
+        state.reload_config().unwrap();
+        let (status, _) = get("/t/jones/ical/family", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(state.tenant("smiths").is_some());
+    }
+
+    #[test]
+    fn test_paginate() {
+        let items: Vec<usize> = (0..250).collect();
+
+        let (page, info) = paginate(items.clone(), None, None);
+        assert_eq!(page, (0..100).collect::<Vec<_>>());
+        assert_eq!(
+            info,
+            Page {
+                total: 250,
+                offset: 0,
+                next_offset: Some(100)
+            }
+        );
+
+        // Limits above the largest page are capped
+        let (page, info) = paginate(items.clone(), Some(200), Some(500));
+        assert_eq!(page, (200..250).collect::<Vec<_>>());
+        assert_eq!(info.next_offset, None);
+
+        let (page, info) = paginate(items.clone(), Some(10), Some(5));
+        assert_eq!(page, vec![10, 11, 12, 13, 14]);
+        assert_eq!(info.next_offset, Some(15));
+
+        // A zero limit still moves clients on to the next page
+        let (page, info) = paginate(items.clone(), Some(10), Some(0));
+        assert_eq!(page, vec![10]);
+        assert_eq!(info.next_offset, Some(11));
+
+        let (page, info) = paginate(items, Some(usize::MAX), None);
+        assert!(page.is_empty());
+        assert_eq!(info.next_offset, None);
+    }
 
     #[tokio::test]
-    async fn test_get_calendar_endpoint() {
+    async fn test_max_stale() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_ICAL))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "server": { "cache_ttl": 1 },
+            "calendars": {
+                "team": {
+                    "sources": [{ "url": format!("{}/team.ics", mock_server.uri()) }],
+                    "max_stale": 2
+                }
+            }
+        }))
+        .unwrap();
+        config.validate().unwrap();
+
+        let state = AppState::new(
+            config,
+            std::env::temp_dir().join("test-config.json"),
+            Fetcher::new().unwrap(),
+        );
+        let app = create_router(state);
+        let get = || {
+            Request::builder()
+                .uri("/ical/team")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(get()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Still young enough to serve once the upstream starts failing
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let response = app.clone().oneshot(get()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-ical-merge-stale"], "true");
+        assert_eq!(response.headers()["x-ical-merge-age"], "1");
+
+        tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+        let response = app.oneshot(get()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["x-ical-merge-age"], "2");
+    }
+
+    #[tokio::test]
+    async fn test_status_and_metrics_endpoints() {
         let mock_server = MockServer::start().await;
 
         Mock::given(method("GET"))
@@ -155,16 +3727,85 @@ END:VCALENDAR"#;
         let mut calendars = HashMap::new();
         calendars.insert(
             "test-calendar".to_string(),
+            CalendarConfig {
+                sources: vec![SourceConfig::Url {
+                    url: format!("{}/test.ics", mock_server.uri()),
+                    steps: vec![Step::Deny {
+                        patterns: vec!["Test".to_string()],
+                        mode: MatchMode::Any,
+                        fields: vec!["summary".to_string()],
+                        action: FilterAction::Enforce,
+                        unless: vec![],
+                        literal: false,
+                    }],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
+                }],
+                steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
+            },
+        );
+        calendars.insert(
+            "unused".to_string(),
             CalendarConfig {
                 sources: vec![SourceConfig::Url {
                     url: format!("{}/test.ics", mock_server.uri()),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
-        let config = Config { calendars };
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
 
         let fetcher = Fetcher::new().unwrap();
         let config_path = std::env::temp_dir().join("test-config.json");
@@ -175,41 +3816,41 @@ END:VCALENDAR"#;
             .uri("/ical/test-calendar")
             .body(Body::empty())
             .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let response = app.oneshot(request).await.unwrap();
-
+        let request = Request::builder()
+            .uri("/status")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-        assert_eq!(
-            response.headers().get("content-type").unwrap(),
-            "text/calendar; charset=utf-8"
-        );
 
         let body = axum::body::to_bytes(response.into_body(), usize::MAX)
             .await
             .unwrap();
-        let body_str = String::from_utf8(body.to_vec()).unwrap();
-        assert!(body_str.contains("Test Event"));
-    }
-
-    #[tokio::test]
-    async fn test_unknown_calendar_returns_404() {
-        let config = Config {
-            calendars: HashMap::new(),
-        };
-
-        let fetcher = Fetcher::new().unwrap();
-        let config_path = std::env::temp_dir().join("test-config.json");
-        let state = AppState::new(config, config_path, fetcher);
-        let app = create_router(state);
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let calendar = &status["calendars"]["test-calendar"];
+        assert_eq!(calendar["merges"], 1);
+        assert_eq!(calendar["events"], 0);
+        assert_eq!(calendar["pipelines"]["source:0"]["steps"][0]["rejected"], 1);
+        assert!(status["calendars"]["unused"].is_null());
+        // Nothing is cached without a cache_ttl
+        assert_eq!(status["cache"]["entries"], 0);
 
         let request = Request::builder()
-            .uri("/ical/nonexistent")
+            .uri("/metrics")
             .body(Body::empty())
             .unwrap();
-
         let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body_str = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body_str.contains("ical_merge_merges_total{calendar=\"test-calendar\"} 1"));
+        assert!(body_str.contains("ical_merge_cache_entries 0\n"));
     }
 
     #[tokio::test]
@@ -236,17 +3877,59 @@ END:VCALENDAR"#;
                     SourceConfig::Url {
                         url: format!("{}/test.ics", mock_server.uri()),
                         steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                        username: None,
+                        password: None,
+                        auth: None,
+                        timeout: None,
+                        client_cert: None,
+                        client_key: None,
+                        insecure_tls: false,
                     },
                     SourceConfig::Url {
                         url: format!("{}/notfound.ics", mock_server.uri()),
                         steps: vec![],
+                        default: DefaultAction::Allow,
+                        label: None,
+                        tags: vec![],
+                        only_future: false,
+                        skip_older_than: None,
+                        username: None,
+                        password: None,
+                        auth: None,
+                        timeout: None,
+                        client_cert: None,
+                        client_key: None,
+                        insecure_tls: false,
                     },
                 ],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
-        let config = Config { calendars };
+        let config = Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        };
 
         let fetcher = Fetcher::new().unwrap();
         let config_path = std::env::temp_dir().join("test-config.json");
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::error::Result;
+
+/// What a supervised task is doing
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskState {
+    Running,
+    /// Crashed or failed and waiting to be restarted
+    Restarting,
+    /// Finished its work and won't be restarted
+    Finished,
+}
+
+/// Health of a supervised task
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskHealth {
+    pub state: TaskState,
+    /// Times the task has been restarted
+    pub restarts: u32,
+    /// Why the task last stopped, if it crashed or failed
+    pub last_error: Option<String>,
+}
+
+/// Runs background tasks, restarting any that panic or fail with a backoff
+/// that doubles up to a limit
+pub struct Supervisor {
+    tasks: Arc<Mutex<BTreeMap<String, TaskHealth>>>,
+    min_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::with_backoff(Duration::from_secs(1), Duration::from_secs(60))
+    }
+
+    /// Supervisor waiting from `min_backoff` up to `max_backoff` between restarts
+    pub fn with_backoff(min_backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            tasks: Arc::new(Mutex::new(BTreeMap::new())),
+            min_backoff,
+            max_backoff,
+        }
+    }
+
+    /// Run the task made by `make` in the background, making a fresh one whenever
+    /// it panics or returns an error. A task that returns `Ok` is done
+    pub fn spawn<F, Fut>(&self, name: &str, make: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let tasks = self.tasks.clone();
+        let name = name.to_string();
+        let (min_backoff, max_backoff) = (self.min_backoff, self.max_backoff);
+
+        let task = name.clone();
+        let set_health = move |state: TaskState, error: Option<String>| {
+            let mut tasks = tasks.lock().unwrap();
+            let health = tasks.entry(task.clone()).or_insert(TaskHealth {
+                state: TaskState::Running,
+                restarts: 0,
+                last_error: None,
+            });
+            if state == TaskState::Running && health.state == TaskState::Restarting {
+                health.restarts += 1;
+            }
+            health.state = state;
+            if error.is_some() {
+                health.last_error = error;
+            }
+        };
+
+        tokio::spawn(async move {
+            let mut backoff = min_backoff;
+            loop {
+                set_health(TaskState::Running, None);
+                let started = Instant::now();
+                let error = match tokio::spawn(make()).await {
+                    Ok(Ok(())) => {
+                        set_health(TaskState::Finished, None);
+                        return;
+                    }
+                    Ok(Err(err)) => err.to_string(),
+                    Err(err) if err.is_panic() => panic_message(err.into_panic()),
+                    Err(err) => err.to_string(),
+                };
+
+                // A task that ran for a good while before failing starts the backoff over
+                if started.elapsed() > max_backoff {
+                    backoff = min_backoff;
+                }
+                tracing::error!(
+                    "Background task '{}' failed, restarting in {:?}: {}",
+                    name,
+                    backoff,
+                    error
+                );
+                set_health(TaskState::Restarting, Some(error));
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(max_backoff);
+            }
+        });
+    }
+
+    /// Snapshot of the health of every task that has been started
+    pub fn tasks(&self) -> BTreeMap<String, TaskHealth> {
+        self.tasks.lock().unwrap().clone()
+    }
+
+    /// Names of tasks that are currently down waiting to be restarted
+    pub fn failing(&self) -> Vec<String> {
+        self.tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, health)| health.state == TaskState::Restarting)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Text of a panic, which is usually a string
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => format!("panicked: {}", message),
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => format!("panicked: {}", message),
+            Err(_) => "panicked".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    async fn wait_for(supervisor: &Supervisor, name: &str, state: TaskState) -> TaskHealth {
+        for _ in 0..100 {
+            if let Some(health) = supervisor.tasks().get(name)
+                && health.state == state
+            {
+                return health.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("task '{}' never reached {:?}", name, state);
+    }
+
+    #[tokio::test]
+    async fn test_restarts_crashed_tasks() {
+        let supervisor =
+            Supervisor::with_backoff(Duration::from_millis(20), Duration::from_millis(100));
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let counter = runs.clone();
+        supervisor.spawn("flaky", move || {
+            let run = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match run {
+                    0 => panic!("first run crashes"),
+                    1 => Err(Error::Config("second run fails".to_string())),
+                    _ => std::future::pending().await,
+                }
+            }
+        });
+
+        let health = wait_for(&supervisor, "flaky", TaskState::Restarting).await;
+        assert_eq!(
+            health.last_error.as_deref(),
+            Some("panicked: first run crashes")
+        );
+        assert_eq!(supervisor.failing(), vec!["flaky"]);
+
+        // Still running on the third attempt
+        for _ in 0..100 {
+            if runs.load(Ordering::SeqCst) == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let health = wait_for(&supervisor, "flaky", TaskState::Running).await;
+        assert_eq!(health.restarts, 2);
+        assert_eq!(
+            health.last_error.as_deref(),
+            Some("Configuration error: second run fails")
+        );
+        assert!(supervisor.failing().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_finished_tasks_are_not_restarted() {
+        let supervisor = Supervisor::new();
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let counter = runs.clone();
+        supervisor.spawn("once", move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { Ok(()) }
+        });
+
+        let health = wait_for(&supervisor, "once", TaskState::Finished).await;
+        assert_eq!(health.restarts, 0);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,152 @@
+use std::ffi::OsStr;
+use std::time::Duration;
+
+/// First file descriptor systemd passes activated sockets on
+#[cfg(target_os = "linux")]
+const LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+/// How many sockets systemd passed, given `LISTEN_PID` and `LISTEN_FDS`. They're only
+/// meant for the process systemd started, not anything it runs in turn
+fn passed_sockets(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> usize {
+    if listen_pid.and_then(|listen_pid| listen_pid.parse::<u32>().ok()) != Some(pid) {
+        return 0;
+    }
+    listen_fds.and_then(|fds| fds.parse().ok()).unwrap_or(0)
+}
+
+/// The socket systemd passed in when the service was socket activated
+#[cfg(target_os = "linux")]
+pub fn activated_listener() -> std::io::Result<Option<std::net::TcpListener>> {
+    use std::os::fd::FromRawFd;
+
+    let count = passed_sockets(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+    if count == 0 {
+        return Ok(None);
+    }
+    if count > 1 {
+        tracing::warn!("systemd passed {} sockets, only the first is used", count);
+    }
+
+    // SAFETY: systemd hands this descriptor to the process and nothing else takes it
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+/// Socket activation is only supported on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn activated_listener() -> std::io::Result<Option<std::net::TcpListener>> {
+    Ok(None)
+}
+
+/// Tell systemd about the service's state, e.g. `READY=1`. Returns whether systemd
+/// was listening, which is only when it set `NOTIFY_SOCKET`
+pub fn notify(state: &str) -> std::io::Result<bool> {
+    match std::env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => send(&socket, state).map(|()| true),
+        None => Ok(false),
+    }
+}
+
+/// Send a state to the notify socket, which is a path or an abstract name starting `@`
+#[cfg(target_os = "linux")]
+fn send(socket: &OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let address = match socket.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(socket)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send(_socket: &OsStr, _state: &str) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// How often systemd expects to hear the service is alive, given `WATCHDOG_USEC`
+/// and `WATCHDOG_PID`
+fn watchdog_timeout(usec: Option<&str>, watchdog_pid: Option<&str>, pid: u32) -> Option<Duration> {
+    if let Some(watchdog_pid) = watchdog_pid
+        && watchdog_pid.parse::<u32>().ok() != Some(pid)
+    {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// How long systemd waits to hear from the service before deciding it's hung, when
+/// the unit sets `WatchdogSec`
+pub fn watchdog() -> Option<Duration> {
+    watchdog_timeout(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passed_sockets() {
+        assert_eq!(passed_sockets(Some("42"), Some("1"), 42), 1);
+        assert_eq!(passed_sockets(Some("42"), Some("2"), 42), 2);
+        // Meant for another process
+        assert_eq!(passed_sockets(Some("41"), Some("1"), 42), 0);
+        assert_eq!(passed_sockets(None, Some("1"), 42), 0);
+        assert_eq!(passed_sockets(Some("42"), None, 42), 0);
+        assert_eq!(passed_sockets(Some("42"), Some("many"), 42), 0);
+    }
+
+    #[test]
+    fn test_watchdog_timeout() {
+        assert_eq!(
+            watchdog_timeout(Some("30000000"), None, 42),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            watchdog_timeout(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(watchdog_timeout(Some("30000000"), Some("41"), 42), None);
+        assert_eq!(watchdog_timeout(Some("0"), None, 42), None);
+        assert_eq!(watchdog_timeout(None, None, 42), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_send() {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        send(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        // systemd usually listens on an abstract socket
+        let name = format!("ical-merge-test-{}", std::process::id());
+        let address = SocketAddr::from_abstract_name(&name).unwrap();
+        let receiver = UnixDatagram::bind_addr(&address).unwrap();
+        send(OsStr::new(&format!("@{}", name)), "WATCHDOG=1").unwrap();
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"WATCHDOG=1");
+
+        assert!(send(OsStr::new("/nonexistent/notify"), "READY=1").is_err());
+    }
+}
@@ -0,0 +1,810 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use icalendar::{Component, Property};
+
+use crate::error::{Error, Result};
+use crate::ical::Event;
+
+/// Where zone files are read from unless `TZDIR` says otherwise
+const DEFAULT_ZONEINFO: &str = "/usr/share/zoneinfo";
+
+/// Event properties holding a single time
+const TIME_PROPERTIES: &[&str] = &["DTSTART", "DTEND", "RECURRENCE-ID"];
+
+/// Event properties holding a comma-separated list of times
+const TIME_LIST_PROPERTIES: &[&str] = &["EXDATE", "RDATE"];
+
+const WEEKDAYS: [&str; 7] = ["SU", "MO", "TU", "WE", "TH", "FR", "SA"];
+
+/// A timezone loaded from the system's tz database
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    name: String,
+    /// UTC timestamps at which each offset (seconds east of UTC) takes effect, in order
+    transitions: Vec<(i64, i32)>,
+    /// Offset in effect before the first transition
+    initial: i32,
+    /// Rule for times after the last transition
+    rule: Rule,
+}
+
+/// A POSIX TZ rule such as `GMT0BST,M3.5.0/1,M10.5.0`
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    std_name: String,
+    std_offset: i32,
+    dst: Option<Dst>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Dst {
+    name: String,
+    offset: i32,
+    /// When daylight time starts, in standard time
+    start: Change,
+    /// When daylight time ends, in daylight time
+    end: Change,
+}
+
+/// A yearly change of offset
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Change {
+    date: RuleDate,
+    /// Seconds after local midnight, may be negative or past a day
+    time: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuleDate {
+    /// `Mm.w.d`: weekday `d` (0 is Sunday) of week `w` (5 is the last) of month `m`
+    Month { month: u32, week: u32, weekday: u32 },
+    /// `Jn`: day `n` of the year from 1, never counting February 29
+    Julian(u32),
+    /// `n`: day `n` of the year from 0, counting February 29
+    Ordinal(u32),
+}
+
+impl Zone {
+    /// Load a zone such as `Europe/London` from the tz database
+    pub fn load(name: &str) -> Result<Self> {
+        let valid = !name.is_empty()
+            && !name.starts_with('/')
+            && name
+                .split('/')
+                .all(|part| !part.is_empty() && part != "." && part != "..")
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'));
+        if !valid {
+            return Err(Error::Config(format!("Invalid timezone '{}'", name)));
+        }
+
+        let dir = std::env::var_os("TZDIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_ZONEINFO));
+        let data = std::fs::read(dir.join(name))
+            .map_err(|e| Error::Config(format!("Unknown timezone '{}': {}", name, e)))?;
+
+        parse_tzif(name, &data)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Offset in seconds east of UTC at a UTC timestamp
+    fn offset_at(&self, utc: i64) -> i32 {
+        match self.transitions.last() {
+            Some((last, _)) if utc < *last => {
+                let idx = self.transitions.partition_point(|(at, _)| *at <= utc);
+                if idx == 0 {
+                    self.initial
+                } else {
+                    self.transitions[idx - 1].1
+                }
+            }
+            _ => self.rule.offset_at(utc),
+        }
+    }
+
+    /// Local time in this zone for a UTC time
+    pub fn from_utc(&self, utc: NaiveDateTime) -> NaiveDateTime {
+        utc + Duration::seconds(self.offset_at(utc.and_utc().timestamp()).into())
+    }
+
+    /// UTC time for a local time in this zone, taking the earlier of two
+    /// ambiguous readings and moving times skipped by a change forward
+    pub fn to_utc(&self, local: NaiveDateTime) -> NaiveDateTime {
+        let secs = local.and_utc().timestamp();
+        // A change never happens twice within a day, so the offsets either side cover both readings
+        let before = self.offset_at(secs - 86400);
+        let after = self.offset_at(secs + 86400);
+
+        let offset = [before, after]
+            .into_iter()
+            .find(|offset| self.offset_at(secs - i64::from(*offset)) == *offset)
+            .unwrap_or(before);
+        local - Duration::seconds(offset.into())
+    }
+
+    /// Rewrite events' UTC and zoned times into this zone, leaving dates,
+    /// floating times and times in zones we don't know alone
+    pub fn convert_events(&self, events: Vec<Event>) -> Vec<Event> {
+        let mut zones = HashMap::new();
+        events
+            .into_iter()
+            .map(|event| self.convert_event(&event, &mut zones))
+            .collect()
+    }
+
+    fn convert_event(&self, event: &Event, zones: &mut HashMap<String, Option<Zone>>) -> Event {
+        let inner = event.inner();
+        let mut converted = icalendar::Event::new();
+        for property in inner.properties().values() {
+            converted.append_property(self.convert_property(property, TIME_PROPERTIES, zones));
+        }
+        for property in inner.multi_properties().values().flatten() {
+            converted.append_multi_property(self.convert_property(
+                property,
+                TIME_LIST_PROPERTIES,
+                zones,
+            ));
+        }
+        for component in inner.components() {
+            converted.append_component(component.clone());
+        }
+        Event::new(converted)
+    }
+
+    fn convert_property(
+        &self,
+        property: &Property,
+        names: &[&str],
+        zones: &mut HashMap<String, Option<Zone>>,
+    ) -> Property {
+        if !names.contains(&property.key()) {
+            return property.clone();
+        }
+
+        let params = property.params();
+        if params
+            .get("VALUE")
+            .is_some_and(|value| !value.value().eq_ignore_ascii_case("DATE-TIME"))
+        {
+            return property.clone();
+        }
+
+        let source = match params.get("TZID").map(|tzid| tzid.value()) {
+            Some(tzid) if tzid == self.name => return property.clone(),
+            Some(tzid) => match zones
+                .entry(tzid.to_string())
+                .or_insert_with(|| Zone::load(tzid).ok())
+            {
+                Some(zone) => Some(&*zone),
+                None => return property.clone(),
+            },
+            None => None,
+        };
+
+        let mut values = Vec::new();
+        for value in property.value().split(',') {
+            let utc = match (value.strip_suffix('Z'), source) {
+                (Some(utc), _) => NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok(),
+                (None, Some(zone)) => NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+                    .ok()
+                    .map(|local| zone.to_utc(local)),
+                // Floating times are meant to be read in whatever zone the reader is in
+                (None, None) => None,
+            };
+            let Some(utc) = utc else {
+                return property.clone();
+            };
+            values.push(self.from_utc(utc).format("%Y%m%dT%H%M%S").to_string());
+        }
+
+        let mut converted = Property::new(property.key(), values.join(","));
+        for param in params.values().filter(|param| param.key() != "TZID") {
+            converted.add_parameter(param.key(), param.value());
+        }
+        converted.add_parameter("TZID", &self.name);
+        converted
+    }
+
+    /// Content lines of a VTIMEZONE describing the zone's current rules
+    pub fn vtimezone(&self) -> Vec<String> {
+        let mut lines = vec!["BEGIN:VTIMEZONE".to_string(), format!("TZID:{}", self.name)];
+        let rule = &self.rule;
+
+        match &rule.dst {
+            None => lines.extend(observance(
+                "STANDARD",
+                "19700101T000000".to_string(),
+                rule.std_offset,
+                rule.std_offset,
+                &rule.std_name,
+                None,
+            )),
+            Some(dst) => {
+                // Both rules are checked when the zone is loaded
+                lines.extend(observance(
+                    "DAYLIGHT",
+                    dst.start.first_local(),
+                    rule.std_offset,
+                    dst.offset,
+                    &dst.name,
+                    dst.start.rrule(),
+                ));
+                lines.extend(observance(
+                    "STANDARD",
+                    dst.end.first_local(),
+                    dst.offset,
+                    rule.std_offset,
+                    &rule.std_name,
+                    dst.end.rrule(),
+                ));
+            }
+        }
+
+        lines.push("END:VTIMEZONE".to_string());
+        lines
+    }
+}
+
+fn observance(
+    kind: &str,
+    start: String,
+    from: i32,
+    to: i32,
+    name: &str,
+    rrule: Option<String>,
+) -> Vec<String> {
+    let mut lines = vec![
+        format!("BEGIN:{}", kind),
+        format!("DTSTART:{}", start),
+        format!("TZOFFSETFROM:{}", format_offset(from)),
+        format!("TZOFFSETTO:{}", format_offset(to)),
+        format!("TZNAME:{}", name),
+    ];
+    if let Some(rrule) = rrule {
+        lines.push(format!("RRULE:{}", rrule));
+    }
+    lines.push(format!("END:{}", kind));
+    lines
+}
+
+/// Format an offset as iCalendar's `+hhmm`, adding seconds only when needed
+fn format_offset(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.unsigned_abs();
+    let (hours, minutes, seconds) = (offset / 3600, offset / 60 % 60, offset % 60);
+    if seconds == 0 {
+        format!("{}{:02}{:02}", sign, hours, minutes)
+    } else {
+        format!("{}{:02}{:02}{:02}", sign, hours, minutes, seconds)
+    }
+}
+
+impl Rule {
+    fn offset_at(&self, utc: i64) -> i32 {
+        let Some(dst) = &self.dst else {
+            return self.std_offset;
+        };
+
+        let Some(local) = chrono::DateTime::from_timestamp(utc + i64::from(self.std_offset), 0)
+        else {
+            return self.std_offset;
+        };
+        let year = local.year();
+        let start = dst.start.utc_in(year, self.std_offset);
+        let end = dst.end.utc_in(year, dst.offset);
+
+        // Southern zones start daylight time late in the year and end it early the next
+        let in_dst = if start < end {
+            utc >= start && utc < end
+        } else {
+            utc >= start || utc < end
+        };
+        if in_dst { dst.offset } else { self.std_offset }
+    }
+}
+
+impl Change {
+    /// UTC timestamp of the change in a year, given the offset in effect before it
+    fn utc_in(&self, year: i32, offset: i32) -> i64 {
+        let midnight = self.date.date_in(year).and_hms_opt(0, 0, 0).unwrap();
+        midnight.and_utc().timestamp() + i64::from(self.time) - i64::from(offset)
+    }
+
+    /// Local time of the change in 1970, where VTIMEZONE rules conventionally start
+    fn first_local(&self) -> String {
+        let midnight = self.date.date_in(1970).and_hms_opt(0, 0, 0).unwrap();
+        (midnight + Duration::seconds(self.time.into()))
+            .format("%Y%m%dT%H%M%S")
+            .to_string()
+    }
+
+    /// The change as a yearly RRULE, if it can be written as one
+    fn rrule(&self) -> Option<String> {
+        let RuleDate::Month {
+            month,
+            week,
+            weekday,
+        } = self.date
+        else {
+            return None;
+        };
+
+        // Changes at times past midnight either side move to another weekday
+        let days = self.time.div_euclid(86400);
+        let shifted = WEEKDAYS[(weekday as i32 + days).rem_euclid(7) as usize];
+        if days == 0 {
+            let week = if week == 5 { -1 } else { week as i32 };
+            return Some(format!(
+                "FREQ=YEARLY;BYMONTH={};BYDAY={}{}",
+                month, week, WEEKDAYS[weekday as usize]
+            ));
+        }
+
+        // Otherwise name the days of the month the shifted weekday can fall on
+        let month_days = days_in_month(2001, month);
+        let first = if week == 5 {
+            -7
+        } else {
+            (week as i32 - 1) * 7 + 1
+        };
+        let range = (first + days)..(first + days + 7);
+        let in_month = if week == 5 {
+            range.end <= 0 && range.start >= -month_days
+        } else {
+            range.start >= 1 && range.end <= month_days + 1
+        };
+        if !in_month {
+            return None;
+        }
+
+        let month_days: Vec<String> = range.map(|day| day.to_string()).collect();
+        Some(format!(
+            "FREQ=YEARLY;BYMONTH={};BYDAY={};BYMONTHDAY={}",
+            month,
+            shifted,
+            month_days.join(",")
+        ))
+    }
+}
+
+impl RuleDate {
+    fn date_in(&self, year: i32) -> NaiveDate {
+        let jan1 = NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+        match *self {
+            RuleDate::Month {
+                month,
+                week,
+                weekday,
+            } => {
+                let first = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+                let first_weekday = first.weekday().num_days_from_sunday();
+                let mut day = 1 + (weekday + 7 - first_weekday) % 7 + (week - 1) * 7;
+                while day > days_in_month(year, month) as u32 {
+                    day -= 7;
+                }
+                first.with_day(day).unwrap()
+            }
+            RuleDate::Julian(day) => {
+                let leap = NaiveDate::from_ymd_opt(year, 2, 29).is_some();
+                let skip_leap_day = if leap && day >= 60 { 1 } else { 0 };
+                jan1 + Duration::days(i64::from(day) - 1 + skip_leap_day)
+            }
+            RuleDate::Ordinal(day) => jan1 + Duration::days(day.into()),
+        }
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> i32 {
+    let next = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)
+    };
+    next.unwrap().pred_opt().unwrap().day() as i32
+}
+
+/// Sequential reader over the big-endian fields of a TZif file
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(Error::Parse("Truncated timezone file".to_string()));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<usize> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+}
+
+/// Parse a TZif file (RFC 8536), which must be version 2 or later with a footer rule
+fn parse_tzif(name: &str, data: &[u8]) -> Result<Zone> {
+    let invalid = |reason: &str| Error::Config(format!("Timezone '{}' {}", name, reason));
+
+    let mut reader = Reader { data };
+    let header = |reader: &mut Reader| -> Result<[usize; 7]> {
+        if reader.take(4)? != b"TZif" {
+            return Err(invalid("is not a TZif file"));
+        }
+        let version = reader.take(1)?[0];
+        reader.take(15)?;
+        let mut counts = [version as usize, 0, 0, 0, 0, 0, 0];
+        for count in counts.iter_mut().skip(1) {
+            *count = reader.u32()?;
+        }
+        Ok(counts)
+    };
+
+    // Skip the version 1 data, which only has 32-bit times and no footer
+    let [
+        version,
+        isutcnt,
+        isstdcnt,
+        leapcnt,
+        timecnt,
+        typecnt,
+        charcnt,
+    ] = header(&mut reader)?;
+    if version < b'2' as usize {
+        return Err(invalid("is too old to include rules for future years"));
+    }
+    reader.take(timecnt * 5 + typecnt * 6 + charcnt + leapcnt * 8 + isstdcnt + isutcnt)?;
+
+    let [_, isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt] = header(&mut reader)?;
+    let times = reader.take(timecnt * 8)?;
+    let indices = reader.take(timecnt)?;
+    let types: Vec<i32> = reader
+        .take(typecnt * 6)?
+        .chunks(6)
+        .map(|ttinfo| i32::from_be_bytes(ttinfo[..4].try_into().unwrap()))
+        .collect();
+    reader.take(charcnt + leapcnt * 12 + isstdcnt + isutcnt)?;
+
+    let initial = *types.first().ok_or_else(|| invalid("has no offsets"))?;
+    let transitions = times
+        .chunks(8)
+        .zip(indices)
+        .map(|(time, index)| {
+            let offset = types.get(*index as usize).copied();
+            offset.map(|offset| (i64::from_be_bytes(time.try_into().unwrap()), offset))
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| invalid("has an invalid transition"))?;
+
+    let footer = std::str::from_utf8(reader.data)
+        .ok()
+        .and_then(|footer| footer.strip_prefix('\n'))
+        .and_then(|footer| footer.split('\n').next())
+        .filter(|footer| !footer.is_empty())
+        .ok_or_else(|| invalid("has no rule for future years"))?;
+    let rule =
+        parse_rule(footer).ok_or_else(|| invalid(&format!("has an invalid rule '{}'", footer)))?;
+    if let Some(dst) = &rule.dst
+        && (dst.start.rrule().is_none() || dst.end.rrule().is_none())
+    {
+        return Err(invalid(&format!(
+            "has a rule '{}' that can't be written as a VTIMEZONE",
+            footer
+        )));
+    }
+
+    Ok(Zone {
+        name: name.to_string(),
+        transitions,
+        initial,
+        rule,
+    })
+}
+
+/// Parse a POSIX TZ rule, e.g. `EST5EDT,M3.2.0,M11.1.0` or `<+0530>-5:30`
+fn parse_rule(text: &str) -> Option<Rule> {
+    let mut rest = text;
+    let std_name = take_name(&mut rest)?;
+    // POSIX offsets count hours west of UTC
+    let std_offset = -take_time(&mut rest)?;
+    if rest.is_empty() {
+        return Some(Rule {
+            std_name,
+            std_offset,
+            dst: None,
+        });
+    }
+
+    let dst_name = take_name(&mut rest)?;
+    let dst_offset = if rest.starts_with(',') {
+        std_offset + 3600
+    } else {
+        -take_time(&mut rest)?
+    };
+
+    let mut changes = rest.strip_prefix(',')?.split(',');
+    let start = parse_change(changes.next()?)?;
+    let end = parse_change(changes.next()?)?;
+    if changes.next().is_some() {
+        return None;
+    }
+
+    Some(Rule {
+        std_name,
+        std_offset,
+        dst: Some(Dst {
+            name: dst_name,
+            offset: dst_offset,
+            start,
+            end,
+        }),
+    })
+}
+
+/// Take a zone abbreviation, either alphabetic or quoted like `<+0530>`
+fn take_name(rest: &mut &str) -> Option<String> {
+    let (name, remainder) = if let Some(quoted) = rest.strip_prefix('<') {
+        let end = quoted.find('>')?;
+        (&quoted[..end], &quoted[end + 1..])
+    } else {
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        rest.split_at(end)
+    };
+    if name.len() < 3 {
+        return None;
+    }
+    *rest = remainder;
+    Some(name.to_string())
+}
+
+/// Take a `[+-]hh[:mm[:ss]]` time in seconds
+fn take_time(rest: &mut &str) -> Option<i32> {
+    let end = rest
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, ':' | '+' | '-')))
+        .unwrap_or(rest.len());
+    let (time, remainder) = rest.split_at(end);
+    *rest = remainder;
+
+    let (sign, time) = match time.strip_prefix('-') {
+        Some(time) => (-1, time),
+        None => (1, time.strip_prefix('+').unwrap_or(time)),
+    };
+    let mut seconds = 0;
+    let mut parts = 0;
+    for (part, scale) in time.split(':').zip([3600, 60, 1]) {
+        seconds += part.parse::<i32>().ok()? * scale;
+        parts += 1;
+    }
+    if parts != time.split(':').count() {
+        return None;
+    }
+    Some(sign * seconds)
+}
+
+/// Parse a `date[/time]` change, the time defaulting to 02:00
+fn parse_change(text: &str) -> Option<Change> {
+    let (date, time) = match text.split_once('/') {
+        Some((date, mut time)) => {
+            let seconds = take_time(&mut time)?;
+            if !time.is_empty() {
+                return None;
+            }
+            (date, seconds)
+        }
+        None => (text, 7200),
+    };
+
+    let date = if let Some(fields) = date.strip_prefix('M') {
+        let mut fields = fields.split('.').map(|field| field.parse::<u32>().ok());
+        let (month, week, weekday) = (fields.next()??, fields.next()??, fields.next()??);
+        if fields.next().is_some()
+            || !(1..=12).contains(&month)
+            || !(1..=5).contains(&week)
+            || weekday > 6
+        {
+            return None;
+        }
+        RuleDate::Month {
+            month,
+            week,
+            weekday,
+        }
+    } else if let Some(day) = date.strip_prefix('J') {
+        RuleDate::Julian(day.parse().ok().filter(|day| (1..=365).contains(day))?)
+    } else {
+        RuleDate::Ordinal(date.parse().ok().filter(|day| *day <= 365)?)
+    };
+
+    Some(Change { date, time })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        let rule = parse_rule("GMT0BST,M3.5.0/1,M10.5.0").unwrap();
+        assert_eq!(rule.std_offset, 0);
+        let dst = rule.dst.unwrap();
+        assert_eq!(dst.offset, 3600);
+        assert_eq!(dst.start.time, 3600);
+        assert_eq!(dst.end.time, 7200);
+        assert_eq!(
+            dst.end.rrule().as_deref(),
+            Some("FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU")
+        );
+
+        let rule = parse_rule("<+0530>-5:30").unwrap();
+        assert_eq!(rule.std_name, "+0530");
+        assert_eq!(rule.std_offset, 19800);
+        assert!(rule.dst.is_none());
+
+        // Changes before midnight or past a day move to another weekday
+        let rule = parse_rule("<-02>2<-01>,M3.5.0/-1,M10.5.0/0").unwrap();
+        assert_eq!(
+            rule.dst.unwrap().start.rrule().as_deref(),
+            Some("FREQ=YEARLY;BYMONTH=3;BYDAY=SA;BYMONTHDAY=-8,-7,-6,-5,-4,-3,-2")
+        );
+        let rule = parse_rule("IST-2IDT,M3.4.4/26,M10.5.0").unwrap();
+        assert_eq!(
+            rule.dst.unwrap().start.rrule().as_deref(),
+            Some("FREQ=YEARLY;BYMONTH=3;BYDAY=FR;BYMONTHDAY=23,24,25,26,27,28,29")
+        );
+
+        assert!(parse_rule("EST5EDT,M3.2.0").is_none());
+        assert!(parse_rule("X1").is_none());
+    }
+
+    #[test]
+    fn test_rule_offsets() {
+        let zone = Zone {
+            name: "Australia/Sydney".to_string(),
+            transitions: Vec::new(),
+            initial: 36000,
+            rule: parse_rule("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap(),
+        };
+
+        // Daylight time spans the new year in the south
+        assert_eq!(
+            zone.from_utc(time("2025-01-10 00:00")),
+            time("2025-01-10 11:00")
+        );
+        assert_eq!(
+            zone.from_utc(time("2025-07-10 00:00")),
+            time("2025-07-10 10:00")
+        );
+        assert_eq!(
+            zone.to_utc(time("2025-12-25 09:00")),
+            time("2025-12-24 22:00")
+        );
+    }
+
+    #[test]
+    fn test_load_zone() {
+        assert!(Zone::load("../etc/passwd").is_err());
+        assert!(Zone::load("/etc/passwd").is_err());
+
+        // The tz database isn't installed everywhere
+        let Ok(zone) = Zone::load("Europe/London") else {
+            return;
+        };
+
+        assert_eq!(
+            zone.from_utc(time("2025-07-01 12:00")),
+            time("2025-07-01 13:00")
+        );
+        assert_eq!(
+            zone.from_utc(time("2025-01-01 12:00")),
+            time("2025-01-01 12:00")
+        );
+        // Before the last transition the zone's history is used
+        assert_eq!(
+            zone.from_utc(time("1970-01-01 12:00")),
+            time("1970-01-01 13:00")
+        );
+
+        // Ambiguous times read as the earlier instant, skipped ones move forward
+        assert_eq!(
+            zone.to_utc(time("2025-10-26 01:30")),
+            time("2025-10-26 00:30")
+        );
+        assert_eq!(
+            zone.to_utc(time("2025-03-30 01:30")),
+            time("2025-03-30 01:30")
+        );
+
+        assert_eq!(
+            zone.vtimezone(),
+            vec![
+                "BEGIN:VTIMEZONE",
+                "TZID:Europe/London",
+                "BEGIN:DAYLIGHT",
+                "DTSTART:19700329T010000",
+                "TZOFFSETFROM:+0000",
+                "TZOFFSETTO:+0100",
+                "TZNAME:BST",
+                "RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU",
+                "END:DAYLIGHT",
+                "BEGIN:STANDARD",
+                "DTSTART:19701025T020000",
+                "TZOFFSETFROM:+0100",
+                "TZOFFSETTO:+0000",
+                "TZNAME:GMT",
+                "RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU",
+                "END:STANDARD",
+                "END:VTIMEZONE",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_convert_events() {
+        let Ok(zone) = Zone::load("Europe/London") else {
+            return;
+        };
+
+        let ical = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\n\
+            UID:utc\r\n\
+            DTSTART:20250701T120000Z\r\n\
+            DTEND:20250701T130000Z\r\n\
+            END:VEVENT\r\n\
+            BEGIN:VEVENT\r\n\
+            UID:zoned\r\n\
+            DTSTART;TZID=America/New_York:20250701T090000\r\n\
+            EXDATE;TZID=America/New_York:20250708T090000,20250715T090000\r\n\
+            END:VEVENT\r\n\
+            BEGIN:VEVENT\r\n\
+            UID:kept\r\n\
+            DTSTART;VALUE=DATE:20250701\r\n\
+            DTEND:20250701T090000\r\n\
+            RECURRENCE-ID;TZID=Custom/Zone:20250701T090000\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        let events = crate::ical::parse_calendar(ical).unwrap().into_events();
+        let original = events.clone();
+        let events = zone.convert_events(events);
+
+        let text = |event: &Event, name: &str| {
+            let property = event.inner().properties().get(name).unwrap();
+            let tzid = property.params().get("TZID").map(|tzid| tzid.value());
+            (property.value().to_string(), tzid.map(str::to_string))
+        };
+        let london = Some("Europe/London".to_string());
+
+        assert_eq!(
+            text(&events[0], "DTSTART"),
+            ("20250701T130000".to_string(), london.clone())
+        );
+        assert_eq!(
+            text(&events[0], "DTEND"),
+            ("20250701T140000".to_string(), london.clone())
+        );
+        if Zone::load("America/New_York").is_ok() {
+            assert_eq!(
+                text(&events[1], "DTSTART"),
+                ("20250701T140000".to_string(), london.clone())
+            );
+            let exdate = &events[1].inner().multi_properties()["EXDATE"][0];
+            assert_eq!(exdate.value(), "20250708T140000,20250715T140000");
+        }
+
+        // Dates, floating times and unknown zones are left as they were
+        assert_eq!(events[2], original[2]);
+    }
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use notify::{Config, Event, EventKind, PollWatcher, RecursiveMode, Watcher};
@@ -15,56 +17,148 @@ fn start_config_watcher_with_interval(
     state: AppState,
     poll_interval: Duration,
 ) -> crate::error::Result<()> {
-    let config_path = state.config_path.as_ref().clone();
-    let config_path_clone = config_path.clone();
+    // Set up the first watch here so problems with it are reported at startup
+    let first = Mutex::new(Some(ConfigWatch::new(&state, poll_interval)?));
+
+    let supervisor = state.supervisor.clone();
+    supervisor.spawn("config-watcher", move || {
+        let state = state.clone();
+        let watch = first.lock().unwrap().take();
+        async move {
+            let watch = match watch {
+                Some(watch) => watch,
+                None => ConfigWatch::new(&state, poll_interval)?,
+            };
+            watch.run(&state).await;
+            Ok(())
+        }
+    });
 
-    // Create channel for file events
-    let (tx, mut rx) = mpsc::unbounded_channel();
+    Ok(())
+}
 
-    // Create PollWatcher with specified polling interval and content comparison
-    let mut watcher = PollWatcher::new(
-        move |res: Result<Event, notify::Error>| {
-            if let Ok(event) = res {
-                // Check if this event is for our config file
-                let is_our_file = event.paths.iter().any(|p| p == &config_path_clone);
+/// A watch on the config file and the channel its changes arrive on
+struct ConfigWatch {
+    // Changes stop arriving once the watcher is dropped
+    watcher: PollWatcher,
+    changes: mpsc::UnboundedReceiver<()>,
+    config_path: PathBuf,
+    /// Files the config includes, which are watched along with it
+    included: Arc<Mutex<Vec<PathBuf>>>,
+}
 
-                if is_our_file
-                    && matches!(
-                        event.kind,
-                        EventKind::Modify(_) | EventKind::Create(_) | EventKind::Any
-                    )
-                {
-                    tracing::debug!("File event detected for config: {:?}", event);
-                    let _ = tx.send(());
+impl ConfigWatch {
+    fn new(state: &AppState, poll_interval: Duration) -> crate::error::Result<Self> {
+        let config_path = state.config_path.as_ref().clone();
+        let config_path_clone = config_path.clone();
+        let included = Arc::new(Mutex::new(Vec::new()));
+        let included_clone = included.clone();
+
+        // Create channel for file events
+        let (tx, changes) = mpsc::unbounded_channel();
+
+        // Create PollWatcher with specified polling interval and content comparison
+        let mut watcher = PollWatcher::new(
+            move |res: Result<Event, notify::Error>| {
+                if let Ok(event) = res {
+                    // Check if this event is for our config file
+                    let is_our_file = event.paths.iter().any(|p| {
+                        p == &config_path_clone || included_clone.lock().unwrap().contains(p)
+                    });
+
+                    if is_our_file
+                        && matches!(
+                            event.kind,
+                            EventKind::Modify(_) | EventKind::Create(_) | EventKind::Any
+                        )
+                    {
+                        tracing::debug!("File event detected for config: {:?}", event);
+                        let _ = tx.send(());
+                    }
                 }
+            },
+            Config::default()
+                .with_poll_interval(poll_interval)
+                .with_compare_contents(true),
+        )?;
+
+        // Watch the parent directory to catch file changes reliably
+        let watch_path = if config_path.is_file() {
+            config_path.parent().unwrap_or(&config_path)
+        } else {
+            &config_path
+        };
+
+        watcher.watch(watch_path, RecursiveMode::NonRecursive)?;
+
+        tracing::info!(
+            "Started watching directory: {:?} for config file: {:?}",
+            watch_path,
+            config_path
+        );
+
+        let mut watch = Self {
+            watcher,
+            changes,
+            config_path,
+            included,
+        };
+        watch.sync_included(state);
+        Ok(watch)
+    }
+
+    /// Watch the files the config includes and its tenants' configs now, and stop
+    /// watching the ones it no longer does
+    fn sync_included(&mut self, state: &AppState) {
+        // A config that can't be read keeps the files it included before
+        let mut files = match crate::config::Config::included_files(&self.config_path) {
+            Ok(files) => files,
+            Err(e) => {
+                tracing::debug!("Can't read the files the config includes: {}", e);
+                return;
+            }
+        };
+        let tenants: Vec<PathBuf> = {
+            let config = state.config.read().unwrap();
+            config
+                .server
+                .tenants
+                .values()
+                .map(|tenant| tenant.path(&self.config_path))
+                .collect()
+        };
+        for tenant in tenants {
+            files.extend(crate::config::Config::included_files(&tenant).unwrap_or_default());
+            files.push(tenant);
+        }
+
+        let mut included = self.included.lock().unwrap();
+        for file in included.iter() {
+            if !files.contains(file) {
+                let _ = self.watcher.unwatch(file);
+            }
+        }
+        let mut watched = Vec::new();
+        for file in files {
+            if included.contains(&file) {
+                watched.push(file);
+                continue;
             }
-        },
-        Config::default()
-            .with_poll_interval(poll_interval)
-            .with_compare_contents(true),
-    )?;
-
-    // Watch the parent directory to catch file changes reliably
-    let watch_path = if config_path.is_file() {
-        config_path.parent().unwrap_or(&config_path)
-    } else {
-        &config_path
-    };
-
-    watcher.watch(watch_path, RecursiveMode::NonRecursive)?;
-
-    tracing::info!(
-        "Started watching directory: {:?} for config file: {:?}",
-        watch_path,
-        config_path
-    );
-
-    // Spawn background task to handle reload events
-    tokio::spawn(async move {
-        // Keep watcher alive by moving it into the task
-        let _watcher = watcher;
-
-        while rx.recv().await.is_some() {
+            // Files that don't exist yet are tried again when the config next changes
+            match self.watcher.watch(&file, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    tracing::debug!("Started watching included config: {:?}", file);
+                    watched.push(file);
+                }
+                Err(e) => tracing::warn!("Can't watch included config {:?}: {}", file, e),
+            }
+        }
+        *included = watched;
+    }
+
+    /// Reload the config whenever it or a file it includes changes
+    async fn run(mut self, state: &AppState) {
+        while self.changes.recv().await.is_some() {
             tracing::debug!("Config file change detected, reloading...");
 
             match state.reload_config() {
@@ -76,16 +170,126 @@ fn start_config_watcher_with_interval(
                     tracing::warn!("Continuing with previous configuration");
                 }
             }
+            self.sync_included(state);
+        }
+    }
+}
+
+/// Start watching the files read by `file://` sources, dropping cached merges
+/// when one changes so the next request reads it again
+pub fn start_source_watcher(state: AppState) -> crate::error::Result<()> {
+    start_source_watcher_with_interval(state, Duration::from_secs(2))
+}
+
+fn start_source_watcher_with_interval(
+    state: AppState,
+    poll_interval: Duration,
+) -> crate::error::Result<()> {
+    let first = Mutex::new(Some(SourceWatch::new(poll_interval)?));
+
+    let supervisor = state.supervisor.clone();
+    supervisor.spawn("source-watcher", move || {
+        let state = state.clone();
+        let watch = first.lock().unwrap().take();
+        async move {
+            let watch = match watch {
+                Some(watch) => watch,
+                None => SourceWatch::new(poll_interval)?,
+            };
+            watch.run(&state, poll_interval).await;
+            Ok(())
         }
     });
 
     Ok(())
 }
 
+/// A watch on the files local sources read, kept in step with the config
+struct SourceWatch {
+    watcher: PollWatcher,
+    changes: mpsc::UnboundedReceiver<PathBuf>,
+    /// Files currently being watched
+    watched: Vec<PathBuf>,
+}
+
+impl SourceWatch {
+    fn new(poll_interval: Duration) -> crate::error::Result<Self> {
+        let (tx, changes) = mpsc::unbounded_channel();
+        let watcher = PollWatcher::new(
+            move |res: Result<Event, notify::Error>| {
+                if let Ok(event) = res
+                    && matches!(
+                        event.kind,
+                        EventKind::Modify(_)
+                            | EventKind::Create(_)
+                            | EventKind::Remove(_)
+                            | EventKind::Any
+                    )
+                {
+                    for path in event.paths {
+                        let _ = tx.send(path);
+                    }
+                }
+            },
+            Config::default()
+                .with_poll_interval(poll_interval)
+                .with_compare_contents(true),
+        )?;
+
+        Ok(Self {
+            watcher,
+            changes,
+            watched: Vec::new(),
+        })
+    }
+
+    /// Watch the files the current config reads and stop watching the ones it no longer does
+    fn sync(&mut self, state: &AppState) {
+        let files = state.local_files();
+
+        for file in &self.watched {
+            if !files.contains(file) {
+                let _ = self.watcher.unwatch(file);
+            }
+        }
+        let mut watched = Vec::new();
+        for file in files {
+            if self.watched.contains(&file) {
+                watched.push(file);
+                continue;
+            }
+            // Files that don't exist yet are tried again on the next sync
+            match self.watcher.watch(&file, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    tracing::debug!("Started watching source file: {:?}", file);
+                    watched.push(file);
+                }
+                Err(e) => tracing::debug!("Can't watch source file {:?} yet: {}", file, e),
+            }
+        }
+        self.watched = watched;
+    }
+
+    /// Drop cached merges whenever a source file changes, checking for new files
+    /// to watch every poll interval in case the config was reloaded
+    async fn run(mut self, state: &AppState, poll_interval: Duration) {
+        let mut resync = tokio::time::interval(poll_interval);
+        loop {
+            tokio::select! {
+                _ = resync.tick() => self.sync(state),
+                Some(path) = self.changes.recv() => {
+                    tracing::info!("Source file {:?} changed, dropping cached merges", path);
+                    state.clear_caches();
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{CalendarConfig, Config, SourceConfig};
+    use crate::config::{CalendarConfig, Config, DefaultAction, SourceConfig};
     use crate::fetcher::Fetcher;
     use std::collections::HashMap;
     use std::fs;
@@ -112,13 +316,41 @@ mod tests {
                 sources: vec![SourceConfig::Url {
                     url: "https://example.com/test1.ics".to_string(),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
         let config = Config {
             calendars: calendars.clone(),
+            sources: HashMap::new(),
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
         };
 
         fs::write(&config_path, serde_json::to_string_pretty(&config).unwrap()).unwrap();
@@ -150,13 +382,41 @@ mod tests {
                 sources: vec![SourceConfig::Url {
                     url: "https://example.com/test2.ics".to_string(),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
         let new_config = Config {
             calendars: calendars.clone(),
+            sources: HashMap::new(),
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
         };
 
         // Write new config - with_compare_contents will detect the change
@@ -216,13 +476,41 @@ mod tests {
                 sources: vec![SourceConfig::Url {
                     url: "https://example.com/test1.ics".to_string(),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 }],
                 steps: vec![],
+                default: DefaultAction::Allow,
+                warning_event: false,
+                aliases: vec![],
+                output_timezone: None,
+                recurrence: Default::default(),
+                flag_conflicts: false,
+                variants: Default::default(),
+                max_stale: None,
+                hide_ended_after: None,
+                refresh_token: None,
             },
         );
 
         let config = Config {
             calendars: calendars.clone(),
+            sources: HashMap::new(),
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
         };
 
         fs::write(&config_path, serde_json::to_string_pretty(&config).unwrap()).unwrap();
@@ -253,4 +541,93 @@ mod tests {
         // Cleanup
         let _ = fs::remove_file(config_path);
     }
+
+    #[tokio::test]
+    async fn test_source_file_change_clears_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("local.ics");
+        fs::write(&file, "BEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n").unwrap();
+
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "server": { "cache_ttl": 300 },
+            "calendars": {
+                "local": {
+                    "sources": [{ "url": format!("file://{}", file.display()) }]
+                }
+            }
+        }))
+        .unwrap();
+        assert_eq!(config.local_files(), vec![file.clone()]);
+
+        let state = AppState::new(
+            config,
+            dir.path().join("config.json"),
+            Fetcher::new().unwrap(),
+        );
+        start_source_watcher_with_interval(state.clone(), Duration::from_millis(100)).unwrap();
+        sleep(Duration::from_millis(300)).await;
+
+        state.cache.insert(
+            "local",
+            std::sync::Arc::new(crate::merge::MergeResult::new(vec![], vec![])),
+        );
+        fs::write(
+            &file,
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:new\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+        )
+        .unwrap();
+
+        let start = std::time::Instant::now();
+        while state.cache.get("local").is_some() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Cache was not cleared when the source file changed"
+            );
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_included_config_change_reloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        let team_path = dir.path().join("team.json");
+        fs::write(
+            &config_path,
+            r#"{
+                "include": ["team.json"],
+                "calendars": { "main": { "sources": [{ "url": "https://example.com/main.ics" }] } }
+            }"#,
+        )
+        .unwrap();
+        fs::write(
+            &team_path,
+            r#"{ "calendars": { "team": { "sources": [{ "url": "https://example.com/team.ics" }] } } }"#,
+        )
+        .unwrap();
+
+        let config = Config::load(&config_path).unwrap();
+        assert_eq!(config.calendars.len(), 2);
+        let state = AppState::new(config, config_path.clone(), Fetcher::new().unwrap());
+        start_config_watcher_with_interval(state.clone(), Duration::from_millis(100)).unwrap();
+        sleep(Duration::from_millis(300)).await;
+
+        fs::write(
+            &team_path,
+            r#"{ "calendars": {
+                "team": { "sources": [{ "url": "https://example.com/team.ics" }] },
+                "rota": { "sources": [{ "url": "https://example.com/rota.ics" }] }
+            } }"#,
+        )
+        .unwrap();
+
+        let start = std::time::Instant::now();
+        while !state.config.read().unwrap().calendars.contains_key("rota") {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Config was not reloaded when an included file changed"
+            );
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
 }
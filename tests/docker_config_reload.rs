@@ -15,7 +15,7 @@
 //!
 //! Run with: cargo test --test docker_config_reload
 
-use ical_merge::config::{CalendarConfig, Config, SourceConfig, Step};
+use ical_merge::config::{CalendarConfig, Config, DefaultAction, SourceConfig, Step};
 use serial_test::serial;
 use std::collections::HashMap;
 use std::fs;
@@ -104,13 +104,41 @@ async fn test_config_reload_in_docker_container() {
             sources: vec![SourceConfig::Url {
                 url: mock_url.clone(),
                 steps: vec![],
+                default: DefaultAction::Allow,
+                label: None,
+                tags: vec![],
+                only_future: false,
+                skip_older_than: None,
+                username: None,
+                password: None,
+                auth: None,
+                timeout: None,
+                client_cert: None,
+                client_key: None,
+                insecure_tls: false,
             }],
             steps: vec![],
+            default: DefaultAction::Allow,
+            warning_event: false,
+            aliases: vec![],
+            output_timezone: None,
+            recurrence: Default::default(),
+            flag_conflicts: false,
+            variants: Default::default(),
+            max_stale: None,
+            hide_ended_after: None,
+            refresh_token: None,
         },
     );
 
     let config = Config {
         calendars: calendars.clone(),
+        sources: HashMap::new(),
+        server: Default::default(),
+        output: Default::default(),
+        fetcher: Default::default(),
+        selftest: Vec::new(),
+        version: None,
     };
     fs::write(&config_path, serde_json::to_string_pretty(&config).unwrap()).unwrap();
 
@@ -194,13 +222,44 @@ async fn test_config_reload_in_docker_container() {
                     pattern: "Initial".to_string(),
                     replacement: "Modified".to_string(),
                     field: "summary".to_string(),
+                    literal: false,
                 }],
+                default: DefaultAction::Allow,
+                label: None,
+                tags: vec![],
+                only_future: false,
+                skip_older_than: None,
+                username: None,
+                password: None,
+                auth: None,
+                timeout: None,
+                client_cert: None,
+                client_key: None,
+                insecure_tls: false,
             }],
             steps: vec![],
+            default: DefaultAction::Allow,
+            warning_event: false,
+            aliases: vec![],
+            output_timezone: None,
+            recurrence: Default::default(),
+            flag_conflicts: false,
+            variants: Default::default(),
+            max_stale: None,
+            hide_ended_after: None,
+            refresh_token: None,
         },
     );
 
-    let updated_config = Config { calendars };
+    let updated_config = Config {
+        sources: HashMap::new(),
+        calendars,
+        server: Default::default(),
+        output: Default::default(),
+        fetcher: Default::default(),
+        selftest: Vec::new(),
+        version: None,
+    };
     fs::write(
         &config_path,
         serde_json::to_string_pretty(&updated_config).unwrap(),
@@ -293,13 +352,41 @@ async fn test_docker_config_reload_with_url_change() {
             sources: vec![SourceConfig::Url {
                 url: format!("{}/cal.ics", mock_url1),
                 steps: vec![],
+                default: DefaultAction::Allow,
+                label: None,
+                tags: vec![],
+                only_future: false,
+                skip_older_than: None,
+                username: None,
+                password: None,
+                auth: None,
+                timeout: None,
+                client_cert: None,
+                client_key: None,
+                insecure_tls: false,
             }],
             steps: vec![],
+            default: DefaultAction::Allow,
+            warning_event: false,
+            aliases: vec![],
+            output_timezone: None,
+            recurrence: Default::default(),
+            flag_conflicts: false,
+            variants: Default::default(),
+            max_stale: None,
+            hide_ended_after: None,
+            refresh_token: None,
         },
     );
 
     let config = Config {
         calendars: calendars.clone(),
+        sources: HashMap::new(),
+        server: Default::default(),
+        output: Default::default(),
+        fetcher: Default::default(),
+        selftest: Vec::new(),
+        version: None,
     };
     fs::write(&config_path, serde_json::to_string_pretty(&config).unwrap()).unwrap();
 
@@ -363,14 +450,45 @@ async fn test_docker_config_reload_with_url_change() {
             sources: vec![SourceConfig::Url {
                 url: format!("{}/cal.ics", mock_url2),
                 steps: vec![],
+                default: DefaultAction::Allow,
+                label: None,
+                tags: vec![],
+                only_future: false,
+                skip_older_than: None,
+                username: None,
+                password: None,
+                auth: None,
+                timeout: None,
+                client_cert: None,
+                client_key: None,
+                insecure_tls: false,
             }],
             steps: vec![],
+            default: DefaultAction::Allow,
+            warning_event: false,
+            aliases: vec![],
+            output_timezone: None,
+            recurrence: Default::default(),
+            flag_conflicts: false,
+            variants: Default::default(),
+            max_stale: None,
+            hide_ended_after: None,
+            refresh_token: None,
         },
     );
 
     fs::write(
         &config_path,
-        serde_json::to_string_pretty(&Config { calendars }).unwrap(),
+        serde_json::to_string_pretty(&Config {
+            sources: HashMap::new(),
+            calendars,
+            server: Default::default(),
+            output: Default::default(),
+            fetcher: Default::default(),
+            selftest: Vec::new(),
+            version: None,
+        })
+        .unwrap(),
     )
     .unwrap();
 
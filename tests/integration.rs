@@ -1,9 +1,14 @@
 use axum::body::Body;
 use axum::http::{Request, StatusCode};
-use ical_merge::config::{CalendarConfig, Config, MatchMode, SourceConfig, Step};
+use futures::future::BoxFuture;
+use ical_merge::config::{
+    CalendarConfig, Config, DefaultAction, FilterAction, MatchMode, SourceConfig, Step,
+};
+use ical_merge::export::{DEFAULT_CSV_COLUMNS, to_csv};
 use ical_merge::fetcher::Fetcher;
 use ical_merge::ical::parse_calendar;
-use ical_merge::merge::merge_calendars;
+use ical_merge::merge::{merge_calendars, served_events};
+use ical_merge::provider::SourceProvider;
 use ical_merge::server::{AppState, create_router};
 use std::collections::HashMap;
 use tower::util::ServiceExt;
@@ -45,29 +50,78 @@ async fn test_full_flow_fetch_filter_modify_merge_serve() {
                             patterns: vec!["(?i)optional".to_string()],
                             mode: MatchMode::Any,
                             fields: vec!["summary".to_string()],
+                            action: FilterAction::Enforce,
+                            unless: vec![],
+                            literal: false,
                         },
                         Step::Allow {
                             patterns: vec!["(?i)meeting".to_string()],
                             mode: MatchMode::Any,
                             fields: vec!["summary".to_string(), "description".to_string()],
+                            action: FilterAction::Enforce,
+                            unless: vec![],
+                            literal: false,
                         },
                         Step::Replace {
                             pattern: "^Meeting:".to_string(),
                             replacement: "[WORK]".to_string(),
                             field: "summary".to_string(),
+                            literal: false,
                         },
                     ],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 },
                 SourceConfig::Url {
                     url: format!("{}/holidays.ics", mock_server.uri()),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 },
             ],
             steps: vec![],
+            default: DefaultAction::Allow,
+            warning_event: false,
+            aliases: vec![],
+            output_timezone: None,
+            recurrence: Default::default(),
+            flag_conflicts: false,
+            variants: Default::default(),
+            max_stale: None,
+            hide_ended_after: None,
+            refresh_token: None,
         },
     );
 
-    let config = Config { calendars };
+    let config = Config {
+        sources: HashMap::new(),
+        calendars,
+        server: Default::default(),
+        output: Default::default(),
+        fetcher: Default::default(),
+        selftest: Vec::new(),
+        version: None,
+    };
 
     let fetcher = Fetcher::new().unwrap();
     let config_path = std::env::temp_dir().join("test-integration-config.json");
@@ -144,13 +198,46 @@ async fn test_filter_behavior_end_to_end() {
                     patterns: vec!["(?i)meeting".to_string()],
                     mode: MatchMode::Any,
                     fields: vec!["summary".to_string(), "description".to_string()],
+                    action: FilterAction::Enforce,
+                    unless: vec![],
+                    literal: false,
                 }],
+                default: DefaultAction::Allow,
+                label: None,
+                tags: vec![],
+                only_future: false,
+                skip_older_than: None,
+                username: None,
+                password: None,
+                auth: None,
+                timeout: None,
+                client_cert: None,
+                client_key: None,
+                insecure_tls: false,
             }],
             steps: vec![],
+            default: DefaultAction::Allow,
+            warning_event: false,
+            aliases: vec![],
+            output_timezone: None,
+            recurrence: Default::default(),
+            flag_conflicts: false,
+            variants: Default::default(),
+            max_stale: None,
+            hide_ended_after: None,
+            refresh_token: None,
         },
     );
 
-    let config = Config { calendars };
+    let config = Config {
+        sources: HashMap::new(),
+        calendars,
+        server: Default::default(),
+        output: Default::default(),
+        fetcher: Default::default(),
+        selftest: Vec::new(),
+        version: None,
+    };
 
     let fetcher = Fetcher::new().unwrap();
     let result = merge_calendars("test", &config, &fetcher).await.unwrap();
@@ -171,13 +258,46 @@ async fn test_filter_behavior_end_to_end() {
                     patterns: vec!["(?i)optional".to_string()],
                     mode: MatchMode::Any,
                     fields: vec!["summary".to_string()],
+                    action: FilterAction::Enforce,
+                    unless: vec![],
+                    literal: false,
                 }],
+                default: DefaultAction::Allow,
+                label: None,
+                tags: vec![],
+                only_future: false,
+                skip_older_than: None,
+                username: None,
+                password: None,
+                auth: None,
+                timeout: None,
+                client_cert: None,
+                client_key: None,
+                insecure_tls: false,
             }],
             steps: vec![],
+            default: DefaultAction::Allow,
+            warning_event: false,
+            aliases: vec![],
+            output_timezone: None,
+            recurrence: Default::default(),
+            flag_conflicts: false,
+            variants: Default::default(),
+            max_stale: None,
+            hide_ended_after: None,
+            refresh_token: None,
         },
     );
 
-    let config = Config { calendars };
+    let config = Config {
+        sources: HashMap::new(),
+        calendars,
+        server: Default::default(),
+        output: Default::default(),
+        fetcher: Default::default(),
+        selftest: Vec::new(),
+        version: None,
+    };
 
     let result = merge_calendars("test", &config, &fetcher).await.unwrap();
 
@@ -213,13 +333,29 @@ async fn test_multiple_sources_with_per_source_filters_and_modifiers() {
                             patterns: vec!["(?i)meeting".to_string()],
                             mode: MatchMode::Any,
                             fields: vec!["summary".to_string()],
+                            action: FilterAction::Enforce,
+                            unless: vec![],
+                            literal: false,
                         },
                         Step::Replace {
                             pattern: "Meeting:".to_string(),
                             replacement: "[WORK]".to_string(),
                             field: "summary".to_string(),
+                            literal: false,
                         },
                     ],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 },
                 SourceConfig::Url {
                     url: format!("{}/personal.ics", mock_server.uri()),
@@ -227,14 +363,45 @@ async fn test_multiple_sources_with_per_source_filters_and_modifiers() {
                         pattern: "^".to_string(),
                         replacement: "[PERSONAL] ".to_string(),
                         field: "summary".to_string(),
+                        literal: false,
                     }],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 },
             ],
             steps: vec![],
+            default: DefaultAction::Allow,
+            warning_event: false,
+            aliases: vec![],
+            output_timezone: None,
+            recurrence: Default::default(),
+            flag_conflicts: false,
+            variants: Default::default(),
+            max_stale: None,
+            hide_ended_after: None,
+            refresh_token: None,
         },
     );
 
-    let config = Config { calendars };
+    let config = Config {
+        sources: HashMap::new(),
+        calendars,
+        server: Default::default(),
+        output: Default::default(),
+        fetcher: Default::default(),
+        selftest: Vec::new(),
+        version: None,
+    };
 
     let fetcher = Fetcher::new().unwrap();
     let result = merge_calendars("test", &config, &fetcher).await.unwrap();
@@ -287,21 +454,64 @@ async fn test_calendar_level_steps() {
                 SourceConfig::Url {
                     url: format!("{}/work.ics", mock_server.uri()),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 },
                 SourceConfig::Url {
                     url: format!("{}/personal.ics", mock_server.uri()),
                     steps: vec![],
+                    default: DefaultAction::Allow,
+                    label: None,
+                    tags: vec![],
+                    only_future: false,
+                    skip_older_than: None,
+                    username: None,
+                    password: None,
+                    auth: None,
+                    timeout: None,
+                    client_cert: None,
+                    client_key: None,
+                    insecure_tls: false,
                 },
             ],
             steps: vec![Step::Replace {
                 pattern: "^".to_string(),
                 replacement: "[MERGED] ".to_string(),
                 field: "summary".to_string(),
+                literal: false,
             }],
+            default: DefaultAction::Allow,
+            warning_event: false,
+            aliases: vec![],
+            output_timezone: None,
+            recurrence: Default::default(),
+            flag_conflicts: false,
+            variants: Default::default(),
+            max_stale: None,
+            hide_ended_after: None,
+            refresh_token: None,
         },
     );
 
-    let config = Config { calendars };
+    let config = Config {
+        sources: HashMap::new(),
+        calendars,
+        server: Default::default(),
+        output: Default::default(),
+        fetcher: Default::default(),
+        selftest: Vec::new(),
+        version: None,
+    };
 
     let fetcher = Fetcher::new().unwrap();
     let result = merge_calendars("test", &config, &fetcher).await.unwrap();
@@ -339,13 +549,46 @@ async fn test_match_mode_all() {
                     patterns: vec!["(?i)meeting".to_string(), "(?i)team".to_string()],
                     mode: MatchMode::All,
                     fields: vec!["summary".to_string()],
+                    action: FilterAction::Enforce,
+                    unless: vec![],
+                    literal: false,
                 }],
+                default: DefaultAction::Allow,
+                label: None,
+                tags: vec![],
+                only_future: false,
+                skip_older_than: None,
+                username: None,
+                password: None,
+                auth: None,
+                timeout: None,
+                client_cert: None,
+                client_key: None,
+                insecure_tls: false,
             }],
             steps: vec![],
+            default: DefaultAction::Allow,
+            warning_event: false,
+            aliases: vec![],
+            output_timezone: None,
+            recurrence: Default::default(),
+            flag_conflicts: false,
+            variants: Default::default(),
+            max_stale: None,
+            hide_ended_after: None,
+            refresh_token: None,
         },
     );
 
-    let config = Config { calendars };
+    let config = Config {
+        sources: HashMap::new(),
+        calendars,
+        server: Default::default(),
+        output: Default::default(),
+        fetcher: Default::default(),
+        selftest: Vec::new(),
+        version: None,
+    };
 
     let fetcher = Fetcher::new().unwrap();
     let result = merge_calendars("test", &config, &fetcher).await.unwrap();
@@ -382,19 +625,53 @@ async fn test_step_ordering_matters() {
                         pattern: "(?i)meeting".to_string(),
                         replacement: "Event".to_string(),
                         field: "summary".to_string(),
+                        literal: false,
                     },
                     Step::Allow {
                         patterns: vec!["Event".to_string()],
                         mode: MatchMode::Any,
                         fields: vec!["summary".to_string()],
+                        action: FilterAction::Enforce,
+                        unless: vec![],
+                        literal: false,
                     },
                 ],
+                default: DefaultAction::Allow,
+                label: None,
+                tags: vec![],
+                only_future: false,
+                skip_older_than: None,
+                username: None,
+                password: None,
+                auth: None,
+                timeout: None,
+                client_cert: None,
+                client_key: None,
+                insecure_tls: false,
             }],
             steps: vec![],
+            default: DefaultAction::Allow,
+            warning_event: false,
+            aliases: vec![],
+            output_timezone: None,
+            recurrence: Default::default(),
+            flag_conflicts: false,
+            variants: Default::default(),
+            max_stale: None,
+            hide_ended_after: None,
+            refresh_token: None,
         },
     );
 
-    let config = Config { calendars };
+    let config = Config {
+        sources: HashMap::new(),
+        calendars,
+        server: Default::default(),
+        output: Default::default(),
+        fetcher: Default::default(),
+        selftest: Vec::new(),
+        version: None,
+    };
 
     let fetcher = Fetcher::new().unwrap();
     let result = merge_calendars("test", &config, &fetcher).await.unwrap();
@@ -410,3 +687,117 @@ async fn test_step_ordering_matters() {
         );
     }
 }
+
+/// Serves calendars from memory, as an embedding program might from its database
+struct MemoryProvider(HashMap<String, String>);
+
+impl SourceProvider for MemoryProvider {
+    fn fetch<'a>(
+        &'a self,
+        identifier: &'a str,
+    ) -> BoxFuture<'a, ical_merge::error::Result<String>> {
+        Box::pin(async move {
+            self.0
+                .get(identifier)
+                .cloned()
+                .ok_or_else(|| ical_merge::error::Error::CalendarNotFound(identifier.to_string()))
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_custom_source_provider() {
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "calendars": {
+            "combined": {
+                "sources": [
+                    { "url": "db:personal" },
+                    { "url": "db:missing" }
+                ]
+            }
+        }
+    }))
+    .unwrap();
+    config.validate().unwrap();
+
+    let provider =
+        MemoryProvider([("db:personal".to_string(), PERSONAL_CALENDAR.to_string())].into());
+    let fetcher = Fetcher::new().unwrap().with_provider("DB", provider);
+
+    let result = merge_calendars("combined", &config, &fetcher)
+        .await
+        .unwrap();
+    let expected = parse_calendar(PERSONAL_CALENDAR).unwrap().events().len();
+    assert_eq!(result.events.len(), expected);
+    assert_eq!(result.errors.len(), 1);
+    assert_eq!(result.errors[0].0, "db:missing");
+}
+
+#[tokio::test]
+async fn test_export_matches_served_calendar() {
+    let now = chrono::Local::now().naive_local();
+    let event = |uid: &str, start: chrono::NaiveDateTime, summary: &str| {
+        format!(
+            "BEGIN:VEVENT\r\nUID:{}\r\nDTSTAMP:20250101T000000Z\r\nDTSTART:{}\r\nDTEND:{}\r\nSUMMARY:{}\r\nEND:VEVENT\r\n",
+            uid,
+            start.format("%Y%m%dT%H%M%S"),
+            (start + chrono::Duration::hours(1)).format("%Y%m%dT%H%M%S"),
+            summary
+        )
+    };
+    let calendar = format!(
+        "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Test//Test//EN\r\n{}{}{}END:VCALENDAR\r\n",
+        event("old", now - chrono::Duration::days(30), "Long finished"),
+        event("soon", now + chrono::Duration::hours(1), "Starting soon"),
+        event("later", now + chrono::Duration::days(10), "Later on")
+    );
+
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/team.ics"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(calendar))
+        .mount(&mock_server)
+        .await;
+
+    // Both of these change what is served from what was merged
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "calendars": {
+            "team": {
+                "sources": [{ "url": format!("{}/team.ics", mock_server.uri()) }],
+                "steps": [{ "type": "soon", "within": 2 }],
+                "hide_ended_after": 7
+            }
+        }
+    }))
+    .unwrap();
+    config.validate().unwrap();
+
+    let fetcher = Fetcher::new().unwrap();
+    let result = merge_calendars("team", &config, &fetcher).await.unwrap();
+    let exported = to_csv(
+        &served_events(result.events, &config.calendars["team"]),
+        DEFAULT_CSV_COLUMNS,
+    );
+
+    let config_path = std::env::temp_dir().join("test-integration-export.json");
+    let app = create_router(AppState::new(config, config_path, Fetcher::new().unwrap()));
+    let get = |uri: &str| {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        }
+    };
+    let served = parse_calendar(&get("/ical/team").await).unwrap();
+
+    assert_eq!(exported, to_csv(served.events(), DEFAULT_CSV_COLUMNS));
+    assert_eq!(exported, get("/csv/team").await);
+    assert!(!exported.contains("Long finished"));
+    assert!(exported.contains("⏰ Starting soon"));
+    assert_eq!(served.events().len(), 2);
+}